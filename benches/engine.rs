@@ -0,0 +1,138 @@
+//! Run with `cargo bench --features testutil`, the reports of `criterion` are in `target/criterion`
+use criterion::measurement::WallTime;
+use criterion::{BenchmarkGroup, Criterion, Throughput, criterion_group, criterion_main};
+use rust_challenge::amount::{Amount, checked_update};
+use rust_challenge::engine::Engine;
+#[cfg(feature = "fixed-point")]
+use rust_challenge::fixed::FixedPoint;
+use rust_challenge::record::ColumnIndex;
+use rust_challenge::store::VecStore;
+use rust_challenge::testutil::{GeneratorConfig, TransactionGenerator, write_csv};
+use rust_challenge::types::CsvInputRow;
+use rust_decimal::Decimal;
+use std::hint::black_box;
+
+const ROWS: usize = 200_000;
+
+fn workload(dispute_ratio: f64) -> Vec<u8> {
+    let config = GeneratorConfig {
+        clients: 1000,
+        dispute_ratio,
+        ..Default::default()
+    };
+    let mut csv = Vec::new();
+    write_csv(TransactionGenerator::new(42, config), ROWS, &mut csv).unwrap();
+    csv
+}
+
+/// A group of benchmarks over `ROWS` rows, so the throughput is reported in rows per second
+fn group<'a>(c: &'a mut Criterion, name: &str) -> BenchmarkGroup<'a, WallTime> {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.sample_size(10);
+    group
+}
+
+/// A deposit and a withdrawal of `amount` per row through the shared balance arithmetic
+fn deposit_and_withdraw<A: Amount>(amount: A) -> (A, A) {
+    let mut balances = (A::ZERO, A::ZERO);
+    for _ in 0..ROWS {
+        let (available, held) =
+            checked_update(balances.0, balances.1, black_box(amount), A::ZERO).unwrap();
+        balances = checked_update(available, held, -black_box(amount), A::ZERO).unwrap();
+    }
+    balances
+}
+
+fn parse(c: &mut Criterion) {
+    let csv = workload(0.3);
+    let mut group = group(c, "parse");
+    group.bench_function("parse_row", |b| {
+        b.iter(|| {
+            let engine = Engine::new();
+            let mut rdr = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_slice());
+            for row in rdr.deserialize::<CsvInputRow>().flatten() {
+                _ = black_box(engine.parse_row(&row));
+            }
+        });
+    });
+    // Compare with `--features testutil,fast-decimal` for the fixed point amount parser
+    group.bench_function("parse_records", |b| {
+        b.iter(|| {
+            let mut rdr = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_slice());
+            let columns = ColumnIndex::new(rdr.headers().unwrap());
+            let mut record = csv::ByteRecord::new();
+            while rdr.read_byte_record(&mut record).unwrap() {
+                _ = black_box(columns.parse(&record));
+            }
+        });
+    });
+    group.finish();
+}
+
+fn process_transaction(c: &mut Criterion) {
+    let transactions = TransactionGenerator::new(42, GeneratorConfig::default())
+        .take(ROWS)
+        .collect::<Vec<_>>();
+    let mut group = group(c, "process_transaction");
+    // Compare with `--features testutil,fx-hash` or `testutil,ahash` for the hasher of the maps
+    group.bench_function("memory_store", |b| {
+        b.iter(|| {
+            let mut engine = Engine::new();
+            for &(client, id, transaction) in &transactions {
+                _ = black_box(engine.process_transaction(client, id, transaction));
+            }
+        });
+    });
+    // The same transactions with an account slot for every client id instead of a map
+    group.bench_function("vec_store", |b| {
+        b.iter(|| {
+            let mut engine = Engine::with_store(VecStore::new());
+            for &(client, id, transaction) in &transactions {
+                _ = black_box(engine.process_transaction(client, id, transaction));
+            }
+        });
+    });
+    group.finish();
+}
+
+fn checked_update_amounts(c: &mut Criterion) {
+    let mut group = group(c, "checked_update");
+    group.bench_function("decimal", |b| {
+        b.iter(|| deposit_and_withdraw(Decimal::new(15, 1)));
+    });
+    // The same balance logic with `i64` units, run with `--features testutil,fixed-point`
+    #[cfg(feature = "fixed-point")]
+    group.bench_function("fixed", |b| {
+        b.iter(|| deposit_and_withdraw(FixedPoint(15_000)));
+    });
+    group.finish();
+}
+
+fn process_csv(c: &mut Criterion) {
+    let csv = workload(0.3);
+    let disputes = workload(0.8);
+    let mut group = group(c, "process_csv");
+    group.bench_function("normal", |b| {
+        b.iter(|| Engine::new().process_csv_bytes(&csv));
+    });
+    group.bench_function("disputes", |b| {
+        b.iter(|| Engine::new().process_csv_bytes(&disputes));
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    parse,
+    process_transaction,
+    checked_update_amounts,
+    process_csv
+);
+criterion_main!(benches);
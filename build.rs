@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The gRPC service of `grpc.rs` is generated from the proto with the vendored protoc, no toolchain is needed
+    #[cfg(feature = "grpc")]
+    {
+        let mut config = tonic_prost_build::Config::new();
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_with_config(config, &["proto/engine.proto"], &["proto"])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_challenge::engine::Engine;
+use rust_challenge::types::CsvInputRow;
+
+// A single row after a fixed header, every row that decodes must parse without panicking
+fuzz_target!(|data: &[u8]| {
+    let mut input = b"type,client,tx,amount,to,timestamp\n".to_vec();
+    input.extend_from_slice(data);
+    let engine = Engine::new();
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_slice());
+    for row in rdr.deserialize::<CsvInputRow>().flatten() {
+        _ = engine.parse_row(&row);
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_challenge::engine::Engine;
+
+// Arbitrary bytes as a whole csv input, including the header
+fuzz_target!(|data: &[u8]| {
+    let mut engine = Engine::new();
+    engine.process_csv_bytes(data);
+    for (_, profile) in engine.accounts() {
+        assert!(profile.available.checked_add(profile.held).is_some());
+    }
+});
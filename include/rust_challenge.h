@@ -0,0 +1,62 @@
+/* C interface of the rust-challenge engine, see src/ffi.rs
+ * Build the library with `cargo rustc --lib --release --features ffi --crate-type staticlib`
+ * Keep in sync with src/ffi.rs, it follows the cbindgen layout so it can be regenerated with
+ * `cbindgen --lang c --output include/rust_challenge.h` */
+
+#ifndef RUST_CHALLENGE_H
+#define RUST_CHALLENGE_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#define ENGINE_OK 0
+
+/* A null pointer or a string which is not valid utf-8 or not a decimal */
+#define ENGINE_INVALID_ARGUMENT 1
+
+/* The row is not a valid transaction, e.g. an unknown type or a missing amount */
+#define ENGINE_PARSE_ERROR 2
+
+/* The transaction was rejected, e.g. insufficient funds or a frozen account */
+#define ENGINE_REJECTED 3
+
+/* The client has no account, or its balances don't fit into an int64_t */
+#define ENGINE_NOT_FOUND 4
+
+typedef struct Engine Engine;
+
+/* The balances of an account in units of 10^-4 */
+typedef struct EngineAccount {
+  int64_t available;
+  int64_t held;
+  int64_t total;
+  bool locked;
+} EngineAccount;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Create an engine with the default config, it must be released with engine_free */
+Engine *engine_new(void);
+
+/* Release an engine created by engine_new, null is ignored */
+void engine_free(Engine *engine);
+
+/* Process a single transaction, same as a csv row, amount is a decimal string
+ * It can be null for transactions without an amount, to is the destination of a transfer or negative */
+int32_t engine_process(Engine *engine,
+                       uint16_t client,
+                       uint32_t tx,
+                       const char *transaction_type,
+                       const char *amount,
+                       int32_t to);
+
+/* Write the balances of client into account */
+int32_t engine_get_account(const Engine *engine, uint16_t client, EngineAccount *account);
+
+#ifdef __cplusplus
+}  /* extern "C" */
+#endif
+
+#endif  /* RUST_CHALLENGE_H */
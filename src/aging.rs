@@ -0,0 +1,167 @@
+use crate::config::DisputeTimeoutAction;
+use crate::engine::Engine;
+use crate::store::StateStore;
+use crate::types::{AccountProfile, ClientId, DisputeState, Timestamp, Transaction, TransactionId};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// The disputes with a timestamp ordered by when they were opened, so the oldest ones are aged first
+/// An entry stays after its dispute is closed, it is skipped when it is popped
+#[derive(Debug, Default)]
+pub struct OpenDisputes(BinaryHeap<Reverse<(Timestamp, ClientId, TransactionId)>>);
+
+impl OpenDisputes {
+    pub fn push(&mut self, opened: Timestamp, client: ClientId, tx: TransactionId) {
+        self.0.push(Reverse((opened, client, tx)));
+    }
+
+    /// Add the open disputes of an account restored from somewhere else
+    pub(crate) fn extend(&mut self, client: ClientId, profile: &AccountProfile) {
+        for (tx, _, record) in profile.transactions.iter() {
+            for case in &record.cases {
+                if let (DisputeState::Open, Some(opened)) = (case.state, case.opened) {
+                    self.push(opened, client, tx);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn append(&mut self, mut other: Self) {
+        self.0.append(&mut other.0);
+    }
+
+    /// Remove all entries in no particular order
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (Timestamp, ClientId, TransactionId)> {
+        self.0.drain().map(|Reverse(entry)| entry)
+    }
+
+    /// Remove the oldest entry if it was opened at or before `until`
+    fn pop_due(&mut self, until: Timestamp) -> Option<(Timestamp, ClientId, TransactionId)> {
+        let Reverse(oldest) = *self.0.peek()?;
+        (oldest.0 <= until).then(|| self.0.pop().expect("peeked entry").0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Close the disputes open for longer than `EngineConfig::dispute_timeout` at `now` with its action,
+    /// returns how many were closed
+    /// They are closed with a `resolve` or `chargeback` of the disputed transaction at `now`, which goes to the
+    /// ledger and the observers like any other row, a rejected one (e.g. of a closed account) stays open
+    pub fn expire_disputes(&mut self, now: Timestamp) -> usize {
+        let Some(timeout) = self.config.dispute_timeout else {
+            return 0;
+        };
+        let mut closed = 0;
+        while let Some((_, client, tx)) = self
+            .open_disputes
+            .pop_due(now.saturating_sub(timeout.after))
+        {
+            let expired: Vec<_> = self
+                .store
+                .get(client)
+                .and_then(|profile| profile.transactions.get(tx))
+                .map(|(_, record)| {
+                    record
+                        .cases
+                        .iter()
+                        .filter(|case| case.state == DisputeState::Open)
+                        .filter(|case| {
+                            case.opened
+                                .is_some_and(|opened| opened.saturating_add(timeout.after) <= now)
+                        })
+                        .map(|case| case.id)
+                        .collect()
+                })
+                .unwrap_or_default();
+            for case_id in expired {
+                let transaction = match timeout.action {
+                    DisputeTimeoutAction::Resolve => Transaction::Resolve(case_id),
+                    DisputeTimeoutAction::Chargeback => Transaction::Chargeback(case_id),
+                };
+                let res = self.process_transaction_at(client, tx, transaction, Some(now));
+                closed += usize::from(res.is_ok());
+            }
+        }
+        closed
+    }
+
+    /// The disputes which are aged by `expire_disputes`
+    pub fn open_disputes(&self) -> &OpenDisputes {
+        &self.open_disputes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DisputeTimeout, DisputeTimeoutAction, EngineConfig};
+    use crate::engine::Engine;
+    use crate::types::{Timestamp, Transaction};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_expire_disputes() {
+        let input = "type,client,tx,amount,timestamp\n\
+            deposit,1,1,10,0\n\
+            deposit,2,2,10,0\n\
+            dispute,1,1,,100\n\
+            dispute,2,2,,150\n\
+            resolve,2,2,,160\n\
+            dispute,2,2,,170\n\
+            deposit,3,3,1,200\n";
+        for (action, available, held, frozen) in [
+            (DisputeTimeoutAction::Resolve, 10, 10, false),
+            (DisputeTimeoutAction::Chargeback, 0, 10, true),
+        ] {
+            let mut engine = Engine::with_config(EngineConfig {
+                dispute_timeout: Some(DisputeTimeout { after: 100, action }),
+                ..EngineConfig::default()
+            });
+            engine.enable_ledger();
+            engine
+                .process_csv_with_errors(input.as_bytes(), |_, _, err| Err::<(), _>(err))
+                .unwrap();
+            // The dispute of client 1 is 100 seconds old at 200, the second dispute of client 2 only 30
+            let profile = engine.account(1).unwrap();
+            assert_eq!(profile.available, Decimal::from(available));
+            assert_eq!(profile.is_frozen(), frozen);
+            let profile = engine.account(2).unwrap();
+            assert_eq!(profile.held, Decimal::from(held));
+            assert_eq!(engine.ledger().unwrap().entries().len(), 8);
+            assert_eq!(engine.expire_disputes(270), 1);
+            assert_eq!(engine.expire_disputes(Timestamp::MAX), 0);
+        }
+
+        // The second dispute of the transaction is opened so late its expiry doesn't fit into a timestamp
+        let input = "type,client,tx,amount,timestamp\n\
+            deposit,1,1,10,0\n\
+            dispute,1,1,2,10\n";
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_timeout: Some(DisputeTimeout {
+                after: 20,
+                action: DisputeTimeoutAction::Resolve,
+            }),
+            ..EngineConfig::default()
+        });
+        engine.process_csv(input.as_bytes());
+        let late = Some(Timestamp::MAX - 1);
+        engine
+            .process_transaction_at(
+                1,
+                1,
+                Transaction::PartialDispute(Decimal::from(3), None),
+                late,
+            )
+            .unwrap();
+        assert_eq!(engine.expire_disputes(Timestamp::MAX - 1), 1);
+        let profile = engine.account(1).unwrap();
+        assert_eq!(profile.held, Decimal::from(3));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::types::TransactionProcessingError;
+use rust_decimal::Decimal;
+use std::fmt::Debug;
+use std::ops::Neg;
+
+/// The arithmetic the balances of an account need, so the balance logic is written once for every representation
+/// It is implemented by `Decimal`, and by the `i64` based `FixedPoint` with the `fixed-point` feature
+pub trait Amount: Copy + Ord + Default + Debug + Neg<Output = Self> {
+    const ZERO: Self;
+
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    fn checked_sub(self, other: Self) -> Option<Self>;
+
+    fn is_zero(self) -> bool {
+        self == Self::ZERO
+    }
+
+    /// None if the amount can't be represented, e.g. it has more decimal places
+    fn from_decimal(amount: Decimal) -> Option<Self>;
+
+    fn to_decimal(self) -> Decimal;
+}
+
+impl Amount for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Decimal::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Decimal::checked_sub(self, other)
+    }
+
+    fn from_decimal(amount: Decimal) -> Option<Self> {
+        Some(amount)
+    }
+
+    fn to_decimal(self) -> Decimal {
+        self
+    }
+}
+
+/// The balances after adding the deltas, the total must not overflow either since we output it
+/// Nothing is mutated here, so a transaction is either fully applied or rejected without side effects
+pub fn checked_update<A: Amount>(
+    available: A,
+    held: A,
+    available_delta: A,
+    held_delta: A,
+) -> Result<(A, A), TransactionProcessingError> {
+    let available = available.checked_add(available_delta);
+    let held = held.checked_add(held_delta);
+    match (available, held) {
+        (Some(available), Some(held)) if available.checked_add(held).is_some() => {
+            Ok((available, held))
+        }
+        _ => Err(TransactionProcessingError::ArithmeticOverflow),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dispute_and_resolve<A: Amount>(amount: A) -> Vec<(A, A)> {
+        let deposited = checked_update(A::ZERO, A::ZERO, amount, A::ZERO).unwrap();
+        let disputed = checked_update(deposited.0, deposited.1, -amount, amount).unwrap();
+        let resolved = checked_update(disputed.0, disputed.1, amount, -amount).unwrap();
+        vec![deposited, disputed, resolved]
+    }
+
+    #[test]
+    fn test_checked_update() {
+        let amount = Decimal::new(15, 1);
+        assert_eq!(
+            dispute_and_resolve(amount),
+            [
+                (amount, Decimal::ZERO),
+                (Decimal::ZERO, amount),
+                (amount, Decimal::ZERO)
+            ]
+        );
+        assert!(matches!(
+            checked_update(Decimal::MAX, Decimal::ZERO, Decimal::ZERO, Decimal::ONE),
+            Err(TransactionProcessingError::ArithmeticOverflow)
+        ));
+
+        #[cfg(feature = "fixed-point")]
+        {
+            use crate::fixed::FixedPoint;
+            let fixed = FixedPoint::from_decimal(amount).unwrap();
+            let converted = dispute_and_resolve(fixed)
+                .into_iter()
+                .map(|(available, held)| (available.to_decimal(), held.to_decimal()))
+                .collect::<Vec<_>>();
+            assert_eq!(converted, dispute_and_resolve(amount));
+            assert!(
+                checked_update(
+                    FixedPoint(i64::MAX),
+                    FixedPoint::ZERO,
+                    FixedPoint::ZERO,
+                    fixed
+                )
+                .is_err()
+            );
+        }
+    }
+}
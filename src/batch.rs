@@ -0,0 +1,41 @@
+use crate::engine::Engine;
+use crate::store::StateStore;
+use crate::types::{ClientId, Transaction, TransactionId, TransactionProcessingError};
+
+impl<S: StateStore> Engine<S> {
+    /// Apply already parsed transactions in order, same as `process_transaction` for each of them
+    /// Returns the result of every transaction, in the same order
+    pub fn process_batch(
+        &mut self,
+        transactions: &[(ClientId, TransactionId, Transaction)],
+    ) -> Vec<Result<(), TransactionProcessingError>> {
+        transactions
+            .iter()
+            .map(|&(client, id, transaction)| self.process_transaction(client, id, transaction))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_process_batch() {
+        let mut engine = Engine::new();
+        let results = engine.process_batch(&[
+            (1, 1, Transaction::Deposit(Decimal::from(5))),
+            (1, 2, Transaction::Withdrawal(Decimal::from(10))),
+            (1, 1, Transaction::Dispute(None)),
+        ]);
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(TransactionProcessingError::AvailableAmountTooLow(..))
+        ));
+        assert!(results[2].is_ok());
+        assert_eq!(engine.account(1).unwrap().held, Decimal::from(5));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::engine::Engine;
+use crate::record::lossy;
+use crate::store::StateStore;
+use crate::types::{RowProcessingError, SnapshotError};
+use csv::{ByteRecord, Position, Reader, StringRecord};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek};
+use std::path::PathBuf;
+
+/// How many rows are processed between two checkpoints of `--checkpoint-dir` by default
+pub const DEFAULT_CHECKPOINT_ROWS: u64 = 1_000_000;
+
+/// How far a batch run got in its inputs
+/// The inputs before `input` are fully applied, and the first `rows` rows of `input`
+/// which end at `byte` and `line`, so a seekable input can continue right after them
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InputPosition {
+    pub input: usize,
+    pub rows: u64,
+    /// The byte offset after the last applied row, 0 when it is not known
+    #[serde(default)]
+    pub byte: u64,
+    #[serde(default)]
+    pub line: u64,
+}
+
+impl InputPosition {
+    /// The start of the input at `index`, or `self` when it is that input
+    pub fn start_of(&self, index: usize) -> Self {
+        if index == self.input {
+            *self
+        } else {
+            Self {
+                input: index,
+                ..Self::default()
+            }
+        }
+    }
+
+    /// The position of `input` after `rows` rows read up to `position`
+    fn after(input: usize, rows: u64, position: &Position) -> Self {
+        Self {
+            input,
+            rows,
+            byte: position.byte(),
+            line: position.line(),
+        }
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Same as `process_csv_with_errors` but the first `start.rows` rows are read without applying them,
+    /// and `on_position` is called with the position after every other row
+    pub fn process_csv_resumed<E>(
+        &mut self,
+        input: impl Read,
+        start: InputPosition,
+        on_position: impl FnMut(&Self, InputPosition) -> Result<(), E>,
+        on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        let skip = |rdr: &mut Reader<_>| {
+            let mut record = ByteRecord::new();
+            let mut rows = 0;
+            while rows < start.rows {
+                match rdr.read_byte_record(&mut record) {
+                    Ok(false) => break,
+                    Err(err) if err.is_io_error() => return Err(err),
+                    Ok(true) | Err(_) => rows += 1,
+                }
+            }
+            Ok(rows)
+        };
+        self.process_csv_positioned(input, start.input, skip, on_position, on_error)
+    }
+
+    /// Same as `process_csv_resumed` but it seeks right after the rows applied before instead of reading them
+    /// Without the byte offset of `start` the rows are read again like `process_csv_resumed` does
+    pub fn process_csv_seeked<E>(
+        &mut self,
+        input: impl Read + Seek,
+        start: InputPosition,
+        on_position: impl FnMut(&Self, InputPosition) -> Result<(), E>,
+        on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        if start.byte == 0 {
+            return self.process_csv_resumed(input, start, on_position, on_error);
+        }
+        let seek = |rdr: &mut Reader<_>| {
+            let mut position = Position::new();
+            position.set_byte(start.byte).set_line(start.line);
+            rdr.seek(position)?;
+            Ok(start.rows)
+        };
+        self.process_csv_positioned(input, start.input, seek, on_position, on_error)
+    }
+
+    fn process_csv_positioned<R: Read, E>(
+        &mut self,
+        input: R,
+        index: usize,
+        start: impl FnOnce(&mut Reader<R>) -> csv::Result<u64>,
+        mut on_position: impl FnMut(&Self, InputPosition) -> Result<(), E>,
+        mut on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        self.process_csv_from(
+            input,
+            start,
+            |_, _| false,
+            |engine, line, record, result| {
+                engine.report_rejected_occurrences(line, &mut on_error)?;
+                match result {
+                    Ok(_) => Ok(()),
+                    Err(err) => on_error(line, &lossy(record), err),
+                }
+            },
+            |engine, rows, position| {
+                on_position(engine, InputPosition::after(index, rows, position))
+            },
+        )
+    }
+}
+
+/// The checkpoints of a long batch run, the latest one is a snapshot with its position in `checkpoint.json`
+/// A run which was interrupted starts again from the latest checkpoint and skips the rows applied before it
+pub struct Checkpoints {
+    dir: PathBuf,
+    every: u64,
+}
+
+impl Checkpoints {
+    /// Save a checkpoint into `dir` every `every` rows
+    pub fn new(dir: impl Into<PathBuf>, every: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            every: every.max(1),
+        }
+    }
+
+    /// The engine and the position of the latest checkpoint, None when there is none yet
+    pub fn load<S: StateStore>(&self) -> Result<Option<(Engine<S>, InputPosition)>, SnapshotError> {
+        let path = self.dir.join("checkpoint.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let (engine, position) = Engine::read_snapshot(BufReader::new(File::open(path)?))?;
+        Ok(Some((engine, position.unwrap_or_default())))
+    }
+
+    /// Save the state of `engine` at `position`
+    /// It is written next to the previous checkpoint and renamed, so a crash never leaves a partial checkpoint
+    pub fn save<S: StateStore>(
+        &self,
+        engine: &Engine<S>,
+        position: InputPosition,
+    ) -> Result<(), SnapshotError> {
+        fs::create_dir_all(&self.dir)?;
+        let tmp = self.dir.join("checkpoint.json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        engine.write_snapshot_at(&mut writer, Some(position))?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(tmp, self.dir.join("checkpoint.json"))?;
+        Ok(())
+    }
+
+    /// Save a checkpoint when `every` rows were applied since `start`
+    pub fn on_position<S: StateStore>(
+        &self,
+        engine: &Engine<S>,
+        start: InputPosition,
+        position: InputPosition,
+    ) -> Result<(), SnapshotError> {
+        if (position.rows - start.rows).is_multiple_of(self.every) {
+            self.save(engine, position)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use std::convert::Infallible;
+    use std::io::Cursor;
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,1,1,5
+deposit,1,2,3
+withdrawal,1,3,1
+deposit,2,4,7
+dispute,1,1,
+";
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("checkpoints-{}", std::process::id()));
+        let checkpoints = Checkpoints::new(&dir, 2);
+        assert!(
+            checkpoints
+                .load::<crate::store::MemoryStore>()
+                .unwrap()
+                .is_none()
+        );
+
+        // The run dies after the third row, the latest checkpoint is after the second row
+        let start = InputPosition {
+            input: 1,
+            ..Default::default()
+        };
+        let mut engine: Engine = Engine::new();
+        let cut = INPUT.lines().take(4).collect::<Vec<_>>().join("\n");
+        let rows = engine
+            .process_csv_resumed(
+                cut.as_bytes(),
+                start,
+                |engine, position| checkpoints.on_position(engine, start, position),
+                |_, _, _| Ok(()),
+            )
+            .unwrap();
+        assert_eq!(rows, 3);
+
+        let (engine, position): (Engine, _) = checkpoints.load().unwrap().unwrap();
+        let applied = INPUT
+            .lines()
+            .take(3)
+            .map(|line| line.len() + 1)
+            .sum::<usize>();
+        assert_eq!(
+            position,
+            InputPosition {
+                input: 1,
+                rows: 2,
+                byte: applied as u64,
+                line: 4,
+            }
+        );
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(8));
+        fs::remove_dir_all(&dir).unwrap();
+        let mut json = Vec::new();
+        engine.write_snapshot(&mut json).unwrap();
+
+        // Both reading the applied rows again and seeking past them end in the same state as a single run
+        for seek in [false, true] {
+            let (mut engine, _): (Engine, _) = Engine::read_snapshot(json.as_slice()).unwrap();
+            let on_position = |_: &Engine, _| Ok::<(), Infallible>(());
+            let rows = if seek {
+                engine
+                    .process_csv_seeked(Cursor::new(INPUT), position, on_position, |_, _, _| Ok(()))
+            } else {
+                engine.process_csv_resumed(
+                    INPUT.as_bytes(),
+                    position,
+                    on_position,
+                    |_, _, _| Ok(()),
+                )
+            };
+            assert_eq!(rows.unwrap(), 5);
+            let mut expected: Engine = Engine::new();
+            _ = expected
+                .process_csv_with_errors(INPUT.as_bytes(), |_, _, _| Ok::<(), Infallible>(()));
+            assert_eq!(engine.store(), expected.store());
+        }
+    }
+}
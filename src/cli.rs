@@ -0,0 +1,873 @@
+use rust_challenge::compression::Compression;
+use rust_challenge::config::{DuplicateIdPolicy, EngineConfig, InvariantChecks, days_to_seconds};
+use rust_challenge::output::OutputFormat;
+use rust_challenge::query::DEFAULT_RECENT_TRANSACTIONS;
+use rust_challenge::replay::ReplayUntil;
+use rust_challenge::report::ReportFormat;
+use rust_challenge::source::InputFormat;
+use rust_challenge::stream::MessageEncoding;
+use rust_challenge::types::ClientId;
+use std::error::Error;
+use std::fs;
+
+/// The text of `--help`, the options are described in the README
+pub const USAGE: &str = "\
+Usage: rust-challenge [OPTIONS] [INPUT]...
+       rust-challenge <COMMAND> [ARGS]...
+
+Process the transactions of the csv inputs, or of stdin without an input or with `-`,
+and write the accounts to stdout.
+
+Commands:
+  report                        per-client statistics instead of the accounts
+  replay --until-tx ID | --until-line N
+                                the full state at a point of a single input
+  diff LEFT RIGHT               the accounts which differ between two runs
+  validate                      check a single input against the schema
+  query SNAPSHOT --client ID    the state of an account of a snapshot
+  export-history --client ID    everything applied to an account
+  gen-data                      a synthetic input (`testutil` feature)
+  serve ADDR                    serve over gRPC after the inputs (`grpc` feature)
+  consume-kafka BROKERS TOPIC   consume a Kafka topic (`kafka` feature)
+
+Processing:
+  --config PATH, --risk-rules PATH, --opening-balances PATH
+  --shards N, --parsers N, --appliers N, --dense-clients
+  --scale N, --rounding reject|half-even|half-up|down, --allow-zero-amounts, --lenient-amounts
+  --dispute-policy reject-if-insufficient|allow-negative-available, --max-disputes N
+  --dispute-window-days N, --dispute-timeout-days N
+  --dispute-timeout-action resolve|chargeback
+  --overdraft-limit AMOUNT, --withdrawal-fee AMOUNT, --withdrawal-fee-percent PERCENTAGE
+  --duplicate-ids allow|warn|reject, --detect-duplicate-rows, --max-transactions N
+  --strict-timestamps, --freeze-on-chargeback always|never|above:AMOUNT
+  --unfreeze-requires-zero-held, --unfreeze-on-reversal, --paranoid, --paranoid=log
+
+Inputs:
+  --input-format csv|parquet, --format FORMAT, --compression none|gzip|zstd, --mmap
+  --delimiter C, --no-headers, --column NAME=COLUMN, --tolerant-types, --type-alias ALIAS=TYPE
+  --clients LIST, --only TYPES, --from TIMESTAMP, --to TIMESTAMP
+
+Rejected rows:
+  --report-errors, --report-errors=PATH, --reject-file PATH, --strict, --dry-run
+  --stats, --stats-file PATH
+
+Outputs:
+  -o, --output PATH, --output-format csv|json|jsonl|sql, --output-scale N, --extended-output
+  --report-format csv|json, --incremental, --flush-every N
+  --save-snapshot PATH, --load-snapshot PATH, --resume, --wal PATH
+  --audit-log PATH, --include-rejected, --tenants
+  --checkpoint-dir DIR, --checkpoint-every N, --spill-dir DIR, --max-resident-transactions N
+  --postgres URL, --postgres-table TABLE, --postgres-ledger-table TABLE
+
+Streams and servers:
+  --consume json|csv, --encoding json|csv, --group GROUP
+  --serve-http ADDR, --listen ADDR
+
+  -h, --help                    print this help
+";
+
+/// Where to report rejected rows
+#[derive(Debug)]
+pub enum ErrorReport {
+    Stderr,
+    File(String),
+}
+
+/// Command line options
+#[derive(Debug, Default)]
+pub struct Options {
+    pub paths: Vec<String>,
+    pub shards: Option<usize>,
+    /// Parse the csv on this many threads while the rows are applied in input order
+    pub parsers: Option<usize>,
+    /// Apply the rows on this many threads partitioned by client, with the same result as a serial run
+    pub appliers: Option<usize>,
+    pub report_errors: Option<ErrorReport>,
+    pub strict: bool,
+    /// Write every rejected row with its reason into this csv file
+    pub reject_file: Option<String>,
+    /// Validate the inputs without persisting anything, the summaries go to stderr
+    pub dry_run: bool,
+    pub load_snapshot: Option<String>,
+    pub save_snapshot: Option<String>,
+    pub serve_http: Option<String>,
+    /// Serve the engine over gRPC on this address after the inputs are processed, the `serve` subcommand
+    pub serve_grpc: Option<String>,
+    /// Keep the accounts of every tenant of the `tenant` column apart, `--output` and snapshots are directories then
+    pub tenants: bool,
+    /// Accept transactions over the TCP line protocol of `tcp.rs` on this address
+    pub listen: Option<String>,
+    pub wal: Option<String>,
+    pub audit_log: Option<String>,
+    /// Upsert the accounts into the PostgreSQL database of this url at the end of the run
+    pub postgres: Option<String>,
+    /// The table of the accounts in `postgres`, `accounts` by default
+    pub postgres_table: Option<String>,
+    /// Append the ledger to this table of `postgres` too
+    pub postgres_ledger_table: Option<String>,
+    pub input_format: InputFormat,
+    /// Write the summaries to this file instead of stdout
+    pub output: Option<String>,
+    pub output_format: OutputFormat,
+    /// Write the changed accounts to stdout every this many rows while processing, see `IncrementalWriter`
+    pub incremental: Option<u64>,
+    /// None means detect it from the extension or the content of each input
+    pub compression: Option<Compression>,
+    /// Read the input files through a memory mapping
+    pub mmap: bool,
+    /// Write the statistics of the run to stderr
+    pub stats: bool,
+    pub stats_file: Option<String>,
+    /// Write per-client statistics in this format instead of the summaries
+    pub report: Option<ReportFormat>,
+    /// Consume a message stream with this encoding from stdin, or from the topic of `kafka`
+    pub consume: Option<MessageEncoding>,
+    /// Consume the messages from a Kafka topic instead of stdin, the `consume-kafka` subcommand
+    pub kafka: Option<Kafka>,
+    pub checkpoint_every: Option<usize>,
+    /// Save a checkpoint of a batch run into this directory every `checkpoint_every` rows and resume from it
+    pub checkpoint_dir: Option<String>,
+    /// Continue the inputs right after the position recorded in the snapshot of `load_snapshot`
+    pub resume: bool,
+    pub spill_dir: Option<String>,
+    /// Keep the accounts in a `VecStore` with a slot for every client id
+    pub dense_clients: bool,
+    pub max_resident_transactions: Option<usize>,
+    pub config: EngineConfig,
+    /// The TOML file with the `[risk]` table of `EngineConfig::risk`, it is loaded before any input is processed
+    pub risk_rules: Option<String>,
+    /// The csv with the balances the accounts start from, see `Engine::seed_opening_balances`
+    pub opening_balances: Option<String>,
+    /// Generate a synthetic input instead of processing one
+    pub gen_data: Option<GenData>,
+    /// Print the state of an account of a snapshot instead of processing an input
+    pub query: Option<Query>,
+    /// Compare the accounts of these two runs instead of processing an input
+    pub diff: Option<(String, String)>,
+    /// Write the ledger entries of this client instead of the summaries
+    pub export_history: Option<ClientId>,
+    /// Record the rejected transactions in the history and the audit log
+    pub include_rejected: bool,
+    /// Process the input up to this point and write the full state instead of the summaries
+    pub replay: Option<ReplayUntil>,
+    /// Check the input against the schema and write a report instead of processing it
+    pub validate: bool,
+    /// Print `USAGE` instead of processing an input
+    pub help: bool,
+}
+
+/// Options of the `gen-data` subcommand, see `TransactionGenerator`
+#[derive(Debug)]
+pub struct GenData {
+    pub rows: usize,
+    pub clients: u16,
+    pub seed: u64,
+    pub dispute_ratio: f64,
+    pub adversarial_ratio: f64,
+    /// Write the csv to this file instead of stdout
+    pub output: Option<String>,
+}
+
+impl Default for GenData {
+    fn default() -> Self {
+        Self {
+            rows: 100_000,
+            clients: 1000,
+            seed: 0,
+            dispute_ratio: 0.3,
+            adversarial_ratio: 0.0,
+            output: None,
+        }
+    }
+}
+
+/// Options of the `query` subcommand, see `Engine::query`
+#[derive(Debug)]
+pub struct Query {
+    pub snapshot: String,
+    pub client: ClientId,
+    pub recent: usize,
+}
+
+/// Options of the `consume-kafka` subcommand, see `KafkaConsumer`
+#[derive(Debug)]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub struct Kafka {
+    pub brokers: String,
+    pub topic: String,
+    pub group: String,
+}
+
+/// The consumer group of `consume-kafka` by default
+const DEFAULT_KAFKA_GROUP: &str = "rust-challenge";
+
+/// How many rows are processed between two flushes of `--incremental` by default
+const DEFAULT_FLUSH_EVERY: u64 = 10_000;
+
+/// Take the value of `flag` from the next argument
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, Box<dyn Error>> {
+    Ok(args
+        .next()
+        .ok_or_else(|| format!("missing value for {flag}"))?)
+}
+
+fn parse_gen_data(mut args: impl Iterator<Item = String>) -> Result<GenData, Box<dyn Error>> {
+    let mut gen_data = GenData::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rows" => gen_data.rows = value(&mut args, &arg)?.parse()?,
+            "--clients" => gen_data.clients = value(&mut args, &arg)?.parse()?,
+            "--seed" => gen_data.seed = value(&mut args, &arg)?.parse()?,
+            "--dispute-ratio" => gen_data.dispute_ratio = value(&mut args, &arg)?.parse()?,
+            "--adversarial-ratio" => {
+                gen_data.adversarial_ratio = value(&mut args, &arg)?.parse()?;
+            }
+            "-o" | "--output" => gen_data.output = Some(value(&mut args, &arg)?),
+            _ => return Err(format!("unknown gen-data option {arg}").into()),
+        }
+    }
+    Ok(gen_data)
+}
+
+fn parse_query(mut args: impl Iterator<Item = String>) -> Result<Query, Box<dyn Error>> {
+    let mut snapshot = None;
+    let mut client = None;
+    let mut recent = DEFAULT_RECENT_TRANSACTIONS;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--client" => client = Some(value(&mut args, &arg)?.parse()?),
+            "--recent" => recent = value(&mut args, &arg)?.parse()?,
+            _ if snapshot.is_none() && !arg.starts_with("--") => snapshot = Some(arg),
+            _ => return Err(format!("unknown query option {arg}").into()),
+        }
+    }
+    match (snapshot, client) {
+        (Some(snapshot), Some(client)) => Ok(Query {
+            snapshot,
+            client,
+            recent,
+        }),
+        _ => Err("query takes a snapshot and --client, e.g. query state.json --client 42".into()),
+    }
+}
+
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, Box<dyn Error>> {
+    let mut options = Options::default();
+    // The help wins over any other argument, including the invalid ones
+    let args: Vec<String> = args.collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        options.help = true;
+        return Ok(options);
+    }
+    let mut args = args.into_iter().peekable();
+    if args.next_if(|arg| arg == "gen-data").is_some() {
+        options.gen_data = Some(parse_gen_data(args)?);
+        return Ok(options);
+    }
+    if args.next_if(|arg| arg == "query").is_some() {
+        options.query = Some(parse_query(args)?);
+        return Ok(options);
+    }
+    if args.next_if(|arg| arg == "diff").is_some() {
+        let (Some(left), Some(right), None) = (args.next(), args.next(), args.next()) else {
+            return Err("diff takes two paths, e.g. diff before.csv after.csv".into());
+        };
+        options.diff = Some((left, right));
+        return Ok(options);
+    }
+    // The `serve` subcommand takes the address of the gRPC server, the inputs are processed before it starts
+    if args.next_if(|arg| arg == "serve").is_some() {
+        let addr = args
+            .next()
+            .ok_or("serve takes an address, e.g. serve 127.0.0.1:50051")?;
+        options.serve_grpc = Some(addr);
+    }
+    // The `consume-kafka` subcommand takes the brokers and the topic, the messages are json unless `--encoding csv`
+    if args.next_if(|arg| arg == "consume-kafka").is_some() {
+        let (Some(brokers), Some(topic)) = (args.next(), args.next()) else {
+            return Err(
+                "consume-kafka takes the brokers and a topic, e.g. consume-kafka localhost:9092 transactions"
+                    .into(),
+            );
+        };
+        options.kafka = Some(Kafka {
+            brokers,
+            topic,
+            group: DEFAULT_KAFKA_GROUP.to_string(),
+        });
+    }
+    // The `report` subcommand replaces the summaries in the output with per-client statistics
+    if args.next_if(|arg| arg == "report").is_some() {
+        options.report = Some(ReportFormat::default());
+    }
+    // The `export-history` subcommand needs `--client`
+    let export_history = args.next_if(|arg| arg == "export-history").is_some();
+    // The `replay` subcommand needs `--until-tx` or `--until-line`, they are only accepted with it
+    let replay = args.next_if(|arg| arg == "replay").is_some();
+    // The `validate` subcommand takes a single input, the csv options of the other flags apply to it
+    options.validate = args.next_if(|arg| arg == "validate").is_some();
+    // The config file is the base of the other flags, so a flag overrides it wherever it is given
+    let mut args: Vec<String> = args.collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--config") {
+        let path = args
+            .get(index + 1)
+            .cloned()
+            .ok_or("missing value for --config")?;
+        args.drain(index..=index + 1);
+        let toml = fs::read_to_string(&path).map_err(|err| format!("{path}: {err}"))?;
+        options.config = EngineConfig::from_toml(&toml).map_err(|err| format!("{path}: {err}"))?;
+    }
+    let mut args = args.into_iter();
+    let mut encoding = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shards" => options.shards = Some(value(&mut args, &arg)?.parse()?),
+            "--parsers" => options.parsers = Some(value(&mut args, &arg)?.parse()?),
+            "--appliers" => options.appliers = Some(value(&mut args, &arg)?.parse()?),
+            "--report-errors" => options.report_errors = Some(ErrorReport::Stderr),
+            "--strict" => options.strict = true,
+            "--reject-file" => options.reject_file = Some(value(&mut args, &arg)?),
+            "--dry-run" => options.dry_run = true,
+            "--tenants" => options.tenants = true,
+            "--allow-zero-amounts" => options.config.allow_zero_amount = true,
+            "--strict-timestamps" => options.config.strict_timestamps = true,
+            "--detect-duplicate-rows" => options.config.detect_duplicate_rows = true,
+            "--lenient-amounts" => options.config.lenient_amounts = true,
+            "--unfreeze-requires-zero-held" => options.config.unfreeze_requires_zero_held = true,
+            "--unfreeze-on-reversal" => options.config.unfreeze_on_reversal = true,
+            "--freeze-on-chargeback" => {
+                options.config.freeze_on_chargeback = value(&mut args, &arg)?.parse()?;
+            }
+            "--max-disputes" => {
+                options.config.max_disputes = Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--max-transactions" => {
+                options.config.max_transactions_per_account =
+                    Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--overdraft-limit" => {
+                options.config.overdraft_limit = Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--withdrawal-fee" => {
+                let fee_policy = options.config.fee_policy.get_or_insert_default();
+                fee_policy.flat = value(&mut args, &arg)?.parse()?;
+            }
+            "--withdrawal-fee-percent" => {
+                let fee_policy = options.config.fee_policy.get_or_insert_default();
+                fee_policy.percentage = value(&mut args, &arg)?.parse()?;
+            }
+            "--dispute-window-days" => {
+                let days: u64 = value(&mut args, &arg)?.parse()?;
+                let seconds =
+                    days_to_seconds(days).ok_or_else(|| format!("{arg} {days} is too long"))?;
+                options.config.dispute_window = Some(seconds);
+            }
+            "--dispute-timeout-days" => {
+                let days: u64 = value(&mut args, &arg)?.parse()?;
+                let seconds =
+                    days_to_seconds(days).ok_or_else(|| format!("{arg} {days} is too long"))?;
+                options.config.dispute_timeout.get_or_insert_default().after = seconds;
+            }
+            "--dispute-timeout-action" => {
+                options
+                    .config
+                    .dispute_timeout
+                    .get_or_insert_default()
+                    .action = value(&mut args, &arg)?.parse()?;
+            }
+            "--duplicate-ids" => {
+                options.config.duplicate_id_policy = value(&mut args, &arg)?.parse()?;
+            }
+            "--dispute-policy" => {
+                options.config.dispute_policy = value(&mut args, &arg)?.parse()?;
+            }
+            "--load-snapshot" => options.load_snapshot = Some(value(&mut args, &arg)?),
+            "--save-snapshot" => options.save_snapshot = Some(value(&mut args, &arg)?),
+            "--serve-http" => options.serve_http = Some(value(&mut args, &arg)?),
+            "--listen" => options.listen = Some(value(&mut args, &arg)?),
+            "--wal" => options.wal = Some(value(&mut args, &arg)?),
+            "--audit-log" => options.audit_log = Some(value(&mut args, &arg)?),
+            "--postgres" => options.postgres = Some(value(&mut args, &arg)?),
+            "--postgres-table" => options.postgres_table = Some(value(&mut args, &arg)?),
+            "--postgres-ledger-table" => {
+                options.postgres_ledger_table = Some(value(&mut args, &arg)?);
+            }
+            "-o" | "--output" => options.output = Some(value(&mut args, &arg)?),
+            "--output-format" => options.output_format = value(&mut args, &arg)?.parse()?,
+            "--input-format" => options.input_format = value(&mut args, &arg)?.parse()?,
+            "--format" => {
+                let format = value(&mut args, &arg)?;
+                options.input_format = format.parse()?;
+                options.output_format = format.parse()?;
+            }
+            "--incremental" => {
+                options.incremental.get_or_insert(DEFAULT_FLUSH_EVERY);
+            }
+            "--flush-every" => options.incremental = Some(value(&mut args, &arg)?.parse()?),
+            "--stats" => options.stats = true,
+            "--stats-file" => options.stats_file = Some(value(&mut args, &arg)?),
+            "--report-format" => {
+                options.report = Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--consume" => options.consume = Some(value(&mut args, &arg)?.parse()?),
+            "--group" => match &mut options.kafka {
+                Some(kafka) => kafka.group = value(&mut args, &arg)?,
+                None => return Err("--group requires consume-kafka".into()),
+            },
+            "--encoding" => encoding = Some(value(&mut args, &arg)?.parse()?),
+            "--checkpoint-every" => {
+                options.checkpoint_every = Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--checkpoint-dir" => options.checkpoint_dir = Some(value(&mut args, &arg)?),
+            "--resume" => options.resume = true,
+            "--spill-dir" => options.spill_dir = Some(value(&mut args, &arg)?),
+            "--dense-clients" => options.dense_clients = true,
+            "--max-resident-transactions" => {
+                options.max_resident_transactions = Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--mmap" => options.mmap = true,
+            "--compression" => options.compression = Some(value(&mut args, &arg)?.parse()?),
+            "--scale" => {
+                let precision = options.config.precision.get_or_insert_default();
+                precision.scale = value(&mut args, &arg)?.parse()?;
+            }
+            "--delimiter" => {
+                options.config.csv.delimiter = match value(&mut args, &arg)?.as_str() {
+                    "\\t" | "tab" => b'\t',
+                    delimiter if delimiter.len() == 1 => delimiter.as_bytes()[0],
+                    delimiter => return Err(format!("invalid delimiter {delimiter}").into()),
+                };
+            }
+            "--tolerant-types" => {
+                options.config.type_aliases.get_or_insert_default();
+            }
+            "--type-alias" => {
+                let mapping = value(&mut args, &arg)?;
+                let (alias, transaction_type) = mapping
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid type alias {mapping}, expected ALIAS=TYPE"))?;
+                options
+                    .config
+                    .type_aliases
+                    .get_or_insert_default()
+                    .insert(alias.trim(), transaction_type.trim());
+            }
+            "--clients" => options.config.filter.clients = Some(value(&mut args, &arg)?.parse()?),
+            "--only" => {
+                let types = value(&mut args, &arg)?
+                    .split(',')
+                    .map(|transaction_type| transaction_type.trim().parse())
+                    .collect::<Result<_, _>>()?;
+                options.config.filter.types = Some(types);
+            }
+            "--from" => options.config.filter.from = Some(value(&mut args, &arg)?.parse()?),
+            "--to" => options.config.filter.to = Some(value(&mut args, &arg)?.parse()?),
+            "--until-tx" if replay => {
+                options.replay = Some(ReplayUntil::Tx(value(&mut args, &arg)?.parse()?));
+            }
+            "--until-line" if replay => {
+                options.replay = Some(ReplayUntil::Line(value(&mut args, &arg)?.parse()?));
+            }
+            "--client" if export_history => {
+                options.export_history = Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--include-rejected" => options.include_rejected = true,
+            "--until-tx" | "--until-line" => {
+                return Err(format!("{arg} is only accepted by the replay subcommand").into());
+            }
+            "--config" => return Err("--config can only be given once".into()),
+            "--risk-rules" => options.risk_rules = Some(value(&mut args, &arg)?),
+            "--opening-balances" => options.opening_balances = Some(value(&mut args, &arg)?),
+            "--paranoid" => options.config.invariant_checks = InvariantChecks::Abort,
+            "--paranoid=log" => options.config.invariant_checks = InvariantChecks::Log,
+            "--no-headers" => options.config.csv.has_headers = false,
+            "--column" => {
+                let mapping = value(&mut args, &arg)?;
+                let (from, to) = mapping.split_once('=').ok_or_else(|| {
+                    format!("invalid column mapping {mapping}, expected NAME=COLUMN")
+                })?;
+                options
+                    .config
+                    .csv
+                    .columns
+                    .insert(from.trim().to_string(), to.trim().to_string());
+            }
+            "--output-scale" => {
+                options.config.output_scale = Some(value(&mut args, &arg)?.parse()?);
+            }
+            "--extended-output" => options.config.extended_output = true,
+            "--rounding" => {
+                let precision = options.config.precision.get_or_insert_default();
+                precision.rounding = value(&mut args, &arg)?.parse()?;
+            }
+            _ => match arg.strip_prefix("--report-errors=") {
+                Some(path) => options.report_errors = Some(ErrorReport::File(path.to_string())),
+                None => options.paths.push(arg),
+            },
+        }
+    }
+    // The topic is consumed like the messages of `--consume`, so the same modes apply to it
+    if options.kafka.is_some() {
+        if options.consume.is_some() {
+            return Err("consume-kafka can't be used together with --consume".into());
+        }
+        options.consume = Some(encoding.unwrap_or(MessageEncoding::Json));
+    } else if encoding.is_some() {
+        return Err("--encoding requires consume-kafka".into());
+    }
+    if export_history {
+        if options.export_history.is_none() {
+            return Err("export-history requires --client".into());
+        }
+        // The history comes from the ledger, which is only complete when the rows are applied in input order
+        if options.shards.is_some()
+            || options.incremental.is_some()
+            || options.report.is_some()
+            || options.consume.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some()
+        {
+            return Err("export-history can't be used together with --shards, --incremental, reports, --consume or the servers".into());
+        }
+    }
+    if replay {
+        if options.replay.is_none() {
+            return Err("replay requires --until-tx or --until-line".into());
+        }
+        // The lines are counted in a single input, and only the state at the stop is written
+        if options.paths.len() > 1
+            || options.shards.is_some()
+            || options.dry_run
+            || options.wal.is_some()
+            || options.save_snapshot.is_some()
+            || options.audit_log.is_some()
+            || options.incremental.is_some()
+            || options.report.is_some()
+            || options.stats
+            || options.stats_file.is_some()
+            || options.consume.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some()
+            || options.spill_dir.is_some()
+            || options.postgres.is_some()
+        {
+            return Err("replay takes a single input and only --load-snapshot, --output and the processing options".into());
+        }
+    }
+    // A parquet file is read as a whole, it has no lines, byte positions or csv dialect
+    if options.input_format != InputFormat::Csv
+        && (options.shards.is_some()
+            || options.parsers.is_some()
+            || options.appliers.is_some()
+            || options.incremental.is_some()
+            || options.checkpoint_dir.is_some()
+            || options.resume
+            || options.tenants
+            || replay
+            || options.validate)
+    {
+        return Err("--input-format parquet can't be used together with --shards, --parsers, --appliers, --incremental, --checkpoint-dir, --resume, --tenants, replay or validate".into());
+    }
+    if options.validate && options.paths.len() > 1 {
+        return Err("validate takes a single input, e.g. validate input.csv".into());
+    }
+    if (options.postgres_table.is_some() || options.postgres_ledger_table.is_some())
+        && options.postgres.is_none()
+    {
+        return Err("--postgres-table and --postgres-ledger-table require --postgres".into());
+    }
+    // A dry run must not write any state, and it reports every would-be rejection
+    if options.dry_run {
+        if options.wal.is_some()
+            || options.save_snapshot.is_some()
+            || options.audit_log.is_some()
+            || options.output.is_some()
+            || options.postgres.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some()
+            || options.consume.is_some()
+        {
+            return Err("--dry-run can't be used together with --wal, --save-snapshot, --audit-log, --output, --postgres, the servers or --consume".into());
+        }
+        options.report_errors.get_or_insert(ErrorReport::Stderr);
+    }
+    // The shards have their own index of the ids, so the ids of other clients can't be checked
+    if options.shards.is_some() && options.config.duplicate_id_policy != DuplicateIdPolicy::Allow {
+        return Err("--shards can't be used together with --duplicate-ids warn or reject".into());
+    }
+    // Rows are applied on worker threads in sharded mode, so we can't report or stop in input order
+    if options.shards.is_some()
+        && (options.strict
+            || options.report_errors.is_some()
+            || options.reject_file.is_some()
+            || options.stats
+            || options.stats_file.is_some())
+    {
+        return Err(
+            "--shards can't be used together with --strict, --report-errors, --reject-file or --stats".into(),
+        );
+    }
+    // The write-ahead log is replayed from the beginning, so it can't start from a snapshot
+    if options.wal.is_some() && options.load_snapshot.is_some() {
+        return Err("--wal can't be used together with --load-snapshot".into());
+    }
+    // The messages are read from stdin on the main thread, and they never end for a server
+    // The rows are read ahead of the engine, so the position of the last applied row is not known
+    if (options.parsers.is_some() || options.appliers.is_some())
+        && (options.shards.is_some()
+            || options.incremental.is_some()
+            || options.checkpoint_dir.is_some()
+            || options.resume
+            || options.tenants)
+    {
+        return Err("--parsers and --appliers can't be used together with --shards, --incremental, --checkpoint-dir, --resume or --tenants".into());
+    }
+    // The appliers own the accounts they apply the rows to, the spilled records are only found by a single store
+    if options.appliers.is_some() && options.spill_dir.is_some() {
+        return Err("--appliers can't be used together with --spill-dir".into());
+    }
+    if options.consume.is_some()
+        && (options.shards.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some())
+    {
+        return Err("--consume can't be used together with --shards or the servers".into());
+    }
+    // Every tenant has its own engine, the modes which own a single engine, a single log or a single table of
+    // accounts are not supported
+    if options.tenants
+        && (options.shards.is_some()
+            || options.wal.is_some()
+            || options.spill_dir.is_some()
+            || options.checkpoint_dir.is_some()
+            || options.incremental.is_some()
+            || options.consume.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some()
+            || options.listen.is_some()
+            || options.replay.is_some()
+            || options.postgres.is_some())
+    {
+        return Err("--tenants can't be used together with --shards, --wal, --spill-dir, --checkpoint-dir, --incremental, --consume, the servers, replay or --postgres".into());
+    }
+    // The reports and the history of every tenant are written to a file of their own
+    if options.tenants
+        && options.output.is_none()
+        && (options.report.is_some() || options.export_history.is_some())
+    {
+        return Err("reports and export-history require --output <dir> with --tenants".into());
+    }
+    // The opening balances are a starting state like a snapshot, and they are not in the write-ahead log
+    if options.opening_balances.is_some()
+        && (options.load_snapshot.is_some() || options.wal.is_some() || options.tenants)
+    {
+        return Err(
+            "--opening-balances can't be used together with --load-snapshot, --wal or --tenants"
+                .into(),
+        );
+    }
+    // The servers never return, and stdin can't be consumed at the same time
+    if options.serve_http.is_some() && options.serve_grpc.is_some() {
+        return Err("--serve-http can't be used together with serve".into());
+    }
+    // Nothing is written after the inputs when a server takes over, so the outputs of the end of a run are refused
+    if (options.serve_http.is_some() || options.serve_grpc.is_some() || options.listen.is_some())
+        && (options.save_snapshot.is_some()
+            || options.audit_log.is_some()
+            || options.postgres.is_some()
+            || options.output.is_some()
+            || options.stats
+            || options.stats_file.is_some()
+            || options.report.is_some()
+            || options.export_history.is_some()
+            || options.dry_run)
+    {
+        return Err("the servers can't be used together with --save-snapshot, --audit-log, --postgres, --output, --stats, reports, export-history or --dry-run".into());
+    }
+    if options.listen.is_some()
+        && (options.serve_http.is_some()
+            || options.serve_grpc.is_some()
+            || options.consume.is_some()
+            || options.incremental.is_some()
+            || options.spill_dir.is_some())
+    {
+        return Err(
+            "--listen can't be used together with --serve-http, serve, --consume, --incremental or --spill-dir"
+                .into(),
+        );
+    }
+    // The accounts are written to stdout as they change, the other modes need all rows first
+    if options.incremental.is_some()
+        && (options.shards.is_some()
+            || options.dry_run
+            || options.output.is_some()
+            || options.report.is_some()
+            || options.output_format != OutputFormat::Csv
+            || options.consume.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some())
+    {
+        return Err("--incremental can't be used together with --shards, --dry-run, --output, reports, --output-format, --consume or the servers".into());
+    }
+    // The spilled records are only loaded back for the transactions being processed, these modes need all of them
+    if options.spill_dir.is_some()
+        && (options.shards.is_some()
+            || options.wal.is_some()
+            || options.load_snapshot.is_some()
+            || options.save_snapshot.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some()
+            || options.config.invariant_checks != InvariantChecks::Off
+            || options.config.dispute_timeout.is_some())
+    {
+        return Err(
+            "--spill-dir can't be used together with --shards, --wal, snapshots, the servers, --paranoid or --dispute-timeout-days"
+                .into(),
+        );
+    }
+    // The servers and the tenants keep their accounts in the default store, and a table per tenant would take
+    // too much memory
+    if options.dense_clients
+        && (options.spill_dir.is_some()
+            || options.serve_http.is_some()
+            || options.serve_grpc.is_some()
+            || options.listen.is_some()
+            || options.tenants)
+    {
+        return Err(
+            "--dense-clients can't be used together with --spill-dir, the servers or --tenants"
+                .into(),
+        );
+    }
+    // A resumed run only sees the state of the checkpoint, and the rows are counted in input order
+    if options.checkpoint_dir.is_some()
+        && (options.shards.is_some()
+            || options.dry_run
+            || options.wal.is_some()
+            || options.spill_dir.is_some()
+            || options.incremental.is_some()
+            || options.consume.is_some()
+            || options.audit_log.is_some()
+            || options.export_history.is_some())
+    {
+        return Err("--checkpoint-dir can't be used together with --shards, --dry-run, --wal, --spill-dir, --incremental, --consume, --audit-log or export-history".into());
+    }
+    // The position is only known when the rows are applied one after another in input order
+    if options.resume {
+        if options.load_snapshot.is_none() {
+            return Err("--resume requires --load-snapshot".into());
+        }
+        if options.shards.is_some() || options.incremental.is_some() {
+            return Err("--resume can't be used together with --shards or --incremental".into());
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn parse(args: &str) -> Result<Options, Box<dyn Error>> {
+        parse_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse(
+            "--parsers 4 --dispute-window-days 2 --withdrawal-fee 0.5 --report-errors=errors.log \
+             -o out.csv --output-format json a.csv b.csv",
+        )
+        .unwrap();
+        assert_eq!(options.paths, vec!["a.csv", "b.csv"]);
+        assert_eq!(options.parsers, Some(4));
+        assert_eq!(options.config.dispute_window, Some(2 * 24 * 60 * 60));
+        assert_eq!(options.config.fee_policy.unwrap().flat, Decimal::new(5, 1));
+        assert!(matches!(
+            options.report_errors,
+            Some(ErrorReport::File(path)) if path == "errors.log"
+        ));
+        assert_eq!(options.output.as_deref(), Some("out.csv"));
+        assert_eq!(options.output_format, OutputFormat::Json);
+
+        let options = parse("replay --until-tx 7 in.csv").unwrap();
+        assert!(matches!(options.replay, Some(ReplayUntil::Tx(7))));
+        let options = parse("serve 127.0.0.1:50051 in.csv").unwrap();
+        assert_eq!(options.serve_grpc.as_deref(), Some("127.0.0.1:50051"));
+        assert_eq!(options.paths, vec!["in.csv"]);
+        let query = parse("query state.json --client 42")
+            .unwrap()
+            .query
+            .unwrap();
+        assert_eq!((query.snapshot.as_str(), query.client), ("state.json", 42));
+        let options = parse("--dry-run in.csv").unwrap();
+        assert!(matches!(options.report_errors, Some(ErrorReport::Stderr)));
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        for (args, expected) in [
+            ("--shards", "missing value for --shards"),
+            ("--shards x", "invalid digit found in string"),
+            (
+                "--dispute-window-days 300000000000000",
+                "--dispute-window-days 300000000000000 is too long",
+            ),
+            (
+                "--dispute-timeout-days 300000000000000",
+                "--dispute-timeout-days 300000000000000 is too long",
+            ),
+            (
+                "--until-tx 5",
+                "--until-tx is only accepted by the replay subcommand",
+            ),
+            (
+                "replay in.csv",
+                "replay requires --until-tx or --until-line",
+            ),
+            (
+                "--shards 2 --strict",
+                "--shards can't be used together with --strict, --report-errors, --reject-file or --stats",
+            ),
+            (
+                "--wal w.log --load-snapshot s.json",
+                "--wal can't be used together with --load-snapshot",
+            ),
+            (
+                "serve 127.0.0.1:50051 --serve-http 127.0.0.1:8080",
+                "--serve-http can't be used together with serve",
+            ),
+            (
+                "--serve-http 127.0.0.1:8080 --save-snapshot s.json",
+                "the servers can't be used together with --save-snapshot, --audit-log, --postgres, --output, \
+                 --stats, reports, export-history or --dry-run",
+            ),
+            (
+                "--listen 127.0.0.1:9000 --stats",
+                "the servers can't be used together with --save-snapshot, --audit-log, --postgres, --output, \
+                 --stats, reports, export-history or --dry-run",
+            ),
+            (
+                "--incremental -o out.csv",
+                "--incremental can't be used together with --shards, --dry-run, --output, reports, \
+                 --output-format, --consume or the servers",
+            ),
+            ("--resume", "--resume requires --load-snapshot"),
+            ("--encoding csv", "--encoding requires consume-kafka"),
+        ] {
+            let err = parse(args).unwrap_err();
+            assert_eq!(err.to_string(), expected, "{args}");
+        }
+    }
+
+    #[test]
+    fn test_help() {
+        assert!(parse("--help").unwrap().help);
+        // Even with arguments which are invalid on their own
+        let options = parse("replay --shards -h").unwrap();
+        assert!(options.help);
+        assert!(options.paths.is_empty());
+        assert!(!parse("in.csv").unwrap().help);
+    }
+}
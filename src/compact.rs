@@ -0,0 +1,425 @@
+use crate::hash::HashMap;
+use crate::types::{DisputeCase, TransactionId, TransactionKind, TransactionRecord};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// The slot of an id which was used without a record, e.g. by a rejected withdrawal
+const NO_RECORD: u32 = u32::MAX;
+/// The bit of a slot which marks the record of a withdrawal, the other bits are the position of the record
+const WITHDRAWAL: u32 = 1 << 31;
+
+/// An entry of `Transactions`
+#[derive(Debug, Eq, PartialEq)]
+pub enum TxRecord {
+    Deposit(TransactionRecord),
+    Withdrawal(TransactionRecord),
+    /// The id was used without a record, e.g. by a rejected withdrawal
+    NoRecord,
+}
+
+/// Every transaction id of an account with the record of its deposit or withdrawal, in a single map
+/// Most records are never looked up again, so the map only holds a 4 byte slot with the kind and the position of the
+/// record, and the records are in a `Vec`, which is about half the size of a `HashMap` holding the records directly
+/// because of the load factor of the map. The slot of a removed record is reused by the next insert
+#[derive(Default)]
+pub struct Transactions {
+    slots: HashMap<TransactionId, u32>,
+    records: Vec<Option<TransactionRecord>>,
+    free: Vec<u32>,
+}
+
+impl Transactions {
+    /// The number of used ids, including the ids without a record
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Whether the id was used by a deposit or a withdrawal of this account, even a rejected one
+    pub fn contains(&self, id: TransactionId) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    pub fn get(&self, id: TransactionId) -> Option<(TransactionKind, &TransactionRecord)> {
+        let (kind, position) = decode(*self.slots.get(&id)?)?;
+        Some((kind, self.records[position].as_ref()?))
+    }
+
+    pub fn get_mut(
+        &mut self,
+        id: TransactionId,
+    ) -> Option<(TransactionKind, &mut TransactionRecord)> {
+        let (kind, position) = decode(*self.slots.get(&id)?)?;
+        Some((kind, self.records[position].as_mut()?))
+    }
+
+    pub fn deposit(&self, id: TransactionId) -> Option<&TransactionRecord> {
+        self.get(id)
+            .and_then(|(kind, record)| (kind == TransactionKind::Deposit).then_some(record))
+    }
+
+    pub fn withdrawal(&self, id: TransactionId) -> Option<&TransactionRecord> {
+        self.get(id)
+            .and_then(|(kind, record)| (kind == TransactionKind::Withdrawal).then_some(record))
+    }
+
+    /// Mark the id as used without a record, false if it is already used
+    pub fn use_id(&mut self, id: TransactionId) -> bool {
+        match self.slots.entry(id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(NO_RECORD);
+                true
+            }
+        }
+    }
+
+    /// Set the record of `id`, it replaces the previous record of the id
+    pub fn insert(&mut self, id: TransactionId, kind: TransactionKind, record: TransactionRecord) {
+        self.remove(id);
+        let position = match self.free.pop() {
+            Some(position) => {
+                self.records[position as usize] = Some(record);
+                position
+            }
+            None => {
+                let position = u32::try_from(self.records.len())
+                    .ok()
+                    .filter(|position| position & WITHDRAWAL == 0)
+                    .expect("too many records");
+                self.records.push(Some(record));
+                position
+            }
+        };
+        let slot = match kind {
+            TransactionKind::Deposit => position,
+            TransactionKind::Withdrawal => position | WITHDRAWAL,
+        };
+        self.slots.insert(id, slot);
+    }
+
+    pub fn remove(&mut self, id: TransactionId) -> Option<TxRecord> {
+        let slot = self.slots.remove(&id)?;
+        let Some((kind, position)) = decode(slot) else {
+            return Some(TxRecord::NoRecord);
+        };
+        self.free.push(position as u32);
+        let record = self.records[position].take()?;
+        Some(match kind {
+            TransactionKind::Deposit => TxRecord::Deposit(record),
+            TransactionKind::Withdrawal => TxRecord::Withdrawal(record),
+        })
+    }
+
+    /// All used ids in arbitrary order
+    pub fn ids(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.slots.keys().copied()
+    }
+
+    /// The records with their id and kind in arbitrary order
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (TransactionId, TransactionKind, &TransactionRecord)> {
+        self.slots.iter().filter_map(|(id, slot)| {
+            let (kind, position) = decode(*slot)?;
+            Some((*id, kind, self.records[position].as_ref()?))
+        })
+    }
+
+    pub fn deposits(&self) -> impl Iterator<Item = (TransactionId, &TransactionRecord)> {
+        self.of_kind(TransactionKind::Deposit)
+    }
+
+    pub fn withdrawals(&self) -> impl Iterator<Item = (TransactionId, &TransactionRecord)> {
+        self.of_kind(TransactionKind::Withdrawal)
+    }
+
+    /// All records of both kinds
+    pub fn values(&self) -> impl Iterator<Item = &TransactionRecord> {
+        self.records.iter().flatten()
+    }
+
+    fn of_kind(
+        &self,
+        kind: TransactionKind,
+    ) -> impl Iterator<Item = (TransactionId, &TransactionRecord)> {
+        self.iter()
+            .filter(move |(_, record_kind, _)| *record_kind == kind)
+            .map(|(id, _, record)| (id, record))
+    }
+}
+
+/// The ids evicted from the `Transactions` of an account, so they are not used again
+/// They are kept as ranges of consecutive ids from their first to their last id, the ids of an account which come
+/// one after another take a single range, while scattered ids take 8 bytes each
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EvictedIds(BTreeMap<TransactionId, TransactionId>);
+
+impl EvictedIds {
+    pub fn contains(&self, id: TransactionId) -> bool {
+        self.0
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(_, last)| id <= *last)
+    }
+
+    /// Add `id`, merging it with the ranges right before and after it
+    pub fn insert(&mut self, id: TransactionId) {
+        if self.contains(id) {
+            return;
+        }
+        let first = match self.0.range(..id).next_back() {
+            Some((first, last)) if last.checked_add(1) == Some(id) => *first,
+            _ => id,
+        };
+        let last = id
+            .checked_add(1)
+            .and_then(|next| self.0.remove(&next))
+            .unwrap_or(id);
+        self.0.insert(first, last);
+    }
+
+    /// The number of ranges
+    pub fn ranges(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The kind and the position of the record of a slot, None for an id without a record
+fn decode(slot: u32) -> Option<(TransactionKind, usize)> {
+    match slot {
+        NO_RECORD => None,
+        _ if slot & WITHDRAWAL != 0 => {
+            Some((TransactionKind::Withdrawal, (slot & !WITHDRAWAL) as usize))
+        }
+        _ => Some((TransactionKind::Deposit, slot as usize)),
+    }
+}
+
+impl PartialEq for Transactions {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .ids()
+                .all(|id| other.contains(id) && self.get(id) == other.get(id))
+    }
+}
+
+impl Eq for Transactions {}
+
+impl fmt::Debug for Transactions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.slots.keys().map(|id| (id, self.get(*id))))
+            .finish()
+    }
+}
+
+/// Serialized as the `deposit_transactions` and `withdrawal_transactions` maps and the `transaction_ids` list of
+/// the earlier layout, the account flattens them into its fields so the snapshots stay compatible
+impl Serialize for Transactions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Records<'a>(&'a Transactions, TransactionKind);
+        impl Serialize for Records<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_map(self.0.of_kind(self.1))
+            }
+        }
+        struct Ids<'a>(&'a Transactions);
+        impl Serialize for Ids<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.ids())
+            }
+        }
+        let mut state = serializer.serialize_struct("Transactions", 3)?;
+        state.serialize_field(
+            "deposit_transactions",
+            &Records(self, TransactionKind::Deposit),
+        )?;
+        state.serialize_field(
+            "withdrawal_transactions",
+            &Records(self, TransactionKind::Withdrawal),
+        )?;
+        state.serialize_field("transaction_ids", &Ids(self))?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Transactions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // A flattened map is buffered with string keys, so the ids are parsed here
+        #[derive(Deserialize)]
+        struct Layout {
+            #[serde(default)]
+            deposit_transactions: HashMap<String, TransactionRecord>,
+            #[serde(default)]
+            withdrawal_transactions: HashMap<String, TransactionRecord>,
+            #[serde(default)]
+            transaction_ids: Vec<TransactionId>,
+        }
+        let layout = Layout::deserialize(deserializer)?;
+        let mut transactions = Self::default();
+        for (records, kind) in [
+            (layout.deposit_transactions, TransactionKind::Deposit),
+            (layout.withdrawal_transactions, TransactionKind::Withdrawal),
+        ] {
+            for (id, record) in records {
+                let id = id.parse().map_err(serde::de::Error::custom)?;
+                transactions.insert(id, kind, record);
+            }
+        }
+        for id in layout.transaction_ids {
+            transactions.use_id(id);
+        }
+        Ok(transactions)
+    }
+}
+
+/// The disputes of a transaction, most transactions are never disputed so an empty list takes a single pointer
+/// instead of the 24 bytes of an empty `Vec`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[allow(clippy::box_collection)]
+pub struct DisputeCases(Option<Box<Vec<DisputeCase>>>);
+
+impl DisputeCases {
+    pub fn push(&mut self, case: DisputeCase) {
+        self.0.get_or_insert_default().push(case);
+    }
+}
+
+impl Deref for DisputeCases {
+    type Target = [DisputeCase];
+
+    fn deref(&self) -> &[DisputeCase] {
+        self.0.as_deref().map_or(&[], Vec::as_slice)
+    }
+}
+
+impl DerefMut for DisputeCases {
+    fn deref_mut(&mut self) -> &mut [DisputeCase] {
+        self.0.as_deref_mut().map_or(&mut [], Vec::as_mut_slice)
+    }
+}
+
+impl<'a> IntoIterator for &'a DisputeCases {
+    type Item = &'a DisputeCase;
+    type IntoIter = std::slice::Iter<'a, DisputeCase>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl From<Vec<DisputeCase>> for DisputeCases {
+    fn from(cases: Vec<DisputeCase>) -> Self {
+        Self((!cases.is_empty()).then(|| Box::new(cases)))
+    }
+}
+
+impl FromIterator<DisputeCase> for DisputeCases {
+    fn from_iter<I: IntoIterator<Item = DisputeCase>>(iter: I) -> Self {
+        iter.into_iter().collect::<Vec<_>>().into()
+    }
+}
+
+impl Serialize for DisputeCases {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for DisputeCases {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DisputeState;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_transactions() {
+        let mut transactions = Transactions::default();
+        transactions.insert(
+            1,
+            TransactionKind::Deposit,
+            TransactionRecord::new(Decimal::ONE, None),
+        );
+        transactions.insert(
+            2,
+            TransactionKind::Withdrawal,
+            TransactionRecord::new(Decimal::TWO, Some(5)),
+        );
+        assert!(transactions.use_id(4) && !transactions.use_id(2));
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions.withdrawal(2).unwrap().timestamp, Some(5));
+        assert!(transactions.deposit(2).is_none() && transactions.get(4).is_none());
+        assert!(transactions.contains(4));
+        assert_eq!(
+            transactions.remove(1),
+            Some(TxRecord::Deposit(TransactionRecord::new(
+                Decimal::ONE,
+                None
+            )))
+        );
+        assert!(!transactions.contains(1) && transactions.remove(1).is_none());
+        // The slot of the removed record is reused
+        transactions.insert(
+            3,
+            TransactionKind::Deposit,
+            TransactionRecord::new(Decimal::TEN, None),
+        );
+        assert_eq!(transactions.records.len(), 2);
+        transactions.get_mut(3).unwrap().1.cases.push(DisputeCase {
+            id: None,
+            amount: Decimal::TEN,
+            state: DisputeState::Open,
+            opened: None,
+        });
+
+        // The snapshots keep the layout of the separate maps and the id list
+        let json = serde_json::to_value(&transactions).unwrap();
+        assert_eq!(json["transaction_ids"].as_array().unwrap().len(), 3);
+        assert!(json["withdrawal_transactions"]["2"].is_object());
+        let mut restored: Transactions = serde_json::from_value(json).unwrap();
+        assert_eq!(restored, transactions);
+        assert_eq!(restored.deposit(3).unwrap().cases.len(), 1);
+        assert_eq!(restored.remove(4), Some(TxRecord::NoRecord));
+    }
+
+    #[test]
+    fn test_record_size() {
+        // The records are the bulk of the memory on deposit heavy inputs, see `Transactions`
+        assert_eq!(size_of::<DisputeCases>(), 8);
+        assert_eq!(size_of::<Option<TransactionRecord>>(), 40);
+    }
+
+    #[test]
+    fn test_evicted_ids() {
+        let mut evicted = EvictedIds::default();
+        for id in [5, 7, 6, 100, u32::MAX, 4] {
+            evicted.insert(id);
+        }
+        assert_eq!(evicted.ranges(), 3);
+        assert!((4..=7).all(|id| evicted.contains(id)));
+        assert!(evicted.contains(100) && evicted.contains(u32::MAX));
+        assert!(!evicted.contains(3) && !evicted.contains(8) && !evicted.contains(99));
+        let json = serde_json::to_string(&evicted).unwrap();
+        assert_eq!(serde_json::from_str::<EvictedIds>(&json).unwrap(), evicted);
+    }
+}
@@ -0,0 +1,150 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The compression format of an input stream
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "invalid compression {s}, expected one of none, gzip, zstd"
+            )),
+        }
+    }
+}
+
+impl Compression {
+    /// Guess the compression from the extension of `path`, None if the extension is not a known one
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Guess the compression from the first bytes of a stream
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Wrap `input` so it yields the decompressed csv stream
+/// When `compression` is None it is detected from the first bytes of the stream, which also works for stdin
+pub fn decompress<'a>(
+    input: impl Read + 'a,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let mut input = BufReader::new(input);
+    let compression = match compression {
+        Some(compression) => compression,
+        None => Compression::detect(input.fill_buf()?),
+    };
+    match compression {
+        Compression::None => Ok(Box::new(input)),
+        // A `.gz` file can be several members concatenated, e.g. by `cat a.csv.gz b.csv.gz`
+        #[cfg(feature = "compression")]
+        Compression::Gzip => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            input,
+        )))),
+        #[cfg(feature = "compression")]
+        Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(input)?)),
+        #[cfg(not(feature = "compression"))]
+        Compression::Gzip | Compression::Zstd => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "compressed input requires the `compression` feature",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        assert_eq!(
+            Compression::from_path("dump.csv.gz"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_path("dump.csv.zst"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_path("dump.csv"), None);
+
+        let mut csv = String::new();
+        decompress("type,client,tx,amount\n".as_bytes(), None)
+            .unwrap()
+            .read_to_string(&mut csv)
+            .unwrap();
+        assert_eq!(csv, "type,client,tx,amount\n");
+
+        let gzip: &[u8] = &[0x1f, 0x8b, 0x08, 0x00];
+        #[cfg(not(feature = "compression"))]
+        assert_eq!(
+            decompress(gzip, None).err().unwrap().kind(),
+            io::ErrorKind::Unsupported
+        );
+        #[cfg(feature = "compression")]
+        assert!(
+            decompress(gzip, None)
+                .unwrap()
+                .read_to_end(&mut Vec::new())
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress_round_trip() {
+        use crate::engine::Engine;
+        use std::io::Write;
+
+        let csv = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\nwithdrawal,1,3,1.5\n";
+        let balances = |input: Box<dyn Read + '_>| {
+            let mut engine = Engine::new();
+            engine.process_csv(input);
+            let mut summaries: Vec<_> = engine.summaries().collect();
+            summaries.sort_by_key(|summary| summary.client);
+            summaries
+        };
+        let expected = balances(Box::new(csv.as_bytes()));
+        assert_eq!(expected.len(), 2);
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(csv.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(csv.as_bytes(), 0).unwrap();
+
+        for (compressed, compression) in [(gzip, Compression::Gzip), (zstd, Compression::Zstd)] {
+            // Detected from the first bytes as well as given explicitly
+            for given in [None, Some(compression)] {
+                let input = decompress(compressed.as_slice(), given).unwrap();
+                assert_eq!(balances(input), expected);
+            }
+        }
+    }
+}
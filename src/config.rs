@@ -0,0 +1,920 @@
+use crate::output::OUTPUT_SCALE;
+use crate::risk::RiskRules;
+use crate::types::{
+    CSV_COLUMNS, ClientId, ConfigError, CsvInputRow, Timestamp, TransactionParsingError,
+    TransactionProcessingError, TransactionType,
+};
+use csv::{Reader, ReaderBuilder, StringRecord};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+/// What to do with an amount which has more decimal places than allowed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Rounding {
+    /// Reject the transaction
+    #[default]
+    Reject,
+    /// Round to the nearest, and to the even number on a tie (banker's rounding)
+    HalfEven,
+    /// Round to the nearest, and away from zero on a tie
+    HalfUp,
+    /// Truncate the extra decimal places
+    Down,
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Rounding::Reject),
+            "half-even" => Ok(Rounding::HalfEven),
+            "half-up" => Ok(Rounding::HalfUp),
+            "down" => Ok(Rounding::Down),
+            _ => Err(format!(
+                "invalid rounding {s}, expected one of reject, half-even, half-up, down"
+            )),
+        }
+    }
+}
+
+impl Rounding {
+    /// The strategy used to round, `Reject` rounds half-even when we have to round anyway (e.g. for output)
+    fn strategy(&self) -> RoundingStrategy {
+        match self {
+            Rounding::Reject | Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+/// The number of decimal places we accept in the input and emit in the output
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PrecisionPolicy {
+    pub scale: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for PrecisionPolicy {
+    fn default() -> Self {
+        Self {
+            scale: 4,
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl PrecisionPolicy {
+    /// Apply the policy to an input amount, trailing zeros beyond the scale are always accepted
+    pub fn apply(&self, amount: Decimal) -> Result<Decimal, TransactionParsingError> {
+        if amount.scale() <= self.scale {
+            return Ok(amount);
+        }
+        let rounded = amount.round_dp_with_strategy(self.scale, self.rounding.strategy());
+        if self.rounding == Rounding::Reject && rounded != amount {
+            return Err(TransactionParsingError::TooManyDecimalPlaces(self.scale));
+        }
+        Ok(rounded)
+    }
+
+    /// Round an output amount and pad it to exactly `scale` decimal places
+    pub fn rescale(&self, amount: Decimal) -> Decimal {
+        let mut rounded = amount.round_dp_with_strategy(self.scale, self.rounding.strategy());
+        rounded.rescale(self.scale);
+        rounded
+    }
+}
+
+/// How to handle a dispute of a deposit when the client already withdrew (part of) the fund
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DisputePolicy {
+    /// Reject the dispute if the available amount is lower than the disputed amount
+    #[default]
+    RejectIfInsufficient,
+    /// Accept the dispute and let the available amount go negative
+    AllowNegativeAvailable,
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject-if-insufficient" => Ok(DisputePolicy::RejectIfInsufficient),
+            "allow-negative-available" => Ok(DisputePolicy::AllowNegativeAvailable),
+            _ => Err(format!(
+                "invalid dispute policy {s}, expected reject-if-insufficient or allow-negative-available"
+            )),
+        }
+    }
+}
+
+/// What happens to a dispute which is still open after `DisputeTimeout::after` seconds
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DisputeTimeoutAction {
+    /// Release the held fund as if a `resolve` row came in
+    #[default]
+    Resolve,
+    /// Escalate it as if a `chargeback` row came in, which freezes the account
+    Chargeback,
+}
+
+impl FromStr for DisputeTimeoutAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resolve" => Ok(DisputeTimeoutAction::Resolve),
+            "chargeback" => Ok(DisputeTimeoutAction::Chargeback),
+            _ => Err(format!(
+                "invalid dispute timeout action {s}, expected resolve or chargeback"
+            )),
+        }
+    }
+}
+
+/// Whether a chargeback freezes the account
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum FreezeOnChargeback {
+    #[default]
+    Always,
+    /// Only freeze when the charged back amount is higher than the threshold, so a small chargeback doesn't lock
+    /// a legitimate account
+    AboveThreshold(Decimal),
+    Never,
+}
+
+impl FreezeOnChargeback {
+    /// Whether a chargeback of `amount` freezes the account
+    pub fn freezes(&self, amount: Decimal) -> bool {
+        match self {
+            FreezeOnChargeback::Always => true,
+            FreezeOnChargeback::AboveThreshold(threshold) => amount > *threshold,
+            FreezeOnChargeback::Never => false,
+        }
+    }
+}
+
+impl FromStr for FreezeOnChargeback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error =
+            || format!("invalid freeze policy {s}, expected one of always, never, above:AMOUNT");
+        match s {
+            "always" => Ok(FreezeOnChargeback::Always),
+            "never" => Ok(FreezeOnChargeback::Never),
+            _ => {
+                let threshold = s.strip_prefix("above:").ok_or_else(error)?;
+                let threshold = Decimal::from_str(threshold.trim()).map_err(|_| error())?;
+                if threshold.is_sign_negative() {
+                    return Err(error());
+                }
+                Ok(FreezeOnChargeback::AboveThreshold(threshold))
+            }
+        }
+    }
+}
+
+/// Close the disputes which are open for longer than `after` seconds, see `Engine::expire_disputes`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct DisputeTimeout {
+    pub after: u64,
+    pub action: DisputeTimeoutAction,
+}
+
+/// What to do with a deposit, withdrawal or transfer reusing the id of another client's transaction
+/// The ids are supposed to be globally unique, but historically only the ids within a client were checked
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DuplicateIdPolicy {
+    #[default]
+    Allow,
+    /// Accept the transaction and notify the observers, see `EngineObserver::on_duplicate_id`
+    Warn,
+    Reject,
+}
+
+impl FromStr for DuplicateIdPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(DuplicateIdPolicy::Allow),
+            "warn" => Ok(DuplicateIdPolicy::Warn),
+            "reject" => Ok(DuplicateIdPolicy::Reject),
+            _ => Err(format!(
+                "invalid duplicate id policy {s}, expected one of allow, warn, reject"
+            )),
+        }
+    }
+}
+
+/// What to do when an account breaks an invariant after a transaction, see `AccountProfile::check_invariants`
+/// The checks go through all records of the account after every transaction, so they are slow on large accounts
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum InvariantChecks {
+    #[default]
+    Off,
+    /// Notify the observers, see `EngineObserver::on_invariant_violation`
+    Log,
+    /// Panic, so a production run fails loudly instead of writing a broken state
+    Abort,
+}
+
+impl FromStr for InvariantChecks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(InvariantChecks::Off),
+            "log" => Ok(InvariantChecks::Log),
+            "abort" => Ok(InvariantChecks::Abort),
+            _ => Err(format!(
+                "invalid invariant checks {s}, expected one of off, log, abort"
+            )),
+        }
+    }
+}
+
+/// The fee charged on every withdrawal, a transfer is a withdrawal of the source so it is charged as well
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct FeePolicy {
+    pub flat: Decimal,
+    /// Percentage of the withdrawn amount, e.g. 1.5 means 1.5%
+    pub percentage: Decimal,
+}
+
+impl FeePolicy {
+    /// The fee of a withdrawal of `amount`, rounded half-even to `scale` decimal places
+    /// None if the fee overflows
+    pub fn fee(&self, amount: Decimal, scale: u32) -> Option<Decimal> {
+        let percentage = amount
+            .checked_mul(self.percentage)?
+            .checked_div(Decimal::ONE_HUNDRED)?;
+        Some(self.flat.checked_add(percentage)?.round_dp(scale))
+    }
+}
+
+/// The csv dialect of the input, so partner files can be read without preprocessing
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Without headers the columns are in the order of `CSV_COLUMNS`
+    pub has_headers: bool,
+    /// Input column names mapped to our column names, e.g. `customer` to `client`
+    pub columns: HashMap<String, String>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            columns: HashMap::new(),
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn reader<R: Read>(&self, input: R) -> Reader<R> {
+        ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input)
+    }
+
+    /// The column names of `rdr` with the mapping applied, the rows are deserialized with these
+    pub fn headers<R: Read>(&self, rdr: &mut Reader<R>) -> csv::Result<StringRecord> {
+        if !self.has_headers {
+            return Ok(StringRecord::from(CSV_COLUMNS.to_vec()));
+        }
+        Ok(rdr
+            .headers()?
+            .iter()
+            .map(|name| self.columns.get(name).map_or(name, String::as_str))
+            .collect())
+    }
+}
+
+/// Maps the transaction types of partner files to ours, e.g. `withdraw` to `withdrawal`
+/// The lookup ignores case, so `Deposit` and `WITHDRAWAL` are accepted as well
+#[derive(Debug, Clone)]
+pub struct TypeAliasTable {
+    aliases: HashMap<String, String>,
+}
+
+impl Default for TypeAliasTable {
+    fn default() -> Self {
+        let mut table = Self {
+            aliases: HashMap::new(),
+        };
+        for (alias, transaction_type) in [
+            ("withdraw", "withdrawal"),
+            ("charge_back", "chargeback"),
+            ("charge-back", "chargeback"),
+            ("resolution", "resolve"),
+        ] {
+            table.insert(alias, transaction_type);
+        }
+        table
+    }
+}
+
+impl TypeAliasTable {
+    /// Add an alias, a later alias of the same name replaces the earlier one
+    pub fn insert(&mut self, alias: &str, transaction_type: &str) {
+        self.aliases
+            .insert(alias.to_lowercase(), transaction_type.to_string());
+    }
+
+    /// The type `transaction_type` stands for, an unknown one is only lowercased
+    pub fn resolve<'a>(&'a self, transaction_type: &'a str) -> Cow<'a, str> {
+        if transaction_type.bytes().any(|b| b.is_ascii_uppercase()) {
+            let lowercase = transaction_type.to_lowercase();
+            return match self.aliases.get(&lowercase) {
+                Some(resolved) => Cow::Borrowed(resolved),
+                None => Cow::Owned(lowercase),
+            };
+        }
+        match self.aliases.get(transaction_type) {
+            Some(resolved) => Cow::Borrowed(resolved),
+            None => Cow::Borrowed(transaction_type),
+        }
+    }
+}
+
+/// The clients to process, parsed from a list of ids and inclusive ranges, e.g. `1-100,500`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ClientFilter {
+    ranges: Vec<RangeInclusive<ClientId>>,
+}
+
+impl ClientFilter {
+    pub fn contains(&self, client: ClientId) -> bool {
+        self.ranges.iter().any(|range| range.contains(&client))
+    }
+}
+
+impl FromStr for ClientFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |id: &str| {
+            id.trim()
+                .parse::<ClientId>()
+                .map_err(|_| format!("invalid client id {id} in {s}"))
+        };
+        let ranges = s
+            .split(',')
+            .map(|part| match part.split_once('-') {
+                Some((start, end)) => {
+                    let range = parse(start)?..=parse(end)?;
+                    match range.is_empty() {
+                        true => Err(format!("invalid client range {part} in {s}")),
+                        false => Ok(range),
+                    }
+                }
+                None => parse(part).map(|id| id..=id),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { ranges })
+    }
+}
+
+/// Restricts the rows which are processed, e.g. to replay only the disputes of a month
+#[derive(Debug, Clone, Default)]
+pub struct RowFilter {
+    /// Only process and output the accounts of these clients, None means all clients
+    /// A transfer to another client is still applied, but that client is not output
+    pub clients: Option<ClientFilter>,
+    /// Only process rows of these types, None means all types
+    pub types: Option<HashSet<TransactionType>>,
+    /// Only process rows with a timestamp at or after this one
+    pub from: Option<Timestamp>,
+    /// Only process rows with a timestamp before this one
+    pub to: Option<Timestamp>,
+}
+
+impl RowFilter {
+    /// Whether `row` is processed, a row without timestamp is skipped when a time range is set
+    pub fn matches(&self, row: &CsvInputRow) -> bool {
+        let in_range = match (self.from, self.to) {
+            (None, None) => true,
+            (from, to) => row.timestamp.is_some_and(|timestamp| {
+                from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp < to)
+            }),
+        };
+        in_range
+            && self
+                .clients
+                .as_ref()
+                .is_none_or(|clients| clients.contains(row.client))
+            && self
+                .types
+                .as_ref()
+                .is_none_or(|types| types.contains(&row.transaction_type))
+    }
+}
+
+/// The configuration of an `Engine`, the default matches the behavior of the original implementation
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// When it is None we accept any number of decimal places and output 4 decimal places
+    pub precision: Option<PrecisionPolicy>,
+    /// Accept deposits, withdrawals and transfers with a zero amount, negative amounts are always rejected
+    pub allow_zero_amount: bool,
+    pub dispute_policy: DisputePolicy,
+    /// How many times a transaction can be disputed, None means unlimited
+    /// Every dispute counts, so `Some(1)` forbids disputing a transaction again after it was resolved
+    pub max_disputes: Option<u32>,
+    /// Only unfreeze an account when it has no held fund
+    pub unfreeze_requires_zero_held: bool,
+    /// Unfreeze the account when a chargeback is reversed and no other chargeback is left,
+    /// otherwise it stays frozen until it is unlocked
+    pub unfreeze_on_reversal: bool,
+    pub freeze_on_chargeback: FreezeOnChargeback,
+    /// How many seconds after a transaction it can still be disputed, None means forever
+    /// It is only checked when both the dispute and the disputed transaction have a timestamp
+    pub dispute_window: Option<u64>,
+    /// Close the disputes open for too long, they are only aged by the timestamps of the input rows
+    pub dispute_timeout: Option<DisputeTimeout>,
+    pub duplicate_id_policy: DuplicateIdPolicy,
+    /// How far available can go below zero on withdrawals, None means it can't go below zero
+    /// It can be overridden per account with `AccountProfile::overdraft_limit`
+    pub overdraft_limit: Option<Decimal>,
+    /// The fee charged on withdrawals, None means withdrawals are free
+    pub fee_policy: Option<FeePolicy>,
+    /// Reject a transaction with a timestamp earlier than the last accepted transaction of the same client
+    pub strict_timestamps: bool,
+    /// Reject a deposit, withdrawal or transfer row identical to an accepted one as `duplicate_row`
+    /// instead of `invalid_transaction_id`, to tell a double-sent row from a reused id
+    pub detect_duplicate_rows: bool,
+    /// Accept formatted amounts like `$1,234.50` in the csv inputs
+    pub lenient_amounts: bool,
+    /// The dialect of the csv inputs
+    pub csv: CsvOptions,
+    /// Accept other spellings of the transaction types, None means only the exact lowercase names
+    pub type_aliases: Option<TypeAliasTable>,
+    /// The rows of the csv inputs and message streams which are processed, the default processes all rows
+    pub filter: RowFilter,
+    /// Output every amount of the summaries and the reports with exactly this many decimal places,
+    /// None means the scale of the precision policy for the balances and the exact value for the other amounts
+    pub output_scale: Option<u32>,
+    pub invariant_checks: InvariantChecks,
+    /// The limits checked before every deposit and withdrawal, None means no limits
+    pub risk: Option<RiskRules>,
+    /// Keep at most this many transactions per account by evicting the oldest settled ones, None means no limit
+    /// A dispute of an evicted transaction is rejected, and an evicted id can't be used again
+    pub max_transactions_per_account: Option<usize>,
+    /// Add the dispute statistics of every client to the summaries, see `AccountSummary::with_dispute_stats`
+    pub extended_output: bool,
+}
+
+impl EngineConfig {
+    /// Parse a TOML config file, a missing key keeps its default, see the README for the keys
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigError> {
+        let document = DocumentMut::from_str(toml)?;
+        let mut config = Self::default();
+        for (section, item) in document.iter() {
+            match section {
+                "allow_zero_amounts" => config.allow_zero_amount = boolean(item, section)?,
+                "strict_timestamps" => config.strict_timestamps = boolean(item, section)?,
+                "detect_duplicate_rows" => {
+                    config.detect_duplicate_rows = boolean(item, section)?;
+                }
+                "lenient_amounts" => config.lenient_amounts = boolean(item, section)?,
+                "duplicate_ids" => config.duplicate_id_policy = parsed(item, section)?,
+                "invariant_checks" => config.invariant_checks = parsed(item, section)?,
+                "overdraft_limit" => config.overdraft_limit = Some(decimal(item, section)?),
+                "max_transactions_per_account" => {
+                    config.max_transactions_per_account = Some(integer(item, section)?);
+                }
+                "precision" => {
+                    let precision = config.precision.get_or_insert_default();
+                    for (key, item, name) in fields(item, section)? {
+                        match key {
+                            "scale" => precision.scale = integer(item, &name)?,
+                            "rounding" => precision.rounding = parsed(item, &name)?,
+                            _ => return Err(ConfigError::UnknownKey(name)),
+                        }
+                    }
+                }
+                "disputes" => {
+                    for (key, item, name) in fields(item, section)? {
+                        match key {
+                            "policy" => config.dispute_policy = parsed(item, &name)?,
+                            "max_disputes" => config.max_disputes = Some(integer(item, &name)?),
+                            "window_days" => {
+                                let seconds = days_to_seconds(integer(item, &name)?)
+                                    .ok_or_else(|| ConfigError::InvalidValue(name.clone()))?;
+                                config.dispute_window = Some(seconds);
+                            }
+                            "timeout_days" => {
+                                let seconds = days_to_seconds(integer(item, &name)?)
+                                    .ok_or_else(|| ConfigError::InvalidValue(name.clone()))?;
+                                config.dispute_timeout.get_or_insert_default().after = seconds;
+                            }
+                            "timeout_action" => {
+                                config.dispute_timeout.get_or_insert_default().action =
+                                    parsed(item, &name)?;
+                            }
+                            _ => return Err(ConfigError::UnknownKey(name)),
+                        }
+                    }
+                }
+                "frozen" => {
+                    for (key, item, name) in fields(item, section)? {
+                        match key {
+                            "unfreeze_requires_zero_held" => {
+                                config.unfreeze_requires_zero_held = boolean(item, &name)?;
+                            }
+                            "unfreeze_on_reversal" => {
+                                config.unfreeze_on_reversal = boolean(item, &name)?;
+                            }
+                            "on_chargeback" => config.freeze_on_chargeback = parsed(item, &name)?,
+                            _ => return Err(ConfigError::UnknownKey(name)),
+                        }
+                    }
+                }
+                "fees" => {
+                    let fee_policy = config.fee_policy.get_or_insert_default();
+                    for (key, item, name) in fields(item, section)? {
+                        match key {
+                            "withdrawal" => fee_policy.flat = decimal(item, &name)?,
+                            "withdrawal_percent" => fee_policy.percentage = decimal(item, &name)?,
+                            _ => return Err(ConfigError::UnknownKey(name)),
+                        }
+                    }
+                }
+                "csv" => {
+                    for (key, item, name) in fields(item, section)? {
+                        match key {
+                            "delimiter" => {
+                                config.csv.delimiter = match string(item, &name)? {
+                                    "tab" => b'\t',
+                                    delimiter if delimiter.len() == 1 => delimiter.as_bytes()[0],
+                                    _ => return Err(ConfigError::InvalidValue(name)),
+                                };
+                            }
+                            "has_headers" => config.csv.has_headers = boolean(item, &name)?,
+                            "columns" => {
+                                for (from, item, name) in fields(item, &name)? {
+                                    let to = string(item, &name)?;
+                                    config.csv.columns.insert(from.to_string(), to.to_string());
+                                }
+                            }
+                            "tolerant_types" => {
+                                config.type_aliases = match boolean(item, &name)? {
+                                    true => {
+                                        config.type_aliases.or_else(|| Some(Default::default()))
+                                    }
+                                    false => None,
+                                };
+                            }
+                            "type_aliases" => {
+                                let aliases = config.type_aliases.get_or_insert_default();
+                                for (alias, item, name) in fields(item, &name)? {
+                                    aliases.insert(alias, string(item, &name)?);
+                                }
+                            }
+                            _ => return Err(ConfigError::UnknownKey(name)),
+                        }
+                    }
+                }
+                "output" => {
+                    for (key, item, name) in fields(item, section)? {
+                        match key {
+                            "scale" => config.output_scale = Some(integer(item, &name)?),
+                            "extended" => config.extended_output = boolean(item, &name)?,
+                            _ => return Err(ConfigError::UnknownKey(name)),
+                        }
+                    }
+                }
+                "risk" => config.risk = Some(RiskRules::from_table(table(item, section)?)?),
+                _ => return Err(ConfigError::UnknownKey(section.to_string())),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The precision of the output amounts, `output_scale` overrides the scale of the precision policy
+    pub fn output_precision(&self) -> Option<PrecisionPolicy> {
+        match self.output_scale {
+            Some(scale) => Some(PrecisionPolicy {
+                scale,
+                rounding: self
+                    .precision
+                    .map(|precision| precision.rounding)
+                    .unwrap_or_default(),
+            }),
+            None => self.precision,
+        }
+    }
+
+    /// The fee of a withdrawal of `amount` with the scale of the precision policy
+    pub fn withdrawal_fee(&self, amount: Decimal) -> Result<Decimal, TransactionProcessingError> {
+        match &self.fee_policy {
+            Some(fee_policy) => {
+                let scale = self
+                    .precision
+                    .map_or(OUTPUT_SCALE, |precision| precision.scale);
+                fee_policy
+                    .fee(amount, scale)
+                    .ok_or(TransactionProcessingError::ArithmeticOverflow)
+            }
+            None => Ok(Decimal::ZERO),
+        }
+    }
+}
+
+pub(crate) fn table<'a>(item: &'a Item, name: &str) -> Result<&'a dyn TableLike, ConfigError> {
+    item.as_table_like()
+        .ok_or_else(|| ConfigError::InvalidValue(name.to_string()))
+}
+
+/// The keys of a table with their full name for the errors, e.g. `csv.delimiter`
+fn fields<'a>(
+    item: &'a Item,
+    section: &str,
+) -> Result<impl Iterator<Item = (&'a str, &'a Item, String)>, ConfigError> {
+    let section = section.to_string();
+    Ok(table(item, &section)?
+        .iter()
+        .map(move |(key, item)| (key, item, format!("{section}.{key}"))))
+}
+
+/// A decimal is written as a string to keep it exact, integers and floats are accepted too
+pub(crate) fn decimal(item: &Item, name: &str) -> Result<Decimal, ConfigError> {
+    let invalid = || ConfigError::InvalidValue(name.to_string());
+    let value = item.as_value().ok_or_else(invalid)?;
+    if let Some(value) = value.as_str() {
+        Decimal::from_str(value.trim()).map_err(|_| invalid())
+    } else if let Some(value) = value.as_integer() {
+        Ok(Decimal::from(value))
+    } else if let Some(value) = value.as_float() {
+        Decimal::try_from(value).map_err(|_| invalid())
+    } else {
+        Err(invalid())
+    }
+}
+
+/// The seconds of a number of days, None when they don't fit into a timestamp
+pub fn days_to_seconds(days: u64) -> Option<u64> {
+    days.checked_mul(24 * 60 * 60)
+}
+
+pub(crate) fn integer<T: TryFrom<i64>>(item: &Item, name: &str) -> Result<T, ConfigError> {
+    item.as_integer()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| ConfigError::InvalidValue(name.to_string()))
+}
+
+pub(crate) fn boolean(item: &Item, name: &str) -> Result<bool, ConfigError> {
+    item.as_bool()
+        .ok_or_else(|| ConfigError::InvalidValue(name.to_string()))
+}
+
+fn string<'a>(item: &'a Item, name: &str) -> Result<&'a str, ConfigError> {
+    item.as_str()
+        .ok_or_else(|| ConfigError::InvalidValue(name.to_string()))
+}
+
+/// A string parsed like the value of the command line flag, e.g. `half-even` for the rounding
+fn parsed<T: FromStr<Err = String>>(item: &Item, name: &str) -> Result<T, ConfigError> {
+    string(item, name)?
+        .parse()
+        .map_err(|err| ConfigError::InvalidValue(format!("{name}: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precision_policy() {
+        let reject = PrecisionPolicy::default();
+        assert_eq!(
+            reject.apply(Decimal::new(12345, 4)).unwrap(),
+            Decimal::new(12345, 4)
+        );
+        assert_eq!(
+            reject.apply(Decimal::new(123450, 5)).unwrap(),
+            Decimal::new(12345, 4)
+        );
+        assert!(reject.apply(Decimal::new(123455, 5)).is_err());
+
+        let half_even = PrecisionPolicy {
+            scale: 2,
+            rounding: Rounding::HalfEven,
+        };
+        assert_eq!(
+            half_even.apply(Decimal::new(1225, 3)).unwrap(),
+            Decimal::new(122, 2)
+        );
+        assert_eq!(half_even.rescale(Decimal::from(1)).to_string(), "1.00");
+
+        let half_up = PrecisionPolicy {
+            scale: 2,
+            rounding: Rounding::HalfUp,
+        };
+        assert_eq!(
+            half_up.apply(Decimal::new(1225, 3)).unwrap(),
+            Decimal::new(123, 2)
+        );
+
+        let down = PrecisionPolicy {
+            scale: 0,
+            rounding: Rounding::Down,
+        };
+        assert_eq!(down.apply(Decimal::new(19, 1)).unwrap(), Decimal::from(1));
+    }
+
+    #[test]
+    fn test_withdrawal_fee() {
+        let mut config = EngineConfig::default();
+        assert_eq!(
+            config.withdrawal_fee(Decimal::from(100)).unwrap(),
+            Decimal::ZERO
+        );
+
+        config.fee_policy = Some(FeePolicy {
+            flat: Decimal::new(5, 1),
+            percentage: Decimal::new(15, 1),
+        });
+        assert_eq!(
+            config.withdrawal_fee(Decimal::from(100)).unwrap(),
+            Decimal::from(2)
+        );
+        assert_eq!(
+            config.withdrawal_fee(Decimal::new(1, 4)).unwrap(),
+            Decimal::new(5, 1)
+        );
+    }
+
+    #[test]
+    fn test_type_aliases() {
+        let mut aliases = TypeAliasTable::default();
+        aliases.insert("Payout", "withdrawal");
+        assert_eq!(aliases.resolve("deposit"), "deposit");
+        assert_eq!(aliases.resolve("Deposit"), "deposit");
+        assert_eq!(aliases.resolve("WITHDRAWAL"), "withdrawal");
+        assert_eq!(aliases.resolve("withdraw"), "withdrawal");
+        assert_eq!(aliases.resolve("Charge_Back"), "chargeback");
+        assert_eq!(aliases.resolve("payout"), "withdrawal");
+        assert_eq!(aliases.resolve("refund"), "refund");
+    }
+
+    #[test]
+    fn test_client_filter() {
+        let filter: ClientFilter = "1-100, 500".parse().unwrap();
+        assert!(filter.contains(1));
+        assert!(filter.contains(100));
+        assert!(!filter.contains(101));
+        assert!(filter.contains(500));
+        assert!("".parse::<ClientFilter>().is_err());
+        assert!("5-1".parse::<ClientFilter>().is_err());
+        assert!("1-70000".parse::<ClientFilter>().is_err());
+    }
+
+    #[test]
+    fn test_row_filter() {
+        let row = |transaction_type, timestamp| CsvInputRow {
+            transaction_type,
+            client: 1,
+            tx: 1,
+            amount: None,
+            to: None,
+            timestamp,
+            case_id: None,
+            interval: None,
+            count: None,
+            reason: None,
+        };
+        let mut filter = RowFilter::default();
+        assert!(filter.matches(&row(TransactionType::Deposit, None)));
+
+        filter.types = Some(HashSet::from([TransactionType::Dispute]));
+        filter.from = Some(100);
+        filter.to = Some(200);
+        assert!(filter.matches(&row(TransactionType::Dispute, Some(100))));
+        assert!(!filter.matches(&row(TransactionType::Dispute, Some(200))));
+        assert!(!filter.matches(&row(TransactionType::Dispute, None)));
+        assert!(!filter.matches(&row(TransactionType::Deposit, Some(150))));
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let config = EngineConfig::from_toml(
+            r#"
+strict_timestamps = true
+detect_duplicate_rows = true
+duplicate_ids = "warn"
+overdraft_limit = "12.5"
+
+[precision]
+scale = 2
+rounding = "half-up"
+
+[disputes]
+policy = "allow-negative-available"
+window_days = 1
+timeout_days = 2
+timeout_action = "chargeback"
+
+[frozen]
+unfreeze_on_reversal = true
+on_chargeback = "above:100"
+
+[fees]
+withdrawal = 1
+
+[csv]
+delimiter = "tab"
+columns = { customer = "client" }
+type_aliases = { payout = "withdrawal" }
+
+[output]
+scale = 4
+extended = true
+
+[risk]
+max_daily_withdrawals = 3
+"#,
+        )
+        .unwrap();
+        assert!(config.strict_timestamps && config.unfreeze_on_reversal);
+        assert_eq!(
+            config.freeze_on_chargeback,
+            FreezeOnChargeback::AboveThreshold(Decimal::from(100))
+        );
+        assert!(config.detect_duplicate_rows);
+        assert_eq!(config.duplicate_id_policy, DuplicateIdPolicy::Warn);
+        assert_eq!(config.overdraft_limit, Some(Decimal::new(125, 1)));
+        assert_eq!(
+            config.precision,
+            Some(PrecisionPolicy {
+                scale: 2,
+                rounding: Rounding::HalfUp
+            })
+        );
+        assert_eq!(config.dispute_policy, DisputePolicy::AllowNegativeAvailable);
+        assert_eq!(config.dispute_window, Some(86400));
+        assert_eq!(
+            config.dispute_timeout,
+            Some(DisputeTimeout {
+                after: 2 * 86400,
+                action: DisputeTimeoutAction::Chargeback
+            })
+        );
+        assert_eq!(config.fee_policy.unwrap().flat, Decimal::ONE);
+        assert_eq!(config.csv.delimiter, b'\t');
+        assert_eq!(config.csv.columns["customer"], "client");
+        assert_eq!(config.type_aliases.unwrap().resolve("Payout"), "withdrawal");
+        assert_eq!(config.output_scale, Some(4));
+        assert!(config.extended_output);
+        assert_eq!(config.risk.unwrap().max_daily_withdrawals, Some(3));
+
+        for toml in [
+            "unknown = 1",
+            "[csv]\nquote = '\"'",
+            "[precision]\nrounding = \"up\"",
+            "strict_timestamps = \"yes\"",
+            "[disputes]\nwindow_days = 300000000000000",
+            "[disputes]\ntimeout_days = 300000000000000",
+        ] {
+            assert!(EngineConfig::from_toml(toml).is_err(), "{toml}");
+        }
+    }
+
+    #[test]
+    fn test_csv_options() {
+        let options = CsvOptions {
+            delimiter: b';',
+            columns: HashMap::from([
+                ("txn_type".to_string(), "type".to_string()),
+                ("customer".to_string(), "client".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let mut rdr = options.reader("txn_type; customer;tx;amount\n".as_bytes());
+        assert_eq!(
+            options.headers(&mut rdr).unwrap(),
+            vec!["type", "client", "tx", "amount"]
+        );
+
+        let options = CsvOptions {
+            has_headers: false,
+            ..Default::default()
+        };
+        let mut rdr = options.reader("deposit,1,1,1\n".as_bytes());
+        assert_eq!(options.headers(&mut rdr).unwrap().len(), CSV_COLUMNS.len());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::output::AccountSummary;
+use crate::types::ClientId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// A field of an account which differs between two runs
+/// A side is None when the client is missing from that run
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct FieldDiff {
+    pub client: ClientId,
+    pub field: &'static str,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+/// Read the summaries written by `write_summaries` keyed by client, the order of the rows doesn't matter
+pub fn read_summaries(input: impl Read) -> csv::Result<BTreeMap<ClientId, AccountSummary>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input)
+        .deserialize()
+        .map(|summary| summary.map(|summary: AccountSummary| (summary.client, summary)))
+        .collect()
+}
+
+/// The available, held, total and locked fields which differ between `left` and `right`, ordered by client
+/// The amounts are compared by value, so `1.5` and `1.5000` are equal
+pub fn diff_summaries(
+    left: &BTreeMap<ClientId, AccountSummary>,
+    right: &BTreeMap<ClientId, AccountSummary>,
+) -> Vec<FieldDiff> {
+    let mut clients: Vec<_> = left.keys().chain(right.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+    let mut diffs = Vec::new();
+    for client in clients {
+        let (left, right) = (left.get(&client), right.get(&client));
+        let fields = |summary: Option<&AccountSummary>| {
+            summary.map(|summary| {
+                [
+                    summary.available.normalize().to_string(),
+                    summary.held.normalize().to_string(),
+                    summary.total.normalize().to_string(),
+                    summary.locked.to_string(),
+                ]
+            })
+        };
+        let (left, right) = (fields(left), fields(right));
+        for (i, field) in ["available", "held", "total", "locked"]
+            .into_iter()
+            .enumerate()
+        {
+            let left = left.as_ref().map(|fields| fields[i].clone());
+            let right = right.as_ref().map(|fields| fields[i].clone());
+            if left != right {
+                diffs.push(FieldDiff {
+                    client,
+                    field,
+                    left,
+                    right,
+                });
+            }
+        }
+    }
+    diffs
+}
+
+/// Write the `diffs` as csv into `writer`, a missing side is an empty field
+pub fn write_diffs(diffs: &[FieldDiff], writer: impl Write) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    // The header is written by the first row, so an empty diff still needs it
+    if diffs.is_empty() {
+        wtr.write_record(["client", "field", "left", "right"])?;
+    }
+    for diff in diffs {
+        wtr.serialize(diff)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_summaries() {
+        let left = read_summaries(
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,3,0,3,false\n3,1,0,1,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let right = read_summaries(
+            "client,available,held,total,locked\n2,2.0000,1.0000,3.0000,true\n1,1.5000,0.0000,1.5000,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let diffs = diff_summaries(&left, &right);
+        let fields: Vec<_> = diffs.iter().map(|diff| (diff.client, diff.field)).collect();
+        assert_eq!(
+            fields,
+            vec![
+                (2, "available"),
+                (2, "held"),
+                (2, "locked"),
+                (3, "available"),
+                (3, "held"),
+                (3, "total"),
+                (3, "locked"),
+            ]
+        );
+        assert_eq!(diffs[0].left.as_deref(), Some("3"));
+        assert_eq!(diffs[0].right.as_deref(), Some("2"));
+        assert_eq!(diffs[3].right, None);
+
+        let mut out = Vec::new();
+        write_diffs(&diffs[..1], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,field,left,right\n2,available,3,2\n"
+        );
+    }
+}
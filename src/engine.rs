@@ -0,0 +1,1787 @@
+use crate::aging::OpenDisputes;
+use crate::amount::checked_update;
+use crate::config::{DuplicateIdPolicy, EngineConfig, InvariantChecks};
+use crate::hash::HashMap;
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::observer::{EngineObserver, Observers};
+use crate::output::AccountSummary;
+use crate::record::{ColumnIndex, deserialize_record, lossy};
+use crate::schedule::{RejectedOccurrence, Schedules};
+use crate::source::CsvSource;
+use crate::store::{MemoryStore, StateStore};
+use crate::transaction::{parse_transaction, validate_transaction};
+use crate::types::{
+    AccountProfile, AccountStatus, ClientId, CsvInputRow, OpeningBalance, ProcessingOutcome,
+    RejectedRow, RowError, RowProcessingError, RowRejection, Timestamp, Transaction, TransactionId,
+    TransactionParsingError, TransactionProcessingError, TransactionType,
+};
+#[cfg(feature = "fs")]
+use crate::wal::WriteAheadLog;
+use csv::{ByteRecord, ReaderBuilder, StringRecord, Trim};
+use rust_decimal::Decimal;
+use std::convert::Infallible;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Read;
+use std::mem;
+use std::str;
+use std::sync::mpsc;
+use std::thread;
+
+/// How many rows can be queued for a shard before the reader blocks
+const SHARD_QUEUE_SIZE: usize = 1024;
+
+/// What the reader of `process_csv_sharded` sends to a shard worker
+enum ShardMessage {
+    Row(CsvInputRow),
+    /// A row of another shard got to this timestamp, the schedules and the disputes of the shard are aged up to it
+    Clock(Timestamp),
+}
+
+/// The engine owns the states of all clients and dispatches transactions to the right account
+/// The states are kept in a `StateStore`, which is an in-memory `HashMap` by default
+#[derive(Debug, Default)]
+pub struct Engine<S: StateStore = MemoryStore> {
+    pub(crate) store: S,
+    #[cfg(feature = "fs")]
+    pub(crate) wal: Option<WriteAheadLog>,
+    pub(crate) config: EngineConfig,
+    pub(crate) ledger: Option<Ledger>,
+    pub(crate) observers: Observers,
+    /// The client of every deposit, withdrawal and transfer, used to detect references to another client
+    pub(crate) tx_index: HashMap<TransactionId, ClientId>,
+    /// The recurring transactions with occurrences left
+    pub(crate) schedules: Schedules,
+    /// The occurrences of the schedules rejected while the last row was applied
+    pub(crate) rejected_occurrences: Vec<RejectedOccurrence>,
+    /// The disputes aged by `EngineConfig::dispute_timeout`
+    pub(crate) open_disputes: OpenDisputes,
+    /// The content hashes of the accepted rows by client and id with `EngineConfig::detect_duplicate_rows`
+    /// A hash is dropped with its evicted id, so they are bounded like the used ids of the accounts
+    pub(crate) row_hashes: HashMap<(ClientId, TransactionId), u64>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    pub fn with_store(store: S) -> Self {
+        let tx_index = store
+            .iter()
+            .flat_map(|(client, profile)| profile.transactions.ids().map(move |id| (id, client)))
+            .collect();
+        let mut open_disputes = OpenDisputes::default();
+        for (client, profile) in store.iter() {
+            open_disputes.extend(client, profile);
+        }
+        Self {
+            store,
+            #[cfg(feature = "fs")]
+            wal: None,
+            config: EngineConfig::default(),
+            ledger: None,
+            observers: Observers::default(),
+            tx_index,
+            schedules: Schedules::default(),
+            rejected_occurrences: Vec::new(),
+            open_disputes,
+            row_hashes: HashMap::default(),
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: EngineConfig) {
+        self.config = config;
+    }
+
+    /// Register an observer which is notified about every accepted transaction
+    pub fn add_observer(&mut self, observer: Box<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Start recording every accepted transaction from now on
+    pub fn enable_ledger(&mut self) {
+        self.ledger.get_or_insert_default();
+    }
+
+    /// Same as `enable_ledger` but the rejected transactions are recorded too
+    pub fn enable_ledger_with_rejections(&mut self) {
+        self.ledger
+            .get_or_insert_default()
+            .set_record_rejections(true);
+    }
+
+    /// The ledger entries of `client` in the order they were applied, empty if the ledger is not enabled
+    pub fn history(&self, client: ClientId) -> Vec<LedgerEntry> {
+        self.ledger.as_ref().map_or_else(Vec::new, |ledger| {
+            ledger
+                .entries()
+                .iter()
+                .filter(|entry| entry.client == client)
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// The ledger of accepted transactions, None if it is not enabled
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    /// Process the transactions inside csv stream `input`
+    pub fn process_csv(&mut self, input: impl Read) {
+        // We will ignore all errors:
+        // 1. csv parsing for a row
+        // 2. transaction processing rejection (as instructed)
+        // Note that we will not print error message and ignore them silently
+        // We do this because we use stdout for the output, and we want to keep it clean
+        self.process_source(&mut CsvSource::new(input, &self.config));
+    }
+
+    /// Same as `process_csv` for csv bytes already in memory, returns the number of rows read
+    /// This is the entry point of the fuzz targets, any bytes must be handled without panicking
+    pub fn process_csv_bytes(&mut self, input: &[u8]) -> u64 {
+        self.process_csv_with_errors(input, |_, _, _| Ok::<(), Infallible>(()))
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// Process the transactions inside csv stream `input` and call `on_error` for every rejected row
+    /// with its line number, the raw record and the reason
+    /// When `on_error` returns an error we stop processing and return it
+    /// Returns the number of rows read, including the rejected ones
+    pub fn process_csv_with_errors<E>(
+        &mut self,
+        input: impl Read,
+        mut on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        self.process_csv_rows(input, |engine, line, record, result| {
+            engine.report_rejected_occurrences(line, &mut on_error)?;
+            match result {
+                Ok(_) => Ok(()),
+                Err(err) => on_error(line, &lossy(record), err),
+            }
+        })
+    }
+
+    /// Same as `process_csv` but every rejected row is returned with its line number, in input order
+    pub fn process_csv_collect_errors(&mut self, input: impl Read) -> Vec<RowError> {
+        let mut errors = Vec::new();
+        _ = self.process_csv_with_errors(input, |line, _, kind| {
+            errors.push(RowError { line, kind });
+            Ok::<(), Infallible>(())
+        });
+        errors
+    }
+
+    /// Process the transactions inside csv stream `input` and call `on_row` for every row
+    /// with the engine, its line number, the raw record and the accepted row or the reason it was rejected
+    /// When `on_row` returns an error we stop processing and return it
+    /// Returns the number of rows read, including the rejected ones
+    pub(crate) fn process_csv_rows<E>(
+        &mut self,
+        input: impl Read,
+        on_row: impl FnMut(
+            &mut Self,
+            u64,
+            &ByteRecord,
+            Result<&CsvInputRow, RowProcessingError>,
+        ) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        self.process_csv_until(input, |_, _| false, on_row)
+    }
+
+    /// Same as `process_csv_rows` but stops right before the first row for which `until` returns true
+    /// with its line number and the row, the rows after it are not read
+    pub(crate) fn process_csv_until<E>(
+        &mut self,
+        input: impl Read,
+        until: impl FnMut(u64, &CsvInputRow) -> bool,
+        on_row: impl FnMut(
+            &mut Self,
+            u64,
+            &ByteRecord,
+            Result<&CsvInputRow, RowProcessingError>,
+        ) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        self.process_csv_from(input, |_| Ok(0), until, on_row, |_, _, _| Ok(()))
+    }
+
+    /// Same as `process_csv_until` but `start` moves the reader past the rows applied before and returns their number,
+    /// and `after_row` is called after every other row with the number of rows read so far and the reader position
+    pub(crate) fn process_csv_from<R: Read, E>(
+        &mut self,
+        input: R,
+        start: impl FnOnce(&mut csv::Reader<R>) -> csv::Result<u64>,
+        mut until: impl FnMut(u64, &CsvInputRow) -> bool,
+        mut on_row: impl FnMut(
+            &mut Self,
+            u64,
+            &ByteRecord,
+            Result<&CsvInputRow, RowProcessingError>,
+        ) -> Result<(), E>,
+        mut after_row: impl FnMut(&mut Self, u64, &csv::Position) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        #[cfg(feature = "trace")]
+        let span = tracing::info_span!("process_csv", rows = tracing::field::Empty).entered();
+        let mut rdr = self.config.csv.reader(input);
+
+        let headers = match self.config.csv.headers(&mut rdr) {
+            Ok(headers) => headers,
+            Err(err) => return on_row(self, 1, &ByteRecord::new(), Err(err.into())).map(|_| 0),
+        };
+        let mut rows = match start(&mut rdr) {
+            Ok(rows) => rows,
+            Err(err) => {
+                let line = rdr.position().line();
+                return on_row(self, line, &ByteRecord::new(), Err(err.into())).map(|_| 0);
+            }
+        };
+        let columns = ColumnIndex::new(&headers);
+        let headers = headers.into_byte_record();
+        // A byte record is reused for every row and only parsed as utf-8 field by field, see `ColumnIndex::parse`
+        let mut record = ByteRecord::new();
+        loop {
+            match rdr.read_byte_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => {
+                    let line = record.position().map_or(0, |pos| pos.line());
+                    let row = self.deserialize_row(&record, &headers, &columns);
+                    if row.as_ref().is_ok_and(|row| until(line, row)) {
+                        break;
+                    }
+                    rows += 1;
+                    match row {
+                        Ok(row) if !self.config.filter.matches(&row) => {}
+                        Ok(row) => match self.process_row(&row) {
+                            Ok(()) => on_row(self, line, &record, Ok(&row))?,
+                            Err(err) => on_row(self, line, &record, Err(err))?,
+                        },
+                        Err(err) => on_row(self, line, &record, Err(err.into()))?,
+                    }
+                    after_row(self, rows, rdr.position())?;
+                }
+                Err(err) => {
+                    rows += 1;
+                    let line = rdr.position().line();
+                    // An I/O error means we can't read any further
+                    let fatal = err.is_io_error();
+                    on_row(self, line, &ByteRecord::new(), Err(err.into()))?;
+                    if fatal {
+                        break;
+                    }
+                    after_row(self, rows, rdr.position())?;
+                }
+            }
+        }
+        #[cfg(feature = "trace")]
+        span.record("rows", rows);
+        Ok(rows)
+    }
+
+    /// Same as `process_csv` but the rows are partitioned by client across `shards` worker threads
+    /// Transactions of different clients are independent, so each shard owns a disjoint set of accounts
+    /// and the rows of a single client are still applied in input order
+    /// A transfer spans two accounts which may live in different shards, so the rows from the first transfer on
+    /// are applied serially once the workers applied the rows before it
+    /// The schedules and the open disputes move to the shard of their client, and the timestamps of the rows are
+    /// sent to all shards, so they run and age at the same rows as in `process_csv`
+    /// The duplicate id policy needs the ids of all clients in a single index, so with a policy other than
+    /// `DuplicateIdPolicy::Allow`, with a write-ahead log or with observers the rows are processed serially by
+    /// `process_csv`
+    pub fn process_csv_sharded(&mut self, input: impl Read, shards: usize) {
+        if self.config.duplicate_id_policy != DuplicateIdPolicy::Allow || !self.observers.is_empty()
+        {
+            self.process_csv(input);
+            return;
+        }
+        // Only the accepted rows are logged, which the reader doesn't know before the workers applied them
+        #[cfg(feature = "fs")]
+        if self.wal.is_some() {
+            self.process_csv(input);
+            return;
+        }
+        let mut rdr = self.config.csv.reader(input);
+        // Without valid headers no row can be deserialized, same as `process_csv`
+        let Ok(headers) = self.config.csv.headers(&mut rdr) else {
+            return;
+        };
+        let columns = ColumnIndex::new(&headers);
+        let headers = headers.into_byte_record();
+        let shards = shards.max(1);
+        let shard = |client: ClientId| client as usize % shards;
+        let mut partitions: Vec<Engine<S>> = (0..shards)
+            .map(|_| Engine {
+                config: self.config.clone(),
+                ledger: self.ledger.as_ref().map(|_| Ledger::new()),
+                ..Engine::default()
+            })
+            .collect();
+        for (client, profile) in self.store.drain() {
+            partitions[shard(client)].store.insert(client, profile);
+        }
+        for schedule in self.schedules.drain() {
+            partitions[shard(schedule.client)].schedules.push(schedule);
+        }
+        for (opened, client, tx) in self.open_disputes.drain() {
+            partitions[shard(client)]
+                .open_disputes
+                .push(opened, client, tx);
+        }
+        for ((client, tx), hash) in self.row_hashes.drain() {
+            partitions[shard(client)]
+                .row_hashes
+                .insert((client, tx), hash);
+        }
+        // Without a dispute timeout or schedules the clock changes nothing, so it is only sent when needed
+        let mut ticking = self.config.dispute_timeout.is_some()
+            || partitions
+                .iter()
+                .any(|partition| !partition.schedules.is_empty());
+        let mut clock = None;
+
+        let mut transfer = None;
+        let partitions = thread::scope(|scope| {
+            let mut senders = Vec::with_capacity(shards);
+            let mut handles = Vec::with_capacity(shards);
+            for mut engine in partitions {
+                let (sender, receiver) = mpsc::sync_channel(SHARD_QUEUE_SIZE);
+                senders.push(sender);
+                handles.push(scope.spawn(move || {
+                    for message in receiver {
+                        match message {
+                            ShardMessage::Row(row) => _ = engine.process_row(&row),
+                            ShardMessage::Clock(now) => {
+                                engine.run_schedules(now);
+                                engine.rejected_occurrences.clear();
+                                engine.expire_disputes(now);
+                            }
+                        }
+                    }
+                    engine
+                }));
+            }
+
+            // The clients which used an id in a deposit or withdrawal row, None once several clients used it
+            // A shard only indexes the ids of its own clients, so the ids of the other shards are checked here
+            let mut owners: HashMap<TransactionId, Option<ClientId>> = HashMap::default();
+            // Errors are ignored silently, same as `process_csv`
+            for record in rdr.byte_records().flatten() {
+                let Ok(row) = self.deserialize_row(&record, &headers, &columns) else {
+                    continue;
+                };
+                if !self.config.filter.matches(&row) {
+                    continue;
+                }
+                // A transfer spans two accounts which may live in different shards, so it is applied after the
+                // workers are done, together with the rest of the input
+                if row.transaction_type == TransactionType::Transfer {
+                    transfer = Some(row);
+                    break;
+                }
+                let skip = match row.transaction_type {
+                    TransactionType::Deposit | TransactionType::Withdrawal => {
+                        owners
+                            .entry(row.tx)
+                            .and_modify(|owner| {
+                                if *owner != Some(row.client) {
+                                    *owner = None;
+                                }
+                            })
+                            .or_insert(Some(row.client));
+                        false
+                    }
+                    // The client never used the id, so `validate_owner` would reject it as `ClientMismatch`
+                    TransactionType::Dispute
+                    | TransactionType::Resolve
+                    | TransactionType::Chargeback
+                    | TransactionType::ChargebackReversal
+                        if owners.get(&row.tx).is_some_and(|owner| {
+                            owner.is_some_and(|owner| shard(owner) != shard(row.client))
+                        }) =>
+                    {
+                        true
+                    }
+                    TransactionType::Schedule => {
+                        ticking = true;
+                        false
+                    }
+                    _ => false,
+                };
+                // A skipped row still moves the clock like in `process_csv`
+                // The receivers only go away if a worker panicked, which is surfaced by `join` below
+                if let Some(now) = row.timestamp
+                    && ticking
+                    && clock.is_none_or(|clock| now > clock)
+                {
+                    clock = Some(now);
+                    for (i, sender) in senders.iter().enumerate() {
+                        if skip || i != shard(row.client) {
+                            _ = sender.send(ShardMessage::Clock(now));
+                        }
+                    }
+                }
+                if !skip {
+                    _ = senders[shard(row.client)].send(ShardMessage::Row(row));
+                }
+            }
+            drop(senders);
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("shard worker panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        for engine in partitions {
+            self.merge_partition(engine);
+        }
+        let Some(transfer) = transfer else {
+            return;
+        };
+        _ = self.process_row(&transfer);
+        for record in rdr.byte_records().flatten() {
+            if let Ok(row) = self.deserialize_row(&record, &headers, &columns)
+                && self.config.filter.matches(&row)
+            {
+                _ = self.process_row(&row);
+            }
+        }
+    }
+
+    /// Move the accounts and the rest of the state of an engine which owned a part of the clients into this one
+    pub(crate) fn merge_partition(&mut self, mut engine: Engine<S>) {
+        for (client, profile) in engine.store.drain() {
+            self.store.insert(client, profile);
+        }
+        for (id, client) in engine.tx_index.drain() {
+            self.tx_index.entry(id).or_insert(client);
+        }
+        for schedule in engine.schedules.iter() {
+            self.schedules.push(*schedule);
+        }
+        self.open_disputes.append(engine.open_disputes);
+        self.row_hashes.extend(engine.row_hashes);
+        if let (Some(ledger), Some(partition)) = (&mut self.ledger, engine.ledger) {
+            ledger.merge(partition);
+        }
+    }
+
+    /// Parse a single input row and apply it to the account of its client
+    /// The scheduled transactions which are due at the timestamp of the row are processed first
+    pub fn process_row(&mut self, row: &CsvInputRow) -> Result<(), RowProcessingError> {
+        Ok(self.apply_row(row)?)
+    }
+
+    fn apply_row(&mut self, row: &CsvInputRow) -> Result<(), RowRejection> {
+        let transaction =
+            (row.transaction_type != TransactionType::Schedule).then(|| self.parse_row(row));
+        self.apply_parsed_row(row, transaction)
+    }
+
+    /// Same as `process_row` with the result of `parse_row` for the row, which is None for a schedule row
+    /// The parsing only depends on the config, so it can be done on another thread
+    pub(crate) fn process_parsed_row(
+        &mut self,
+        row: &CsvInputRow,
+        transaction: Option<Result<Transaction, TransactionParsingError>>,
+    ) -> Result<(), RowProcessingError> {
+        Ok(self.apply_parsed_row(row, transaction)?)
+    }
+
+    fn apply_parsed_row(
+        &mut self,
+        row: &CsvInputRow,
+        transaction: Option<Result<Transaction, TransactionParsingError>>,
+    ) -> Result<(), RowRejection> {
+        self.rejected_occurrences.clear();
+        if let Some(timestamp) = row.timestamp {
+            self.without_wal(|engine| {
+                engine.run_schedules(timestamp);
+                engine.expire_disputes(timestamp);
+            });
+        }
+        let Some(transaction) = transaction else {
+            return self.process_schedule_row(row);
+        };
+        let transaction = transaction?;
+        let hash = self.duplicate_row_hash(row)?;
+        self.process_transaction_at(row.client, row.tx, transaction, row.timestamp)?;
+        if let Some(hash) = hash {
+            self.row_hashes.insert((row.client, row.tx), hash);
+        }
+        Ok(())
+    }
+
+    /// Run `f` without logging its transactions into the write-ahead log, for the occurrences of the schedules
+    /// and the disputes closed by the timeout, which the replay of the logged rows does again
+    pub(crate) fn without_wal<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        #[cfg(feature = "fs")]
+        let wal = self.wal.take();
+        let result = f(self);
+        #[cfg(feature = "fs")]
+        {
+            self.wal = wal;
+        }
+        result
+    }
+
+    /// The content hash of a row which creates a transaction when `detect_duplicate_rows` is set
+    /// A row identical to an accepted one is a duplicate as long as its transaction id is still used,
+    /// a row reusing an evicted id is rejected as an invalid id instead
+    fn duplicate_row_hash(
+        &self,
+        row: &CsvInputRow,
+    ) -> Result<Option<u64>, TransactionProcessingError> {
+        if !self.config.detect_duplicate_rows
+            || !matches!(
+                row.transaction_type,
+                TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+            )
+        {
+            return Ok(None);
+        }
+        let mut hasher = DefaultHasher::new();
+        (row.transaction_type, row.client, row.tx, row.amount, row.to).hash(&mut hasher);
+        let hash = hasher.finish();
+        let used = self
+            .store
+            .get(row.client)
+            .is_some_and(|profile| profile.transactions.contains(row.tx));
+        if used && self.row_hashes.get(&(row.client, row.tx)) == Some(&hash) {
+            return Err(TransactionProcessingError::DuplicateRow(row.tx));
+        }
+        Ok(Some(hash))
+    }
+
+    /// Same as `process_row`, a rejected row is returned with its `line` number and the reason
+    pub fn process_row_detailed(&mut self, line: u64, row: &CsvInputRow) -> ProcessingOutcome {
+        match self.apply_row(row) {
+            Ok(()) => ProcessingOutcome::Accepted,
+            Err(RowRejection::Parsing(error)) => ProcessingOutcome::RejectedParse(RejectedRow {
+                line,
+                row: row.clone(),
+                error,
+            }),
+            Err(RowRejection::Processing(error)) => {
+                ProcessingOutcome::RejectedProcessing(RejectedRow {
+                    line,
+                    row: row.clone(),
+                    error,
+                })
+            }
+        }
+    }
+
+    /// Parse and validate a single input row with the policies of this engine
+    pub fn parse_row(&self, row: &CsvInputRow) -> Result<Transaction, TransactionParsingError> {
+        self.apply_policies(parse_transaction(row)?)
+    }
+
+    /// Parse a transaction type with the type aliases of the config applied
+    pub fn parse_type(
+        &self,
+        transaction_type: &str,
+    ) -> Result<TransactionType, TransactionParsingError> {
+        match &self.config.type_aliases {
+            Some(aliases) => aliases.resolve(transaction_type).parse(),
+            None => transaction_type.parse(),
+        }
+    }
+
+    /// Deserialize a csv record with `headers` and the config of this engine, see `deserialize_record`
+    pub(crate) fn deserialize_row(
+        &self,
+        record: &ByteRecord,
+        headers: &ByteRecord,
+        columns: &ColumnIndex,
+    ) -> csv::Result<CsvInputRow> {
+        deserialize_record(&self.config, record, headers, columns)
+    }
+
+    /// Apply the precision policy to the amount of a parsed transaction and validate it
+    pub(crate) fn apply_policies(
+        &self,
+        mut transaction: Transaction,
+    ) -> Result<Transaction, TransactionParsingError> {
+        if let (Some(precision), Some(amount)) = (&self.config.precision, transaction.amount()) {
+            transaction = transaction.with_amount(precision.apply(amount)?);
+        }
+        validate_transaction(&transaction, self.config.allow_zero_amount)?;
+        Ok(transaction)
+    }
+
+    /// Apply a transaction to the account of `client`, the account is created on first use
+    pub fn process_transaction(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        self.process_transaction_at(client, id, transaction, None)
+    }
+
+    /// Same as `process_transaction` for a transaction which happened at `timestamp`
+    pub fn process_transaction_at(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionProcessingError> {
+        #[cfg(feature = "trace")]
+        let span = tracing::debug_span!(
+            "transaction",
+            client,
+            tx = id,
+            r#type = transaction.type_name(),
+            outcome = tracing::field::Empty,
+        )
+        .entered();
+        let result = self.apply_transaction_at(client, id, transaction, timestamp);
+        if let Some(max) = self.config.max_transactions_per_account {
+            self.evict_transactions(client, max);
+            if let Transaction::Transfer(to, _) = transaction {
+                self.evict_transactions(to, max);
+            }
+        }
+        if let (Err(err), Some(ledger)) = (&result, &mut self.ledger)
+            && ledger.records_rejections()
+        {
+            ledger.record_rejection(client, id, &transaction, err, self.store.get(client));
+        }
+        #[cfg(feature = "trace")]
+        match &result {
+            Ok(()) => span.record("outcome", "accepted"),
+            Err(err) => span.record("outcome", err.reason()),
+        };
+        result
+    }
+
+    fn apply_transaction_at(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionProcessingError> {
+        #[cfg(feature = "fs")]
+        if let Some(wal) = &mut self.wal {
+            wal.append(client, id, &transaction, timestamp)
+                .map_err(TransactionProcessingError::WalWriteFailed)?;
+            let result = self.validate_and_apply(client, id, transaction, timestamp);
+            // Only the accepted transactions stay in the log
+            if result.is_err()
+                && let Some(wal) = &mut self.wal
+            {
+                wal.discard_last()
+                    .map_err(TransactionProcessingError::WalWriteFailed)?;
+            }
+            return result;
+        }
+        self.validate_and_apply(client, id, transaction, timestamp)
+    }
+
+    fn validate_and_apply(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionProcessingError> {
+        self.store
+            .prepare_transaction(client, id)
+            .map_err(TransactionProcessingError::StoreFailed)?;
+        if let Transaction::Transfer(to, _) = transaction {
+            self.store
+                .prepare_transaction(to, id)
+                .map_err(TransactionProcessingError::StoreFailed)?;
+        }
+        self.validate_reserved_id(client, id, &transaction)?;
+        self.validate_owner(client, id, &transaction)?;
+        let duplicate_of = self.validate_global_id(client, id, &transaction)?;
+        match transaction {
+            Transaction::Transfer(to, amount) => {
+                self.process_transfer(client, to, id, amount, timestamp)?
+            }
+            _ => self.store.get_or_create(client).process_transaction_at(
+                id,
+                transaction,
+                timestamp,
+                &self.config,
+            )?,
+        }
+        self.check_invariants(client, id);
+        if let Transaction::Transfer(to, _) = transaction {
+            self.check_invariants(to, id);
+        }
+        self.store.record_transaction(client, id, &transaction);
+        if let (Transaction::Dispute(_) | Transaction::PartialDispute(..), Some(timestamp)) =
+            (transaction, timestamp)
+        {
+            self.open_disputes.push(timestamp, client, id);
+        }
+        if let Transaction::Deposit(_) | Transaction::Withdrawal(_) | Transaction::Transfer(..) =
+            transaction
+        {
+            self.tx_index.entry(id).or_insert(client);
+        }
+        if let Some(ledger) = &mut self.ledger {
+            ledger.record(client, id, &transaction, self.store.get_or_create(client));
+            if let Transaction::Transfer(to, _) = transaction {
+                ledger.record(to, id, &transaction, self.store.get_or_create(to));
+            }
+        }
+        if !self.observers.is_empty() {
+            let frozen = self.store.get_or_create(client).is_frozen();
+            self.observers.notify(client, id, &transaction, frozen);
+        }
+        if let Some(owner) = duplicate_of {
+            self.observers.notify_duplicate_id(client, id, owner);
+        }
+        Ok(())
+    }
+
+    /// Evict the oldest transactions of `client` beyond `max`, they are removed from the index and the row hashes as well
+    fn evict_transactions(&mut self, client: ClientId, max: usize) {
+        if self
+            .store
+            .get(client)
+            .is_none_or(|profile| profile.transactions.len() <= max)
+        {
+            return;
+        }
+        for id in self.store.get_or_create(client).evict_transactions(max) {
+            if self.tx_index.get(&id) == Some(&client) {
+                self.tx_index.remove(&id);
+            }
+            self.row_hashes.remove(&(client, id));
+        }
+    }
+
+    /// Check the invariants of the account of `client` after transaction `id` with the configured mode
+    fn check_invariants(&mut self, client: ClientId, id: TransactionId) {
+        if self.config.invariant_checks == InvariantChecks::Off {
+            return;
+        }
+        let Some(Err(violation)) = self.store.get(client).map(AccountProfile::check_invariants)
+        else {
+            return;
+        };
+        match self.config.invariant_checks {
+            InvariantChecks::Abort => {
+                panic!("invariant violated by client {client} after transaction {id}: {violation}")
+            }
+            _ => self
+                .observers
+                .notify_invariant_violation(client, id, &violation),
+        }
+    }
+
+    /// Reject a new transaction id which is left to an occurrence of a pending schedule of either client
+    fn validate_reserved_id(
+        &self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let reserved = match transaction {
+            Transaction::Deposit(_) | Transaction::Withdrawal(_) => {
+                self.schedules.reserves(client, id)
+            }
+            Transaction::Transfer(to, _) => {
+                self.schedules.reserves(client, id) || self.schedules.reserves(*to, id)
+            }
+            _ => false,
+        };
+        if reserved {
+            return Err(TransactionProcessingError::InvalidTransactionId(id));
+        }
+        Ok(())
+    }
+
+    /// Reject a dispute, resolve or chargeback of a transaction which only exists for another client
+    /// The account would reject it as an unknown id anyway, but the mismatch is worth reporting on its own
+    fn validate_owner(
+        &self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        if !matches!(
+            transaction,
+            Transaction::Dispute(_)
+                | Transaction::PartialDispute(..)
+                | Transaction::Resolve(_)
+                | Transaction::Chargeback(_)
+                | Transaction::ChargebackReversal(_)
+        ) {
+            return Ok(());
+        }
+        match self.tx_index.get(&id) {
+            Some(&owner) if owner != client => {
+                let known = self
+                    .store
+                    .get(client)
+                    .is_some_and(|profile| profile.transactions.get(id).is_some());
+                if known {
+                    Ok(())
+                } else {
+                    Err(TransactionProcessingError::ClientMismatch(owner))
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a new transaction id against the ids of the other clients with the duplicate id policy
+    /// Returns the other client when the duplicate is accepted with a warning
+    fn validate_global_id(
+        &self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: &Transaction,
+    ) -> Result<Option<ClientId>, TransactionProcessingError> {
+        if !matches!(
+            transaction,
+            Transaction::Deposit(_) | Transaction::Withdrawal(_) | Transaction::Transfer(..)
+        ) {
+            return Ok(None);
+        }
+        match (self.tx_index.get(&id), self.config.duplicate_id_policy) {
+            (Some(&owner), DuplicateIdPolicy::Reject) if owner != client => Err(
+                TransactionProcessingError::DuplicateTransactionId(id, owner),
+            ),
+            (Some(&owner), DuplicateIdPolicy::Warn) if owner != client => Ok(Some(owner)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Add the transactions of an account restored from somewhere else to the index
+    pub(crate) fn index_transactions(&mut self, client: ClientId, profile: &AccountProfile) {
+        for id in profile.transactions.ids() {
+            self.tx_index.entry(id).or_insert(client);
+        }
+        self.open_disputes.extend(client, profile);
+    }
+
+    /// A transfer is a withdrawal from `from` and a deposit into `to` sharing the same transaction id
+    /// It is recorded on both sides, so it can be disputed as a withdrawal by the source client
+    /// or as a deposit by the destination client
+    fn process_transfer(
+        &mut self,
+        from: ClientId,
+        to: ClientId,
+        id: TransactionId,
+        amount: Decimal,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionProcessingError> {
+        if from == to {
+            return Err(TransactionProcessingError::TransferToSelf);
+        }
+        // Validate everything the credit checks before we debit the source, so both sides are applied or none of them
+        // A destination which doesn't exist yet is checked as a new account and only created by the credit
+        let mut new_account = AccountProfile::default();
+        let destination = match self.store.get(to) {
+            Some(_) => self.store.get_or_create(to),
+            None => &mut new_account,
+        };
+        destination.validate_incoming(id)?;
+        destination.validate_timestamp(timestamp, &self.config)?;
+        destination.enforce_risk(&Transaction::Deposit(amount), timestamp, &self.config)?;
+        checked_update(
+            destination.available,
+            destination.held,
+            amount,
+            Decimal::ZERO,
+        )?;
+        let source = self.store.get_or_create(from);
+        let undo = source.debit_undo();
+        source.process_transaction_at(
+            id,
+            Transaction::Withdrawal(amount),
+            timestamp,
+            &self.config,
+        )?;
+        // A check of the credit missed above must not leave the transfer half applied
+        if let Err(err) = self.store.get_or_create(to).process_transaction_at(
+            id,
+            Transaction::Deposit(amount),
+            timestamp,
+            &self.config,
+        ) {
+            self.store.get_or_create(from).undo_debit(id, undo);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Start the account of `client` from `profile`, e.g. one of `AccountProfile::with_balances`,
+    /// instead of crafting deposits to reach its balances
+    /// A previous account of `client` is replaced and returned, seeding is not written to the write-ahead log
+    pub fn seed_account(
+        &mut self,
+        client: ClientId,
+        profile: AccountProfile,
+    ) -> Option<AccountProfile> {
+        let previous =
+            (self.store.get(client).is_some()).then(|| mem::take(self.store.get_or_create(client)));
+        if let Some(previous) = &previous {
+            for id in previous.transactions.ids() {
+                if self.tx_index.get(&id) == Some(&client) {
+                    self.tx_index.remove(&id);
+                }
+            }
+        }
+        self.index_transactions(client, &profile);
+        self.store.insert(client, profile);
+        previous
+    }
+
+    /// Seed the accounts of the rows of a csv with `client,available,held,locked` columns, before any transaction
+    /// The other columns are ignored, so the output of a previous run carries its balances over
+    /// A locked account starts frozen, returns how many accounts were seeded
+    pub fn seed_opening_balances(&mut self, input: impl Read) -> csv::Result<usize> {
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(input);
+        let mut count = 0;
+        for balance in rdr.deserialize() {
+            let balance: OpeningBalance = balance?;
+            let mut profile = AccountProfile::with_balances(balance.available, balance.held);
+            if balance.locked {
+                profile.status = AccountStatus::Frozen;
+            }
+            self.seed_account(balance.client, profile);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Set the overdraft limit of `client`, which takes precedence over `EngineConfig::overdraft_limit`
+    pub fn set_overdraft_limit(&mut self, client: ClientId, limit: Decimal) {
+        self.store.get_or_create(client).overdraft_limit = Some(limit);
+    }
+
+    /// Clear the frozen flag of `client` after a chargeback
+    /// This is the same as an `unlock` row, the transaction id is not used for it
+    pub fn unfreeze(&mut self, client: ClientId) -> Result<(), TransactionProcessingError> {
+        self.process_transaction(client, 0, Transaction::Unlock)
+    }
+
+    /// The account of `client`, None if we never saw this client
+    pub fn account(&self, client: ClientId) -> Option<&AccountProfile> {
+        self.store.get(client)
+    }
+
+    /// Whether an accepted deposit, withdrawal or transfer with id `tx` is in the id index of any client
+    /// The index is rebuilt from snapshots, but ids evicted by `max_transactions_per_account` report false,
+    /// so a replay of those rows is not detected
+    pub fn has_seen(&self, tx: TransactionId) -> bool {
+        self.tx_index.contains_key(&tx)
+    }
+
+    /// All accounts in arbitrary order
+    pub fn accounts(&self) -> impl Iterator<Item = (ClientId, &AccountProfile)> {
+        self.store.iter()
+    }
+
+    /// The output summary of `client` rounded with the output precision of this engine, see `EngineConfig::output_precision`
+    /// It includes the collected fees when a fee policy is configured
+    pub fn summary(&self, client: ClientId) -> Option<AccountSummary> {
+        self.account(client)
+            .map(|profile| self.summarize(client, profile))
+    }
+
+    /// Whether the rows and the account of `client` pass the client filter of the config
+    pub fn is_selected(&self, client: ClientId) -> bool {
+        self.config
+            .filter
+            .clients
+            .as_ref()
+            .is_none_or(|clients| clients.contains(client))
+    }
+
+    /// The output summaries of all selected accounts, see `summary` and `is_selected`
+    pub fn summaries(&self) -> impl Iterator<Item = AccountSummary> {
+        self.accounts()
+            .filter(|(client, _)| self.is_selected(*client))
+            .map(|(client, profile)| self.summarize(client, profile))
+    }
+
+    fn summarize(&self, client: ClientId, profile: &AccountProfile) -> AccountSummary {
+        let precision = self.config.output_precision();
+        let precision = precision.as_ref();
+        let summary = AccountSummary::with_precision(client, profile, precision);
+        let summary = match self.config.fee_policy {
+            Some(_) => summary.with_fees(profile, precision),
+            None => summary,
+        };
+        match self.config.extended_output {
+            true => summary.with_dispute_stats(profile, precision),
+            false => summary,
+        }
+    }
+
+    /// The withdrawal fees collected from all clients
+    pub fn total_fees(&self) -> Decimal {
+        self.accounts()
+            .map(|(_, profile)| profile.fees_collected)
+            .fold(Decimal::ZERO, Decimal::saturating_add)
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DisputeTimeout, DisputeTimeoutAction, RowFilter};
+    use crate::risk::RiskRules;
+    use crate::types::RiskLimit;
+
+    #[test]
+    fn test_process_csv() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+deposit, 1, 3, 2.0
+withdrawal, 1, 4, 1.5
+withdrawal, 2, 5, 3.0
+broken row
+dispute, 2, 2,
+";
+        let mut engine = Engine::new();
+        engine.process_csv(input.as_bytes());
+
+        assert_eq!(engine.accounts().count(), 2);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::new(15, 1));
+        assert_eq!(engine.account(2).unwrap().available, Decimal::from(0));
+        assert_eq!(engine.account(2).unwrap().held, Decimal::from(2));
+    }
+
+    #[test]
+    fn test_process_csv_bytes() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut engine = Engine::new();
+        let rows =
+            engine.process_csv_bytes(b"type,client,tx,amount\ndeposit,1,1,1.0\n\xff\xfe,1\n");
+        assert_eq!(rows, 2);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(1));
+
+        // Random mutations of a valid input must never panic
+        let valid = b"type,client,tx,amount,to\ndeposit,1,1,1.5\ntransfer,1,2,1,2\ndispute,1,1,\n";
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let mut input = valid.to_vec();
+            for _ in 0..rng.gen_range(1..8) {
+                let i = rng.gen_range(0..input.len());
+                input[i] = rng.r#gen();
+            }
+            Engine::new().process_csv_bytes(&input);
+        }
+    }
+
+    #[test]
+    fn test_process_csv_sharded() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 0..1000u32 {
+            let client = tx % 7;
+            match tx % 5 {
+                0 | 1 => input += &format!("deposit,{client},{tx},{}.5\n", tx % 13),
+                2 => input += &format!("withdrawal,{client},{tx},{}\n", tx % 11),
+                3 => input += &format!("dispute,{client},{},\n", tx - 3),
+                _ => input += &format!("chargeback,{client},{},\n", tx - 4),
+            }
+        }
+
+        let mut expected = Engine::new();
+        expected.process_csv(input.as_bytes());
+
+        let mut engine = Engine::new();
+        engine.process_csv_sharded(input.as_bytes(), 3);
+        assert_eq!(engine.store(), expected.store());
+
+        // Clients 1 and 2 are in different shards, a dispute of the deposit of client 1 by client 2 is rejected
+        let input =
+            "type,client,tx,amount\ndeposit,1,1,5\ndispute,2,1,\ndeposit,2,2,1\nchargeback,2,1,\n";
+        let mut expected = Engine::new();
+        expected.process_csv(input.as_bytes());
+        let mut engine = Engine::new();
+        engine.process_csv_sharded(input.as_bytes(), 2);
+        assert_eq!(engine.store(), expected.store());
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(5));
+
+        // The id of client 1 is still a duplicate for client 2
+        let input = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,2,1,3\n";
+        let mut engine = Engine::with_config(EngineConfig {
+            duplicate_id_policy: DuplicateIdPolicy::Reject,
+            ..Default::default()
+        });
+        engine.process_csv_sharded(input.as_bytes(), 2);
+        assert!(engine.account(2).is_none());
+
+        // The rows from the first transfer on are applied serially
+        let input = "type,client,tx,amount,to\ndeposit,1,1,5,\ndeposit,2,2,1,\ntransfer,1,3,2,2\n\
+            withdrawal,2,4,2,\ntransfer,2,5,1,3\n";
+        let mut expected = Engine::new();
+        expected.process_csv(input.as_bytes());
+        let mut engine = Engine::new();
+        engine.process_csv_sharded(input.as_bytes(), 2);
+        assert_eq!(engine.store(), expected.store());
+        assert_eq!(engine.account(3).unwrap().available, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_process_csv_sharded_clock() {
+        let config = EngineConfig {
+            dispute_timeout: Some(DisputeTimeout {
+                after: 50,
+                action: DisputeTimeoutAction::Chargeback,
+            }),
+            detect_duplicate_rows: true,
+            ..Default::default()
+        };
+        // Clients 1 and 2 are in different shards, only the rows of client 2 move the clock after the schedule
+        // and the dispute of client 1
+        let before = "type,client,tx,amount,timestamp,interval,count\n\
+            deposit,1,1,10,0,,\n\
+            schedule,1,100,2,10,10,5\n\
+            dispute,1,1,,20,,\n";
+        let after = "deposit,2,2,1,30,,\n\
+            deposit,2,3,1,80,,\n\
+            withdrawal,1,4,10,,,\n";
+
+        let mut expected = Engine::with_config(config.clone());
+        expected.process_csv(format!("{before}{after}").as_bytes());
+        // All occurrences are deposited and the dispute is charged back before the withdrawal
+        let profile = expected.account(1).unwrap();
+        assert_eq!(profile.available, Decimal::from(10));
+        assert!(profile.is_frozen());
+
+        let mut engine = Engine::with_config(config.clone());
+        engine.process_csv_sharded(format!("{before}{after}").as_bytes(), 2);
+        assert_eq!(engine.store(), expected.store());
+        assert_eq!(engine.row_hashes, expected.row_hashes);
+
+        // The pending schedule and the open dispute of an earlier input go to the shard of client 1
+        let mut engine = Engine::with_config(config);
+        engine.process_csv(before.as_bytes());
+        assert_eq!(engine.schedules().len(), 1);
+        engine.process_csv_sharded(
+            format!("type,client,tx,amount,timestamp,interval,count\n{after}").as_bytes(),
+            2,
+        );
+        assert_eq!(engine.store(), expected.store());
+        assert_eq!(engine.row_hashes, expected.row_hashes);
+        assert!(engine.schedules().is_empty());
+    }
+
+    #[test]
+    fn test_csv_dialect() {
+        let mut config = EngineConfig::default();
+        config.csv.delimiter = b';';
+        config.csv.columns = std::collections::HashMap::from([
+            ("txn_type".to_string(), "type".to_string()),
+            ("customer".to_string(), "client".to_string()),
+        ]);
+        let mut engine = Engine::with_config(config);
+        engine.process_csv("txn_type;customer;tx;amount\ndeposit;1;1;1,5\n".as_bytes());
+        assert!(engine.account(1).is_none());
+        engine.process_csv("txn_type;customer;tx;amount\ndeposit;1;1;1.5\n".as_bytes());
+        assert_eq!(engine.account(1).unwrap().available, Decimal::new(15, 1));
+
+        let mut config = EngineConfig::default();
+        config.csv.has_headers = false;
+        let mut engine = Engine::with_config(config);
+        engine.process_csv_sharded("deposit,1,1,2\ndeposit,2,2,3\n".as_bytes(), 2);
+        assert_eq!(engine.accounts().count(), 2);
+    }
+
+    #[test]
+    fn test_type_aliases() {
+        let input = "type,client,tx,amount\nDeposit,1,1,5\nWITHDRAW,1,2,1\nDispute,1,2,\nCharge_Back,1,2,\n";
+        let mut engine = Engine::new();
+        engine.process_csv(input.as_bytes());
+        assert!(engine.account(1).is_none());
+
+        let mut engine = Engine::with_config(EngineConfig {
+            type_aliases: Some(Default::default()),
+            ..Default::default()
+        });
+        engine.process_csv(input.as_bytes());
+        let profile = engine.account(1).unwrap();
+        assert_eq!(profile.available, Decimal::from(5));
+        assert!(profile.is_frozen());
+    }
+
+    #[test]
+    fn test_invariant_checks() {
+        struct Violations(std::sync::Arc<std::sync::Mutex<Vec<(ClientId, TransactionId)>>>);
+        impl EngineObserver for Violations {
+            fn on_invariant_violation(
+                &mut self,
+                client: ClientId,
+                tx: TransactionId,
+                _violation: &crate::types::InvariantViolation,
+            ) {
+                self.0.lock().unwrap().push((client, tx));
+            }
+        }
+
+        let input = "type,client,tx,amount,to\ndeposit,1,1,5,\ntransfer,1,2,2,2\ndispute,2,2,,\nchargeback,2,2,,\n";
+        let mut engine = Engine::with_config(EngineConfig {
+            invariant_checks: InvariantChecks::Abort,
+            ..Default::default()
+        });
+        engine.process_csv(input.as_bytes());
+        assert!(engine.account(2).unwrap().is_frozen());
+
+        let violations = std::sync::Arc::default();
+        let mut engine = Engine::with_config(EngineConfig {
+            invariant_checks: InvariantChecks::Log,
+            ..Default::default()
+        });
+        engine.add_observer(Box::new(Violations(std::sync::Arc::clone(&violations))));
+        engine.store.insert(
+            3,
+            AccountProfile {
+                available: Decimal::from(5),
+                ..Default::default()
+            },
+        );
+        engine
+            .process_transaction(3, 1, Transaction::Deposit(Decimal::ONE))
+            .unwrap();
+        assert_eq!(*violations.lock().unwrap(), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn test_client_filter() {
+        let input = "type,client,tx,amount,to\ndeposit,1,1,5,\ndeposit,2,2,5,\ntransfer,1,3,2,3\ndeposit,7,4,1,\n";
+        let mut engine = Engine::with_config(EngineConfig {
+            filter: RowFilter {
+                clients: Some("1,5-9".parse().unwrap()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        engine.process_csv(input.as_bytes());
+        assert!(engine.account(2).is_none());
+        // The recipient of a transfer is credited but not output
+        assert_eq!(engine.account(3).unwrap().available, Decimal::from(2));
+        let mut clients: Vec<_> = engine.summaries().map(|summary| summary.client).collect();
+        clients.sort();
+        assert_eq!(clients, vec![1, 7]);
+    }
+
+    #[test]
+    fn test_extended_output() {
+        let input = "type,client,tx,amount
+deposit,1,1,1.5
+deposit,1,2,2
+deposit,1,3,3
+dispute,1,1,
+dispute,1,2,
+resolve,1,2,
+dispute,1,3,
+chargeback,1,3,
+deposit,2,4,1
+";
+        let mut engine = Engine::with_config(EngineConfig {
+            extended_output: true,
+            ..Default::default()
+        });
+        engine.process_csv(input.as_bytes());
+        let mut summaries: Vec<_> = engine.summaries().collect();
+        summaries.sort_by_key(|summary| summary.client);
+        let stats: Vec<_> = summaries
+            .iter()
+            .map(|summary| {
+                (
+                    summary.open_disputes,
+                    summary.resolved_disputes,
+                    summary.chargebacks,
+                    summary.disputed_amount_held,
+                )
+            })
+            .collect();
+        assert_eq!(
+            stats,
+            [
+                (Some(1), Some(1), Some(1), Some(Decimal::new(15000, 4))),
+                (Some(0), Some(0), Some(0), Some(Decimal::new(0, 4))),
+            ]
+        );
+
+        let mut output = vec![];
+        crate::output::write_summaries(summaries, &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().starts_with(
+            "client,available,held,total,locked,open_disputes,resolved_disputes,chargebacks,disputed_amount_held\n\
+             1,2.0000,1.5000,3.5000,true,1,1,1,1.5000\n"
+        ));
+    }
+
+    #[test]
+    fn test_process_csv_with_errors() {
+        let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,1,1.0
+unknown,1,2,1.0
+withdrawal,1,x,1.0
+withdrawal,1,3,5.0
+";
+        let mut engine = Engine::new();
+        let mut errors = vec![];
+        let res = engine.process_csv_with_errors(input.as_bytes(), |line, _, err| {
+            errors.push((line, err));
+            Ok::<(), Infallible>(())
+        });
+        assert_eq!(res, Ok(5));
+        assert_eq!(errors.len(), 4);
+        assert!(matches!(errors[0], (3, RowProcessingError::Processing(_))));
+        // An unknown type is rejected when the row is deserialized
+        assert!(matches!(errors[1], (4, RowProcessingError::Csv(_))));
+        assert!(matches!(errors[2], (5, RowProcessingError::Csv(_))));
+        assert!(matches!(errors[3], (6, RowProcessingError::Processing(_))));
+
+        // Stop at the first error
+        let mut engine = Engine::new();
+        let res = engine.process_csv_with_errors(input.as_bytes(), |line, _, _| Err(line));
+        assert_eq!(res, Err(3));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(1));
+
+        let errors = Engine::new().process_csv_collect_errors(input.as_bytes());
+        let lines: Vec<_> = errors.iter().map(|err| err.line).collect();
+        assert_eq!(lines, [3, 4, 5, 6]);
+        assert!(
+            errors[3]
+                .to_string()
+                .starts_with("line 6: transaction rejected: available amount 1")
+        );
+    }
+
+    #[test]
+    fn test_transfer() {
+        let input = "type,client,tx,amount,to
+deposit,1,1,10.0
+transfer,1,2,4.0,2
+transfer,1,3,7.0,2
+transfer,1,4,1.0,1
+transfer,1,5,1.0
+";
+        let mut engine = Engine::new();
+        engine.process_csv(input.as_bytes());
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(6));
+        assert_eq!(engine.account(2).unwrap().available, Decimal::from(4));
+
+        // The destination can dispute it as a deposit
+        assert!(
+            engine
+                .process_transaction(2, 2, Transaction::Dispute(None))
+                .is_ok()
+        );
+        assert_eq!(engine.account(2).unwrap().held, Decimal::from(4));
+        assert!(
+            engine
+                .process_transaction(2, 2, Transaction::Chargeback(None))
+                .is_ok()
+        );
+        assert!(engine.account(2).unwrap().is_frozen());
+
+        // Nothing is debited when the destination is frozen
+        let res = engine.process_transaction(1, 6, Transaction::Transfer(2, Decimal::from(1)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::AccountIsFrozen)
+        ));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(6));
+
+        // A rejected transfer doesn't create its destination
+        let res = engine.process_transaction(1, 7, Transaction::Transfer(3, Decimal::from(9)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::AvailableAmountTooLow(..))
+        ));
+        assert!(engine.account(3).is_none());
+
+        // Nothing is debited when the credit would overflow
+        engine
+            .process_transaction(3, 8, Transaction::Deposit(Decimal::MAX))
+            .unwrap();
+        let res = engine.process_transaction(1, 9, Transaction::Transfer(3, Decimal::from(1)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::ArithmeticOverflow)
+        ));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(6));
+        assert!(!engine.account(1).unwrap().transactions.contains(9));
+    }
+
+    #[test]
+    fn test_risk_rules() {
+        let mut engine = Engine::with_config(EngineConfig {
+            risk: Some(RiskRules {
+                max_deposit: Some(Decimal::from(10)),
+                max_daily_volume: Some(Decimal::from(15)),
+                freeze: true,
+                ..Default::default()
+            }),
+            invariant_checks: InvariantChecks::Abort,
+            ..Default::default()
+        });
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(10)))
+            .unwrap();
+        engine
+            .process_transaction(2, 2, Transaction::Deposit(Decimal::from(8)))
+            .unwrap();
+        // Nothing is debited when the deposit of the destination exceeds a limit
+        let res = engine.process_transaction(1, 3, Transaction::Transfer(2, Decimal::from(8)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::RiskLimitExceeded(
+                RiskLimit::DailyVolume(_)
+            ))
+        ));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(10));
+        let destination = engine.account(2).unwrap();
+        assert!(destination.is_frozen() && destination.risk.tripped);
+
+        let res = engine.process_transaction(1, 4, Transaction::Withdrawal(Decimal::from(6)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::RiskLimitExceeded(
+                RiskLimit::DailyVolume(_)
+            ))
+        ));
+        assert!(engine.unfreeze(1).is_ok());
+        let res = engine.process_transaction(3, 5, Transaction::Deposit(Decimal::from(11)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::RiskLimitExceeded(
+                RiskLimit::MaxDeposit(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_client_mismatch() {
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        engine
+            .process_transaction(1, 2, Transaction::Transfer(2, Decimal::from(1)))
+            .unwrap();
+        let res = engine.process_transaction(2, 1, Transaction::Dispute(None));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::ClientMismatch(1))
+        ));
+        // The destination of a transfer has its own record of the transaction
+        assert!(
+            engine
+                .process_transaction(2, 2, Transaction::Dispute(None))
+                .is_ok()
+        );
+        let res = engine.process_transaction(2, 3, Transaction::Dispute(None));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidTransactionId(3))
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_id_policy() {
+        let mut engine = Engine::with_config(EngineConfig {
+            duplicate_id_policy: DuplicateIdPolicy::Reject,
+            ..Default::default()
+        });
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        let res = engine.process_transaction(2, 1, Transaction::Deposit(Decimal::from(5)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::DuplicateTransactionId(1, 1))
+        ));
+        // A transfer uses its id on both sides, which is not a duplicate
+        engine
+            .process_transaction(1, 2, Transaction::Transfer(2, Decimal::from(1)))
+            .unwrap();
+        assert!(engine.account(2).unwrap().transactions.deposit(2).is_some());
+
+        // A flag id is only a reference, while an adjustment uses its id
+        engine
+            .process_transaction(1, 1, Transaction::Flag(3))
+            .unwrap();
+        let res = engine.process_transaction(1, 1, Transaction::Adjustment(Decimal::ONE, 3));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidTransactionId(1))
+        ));
+        assert_eq!(engine.account(1).unwrap().flags.len(), 1);
+
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        assert!(
+            engine
+                .process_transaction(2, 1, Transaction::Deposit(Decimal::from(5)))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_has_seen() {
+        let mut engine = Engine::with_config(EngineConfig {
+            max_transactions_per_account: Some(1),
+            ..Default::default()
+        });
+        for id in 1..=2 {
+            engine
+                .process_transaction(1, id, Transaction::Deposit(Decimal::from(5)))
+                .unwrap();
+        }
+        assert!(engine.has_seen(2));
+        assert!(!engine.has_seen(3));
+        // The evicted id is forgotten
+        assert!(!engine.has_seen(1));
+    }
+
+    #[test]
+    fn test_evicted_ids() {
+        let config = EngineConfig {
+            max_transactions_per_account: Some(1),
+            ..Default::default()
+        };
+        let reasons = |engine: &mut Engine, input: &str| {
+            engine
+                .process_csv_collect_errors(input.as_bytes())
+                .into_iter()
+                .map(|err| (err.line, err.kind.reason()))
+                .collect::<Vec<_>>()
+        };
+
+        // The ids don't have to grow, only the evicted ids are rejected
+        let mut engine = Engine::with_config(config.clone());
+        let input = "type,client,tx,amount\ndeposit,1,100,1\ndeposit,1,101,1\ndeposit,1,5,7\n\
+            dispute,1,6,\ndispute,1,100,\ndeposit,1,100,1\n";
+        assert_eq!(
+            reasons(&mut engine, input),
+            [
+                (5, "invalid_transaction_id"),
+                (6, "transaction_evicted"),
+                (7, "invalid_transaction_id")
+            ]
+        );
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(9));
+
+        // A transfer reusing an evicted id of the destination is rejected before the source is debited
+        let mut engine = Engine::with_config(config);
+        let input = "type,client,tx,amount,to\ndeposit,1,1,10,\ndeposit,2,10,1,\ndeposit,2,11,1,\n\
+            transfer,1,10,2,2\ntransfer,1,5,2,2\n";
+        assert_eq!(reasons(&mut engine, input), [(5, "invalid_transaction_id")]);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(8));
+        assert_eq!(engine.account(2).unwrap().available, Decimal::from(4));
+    }
+
+    #[test]
+    fn test_lenient_amounts() {
+        let input = "type,client,tx,amount
+deposit,1,1,\"$1,000.50\"
+withdrawal,1,2, $ 0.50
+deposit,1,3,\"1,00\"
+";
+        let mut engine = Engine::new();
+        assert_eq!(engine.process_csv_collect_errors(input.as_bytes()).len(), 3);
+
+        let mut engine = Engine::with_config(EngineConfig {
+            lenient_amounts: true,
+            ..Default::default()
+        });
+        let errors = engine.process_csv_collect_errors(input.as_bytes());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            (errors[0].line, errors[0].kind.reason()),
+            (4, "malformed_row")
+        );
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(1000));
+    }
+
+    #[test]
+    fn test_detect_duplicate_rows() {
+        let input = "type,client,tx,amount
+deposit,1,1,5
+deposit,1,1,5.0
+deposit,1,1,6
+withdrawal,1,2,1
+withdrawal,1,2,1
+";
+        let reasons = |config| {
+            let mut engine = Engine::with_config(config);
+            engine
+                .process_csv_collect_errors(input.as_bytes())
+                .into_iter()
+                .map(|err| (err.line, err.kind.reason()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            reasons(EngineConfig::default()),
+            [
+                (3, "invalid_transaction_id"),
+                (4, "invalid_transaction_id"),
+                (6, "invalid_transaction_id")
+            ]
+        );
+        // Only the rows identical to an accepted one are duplicates, the same id with another amount is not
+        let config = EngineConfig {
+            detect_duplicate_rows: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            reasons(config),
+            [
+                (3, "duplicate_row"),
+                (4, "invalid_transaction_id"),
+                (6, "duplicate_row")
+            ]
+        );
+
+        // The hashes are evicted with their ids
+        let mut engine = Engine::with_config(EngineConfig {
+            detect_duplicate_rows: true,
+            max_transactions_per_account: Some(1),
+            ..Default::default()
+        });
+        let errors = engine.process_csv_collect_errors(
+            "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,5\ndeposit,1,1,5\n".as_bytes(),
+        );
+        assert_eq!(engine.row_hashes.len(), 1);
+        // The evicted id can't be used again, so the row is no duplicate but an invalid id
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind.reason(), "invalid_transaction_id");
+    }
+
+    #[test]
+    fn test_process_row_errors() {
+        let mut engine = Engine::new();
+        let row = CsvInputRow {
+            transaction_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: None,
+            to: None,
+            timestamp: None,
+            case_id: None,
+            interval: None,
+            count: None,
+            reason: None,
+        };
+        assert!(matches!(
+            engine.process_row(&row),
+            Err(RowProcessingError::Parsing(_))
+        ));
+
+        let row = CsvInputRow {
+            amount: Some(Decimal::from(1)),
+            ..row
+        };
+        assert!(engine.process_row(&row).is_ok());
+        assert!(matches!(
+            engine.process_row(&row),
+            Err(RowProcessingError::Processing(_))
+        ));
+    }
+
+    #[test]
+    fn test_seed_account() {
+        let mut engine = Engine::with_config(EngineConfig {
+            invariant_checks: InvariantChecks::Abort,
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(1)))
+            .unwrap();
+        let previous = engine.seed_account(
+            1,
+            AccountProfile::with_balances(Decimal::from(10), Decimal::from(2)),
+        );
+        assert_eq!(previous.unwrap().available, Decimal::from(1));
+        // The seeded balances don't come from transactions, the invariants still hold
+        engine
+            .process_transaction(1, 1, Transaction::Withdrawal(Decimal::from(10)))
+            .unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!(
+            (account.available, account.held),
+            (Decimal::ZERO, Decimal::from(2))
+        );
+        assert!(
+            engine
+                .process_transaction(1, 1, Transaction::Dispute(None))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_seed_opening_balances() {
+        let mut engine = Engine::new();
+        let input = "client,available,held,total,locked\n1,5.5,1,6.5,false\n2,3,0,3,true\n";
+        assert_eq!(engine.seed_opening_balances(input.as_bytes()).unwrap(), 2);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::new(55, 1));
+        assert!(engine.account(2).unwrap().is_frozen());
+        assert!(
+            engine
+                .seed_opening_balances("client,held\n1,1\n".as_bytes())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_process_row_detailed() {
+        let mut engine = Engine::new();
+        let row = CsvInputRow::new(1, 1, &Transaction::Deposit(Decimal::from(1)));
+        assert!(matches!(
+            engine.process_row_detailed(2, &row),
+            ProcessingOutcome::Accepted
+        ));
+        let ProcessingOutcome::RejectedProcessing(rejected) = engine.process_row_detailed(3, &row)
+        else {
+            panic!("duplicate deposit accepted");
+        };
+        assert_eq!((rejected.line, &rejected.row), (3, &row));
+        assert!(matches!(
+            rejected.error,
+            TransactionProcessingError::InvalidTransactionId(1)
+        ));
+
+        let row = CsvInputRow {
+            amount: None,
+            ..row
+        };
+        assert!(matches!(
+            engine.process_row_detailed(4, &row),
+            ProcessingOutcome::RejectedParse(RejectedRow {
+                line: 4,
+                error: TransactionParsingError::MissingAmount,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::engine::Engine;
+use crate::output::OUTPUT_SCALE;
+use crate::types::{CsvInputRow, RowProcessingError};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::ffi::{CStr, c_char};
+use std::str::FromStr;
+
+pub const ENGINE_OK: i32 = 0;
+/// A null pointer or a string which is not valid utf-8 or not a decimal
+pub const ENGINE_INVALID_ARGUMENT: i32 = 1;
+/// The row is not a valid transaction, e.g. an unknown type or a missing amount
+pub const ENGINE_PARSE_ERROR: i32 = 2;
+/// The transaction was rejected, e.g. insufficient funds or a frozen account
+pub const ENGINE_REJECTED: i32 = 3;
+/// The client has no account, or its balances don't fit into an `int64_t`
+pub const ENGINE_NOT_FOUND: i32 = 4;
+
+/// The balances of an account in units of 10^-4 (`OUTPUT_SCALE`), declared in `include/rust_challenge.h`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct EngineAccount {
+    pub available: i64,
+    pub held: i64,
+    pub total: i64,
+    pub locked: bool,
+}
+
+/// Create an engine with the default config, it must be released with `engine_free`
+#[unsafe(no_mangle)]
+pub extern "C" fn engine_new() -> *mut Engine {
+    Box::into_raw(Box::new(Engine::new()))
+}
+
+/// Release an engine created by `engine_new`, null is ignored
+///
+/// # Safety
+/// `engine` must be null or a pointer returned by `engine_new` which was not released yet
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_free(engine: *mut Engine) {
+    if !engine.is_null() {
+        drop(unsafe { Box::from_raw(engine) });
+    }
+}
+
+/// Process a single transaction, same as a csv row, `amount` is a decimal string
+/// It can be null for transactions without an amount, `to` is the destination of a transfer or negative
+///
+/// # Safety
+/// `engine` must be a live pointer returned by `engine_new`, `transaction_type` and `amount` (if not null)
+/// must be nul-terminated strings
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_process(
+    engine: *mut Engine,
+    client: u16,
+    tx: u32,
+    transaction_type: *const c_char,
+    amount: *const c_char,
+    to: i32,
+) -> i32 {
+    let Some(engine) = (unsafe { engine.as_mut() }) else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    let Some(transaction_type) = (unsafe { to_str(transaction_type) }) else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    let Ok(transaction_type) = engine.parse_type(transaction_type) else {
+        return ENGINE_PARSE_ERROR;
+    };
+    let amount = match unsafe { to_str(amount) }.map(Decimal::from_str) {
+        None if !amount.is_null() => return ENGINE_INVALID_ARGUMENT,
+        None => None,
+        Some(Ok(amount)) => Some(amount),
+        Some(Err(_)) => return ENGINE_INVALID_ARGUMENT,
+    };
+    let row = CsvInputRow {
+        transaction_type,
+        client,
+        tx,
+        amount,
+        to: u16::try_from(to).ok(),
+        timestamp: None,
+        case_id: None,
+        interval: None,
+        count: None,
+        reason: None,
+    };
+    match engine.process_row(&row) {
+        Ok(()) => ENGINE_OK,
+        Err(RowProcessingError::Processing(_)) => ENGINE_REJECTED,
+        Err(_) => ENGINE_PARSE_ERROR,
+    }
+}
+
+/// Write the balances of `client` into `account`
+///
+/// # Safety
+/// `engine` must be a live pointer returned by `engine_new` and `account` must be valid for writes
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_get_account(
+    engine: *const Engine,
+    client: u16,
+    account: *mut EngineAccount,
+) -> i32 {
+    let (Some(engine), Some(account)) = (unsafe { engine.as_ref() }, unsafe { account.as_mut() })
+    else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    let Some(summary) = engine.summary(client) else {
+        return ENGINE_NOT_FOUND;
+    };
+    match (
+        to_units(summary.available),
+        to_units(summary.held),
+        to_units(summary.total),
+    ) {
+        (Some(available), Some(held), Some(total)) => {
+            *account = EngineAccount {
+                available,
+                held,
+                total,
+                locked: summary.locked,
+            };
+            ENGINE_OK
+        }
+        _ => ENGINE_NOT_FOUND,
+    }
+}
+
+/// None for null or a string which is not valid utf-8
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+fn to_units(amount: Decimal) -> Option<i64> {
+    amount
+        .checked_mul(Decimal::from(10i64.pow(OUTPUT_SCALE)))?
+        .round()
+        .to_i64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn test_ffi() {
+        let engine = engine_new();
+        unsafe {
+            let res = engine_process(engine, 1, 1, c"deposit".as_ptr(), c"1.5".as_ptr(), -1);
+            assert_eq!(res, ENGINE_OK);
+            let res = engine_process(engine, 1, 2, c"transfer".as_ptr(), c"0.5".as_ptr(), 2);
+            assert_eq!(res, ENGINE_OK);
+            let res = engine_process(engine, 1, 3, c"withdrawal".as_ptr(), c"5".as_ptr(), -1);
+            assert_eq!(res, ENGINE_REJECTED);
+            let res = engine_process(engine, 1, 4, c"deposit".as_ptr(), ptr::null(), -1);
+            assert_eq!(res, ENGINE_PARSE_ERROR);
+            let res = engine_process(engine, 1, 4, c"deposit".as_ptr(), c"x".as_ptr(), -1);
+            assert_eq!(res, ENGINE_INVALID_ARGUMENT);
+
+            let mut account = EngineAccount::default();
+            assert_eq!(engine_get_account(engine, 1, &mut account), ENGINE_OK);
+            assert_eq!(
+                account,
+                EngineAccount {
+                    available: 10000,
+                    held: 0,
+                    total: 10000,
+                    locked: false,
+                }
+            );
+            assert_eq!(
+                engine_get_account(engine, 3, &mut account),
+                ENGINE_NOT_FOUND
+            );
+            engine_free(engine);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::output::AccountSummary;
+use crate::service::{AccountUpdate, EngineService};
+use crate::types::{ClientId, CsvInputRow, TransactionType};
+use proto::engine_server::{Engine, EngineServer};
+use serde::Deserialize;
+use serde::de::IntoDeserializer;
+use serde::de::value::Error as ValueError;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+/// The messages and the service generated from `proto/engine.proto`
+pub mod proto {
+    tonic::include_proto!("engine");
+}
+
+/// How many updates can be queued for a `StreamAccounts` call before the thread reading them waits
+const UPDATE_QUEUE_SIZE: usize = 64;
+
+/// The `Engine` service of `proto/engine.proto` on top of `EngineService`
+/// - `SubmitTransaction` answers with the account of the client, a rejected transaction with `INVALID_ARGUMENT`
+///   when it can't be parsed and `FAILED_PRECONDITION` with the reason otherwise
+/// - `GetAccount` answers `NOT_FOUND` for an unknown client
+/// - `StreamAccounts` streams an `AccountUpdate` for every account changed after the call, like `/updates` of the
+///   HTTP API, and ends when the subscriber falls behind
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    service: EngineService,
+}
+
+impl GrpcService {
+    pub fn new(service: EngineService) -> Self {
+        Self { service }
+    }
+}
+
+/// Serve `GrpcService` on `addr` until the process is stopped, on a runtime of its own
+pub fn serve(addr: impl ToSocketAddrs, service: EngineService) -> io::Result<()> {
+    let addr: SocketAddr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to serve on"))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime
+        .block_on(
+            tonic::transport::Server::builder()
+                .add_service(EngineServer::new(GrpcService::new(service)))
+                .serve(addr),
+        )
+        .map_err(io::Error::other)
+}
+
+fn client_id(client: u32) -> Result<ClientId, Status> {
+    ClientId::try_from(client)
+        .map_err(|_| Status::invalid_argument(format!("client {client} is out of range")))
+}
+
+fn decimal(amount: &str) -> Result<rust_decimal::Decimal, Status> {
+    amount
+        .parse()
+        .map_err(|err| Status::invalid_argument(format!("invalid amount {amount}: {err}")))
+}
+
+/// The transaction as an input row, so it goes through the same parsing as a csv row
+fn input_row(transaction: proto::Transaction) -> Result<CsvInputRow, Status> {
+    let transaction_type =
+        TransactionType::deserialize(transaction.r#type.as_str().into_deserializer())
+            .map_err(|err: ValueError| Status::invalid_argument(err.to_string()))?;
+    Ok(CsvInputRow {
+        transaction_type,
+        client: client_id(transaction.client)?,
+        tx: transaction.tx,
+        amount: transaction.amount.as_deref().map(decimal).transpose()?,
+        to: transaction.to.map(client_id).transpose()?,
+        timestamp: transaction.timestamp,
+        case_id: transaction.case_id,
+        interval: transaction.interval,
+        count: transaction.count,
+        reason: transaction.reason,
+    })
+}
+
+fn account(summary: AccountSummary) -> proto::Account {
+    proto::Account {
+        client: summary.client.into(),
+        available: summary.available.to_string(),
+        held: summary.held.to_string(),
+        total: summary.total.to_string(),
+        locked: summary.locked,
+    }
+}
+
+fn account_update(update: AccountUpdate) -> proto::AccountUpdate {
+    proto::AccountUpdate {
+        client: update.client.into(),
+        tx: update.tx,
+        available: update.available.to_string(),
+        held: update.held.to_string(),
+        locked: update.locked,
+    }
+}
+
+#[tonic::async_trait]
+impl Engine for GrpcService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let row = input_row(request.into_inner())?;
+        self.service
+            .submit_row(&row)
+            .map_err(|err| Status::failed_precondition(err.to_string()))?;
+        let summary = self
+            .service
+            .get_account(row.client)
+            .ok_or_else(|| Status::not_found("account not found"))?;
+        Ok(Response::new(account(summary)))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client = client_id(request.into_inner().client)?;
+        match self.service.get_account(client) {
+            Some(summary) => Ok(Response::new(account(summary))),
+            None => Err(Status::not_found("account not found")),
+        }
+    }
+
+    type StreamAccountsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::AccountUpdate, Status>> + Send>>;
+
+    /// The subscription is a blocking channel, so it is read on a blocking thread which stops with the call
+    async fn stream_accounts(
+        &self,
+        _request: Request<proto::StreamAccountsRequest>,
+    ) -> Result<Response<Self::StreamAccountsStream>, Status> {
+        let subscription = self.service.subscribe();
+        let (sender, updates) = mpsc::channel(UPDATE_QUEUE_SIZE);
+        tokio::task::spawn_blocking(move || {
+            for update in subscription {
+                if sender.blocking_send(Ok(account_update(update))).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(updates))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn transaction(
+        transaction_type: &str,
+        client: u32,
+        tx: u32,
+        amount: &str,
+    ) -> proto::Transaction {
+        proto::Transaction {
+            r#type: transaction_type.to_string(),
+            client,
+            tx,
+            amount: Some(amount.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_service() {
+        let grpc = GrpcService::new(EngineService::default());
+        let mut updates = grpc
+            .stream_accounts(Request::new(proto::StreamAccountsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let account = grpc
+            .submit_transaction(Request::new(transaction("deposit", 1, 1, "1.5")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(account.available, "1.5000");
+
+        let status = grpc
+            .submit_transaction(Request::new(transaction("withdrawal", 1, 2, "3")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = grpc
+            .submit_transaction(Request::new(transaction("refund", 1, 3, "3")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = grpc
+            .submit_transaction(Request::new(transaction("deposit", 70_000, 4, "3")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let account = grpc
+            .get_account(Request::new(proto::GetAccountRequest { client: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(account.total, "1.5000");
+        let status = grpc
+            .get_account(Request::new(proto::GetAccountRequest { client: 2 }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        // Only the accepted deposit changed an account
+        let update = updates.next().await.unwrap().unwrap();
+        assert_eq!((update.client, update.tx), (1, 1));
+        assert_eq!(update.available, "1.5000");
+    }
+}
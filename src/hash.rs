@@ -0,0 +1,14 @@
+/// The hasher of the maps keyed by client or transaction id: the accounts of `MemoryStore`, the transactions of an
+/// account and the index of the engine. It is the `RandomState` of std by default, `ahash` with the `ahash` feature
+/// and the `FxHasher` of `rustc-hash` with the `fx-hash` feature. The integer keys come from the input, so only the
+/// default one is resistant to an input crafted to collide, and the run can be slowed down by such an input with the
+/// others
+#[cfg(feature = "ahash")]
+pub type BuildHasher = ahash::RandomState;
+#[cfg(all(feature = "fx-hash", not(feature = "ahash")))]
+pub type BuildHasher = rustc_hash::FxBuildHasher;
+#[cfg(not(any(feature = "ahash", feature = "fx-hash")))]
+pub type BuildHasher = std::collections::hash_map::RandomState;
+
+pub type HashMap<K, V> = std::collections::HashMap<K, V, BuildHasher>;
+pub type HashSet<T> = std::collections::HashSet<T, BuildHasher>;
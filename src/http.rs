@@ -0,0 +1,201 @@
+use crate::service::EngineService;
+use crate::types::{ClientId, CsvInputRow};
+use axum::Json;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use serde::Serialize;
+use std::io;
+use std::net::ToSocketAddrs;
+use tokio::sync::mpsc;
+
+/// The largest body of a request, a larger one is rejected with 413
+pub const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// How many updates can be queued for a WebSocket client before the thread reading them waits
+const UPDATE_QUEUE_SIZE: usize = 64;
+
+/// A JSON API on top of `EngineService`, served by axum
+/// - `POST /transactions` with a json body like `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`
+/// - `GET /accounts/{client}`
+/// - `GET /accounts`
+/// - `GET /metrics` in the Prometheus text format
+/// - `GET /updates` upgraded to a WebSocket, it pushes an `AccountUpdate` as json for every changed account
+pub fn router(service: EngineService) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts", get(accounts))
+        .route("/accounts/{client}", get(account))
+        .route("/metrics", get(metrics))
+        .route("/updates", get(updates))
+        .fallback(|| async { error(StatusCode::NOT_FOUND, "not found") })
+        .method_not_allowed_fallback(|| async {
+            error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        })
+        .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+        .with_state(service)
+}
+
+/// Serve `router` on `addr` until the process is stopped, on a runtime of its own
+/// A failed connection is only logged by axum, the server keeps accepting the others
+pub fn serve(addr: impl ToSocketAddrs, service: EngineService) -> io::Result<()> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        axum::serve(listener, router(service)).await
+    })
+}
+
+fn error(status: StatusCode, message: impl ToString) -> Response {
+    #[derive(Serialize)]
+    struct Error {
+        error: String,
+    }
+    let error = Error {
+        error: message.to_string(),
+    };
+    (status, Json(error)).into_response()
+}
+
+async fn submit_transaction(State(service): State<EngineService>, body: Bytes) -> Response {
+    let row: CsvInputRow = match serde_json::from_slice(&body) {
+        Ok(row) => row,
+        Err(err) => return error(StatusCode::BAD_REQUEST, err),
+    };
+    match service.submit_row(&row) {
+        Ok(()) => Json(service.get_account(row.client)).into_response(),
+        Err(err) => error(StatusCode::UNPROCESSABLE_ENTITY, err),
+    }
+}
+
+async fn accounts(State(service): State<EngineService>) -> Response {
+    Json(service.accounts()).into_response()
+}
+
+async fn account(State(service): State<EngineService>, Path(client): Path<String>) -> Response {
+    match client.parse::<ClientId>() {
+        Err(err) => error(StatusCode::BAD_REQUEST, err),
+        Ok(client) => match service.get_account(client) {
+            Some(account) => Json(account).into_response(),
+            None => error(StatusCode::NOT_FOUND, "account not found"),
+        },
+    }
+}
+
+async fn metrics(State(service): State<EngineService>) -> Response {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        service.metrics(),
+    )
+        .into_response()
+}
+
+async fn updates(
+    State(service): State<EngineService>,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    match upgrade {
+        Ok(upgrade) => upgrade.on_upgrade(move |socket| push_updates(socket, service)),
+        Err(_) => error(StatusCode::BAD_REQUEST, "expected a websocket upgrade"),
+    }
+}
+
+/// Push the account updates to a WebSocket client until it goes away or falls too far behind
+/// The subscription is a blocking channel, so it is read on a blocking thread which stops with the client.
+/// A slow client fills the queues until the service drops its subscription, then the socket is closed
+async fn push_updates(mut socket: WebSocket, service: EngineService) {
+    let subscription = service.subscribe();
+    let (sender, mut updates) = mpsc::channel(UPDATE_QUEUE_SIZE);
+    tokio::task::spawn_blocking(move || {
+        for update in subscription {
+            if sender.blocking_send(update).is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(update) = updates.recv().await {
+        let json = serde_json::to_string(&update).expect("failed to serialize update");
+        if socket.send(Message::Text(json.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn request(router: &Router, method: &str, path: &str, body: Vec<u8>) -> (u16, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::from(body))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status().as_u16();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let router = router(EngineService::default());
+        let post = |body: &[u8]| request(&router, "POST", "/transactions", body.to_vec());
+
+        let body = br#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}"#;
+        assert_eq!(post(body).await.0, 200);
+
+        let body = br#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "3"}"#;
+        assert_eq!(post(body).await.0, 422);
+
+        assert_eq!(post(b"not json").await.0, 400);
+        // The body is never read beyond the limit
+        assert_eq!(post(&vec![b' '; MAX_BODY_SIZE + 1]).await.0, 413);
+
+        let (status, body) = request(&router, "GET", "/accounts/1", Vec::new()).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        );
+
+        let (status, body) = request(&router, "GET", "/accounts/2", Vec::new()).await;
+        assert_eq!(status, 404);
+        assert_eq!(body, r#"{"error":"account not found"}"#);
+        assert_eq!(
+            request(&router, "GET", "/accounts/x", Vec::new()).await.0,
+            400
+        );
+
+        let (status, body) = request(&router, "GET", "/accounts", Vec::new()).await;
+        assert_eq!(status, 200);
+        assert!(body.starts_with('['));
+
+        assert_eq!(
+            request(&router, "DELETE", "/accounts", Vec::new()).await.0,
+            405
+        );
+        assert_eq!(request(&router, "GET", "/unknown", Vec::new()).await.0, 404);
+        assert_eq!(request(&router, "GET", "/updates", Vec::new()).await.0, 400);
+
+        let (status, body) = request(&router, "GET", "/metrics", Vec::new()).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("engine_transactions_processed_total{type=\"deposit\"} 1\n"));
+        assert!(body.contains(
+            "engine_transactions_rejected_total{reason=\"insufficient_funds\",type=\"withdrawal\"} 1\n"
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::engine::Engine;
+use crate::output::AccountSummary;
+use crate::record::lossy;
+use crate::store::StateStore;
+use crate::types::{ClientId, CsvInputRow, RowProcessingError, TransactionType};
+use csv::StringRecord;
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+
+/// Writes the summary of an account while the input is still being processed
+/// A closed account is written right away since it can't change anymore, the other changed accounts
+/// are written every `flush_every` rows and at the end. An account can be written more than once,
+/// the last row of a client is its final state
+#[derive(Debug)]
+pub struct IncrementalWriter<W: Write> {
+    wtr: csv::Writer<W>,
+    flush_every: u64,
+    rows: u64,
+    changed: BTreeSet<ClientId>,
+}
+
+impl<W: Write> IncrementalWriter<W> {
+    pub fn new(writer: W, flush_every: u64) -> Self {
+        Self {
+            wtr: csv::Writer::from_writer(writer),
+            flush_every: flush_every.max(1),
+            rows: 0,
+            changed: BTreeSet::new(),
+        }
+    }
+
+    /// Record a processed row, `row` is None when it was rejected
+    pub fn record<S: StateStore>(
+        &mut self,
+        engine: &Engine<S>,
+        row: Option<&CsvInputRow>,
+    ) -> csv::Result<()> {
+        self.rows += 1;
+        if let Some(row) = row {
+            if row.transaction_type == TransactionType::CloseAccount {
+                self.changed.remove(&row.client);
+                self.write(engine.summary(row.client))?;
+                self.wtr.flush()?;
+            } else {
+                self.changed.insert(row.client);
+                self.changed
+                    .extend(row.to.filter(|to| engine.is_selected(*to)));
+            }
+        }
+        if self.rows.is_multiple_of(self.flush_every) {
+            self.flush(engine)?;
+        }
+        Ok(())
+    }
+
+    /// Write all accounts changed since the last flush
+    pub fn flush<S: StateStore>(&mut self, engine: &Engine<S>) -> csv::Result<()> {
+        for client in std::mem::take(&mut self.changed) {
+            self.write(engine.summary(client))?;
+        }
+        self.wtr.flush()?;
+        Ok(())
+    }
+
+    fn write(&mut self, summary: Option<AccountSummary>) -> csv::Result<()> {
+        match summary {
+            Some(summary) => self.wtr.serialize(summary),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Same as `process_csv_with_errors`, every accepted row is recorded in `output`
+    /// Call `IncrementalWriter::flush` after the last input to write the remaining changes
+    pub fn process_csv_incremental<W: Write, E: From<csv::Error>>(
+        &mut self,
+        input: impl Read,
+        output: &mut IncrementalWriter<W>,
+        mut on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        self.process_csv_rows(input, |engine, line, record, result| {
+            engine.report_rejected_occurrences(line, &mut on_error)?;
+            match result {
+                Ok(row) => Ok(output.record(engine, Some(row))?),
+                Err(err) => {
+                    on_error(line, &lossy(record), err)?;
+                    Ok(output.record(engine, None)?)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incremental_output() {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1\n\
+            deposit,2,2,2\n\
+            close_account,2,0,\n\
+            deposit,1,3,1\n\
+            deposit,3,4,1\n";
+        let mut engine = Engine::new();
+        let mut output = IncrementalWriter::new(Vec::new(), 4);
+        let rows = engine
+            .process_csv_incremental(input.as_bytes(), &mut output, |_, _, _| {
+                Ok::<(), csv::Error>(())
+            })
+            .unwrap();
+        assert_eq!(rows, 5);
+        output.flush(&engine).unwrap();
+        let out = String::from_utf8(output.wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            out,
+            "client,available,held,total,locked\n\
+            2,2.0000,0.0000,2.0000,false\n\
+            1,2.0000,0.0000,2.0000,false\n\
+            3,1.0000,0.0000,1.0000,false\n"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use crate::engine::Engine;
+use crate::store::StateStore;
+use crate::stream::MessageEncoding;
+use rdkafka::Message;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::KafkaError;
+use std::convert::Infallible;
+use std::io;
+use std::time::Duration;
+
+/// How long a poll waits for a message, the accounts are emitted when the topic stays idle for that long
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A consumer of a Kafka topic in a consumer group, the offsets are only committed after a checkpoint
+pub struct KafkaConsumer {
+    consumer: BaseConsumer,
+}
+
+impl KafkaConsumer {
+    /// Join `group` on the `brokers`, e.g. `localhost:9092`, and subscribe to `topic`
+    /// A new group starts at the earliest message of the topic, an existing one after its committed offsets
+    pub fn subscribe(brokers: &str, group: &str, topic: &str) -> Result<Self, KafkaError> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+        consumer.subscribe(&[topic])?;
+        Ok(Self { consumer })
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Apply the messages of a Kafka topic until the process is stopped or the consumer fails
+    /// `on_checkpoint` is called with the engine after every `every` messages, and when the topic is idle with
+    /// messages applied since the last call. The offsets are committed after it succeeds, so the messages after the
+    /// last checkpoint are consumed again after a restart. Rejected and malformed messages are ignored silently,
+    /// same as `consume_messages`
+    pub fn consume_kafka(
+        &mut self,
+        consumer: &KafkaConsumer,
+        encoding: MessageEncoding,
+        every: usize,
+        mut on_checkpoint: impl FnMut(&Self) -> io::Result<()>,
+    ) -> io::Result<Infallible> {
+        let every = every.max(1);
+        let mut pending = 0;
+        loop {
+            match consumer.consumer.poll(POLL_TIMEOUT) {
+                None if pending == 0 => continue,
+                None => {}
+                Some(message) => {
+                    let message = message.map_err(io::Error::other)?;
+                    self.consume_message(
+                        message
+                            .payload()
+                            .and_then(|payload| encoding.decode(payload)),
+                    );
+                    pending += 1;
+                    if pending < every {
+                        continue;
+                    }
+                }
+            }
+            on_checkpoint(self)?;
+            consumer
+                .consumer
+                .commit_consumer_state(CommitMode::Sync)
+                .map_err(io::Error::other)?;
+            pending = 0;
+        }
+    }
+}
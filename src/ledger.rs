@@ -0,0 +1,256 @@
+use crate::types::{
+    AccountProfile, ClientId, ReasonCode, Transaction, TransactionId, TransactionProcessingError,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One accepted transaction together with the balances of the account right after it
+/// A rejected transaction has the reason in `rejection` and the unchanged balances
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct LedgerEntry {
+    /// Milliseconds since the unix epoch
+    pub timestamp: u128,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: String,
+    pub amount: Option<Decimal>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+    /// Only set for a rejected transaction, see `Ledger::set_record_rejections`
+    #[serde(default)]
+    pub rejection: Option<String>,
+    /// The reason code of an adjustment or a flag
+    #[serde(default)]
+    pub reason: Option<ReasonCode>,
+}
+
+/// An append-only record of every accepted transaction, in the order they were applied
+/// Rejected transactions are only recorded after `set_record_rejections`, the accepted entries of a client explain its
+/// final balance
+#[derive(Debug, Default)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+    record_rejections: bool,
+}
+
+/// Milliseconds since the unix epoch, there is no clock on `wasm32-unknown-unknown` so it is always 0 there
+fn now_millis() -> u128 {
+    #[cfg(not(target_arch = "wasm32"))]
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    #[cfg(target_arch = "wasm32")]
+    return 0;
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the rejected transactions from now on too, e.g. to investigate the history of a client
+    pub fn set_record_rejections(&mut self, record_rejections: bool) {
+        self.record_rejections = record_rejections;
+    }
+
+    pub fn records_rejections(&self) -> bool {
+        self.record_rejections
+    }
+
+    /// Record `transaction` which was just applied to `profile`
+    pub fn record(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        transaction: &Transaction,
+        profile: &AccountProfile,
+    ) {
+        let timestamp = now_millis();
+        self.entries.push(LedgerEntry {
+            timestamp,
+            client,
+            tx,
+            transaction_type: transaction.type_name().to_string(),
+            amount: transaction.amount(),
+            available: profile.available,
+            held: profile.held,
+            locked: profile.is_frozen(),
+            rejection: None,
+            reason: transaction.reason(),
+        });
+    }
+
+    /// Record `transaction` which was rejected with `error`, `profile` is None for an unknown client
+    pub fn record_rejection(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        transaction: &Transaction,
+        error: &TransactionProcessingError,
+        profile: Option<&AccountProfile>,
+    ) {
+        self.entries.push(LedgerEntry {
+            timestamp: now_millis(),
+            client,
+            tx,
+            transaction_type: transaction.type_name().to_string(),
+            amount: transaction.amount(),
+            available: profile.map_or(Decimal::ZERO, |profile| profile.available),
+            held: profile.map_or(Decimal::ZERO, |profile| profile.held),
+            locked: profile.is_some_and(AccountProfile::is_frozen),
+            rejection: Some(error.to_string()),
+            reason: transaction.reason(),
+        });
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    /// Move the entries of `other` into this ledger, keeping all entries ordered by timestamp
+    pub fn merge(&mut self, other: Ledger) {
+        self.entries.extend(other.entries);
+        self.entries.sort_by_key(|entry| entry.timestamp);
+    }
+
+    /// Write all entries as csv with a header row
+    pub fn write_csv(&self, writer: impl Write) -> csv::Result<()> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        for entry in &self.entries {
+            wtr.serialize(entry)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_ledger() {
+        let mut engine = Engine::new();
+        engine.enable_ledger();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        assert!(
+            engine
+                .process_transaction(1, 2, Transaction::Withdrawal(Decimal::from(10)))
+                .is_err()
+        );
+        engine
+            .process_transaction(1, 2, Transaction::Transfer(2, Decimal::from(2)))
+            .unwrap_err();
+        engine
+            .process_transaction(1, 3, Transaction::Transfer(2, Decimal::from(2)))
+            .unwrap();
+        engine
+            .process_transaction(1, 4, Transaction::Deposit(Decimal::from(1)))
+            .unwrap();
+        engine
+            .process_transaction(1, 4, Transaction::Dispute(None))
+            .unwrap();
+
+        let entries = engine.ledger().unwrap().entries();
+        let summary = entries
+            .iter()
+            .map(|e| {
+                (
+                    e.client,
+                    e.tx,
+                    e.transaction_type.as_str(),
+                    e.available,
+                    e.held,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (1, 1, "deposit", Decimal::from(5), Decimal::from(0)),
+                (1, 3, "transfer", Decimal::from(3), Decimal::from(0)),
+                (2, 3, "transfer", Decimal::from(2), Decimal::from(0)),
+                (1, 4, "deposit", Decimal::from(4), Decimal::from(0)),
+                (1, 4, "dispute", Decimal::from(3), Decimal::from(1)),
+            ]
+        );
+
+        let mut out = Vec::new();
+        engine.ledger().unwrap().write_csv(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(
+            "timestamp,client,tx,type,amount,available,held,locked,rejection,reason\n"
+        ));
+        assert_eq!(out.lines().count(), 6);
+    }
+
+    #[test]
+    fn test_adjustment_reason() {
+        let mut engine = Engine::new();
+        engine.enable_ledger();
+        engine.process_csv(
+            "type,client,tx,amount,reason\n\
+            deposit,1,1,5,\n\
+            adjustment,1,2,-1.5,12\n\
+            flag,1,1,,3\n"
+                .as_bytes(),
+        );
+        let entries = engine.ledger().unwrap().entries();
+        let summary = entries
+            .iter()
+            .map(|e| (e.transaction_type.as_str(), e.amount, e.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("deposit", Some(Decimal::from(5)), None),
+                ("adjustment", Some(Decimal::new(-15, 1)), Some(12)),
+                ("flag", None, Some(3)),
+            ]
+        );
+        assert_eq!(engine.account(1).unwrap().available, Decimal::new(35, 1));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut engine = Engine::new();
+        engine.enable_ledger_with_rejections();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        engine
+            .process_transaction(2, 2, Transaction::Deposit(Decimal::from(1)))
+            .unwrap();
+        engine
+            .process_transaction(1, 3, Transaction::Withdrawal(Decimal::from(10)))
+            .unwrap_err();
+        engine
+            .process_transaction(1, 4, Transaction::Transfer(2, Decimal::from(2)))
+            .unwrap();
+
+        let history = engine.history(1);
+        let summary = history
+            .iter()
+            .map(|e| (e.tx, e.available, e.rejection.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (1, Decimal::from(5), false),
+                (3, Decimal::from(5), true),
+                (4, Decimal::from(3), false),
+            ]
+        );
+        assert_eq!(engine.history(2).len(), 2);
+        assert!(Engine::new().history(1).is_empty());
+    }
+}
@@ -1,2 +1,67 @@
+pub mod aging;
+pub mod amount;
+pub mod batch;
+#[cfg(feature = "fs")]
+pub mod checkpoint;
+pub mod compact;
+pub mod compression;
+pub mod config;
+pub mod diff;
+pub mod engine;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(any(feature = "fast-decimal", feature = "fixed-point"))]
+pub mod fixed;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hash;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod incremental;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod ledger;
+#[cfg(feature = "service")]
+pub mod metrics;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+pub mod observer;
+pub mod output;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod pipeline;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod query;
+pub mod record;
+#[cfg(feature = "arrow")]
+pub mod record_batch;
+pub mod replay;
+pub mod report;
+pub mod risk;
+pub mod schedule;
+#[cfg(feature = "service")]
+pub mod service;
+pub mod sink;
+#[cfg(feature = "fs")]
+pub mod snapshot;
+pub mod source;
+#[cfg(feature = "fs")]
+pub mod spill;
+pub mod stats;
+pub mod store;
+pub mod stream;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+pub mod tenant;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
 pub mod transaction;
 pub mod types;
+pub mod validate;
+#[cfg(feature = "fs")]
+pub mod wal;
+#[cfg(feature = "wasm")]
+pub mod wasm;
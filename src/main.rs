@@ -1,60 +1,905 @@
-use csv::ReaderBuilder;
-use rust_challenge::transaction::parse_transaction;
-use rust_challenge::types::{AccountProfile, ClientId, CsvInputRow};
-use std::collections::HashMap;
+mod cli;
+
+use cli::{ErrorReport, GenData, Kafka, Options, Query, USAGE, parse_args};
+use csv::StringRecord;
+use rust_challenge::checkpoint::{Checkpoints, DEFAULT_CHECKPOINT_ROWS, InputPosition};
+use rust_challenge::compression::{Compression, decompress};
+use rust_challenge::config::{DuplicateIdPolicy, InvariantChecks};
+use rust_challenge::diff::{diff_summaries, read_summaries, write_diffs};
+use rust_challenge::engine::Engine;
+use rust_challenge::incremental::IncrementalWriter;
+use rust_challenge::observer::EngineObserver;
+use rust_challenge::output::{AccountSummary, OutputFormat, write_summaries_as};
+use rust_challenge::replay::ReplayUntil;
+use rust_challenge::report::write_reports;
+use rust_challenge::risk::RiskRules;
+use rust_challenge::source::InputFormat;
+use rust_challenge::spill::{DEFAULT_RESIDENT_TRANSACTIONS, SpillStore};
+use rust_challenge::stats::RunStats;
+use rust_challenge::store::{StateStore, VecStore};
+use rust_challenge::stream::MessageEncoding;
+use rust_challenge::tenant::Tenants;
+use rust_challenge::types::{
+    ClientId, InvariantViolation, RowError, RowProcessingError, TransactionId,
+};
+use rust_challenge::validate::validate_csv;
+use std::collections::BTreeMap;
 use std::env;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
 
-/// Process the transactions inside csv file from `path` and mutate states in `accounts`
-fn process_csv(
-    accounts: &mut HashMap<ClientId, AccountProfile>,
-    path: &str,
-) -> Result<(), Box<dyn Error>> {
-    let file = File::open(path)?;
-    let mut rdr = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .flexible(true)
-        .from_reader(file);
-
-    // We will ignore all errors:
-    // 1. csv parsing for a row
-    // 2. transaction processing rejection (as instructed)
-    // Note that we will not print error message and ignore them silently
-    // We do this because we use stdout for the output, and we want to keep it clean
-    for row in rdr.deserialize::<CsvInputRow>().flatten() {
-        if let Ok(transaction) = parse_transaction(&row) {
-            _ = accounts
-                .entry(row.client)
-                .or_default()
-                .process_transaction(row.tx, transaction);
-        }
-    }
-    Ok(())
-}
-
-fn output_accounts(accounts: &HashMap<ClientId, AccountProfile>) {
-    println!("client,available,held,total,locked");
-    // This will output clients in arbitrary order, but it is fine as mentioned in the instructions
-    for (id, p) in accounts {
-        // Output with 4 digits after decimal point
-        println!(
-            "{},{:.4},{:.4},{:.4},{}",
-            id,
-            p.available,
-            p.held,
-            p.available + p.held,
-            p.frozen
+/// Print a warning to stderr for every transaction id reused across clients
+struct DuplicateIdWarnings;
+
+impl EngineObserver for DuplicateIdWarnings {
+    fn on_duplicate_id(&mut self, client: ClientId, tx: TransactionId, owner: ClientId) {
+        eprintln!(
+            "warning: transaction id {tx} of client {client} is already used by client {owner}"
+        );
+    }
+}
+
+/// Print every broken invariant to stderr for `--paranoid=log`
+struct InvariantWarnings;
+
+impl EngineObserver for InvariantWarnings {
+    fn on_invariant_violation(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        violation: &InvariantViolation,
+    ) {
+        eprintln!(
+            "error: invariant violated by client {client} after transaction {tx}: {violation}"
         );
     }
 }
 
+/// How many messages are consumed between two checkpoints by default
+const DEFAULT_CHECKPOINT_EVERY: usize = 1000;
+
+/// Where the rejected rows are reported, the statistics, the incremental output and the checkpoints of a run
+struct RunOutputs {
+    error_log: Option<Box<dyn Write + Send>>,
+    /// The rejected rows of `--reject-file` with their reason
+    rejects: Option<csv::Writer<File>>,
+    stats: RunStats,
+    incremental: Option<IncrementalWriter<io::Stdout>>,
+    checkpoints: Option<Checkpoints>,
+    /// Where the run starts in its inputs when it resumes a checkpoint or a snapshot with `--resume`
+    start: InputPosition,
+    /// The position after the last row read, it is saved with the snapshot
+    position: Option<InputPosition>,
+}
+
+/// An input of the run
+enum Input<'a> {
+    Stream(Box<dyn Read + 'a>),
+    /// A plain file which the run resumes in the middle of, so it seeks past the rows applied before,
+    /// or a parquet file which is read in place
+    Seekable(File),
+}
+
+/// An input given as a url, e.g. `s3://bucket/key`, rather than as a local file
+fn is_object_url(path: &str) -> bool {
+    path.contains("://")
+}
+
+/// Open an input file, or stream an object of `s3://`, `gs://` or `az://` with the `object-store` feature
+fn open_path(path: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    if !is_object_url(path) {
+        return Ok(Box::new(File::open(path)?));
+    }
+    #[cfg(feature = "object-store")]
+    {
+        use rust_challenge::object_store::ObjectUrl;
+        let url = ObjectUrl::parse(path).ok_or_else(|| {
+            format!("{path}: expected s3://bucket/key, gs://bucket/key or az://container/blob")
+        })?;
+        Ok(Box::new(url.open()?))
+    }
+    #[cfg(not(feature = "object-store"))]
+    Err(format!("{path}: object urls require the `object-store` feature").into())
+}
+
+/// Process the input at position `index` of the inputs
+fn process_input<S: StateStore>(
+    engine: &mut Engine<S>,
+    options: &Options,
+    outputs: &mut RunOutputs,
+    index: usize,
+    input: Input,
+) -> Result<(), Box<dyn Error>> {
+    if index < outputs.start.input {
+        return Ok(());
+    }
+    let start = outputs.start.start_of(index);
+    let RunOutputs {
+        error_log,
+        rejects,
+        stats,
+        incremental,
+        checkpoints,
+        position,
+        ..
+    } = outputs;
+    let on_error = |line, record: &StringRecord, err| {
+        report_error(options, error_log, rejects, stats, line, record, err)
+    };
+    *position = Some(start);
+    let on_position = |engine: &Engine<S>, at| -> Result<(), Box<dyn Error>> {
+        *position = Some(at);
+        if let Some(checkpoints) = checkpoints {
+            checkpoints.on_position(engine, start, at)?;
+        }
+        Ok(())
+    };
+    let rows = match input {
+        #[cfg(feature = "parquet")]
+        input if options.input_format == InputFormat::Parquet => {
+            process_parquet(engine, input, on_error)?
+        }
+        Input::Seekable(file) => {
+            engine.process_csv_seeked(BufReader::new(file), start, on_position, on_error)?
+        }
+        Input::Stream(input) => {
+            if let Some(shards) = options.shards {
+                engine.process_csv_sharded(input, shards);
+                return Ok(());
+            }
+            if options.parsers.is_some() || options.appliers.is_some() {
+                // The error crosses the applier thread, it is passed as its message
+                let mut on_error = on_error;
+                let on_error = |line, record: &StringRecord, err| {
+                    on_error(line, record, err).map_err(|err| err.to_string())
+                };
+                let parsers = options.parsers.unwrap_or(1);
+                match options.appliers {
+                    Some(appliers) => {
+                        engine.process_csv_partitioned(input, parsers, appliers, on_error)?
+                    }
+                    None => engine.process_csv_pipelined(input, parsers, on_error)?,
+                }
+            } else {
+                match incremental {
+                    Some(output) => engine.process_csv_incremental(input, output, on_error)?,
+                    None => engine.process_csv_resumed(input, start, on_position, on_error)?,
+                }
+            }
+        }
+    };
+    // The rows applied before the run resumed are not counted again
+    stats.rows_read += rows.saturating_sub(start.rows);
+    if let Some(rejects) = rejects {
+        rejects.flush()?;
+    }
+    Ok(())
+}
+
+/// Process a parquet input, a file is read in place and a stream is read into memory first
+/// since the footer of a parquet file is at its end
+#[cfg(feature = "parquet")]
+fn process_parquet<S: StateStore>(
+    engine: &mut Engine<S>,
+    input: Input,
+    on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), Box<dyn Error>>,
+) -> Result<u64, Box<dyn Error>> {
+    match input {
+        Input::Seekable(file) => engine.process_parquet_with_errors(file, on_error),
+        Input::Stream(mut input) => {
+            let mut bytes = Vec::new();
+            input.read_to_end(&mut bytes)?;
+            engine.process_parquet_with_errors(bytes::Bytes::from(bytes), on_error)
+        }
+    }
+}
+
+/// Report a rejected row according to the options, fails with `--strict`
+fn report_error(
+    options: &Options,
+    error_log: &mut Option<Box<dyn Write + Send>>,
+    rejects: &mut Option<csv::Writer<File>>,
+    stats: &mut RunStats,
+    line: u64,
+    record: &StringRecord,
+    err: RowProcessingError,
+) -> Result<(), Box<dyn Error>> {
+    stats.record_rejection(&err);
+    if let Some(log) = error_log {
+        let row = record.iter().collect::<Vec<_>>().join(",");
+        writeln!(log, "line {line}: {err}: {row}")?;
+    }
+    // The fields of the row followed by the reason, so the row can be fixed and sent again
+    if let Some(rejects) = rejects {
+        let reason = err.to_string();
+        rejects.write_record(record.iter().chain([reason.as_str()]))?;
+    }
+    // The message is printed with `Debug` by `main`, so it is returned as a plain string
+    if options.strict {
+        return Err(RowError { line, kind: err }.to_string().into());
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let path = &env::args()
-        .nth(1)
-        .ok_or("missing argument: path to input csv file")?;
-    let mut accounts: HashMap<ClientId, AccountProfile> = HashMap::new();
-    process_csv(&mut accounts, path)?;
-    output_accounts(&accounts);
+    let start = Instant::now();
+    #[cfg(feature = "trace")]
+    init_tracing();
+    let mut options = parse_args(env::args().skip(1))?;
+    if options.help {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    if let Some(path) = &options.risk_rules {
+        options.config.risk = Some(RiskRules::parse(&fs::read_to_string(path)?)?);
+    }
+    if let Some(gen_data) = &options.gen_data {
+        return generate_data(gen_data);
+    }
+    if let Some(query) = &options.query {
+        return query_account(query);
+    }
+    if let Some((left, right)) = &options.diff {
+        return diff(left, right);
+    }
+    if options.validate {
+        return validate(&options);
+    }
+    let mut outputs = RunOutputs {
+        error_log: match &options.report_errors {
+            None => None,
+            Some(ErrorReport::Stderr) => Some(Box::new(io::stderr())),
+            Some(ErrorReport::File(path)) => Some(Box::new(File::create(path)?)),
+        },
+        rejects: match &options.reject_file {
+            Some(path) => Some(
+                csv::WriterBuilder::new()
+                    .flexible(true)
+                    .from_writer(File::create(path)?),
+            ),
+            None => None,
+        },
+        stats: RunStats::default(),
+        incremental: options
+            .incremental
+            .map(|every| IncrementalWriter::new(io::stdout(), every)),
+        checkpoints: None,
+        start: InputPosition::default(),
+        position: None,
+    };
+    if let Some(until) = options.replay {
+        return replay(&options, until, &mut outputs);
+    }
+    if options.tenants {
+        return run_tenants(&options, &mut outputs, start);
+    }
+    if let Some(dir) = &options.spill_dir {
+        let capacity = options
+            .max_resident_transactions
+            .unwrap_or(DEFAULT_RESIDENT_TRANSACTIONS);
+        let mut engine = Engine::with_store(SpillStore::new(dir, capacity));
+        seed_opening_balances(&mut engine, &options)?;
+        let engine = process_inputs(engine, &options, &mut outputs)?;
+        let engine = finish(engine, &options, outputs.position)?;
+        return write_stats(outputs.stats, &engine, &options, start);
+    }
+    if options.dense_clients {
+        let engine: Engine<VecStore> = restore_engine(&options, &mut outputs)?;
+        let engine = process_inputs(engine, &options, &mut outputs)?;
+        let engine = finish(engine, &options, outputs.position)?;
+        return write_stats(outputs.stats, &engine, &options, start);
+    }
+    let engine: Engine = restore_engine(&options, &mut outputs)?;
+    let engine = process_inputs(engine, &options, &mut outputs)?;
+    if let Some(addr) = &options.serve_http {
+        return serve_http(addr, engine);
+    }
+    if let Some(addr) = &options.serve_grpc {
+        return serve_grpc(addr, engine);
+    }
+    if let Some(addr) = &options.listen {
+        return listen(addr, engine);
+    }
+    let engine = finish(engine, &options, outputs.position)?;
+    write_stats(outputs.stats, &engine, &options, start)
+}
+
+/// The engine to start from: the latest checkpoint, the snapshot, the recovered write-ahead log or a new one
+/// The position to resume the inputs from goes into `outputs`
+fn restore_engine<S: StateStore>(
+    options: &Options,
+    outputs: &mut RunOutputs,
+) -> Result<Engine<S>, Box<dyn Error>> {
+    let mut checkpoint = None;
+    if let Some(dir) = &options.checkpoint_dir {
+        let every = options
+            .checkpoint_every
+            .map_or(DEFAULT_CHECKPOINT_ROWS, |every| every as u64);
+        let checkpoints = Checkpoints::new(dir, every);
+        checkpoint = checkpoints.load()?;
+        outputs.checkpoints = Some(checkpoints);
+    }
+    // A checkpoint already contains the snapshot the interrupted run started from
+    let (engine, resume_at) = match (checkpoint, &options.load_snapshot, &options.wal) {
+        (Some((engine, position)), _, _) => (engine, Some(position)),
+        (None, Some(path), _) if options.resume => {
+            let (engine, position) = Engine::load_snapshot_at(path)?;
+            let position =
+                position.ok_or_else(|| format!("{path} has no input position to resume from"))?;
+            (engine, Some(position))
+        }
+        (None, Some(path), _) => (Engine::load_snapshot(path)?, None),
+        (None, _, Some(path)) => (Engine::recover_with(path, options.config.clone())?, None),
+        _ => {
+            let mut engine = Engine::default();
+            seed_opening_balances(&mut engine, options)?;
+            (engine, None)
+        }
+    };
+    outputs.start = resume_at.unwrap_or_default();
+    Ok(engine)
+}
+
+/// Write the spans of the `trace` feature to stderr as they close, with their fields and timings
+/// `RUST_LOG` filters them, e.g. `RUST_LOG=info` leaves out the span of every transaction
+#[cfg(feature = "trace")]
+fn init_tracing() {
+    use tracing_subscriber::EnvFilter;
+    use tracing_subscriber::fmt::format::FmtSpan;
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("rust_challenge=debug"));
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_ansi(io::IsTerminal::is_terminal(&io::stderr()))
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+}
+
+/// Start the accounts of a new engine from the balances of `--opening-balances`
+/// A resumed checkpoint already contains them
+fn seed_opening_balances<S: StateStore>(
+    engine: &mut Engine<S>,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = &options.opening_balances {
+        engine
+            .seed_opening_balances(BufReader::new(File::open(path)?))
+            .map_err(|err| format!("invalid opening balances {path}: {err}"))?;
+    }
+    Ok(())
+}
+
+/// Apply the options to `engine` and process all inputs
+fn process_inputs<S: StateStore>(
+    mut engine: Engine<S>,
+    options: &Options,
+    outputs: &mut RunOutputs,
+) -> Result<Engine<S>, Box<dyn Error>> {
+    engine.set_config(options.config.clone());
+    if options.config.duplicate_id_policy == DuplicateIdPolicy::Warn {
+        engine.add_observer(Box::new(DuplicateIdWarnings));
+    }
+    if options.config.invariant_checks == InvariantChecks::Log {
+        engine.add_observer(Box::new(InvariantWarnings));
+    }
+    if options.include_rejected {
+        engine.enable_ledger_with_rejections();
+    } else if options.audit_log.is_some()
+        || options.export_history.is_some()
+        || options.postgres_ledger_table.is_some()
+    {
+        engine.enable_ledger();
+    }
+    // Read from stdin when there is no path, so we can be used in a pipeline
+    if options.paths.is_empty()
+        && options.serve_http.is_none()
+        && options.serve_grpc.is_none()
+        && options.listen.is_none()
+        && options.consume.is_none()
+    {
+        let input = decompress(io::stdin().lock(), options.compression)?;
+        process_input(&mut engine, options, outputs, 0, Input::Stream(input))?;
+    }
+    // Multiple files are processed one after another in the order they are given
+    for (index, path) in options.paths.iter().enumerate() {
+        if path == "-" {
+            let input = decompress(io::stdin().lock(), options.compression)?;
+            process_input(&mut engine, options, outputs, index, Input::Stream(input))?;
+            continue;
+        }
+        let compression = options.compression.or(Compression::from_path(path));
+        let resumed = outputs.start.input == index && outputs.start.byte > 0;
+        if is_object_url(path) {
+            let input = decompress(open_path(path)?, compression)?;
+            process_input(&mut engine, options, outputs, index, Input::Stream(input))?;
+        } else if options.mmap {
+            let mapped = map_file(path)?;
+            let input = decompress(&*mapped, compression)?;
+            process_input(&mut engine, options, outputs, index, Input::Stream(input))?;
+        } else if (resumed || options.input_format != InputFormat::Csv) && compression.is_none() {
+            let input = Input::Seekable(File::open(path)?);
+            process_input(&mut engine, options, outputs, index, input)?;
+        } else {
+            let input = decompress(open_path(path)?, compression)?;
+            process_input(&mut engine, options, outputs, index, Input::Stream(input))?;
+        }
+    }
+    if let Some(output) = &mut outputs.incremental {
+        output.flush(&engine)?;
+    }
+    // The last checkpoint is after all inputs, so running the same command again doesn't apply anything twice
+    if let Some(checkpoints) = &outputs.checkpoints {
+        checkpoints.save(&engine, outputs.position.unwrap_or(outputs.start))?;
+    }
+    Ok(engine)
+}
+
+/// Print the state of an account of a snapshot as json
+fn query_account(query: &Query) -> Result<(), Box<dyn Error>> {
+    let engine: Engine = Engine::load_snapshot(&query.snapshot)?;
+    let account = engine
+        .query(query.client, query.recent)
+        .ok_or_else(|| format!("client {} is not in {}", query.client, query.snapshot))?;
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &account)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Write the accounts which differ between two runs to stdout, exits with status 1 when there is a difference
+fn diff(left: &str, right: &str) -> Result<(), Box<dyn Error>> {
+    let diffs = diff_summaries(&load_summaries(left)?, &load_summaries(right)?);
+    write_diffs(&diffs, io::stdout().lock())?;
+    if !diffs.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Write the schema report of the input as json to stdout or `--output`, exits with status 1 when it is not valid
+fn validate(options: &Options) -> Result<(), Box<dyn Error>> {
+    let input = match options.paths.first().filter(|path| *path != "-") {
+        Some(path) => decompress(
+            open_path(path)?,
+            options.compression.or(Compression::from_path(path)),
+        )?,
+        None => decompress(io::stdin().lock(), options.compression)?,
+    };
+    let report = validate_csv(input, &options.config)?;
+    let mut writer: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    serde_json::to_writer_pretty(&mut writer, &report)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    if !report.is_valid() {
+        std::process::exit(1);
+    }
     Ok(())
 }
+
+/// The summaries of a run from its csv output, or from a snapshot when the path ends with `.json`
+fn load_summaries(path: &str) -> Result<BTreeMap<ClientId, AccountSummary>, Box<dyn Error>> {
+    if path.ends_with(".json") {
+        let engine: Engine = Engine::load_snapshot(path)?;
+        return Ok(engine
+            .summaries()
+            .map(|summary| (summary.client, summary))
+            .collect());
+    }
+    Ok(read_summaries(File::open(path)?)?)
+}
+
+/// Process the input up to `until` and write the full state at that point as a snapshot
+fn replay(
+    options: &Options,
+    until: ReplayUntil,
+    outputs: &mut RunOutputs,
+) -> Result<(), Box<dyn Error>> {
+    let mut engine: Engine = match &options.load_snapshot {
+        Some(path) => Engine::load_snapshot(path)?,
+        None => Engine::new(),
+    };
+    seed_opening_balances(&mut engine, options)?;
+    engine.set_config(options.config.clone());
+    let input = match options.paths.first().filter(|path| *path != "-") {
+        Some(path) => decompress(
+            open_path(path)?,
+            options.compression.or(Compression::from_path(path)),
+        )?,
+        None => decompress(io::stdin().lock(), options.compression)?,
+    };
+    let RunOutputs {
+        error_log,
+        rejects,
+        stats,
+        ..
+    } = outputs;
+    let reached = engine.replay_csv(input, until, |line, record, err| {
+        report_error(options, error_log, rejects, stats, line, record, err)
+    })?;
+    if let Some(rejects) = rejects {
+        rejects.flush()?;
+    }
+    if !reached {
+        let until = match until {
+            ReplayUntil::Tx(tx) => format!("transaction {tx}"),
+            ReplayUntil::Line(line) => format!("line {line}"),
+        };
+        eprintln!("warning: {until} was not reached, the state is after the whole input");
+    }
+    match &options.output {
+        Some(path) => engine.save_snapshot(path)?,
+        None => {
+            let mut stdout = io::stdout().lock();
+            engine.write_snapshot(&mut stdout)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(())
+}
+
+/// Process the inputs with an engine per tenant, the outputs, snapshots and audit logs are per tenant too
+fn run_tenants(
+    options: &Options,
+    outputs: &mut RunOutputs,
+    start: Instant,
+) -> Result<(), Box<dyn Error>> {
+    let mut tenants = match &options.load_snapshot {
+        Some(dir) => Tenants::load_snapshots(dir, options.config.clone())?,
+        None => Tenants::new(options.config.clone()),
+    };
+    let duplicate_ids = options.config.duplicate_id_policy == DuplicateIdPolicy::Warn;
+    let invariants = options.config.invariant_checks == InvariantChecks::Log;
+    let include_rejected = options.include_rejected;
+    let ledger = options.audit_log.is_some() || options.export_history.is_some();
+    tenants.set_setup(move |engine| {
+        if duplicate_ids {
+            engine.add_observer(Box::new(DuplicateIdWarnings));
+        }
+        if invariants {
+            engine.add_observer(Box::new(InvariantWarnings));
+        }
+        if include_rejected {
+            engine.enable_ledger_with_rejections();
+        } else if ledger {
+            engine.enable_ledger();
+        }
+    });
+    let RunOutputs {
+        error_log,
+        rejects,
+        stats,
+        ..
+    } = outputs;
+    let mut on_error = |line, record: &StringRecord, err| {
+        report_error(options, error_log, rejects, stats, line, record, err)
+    };
+    let mut rows = 0;
+    if options.paths.is_empty() {
+        let input = decompress(io::stdin().lock(), options.compression)?;
+        rows += tenants.process_csv_with_errors(input, &mut on_error)?;
+    }
+    for path in &options.paths {
+        let input = match path.as_str() {
+            "-" => decompress(io::stdin().lock(), options.compression)?,
+            path => decompress(
+                open_path(path)?,
+                options.compression.or(Compression::from_path(path)),
+            )?,
+        };
+        rows += tenants.process_csv_with_errors(input, &mut on_error)?;
+    }
+    if let Some(rejects) = rejects {
+        rejects.flush()?;
+    }
+    stats.rows_read += rows;
+
+    write_tenant_outputs(&tenants, options)?;
+    for (_, engine) in tenants.iter() {
+        stats.add_accounts(engine);
+    }
+    stats.elapsed_ms = start.elapsed().as_millis();
+    save_stats(stats, options)
+}
+
+/// Write the snapshots, the audit logs and the accounts of every tenant
+fn write_tenant_outputs(tenants: &Tenants, options: &Options) -> Result<(), Box<dyn Error>> {
+    if options.dry_run {
+        eprintln!("dry run, nothing was persisted, the final balances would be:");
+        tenants.write_summaries(io::stderr().lock())?;
+        return Ok(());
+    }
+    if let Some(dir) = &options.save_snapshot {
+        tenants.save_snapshots(dir)?;
+    }
+    if let Some(dir) = &options.audit_log {
+        fs::create_dir_all(dir)?;
+        for (tenant, engine) in tenants.iter() {
+            if let Some(ledger) = engine.ledger() {
+                ledger.write_csv(File::create(Path::new(dir).join(format!("{tenant}.csv")))?)?;
+            }
+        }
+    }
+    match &options.output {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            for (tenant, engine) in tenants.iter() {
+                let path =
+                    Path::new(dir).join(format!("{tenant}.{}", options.output_format.extension()));
+                write_atomically(&path.to_string_lossy(), |writer| {
+                    write_accounts(engine, options, writer)
+                })?;
+            }
+        }
+        None => tenants.write_summaries(io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+/// Consume the message stream on stdin if requested, then write the outputs
+fn finish<S: StateStore>(
+    mut engine: Engine<S>,
+    options: &Options,
+    position: Option<InputPosition>,
+) -> Result<Engine<S>, Box<dyn Error>> {
+    let Some(encoding) = options.consume else {
+        write_outputs(&engine, options, position)?;
+        return Ok(engine);
+    };
+    // The outputs are written at every checkpoint, including the end of the stream
+    // The snapshots don't record a position, the messages can't be resumed from one
+    let every = options.checkpoint_every.unwrap_or(DEFAULT_CHECKPOINT_EVERY);
+    let on_checkpoint = |engine: &Engine<S>| {
+        write_outputs(engine, options, None).map_err(|err| io::Error::other(err.to_string()))
+    };
+    if let Some(kafka) = &options.kafka {
+        consume_kafka(&mut engine, kafka, encoding, every, on_checkpoint)?;
+    } else {
+        engine.consume_messages(io::stdin().lock(), encoding, every, on_checkpoint)?;
+    }
+    Ok(engine)
+}
+
+/// Consume the topic of `consume-kafka`, this never returns on success
+#[cfg(feature = "kafka")]
+fn consume_kafka<S: StateStore>(
+    engine: &mut Engine<S>,
+    kafka: &Kafka,
+    encoding: MessageEncoding,
+    every: usize,
+    on_checkpoint: impl FnMut(&Engine<S>) -> io::Result<()>,
+) -> Result<(), Box<dyn Error>> {
+    use rust_challenge::kafka::KafkaConsumer;
+    let consumer = KafkaConsumer::subscribe(&kafka.brokers, &kafka.group, &kafka.topic)?;
+    engine.consume_kafka(&consumer, encoding, every, on_checkpoint)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "kafka"))]
+fn consume_kafka<S: StateStore>(
+    _engine: &mut Engine<S>,
+    _kafka: &Kafka,
+    _encoding: MessageEncoding,
+    _every: usize,
+    _on_checkpoint: impl FnMut(&Engine<S>) -> io::Result<()>,
+) -> Result<(), Box<dyn Error>> {
+    Err("consume-kafka requires the `kafka` feature".into())
+}
+
+/// Write the statistics of the run as json to stderr or the stats file
+fn write_stats<S: StateStore>(
+    mut stats: RunStats,
+    engine: &Engine<S>,
+    options: &Options,
+    start: Instant,
+) -> Result<(), Box<dyn Error>> {
+    stats.finish(engine, start.elapsed());
+    save_stats(&stats, options)
+}
+
+/// Write the statistics of the run to `--stats-file`, or to stderr with `--stats`
+fn save_stats(stats: &RunStats, options: &Options) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = &options.stats_file {
+        serde_json::to_writer_pretty(File::create(path)?, stats)?;
+    } else if options.stats {
+        serde_json::to_writer_pretty(io::stderr(), stats)?;
+        eprintln!();
+    }
+    Ok(())
+}
+
+/// Write the snapshot with the input `position`, the audit log and the summaries of all accounts
+fn write_outputs<S: StateStore>(
+    engine: &Engine<S>,
+    options: &Options,
+    position: Option<InputPosition>,
+) -> Result<(), Box<dyn Error>> {
+    // The balances of a dry run are not authoritative, so they don't go to stdout
+    if options.dry_run {
+        eprintln!("dry run, nothing was persisted, the final balances would be:");
+        let format = if options.output_format.is_text() {
+            options.output_format
+        } else {
+            OutputFormat::Csv
+        };
+        write_summaries_as(engine.summaries(), format, io::stderr().lock())?;
+        return Ok(());
+    }
+    if let Some(path) = &options.save_snapshot {
+        engine.save_snapshot_at(path, position)?;
+    }
+    if let (Some(path), Some(ledger)) = (&options.audit_log, engine.ledger()) {
+        ledger.write_csv(File::create(path)?)?;
+    }
+    if let Some(url) = &options.postgres {
+        write_postgres(engine, url, options)?;
+    }
+    // The accounts of an incremental run are already written
+    match &options.output {
+        Some(path) => write_atomically(path, |writer| write_accounts(engine, options, writer))?,
+        None if options.incremental.is_none() => {
+            write_accounts(engine, options, io::stdout().lock())?
+        }
+        None => {}
+    }
+    // The total doesn't fit into the csv of the accounts, so it goes to stderr
+    if engine.config().fee_policy.is_some() {
+        eprintln!("total fees collected: {}", engine.total_fees());
+    }
+    Ok(())
+}
+
+/// Upsert the accounts, and append the ledger with `--postgres-ledger-table`, in a single transaction
+#[cfg(feature = "postgres")]
+fn write_postgres<S: StateStore>(
+    engine: &Engine<S>,
+    url: &str,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    use rust_challenge::postgres::PostgresSink;
+    use rust_challenge::sink::{DEFAULT_SQL_TABLE, write_to_sink};
+    let table = options
+        .postgres_table
+        .as_deref()
+        .unwrap_or(DEFAULT_SQL_TABLE);
+    let mut sink = PostgresSink::connect(url, table)?;
+    if let (Some(table), Some(ledger)) = (&options.postgres_ledger_table, engine.ledger()) {
+        sink.write_ledger(table, ledger.entries());
+    }
+    write_to_sink(engine.summaries(), &mut sink)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "postgres"))]
+fn write_postgres<S: StateStore>(
+    _engine: &Engine<S>,
+    _url: &str,
+    _options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    Err("--postgres requires the `postgres` feature".into())
+}
+
+/// Write the history of a client, the reports or the summaries of all accounts
+fn write_accounts<S: StateStore>(
+    engine: &Engine<S>,
+    options: &Options,
+    writer: impl Write,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(client) = options.export_history {
+        let mut wtr = csv::Writer::from_writer(writer);
+        for entry in engine.history(client) {
+            wtr.serialize(entry)?;
+        }
+        wtr.flush()?;
+        return Ok(());
+    }
+    match options.report {
+        Some(format) => write_reports(engine.reports(), format, writer)?,
+        None => write_summaries_as(engine.summaries(), options.output_format, writer)?,
+    }
+    Ok(())
+}
+
+/// Write to a temporary file next to `path` and rename it on success,
+/// so a reader of `path` never sees a partial output, also when it is rewritten at every checkpoint
+fn write_atomically(
+    path: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let tmp = format!("{path}.tmp");
+    let result = File::create(&tmp)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.into_inner()?.sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&tmp, path)?));
+    if result.is_err() {
+        _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Write a synthetic input for benchmarks
+#[cfg(feature = "testutil")]
+fn generate_data(gen_data: &GenData) -> Result<(), Box<dyn Error>> {
+    use rust_challenge::testutil::{GeneratorConfig, TransactionGenerator, write_csv};
+    let config = GeneratorConfig {
+        clients: gen_data.clients,
+        dispute_ratio: gen_data.dispute_ratio,
+        adversarial_ratio: gen_data.adversarial_ratio,
+        ..Default::default()
+    };
+    let generator = TransactionGenerator::new(gen_data.seed, config);
+    match &gen_data.output {
+        Some(path) => write_csv(
+            generator,
+            gen_data.rows,
+            BufWriter::new(File::create(path)?),
+        )?,
+        None => write_csv(generator, gen_data.rows, io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "testutil"))]
+fn generate_data(_gen_data: &GenData) -> Result<(), Box<dyn Error>> {
+    Err("gen-data requires the `testutil` feature".into())
+}
+
+#[cfg(feature = "mmap")]
+fn map_file(path: &str) -> Result<rust_challenge::mmap::Mmap, Box<dyn Error>> {
+    Ok(rust_challenge::mmap::map_file(path)?)
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map_file(_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err("--mmap requires the `mmap` feature".into())
+}
+
+/// Serve the engine over HTTP after the input files are processed, this never returns on success
+#[cfg(feature = "http")]
+fn serve_http(addr: &str, engine: Engine) -> Result<(), Box<dyn Error>> {
+    use rust_challenge::service::EngineService;
+    rust_challenge::http::serve(addr, EngineService::new(engine))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+fn serve_http(_addr: &str, _engine: Engine) -> Result<(), Box<dyn Error>> {
+    Err("--serve-http requires the `http` feature".into())
+}
+
+/// Serve the engine over gRPC after the input files are processed, this never returns on success
+#[cfg(feature = "grpc")]
+fn serve_grpc(addr: &str, engine: Engine) -> Result<(), Box<dyn Error>> {
+    use rust_challenge::service::EngineService;
+    rust_challenge::grpc::serve(addr, EngineService::new(engine))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc(_addr: &str, _engine: Engine) -> Result<(), Box<dyn Error>> {
+    Err("serve requires the `grpc` feature".into())
+}
+
+/// Accept transactions over the TCP line protocol after the input files are processed, this never returns on success
+#[cfg(feature = "tcp")]
+fn listen(addr: &str, engine: Engine) -> Result<(), Box<dyn Error>> {
+    use rust_challenge::service::EngineService;
+    rust_challenge::tcp::listen(addr, EngineService::new(engine))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "tcp"))]
+fn listen(_addr: &str, _engine: Engine) -> Result<(), Box<dyn Error>> {
+    Err("--listen requires the `tcp` feature".into())
+}
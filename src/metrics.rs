@@ -0,0 +1,177 @@
+use crate::output::AccountSummary;
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::fmt;
+use std::time::Duration;
+
+/// The upper bounds of the processing latency histogram in seconds
+const LATENCY_BUCKETS: [f64; 9] = [
+    0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1,
+];
+
+/// The counters and the latency histogram of the transactions submitted to an `EngineService`
+/// They live in a `prometheus` registry of their own, which `render` writes in the Prometheus text format
+pub struct ServiceMetrics {
+    registry: Registry,
+    processed: IntCounterVec,
+    /// Labeled by the type and the reason, see `RowProcessingError::reason`
+    rejected: IntCounterVec,
+    latency: Histogram,
+    available: Gauge,
+    held: Gauge,
+    accounts: IntGauge,
+}
+
+impl Default for ServiceMetrics {
+    fn default() -> Self {
+        let processed = IntCounterVec::new(
+            Opts::new(
+                "engine_transactions_processed_total",
+                "Transactions submitted to the engine",
+            ),
+            &["type"],
+        )
+        .expect("valid metric");
+        let rejected = IntCounterVec::new(
+            Opts::new(
+                "engine_transactions_rejected_total",
+                "Transactions rejected by the engine",
+            ),
+            &["type", "reason"],
+        )
+        .expect("valid metric");
+        let latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "engine_processing_seconds",
+                "Time to process a submitted transaction",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        )
+        .expect("valid metric");
+        let available = Gauge::new(
+            "engine_available_amount",
+            "Available funds across all accounts",
+        )
+        .expect("valid metric");
+        let held = Gauge::new("engine_held_amount", "Held funds across all accounts")
+            .expect("valid metric");
+        let accounts =
+            IntGauge::new("engine_accounts", "Accounts known to the engine").expect("valid metric");
+
+        let registry = Registry::new();
+        for metric in [
+            Box::new(processed.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(rejected.clone()),
+            Box::new(latency.clone()),
+            Box::new(available.clone()),
+            Box::new(held.clone()),
+            Box::new(accounts.clone()),
+        ] {
+            registry.register(metric).expect("metric registered twice");
+        }
+        Self {
+            registry,
+            processed,
+            rejected,
+            latency,
+            available,
+            held,
+            accounts,
+        }
+    }
+}
+
+impl fmt::Debug for ServiceMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceMetrics").finish_non_exhaustive()
+    }
+}
+
+impl ServiceMetrics {
+    /// Record a processed transaction, `rejection` is the reason if it was rejected
+    pub fn record(
+        &mut self,
+        transaction_type: &'static str,
+        rejection: Option<&'static str>,
+        elapsed: Duration,
+    ) {
+        self.processed.with_label_values(&[transaction_type]).inc();
+        if let Some(reason) = rejection {
+            self.rejected
+                .with_label_values(&[transaction_type, reason])
+                .inc();
+        }
+        self.latency.observe(elapsed.as_secs_f64());
+    }
+
+    /// The metrics in the Prometheus text format, the balance gauges are summed over `accounts`
+    /// The gauges are floating point, so a huge balance is rounded, and the sums saturate like `Engine::total_fees`
+    pub fn render(&self, accounts: &[AccountSummary]) -> String {
+        let available = accounts
+            .iter()
+            .map(|account| account.available)
+            .fold(Decimal::ZERO, Decimal::saturating_add);
+        let held = accounts
+            .iter()
+            .map(|account| account.held)
+            .fold(Decimal::ZERO, Decimal::saturating_add);
+        self.available.set(available.to_f64().unwrap_or(f64::NAN));
+        self.held.set(held.to_f64().unwrap_or(f64::NAN));
+        self.accounts.set(accounts.len() as i64);
+        let mut out = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut out)
+            .expect("failed to encode metrics");
+        String::from_utf8(out).expect("metrics are utf-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = ServiceMetrics::default();
+        metrics.record("deposit", None, Duration::from_micros(20));
+        metrics.record(
+            "withdrawal",
+            Some("insufficient_funds"),
+            Duration::from_secs(1),
+        );
+        let accounts = [AccountSummary {
+            client: 1,
+            available: Decimal::new(15, 1),
+            held: Decimal::ZERO,
+            total: Decimal::new(15, 1),
+            locked: false,
+            fees_collected: None,
+            open_disputes: None,
+            resolved_disputes: None,
+            chargebacks: None,
+            disputed_amount_held: None,
+        }];
+        let out = metrics.render(&accounts);
+        assert!(out.contains("engine_transactions_processed_total{type=\"deposit\"} 1\n"));
+        assert!(out.contains(
+            "engine_transactions_rejected_total{reason=\"insufficient_funds\",type=\"withdrawal\"} 1\n"
+        ));
+        assert!(out.contains("engine_available_amount 1.5\n"));
+        assert!(out.contains("engine_processing_seconds_bucket{le=\"0.00005\"} 1\n"));
+        assert!(out.contains("engine_processing_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(out.contains("engine_processing_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("engine_processing_seconds_count 2\n"));
+
+        // Two balances near the maximum don't overflow the sum
+        let huge = AccountSummary {
+            available: Decimal::MAX,
+            total: Decimal::MAX,
+            ..accounts[0].clone()
+        };
+        let out = metrics.render(&[huge.clone(), huge]);
+        assert!(out.contains("engine_available_amount 79228162514264340000000000000\n"));
+    }
+}
@@ -0,0 +1,40 @@
+pub use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Map the whole file at `path` read-only, the mapping derefs to the bytes of the file
+/// Parsing from the mapping avoids the read syscalls of a buffered reader on very large inputs
+/// The file must not be truncated while it is mapped, reading the missing pages raises SIGBUS on unix
+pub fn map_file(path: impl AsRef<Path>) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is read-only and we only parse it, the file is not expected to change during the run
+    let mmap = unsafe { Mmap::map(&file)? };
+    // The input is parsed front to back, the hint only affects read-ahead so its result is ignored
+    #[cfg(unix)]
+    let _ = mmap.advise(memmap2::Advice::Sequential);
+    Ok(mmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use rust_decimal::Decimal;
+    use std::fs;
+
+    #[test]
+    fn test_mmap() {
+        let path = std::env::temp_dir().join(format!("mmap-test-{}.csv", std::process::id()));
+        fs::write(&path, "type,client,tx,amount\ndeposit,1,1,2.5\n").unwrap();
+        let mmap = map_file(&path).unwrap();
+        let mut engine = Engine::new();
+        assert_eq!(engine.process_csv_bytes(&mmap), 1);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::new(25, 1));
+        drop(mmap);
+
+        fs::write(&path, "").unwrap();
+        assert!(map_file(&path).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use bytes::Bytes;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use object_store::ObjectStore as Store;
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// The cloud storage of an object url
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ObjectStore {
+    /// `s3://bucket/key`
+    S3,
+    /// `gs://bucket/key`
+    Gcs,
+    /// `az://container/blob`, the storage account is `AZURE_STORAGE_ACCOUNT_NAME`
+    Azure,
+}
+
+/// An object in a cloud storage, it is streamed with the client of `object_store`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ObjectUrl {
+    pub store: ObjectStore,
+    pub bucket: String,
+    pub key: String,
+}
+
+impl ObjectUrl {
+    /// None if `url` has another scheme, or no bucket or key
+    pub fn parse(url: &str) -> Option<Self> {
+        let (scheme, path) = url.split_once("://")?;
+        let store = match scheme {
+            "s3" => ObjectStore::S3,
+            "gs" => ObjectStore::Gcs,
+            "az" => ObjectStore::Azure,
+            _ => return None,
+        };
+        let (bucket, key) = path.split_once('/')?;
+        if bucket.is_empty() || key.is_empty() {
+            return None;
+        }
+        Some(Self {
+            store,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    /// The client of the storage for the bucket, configured from the environment like the SDK of the storage,
+    /// e.g. `AWS_ACCESS_KEY_ID`, `GOOGLE_SERVICE_ACCOUNT` or `AZURE_STORAGE_ACCOUNT_NAME`
+    pub fn store(&self) -> object_store::Result<Arc<dyn Store>> {
+        Ok(match self.store {
+            ObjectStore::S3 => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(&self.bucket)
+                    .build()?,
+            ),
+            ObjectStore::Gcs => Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(&self.bucket)
+                    .build()?,
+            ),
+            ObjectStore::Azure => Arc::new(
+                MicrosoftAzureBuilder::from_env()
+                    .with_container_name(&self.bucket)
+                    .build()?,
+            ),
+        })
+    }
+
+    /// Start streaming the object, nothing is written to disk
+    pub fn open(&self) -> io::Result<ObjectReader> {
+        ObjectReader::new(self.store().map_err(io::Error::other)?, &self.key)
+    }
+}
+
+impl fmt::Display for ObjectUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.store {
+            ObjectStore::S3 => "s3",
+            ObjectStore::Gcs => "gs",
+            ObjectStore::Azure => "az",
+        };
+        write!(f, "{scheme}://{}/{}", self.bucket, self.key)
+    }
+}
+
+/// The content of an object, read chunk by chunk from the stream of the `GetResult`
+/// The requests run on a runtime of the reader, so it can be used like any other input
+pub struct ObjectReader {
+    runtime: Runtime,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    /// Start streaming the object at `key` of `store`
+    pub fn new(store: Arc<dyn Store>, key: &str) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let result = runtime
+            .block_on(store.get(&Path::from(key)))
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            runtime,
+            stream: result.into_stream(),
+            chunk: Bytes::new(),
+        })
+    }
+}
+
+impl Read for ObjectReader {
+    /// A failed download is an error, so it is never mistaken for a short input
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(chunk) => self.chunk = chunk.map_err(io::Error::other)?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_object_url() {
+        let url = ObjectUrl::parse("s3://bank/2024/01/input.csv.gz").unwrap();
+        assert_eq!(url.store, ObjectStore::S3);
+        assert_eq!(
+            (url.bucket.as_str(), url.key.as_str()),
+            ("bank", "2024/01/input.csv.gz")
+        );
+        assert_eq!(url.to_string(), "s3://bank/2024/01/input.csv.gz");
+        assert_eq!(
+            ObjectUrl::parse("az://inputs/a.csv").unwrap().store,
+            ObjectStore::Azure
+        );
+        assert_eq!(ObjectUrl::parse("gs://bank"), None);
+        assert_eq!(ObjectUrl::parse("gs:///a.csv"), None);
+        assert_eq!(ObjectUrl::parse("ftp://bank/a.csv"), None);
+        assert_eq!(ObjectUrl::parse("input.csv"), None);
+    }
+
+    #[test]
+    fn test_object_reader() {
+        let store = Arc::new(object_store::memory::InMemory::new());
+        let content = "type,client,tx,amount\ndeposit,1,1,1.5\n".repeat(1000);
+        let runtime = Runtime::new().unwrap();
+        runtime
+            .block_on(store.put(&Path::from("a/input.csv"), content.clone().into()))
+            .unwrap();
+
+        let mut read = String::new();
+        ObjectReader::new(store.clone(), "a/input.csv")
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, content);
+        assert!(ObjectReader::new(store, "a/missing.csv").is_err());
+    }
+}
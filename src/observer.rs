@@ -0,0 +1,181 @@
+use crate::types::{ClientId, InvariantViolation, ReasonCode, Transaction, TransactionId};
+use rust_decimal::Decimal;
+use std::fmt;
+
+/// Callbacks on the state changes of accepted transactions, e.g. to alert the fraud team when an account is frozen
+/// All methods do nothing by default, so an observer only implements the events it cares about
+/// Observers are called on the thread processing the transaction, an engine with observers processes the rows of
+/// `process_csv_sharded` serially
+pub trait EngineObserver: Send {
+    fn on_deposit(&mut self, _client: ClientId, _tx: TransactionId, _amount: Decimal) {}
+
+    fn on_withdrawal(&mut self, _client: ClientId, _tx: TransactionId, _amount: Decimal) {}
+
+    fn on_transfer(
+        &mut self,
+        _from: ClientId,
+        _to: ClientId,
+        _tx: TransactionId,
+        _amount: Decimal,
+    ) {
+    }
+
+    fn on_dispute_opened(&mut self, _client: ClientId, _tx: TransactionId) {}
+
+    fn on_dispute_resolved(&mut self, _client: ClientId, _tx: TransactionId) {}
+
+    fn on_chargeback(&mut self, _client: ClientId, _tx: TransactionId) {}
+
+    /// The account is unfrozen too with `EngineConfig::unfreeze_on_reversal`, `on_account_unfrozen` is not called
+    fn on_chargeback_reversed(&mut self, _client: ClientId, _tx: TransactionId) {}
+
+    fn on_account_frozen(&mut self, _client: ClientId) {}
+
+    fn on_account_unfrozen(&mut self, _client: ClientId) {}
+
+    fn on_account_opened(&mut self, _client: ClientId) {}
+
+    fn on_account_closed(&mut self, _client: ClientId) {}
+
+    /// An operator corrected available by the signed `amount`
+    fn on_adjustment(
+        &mut self,
+        _client: ClientId,
+        _tx: TransactionId,
+        _amount: Decimal,
+        _reason: ReasonCode,
+    ) {
+    }
+
+    fn on_account_flagged(&mut self, _client: ClientId, _tx: TransactionId, _reason: ReasonCode) {}
+
+    /// A transaction of `client` was accepted with the id of a transaction of `owner`, see `DuplicateIdPolicy::Warn`
+    fn on_duplicate_id(&mut self, _client: ClientId, _tx: TransactionId, _owner: ClientId) {}
+
+    /// The account of `client` broke an invariant after transaction `tx`, see `InvariantChecks::Log`
+    fn on_invariant_violation(
+        &mut self,
+        _client: ClientId,
+        _tx: TransactionId,
+        _violation: &InvariantViolation,
+    ) {
+    }
+}
+
+/// The observers registered on an engine
+#[derive(Default)]
+pub(crate) struct Observers(Vec<Box<dyn EngineObserver>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Box<dyn EngineObserver>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn notify_duplicate_id(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        owner: ClientId,
+    ) {
+        for observer in &mut self.0 {
+            observer.on_duplicate_id(client, tx, owner);
+        }
+    }
+
+    pub(crate) fn notify_invariant_violation(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        violation: &InvariantViolation,
+    ) {
+        for observer in &mut self.0 {
+            observer.on_invariant_violation(client, tx, violation);
+        }
+    }
+
+    /// Notify all observers about an accepted transaction, `frozen` is whether the account is frozen after it
+    pub(crate) fn notify(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        transaction: &Transaction,
+        frozen: bool,
+    ) {
+        for observer in &mut self.0 {
+            match *transaction {
+                Transaction::Deposit(amount) => observer.on_deposit(client, tx, amount),
+                Transaction::Withdrawal(amount) => observer.on_withdrawal(client, tx, amount),
+                Transaction::Transfer(to, amount) => observer.on_transfer(client, to, tx, amount),
+                Transaction::Dispute(_) | Transaction::PartialDispute(..) => {
+                    observer.on_dispute_opened(client, tx)
+                }
+                Transaction::Resolve(_) => observer.on_dispute_resolved(client, tx),
+                Transaction::Chargeback(_) => {
+                    observer.on_chargeback(client, tx);
+                    // A chargeback is only accepted by an open account, see `FreezeOnChargeback`
+                    if frozen {
+                        observer.on_account_frozen(client);
+                    }
+                }
+                Transaction::ChargebackReversal(_) => observer.on_chargeback_reversed(client, tx),
+                Transaction::Unlock => observer.on_account_unfrozen(client),
+                Transaction::OpenAccount => observer.on_account_opened(client),
+                Transaction::CloseAccount => observer.on_account_closed(client),
+                Transaction::Adjustment(amount, reason) => {
+                    observer.on_adjustment(client, tx, amount, reason)
+                }
+                Transaction::Flag(reason) => observer.on_account_flagged(client, tx, reason),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct FrozenAlerts(Arc<Mutex<Vec<ClientId>>>);
+
+    impl EngineObserver for FrozenAlerts {
+        fn on_account_frozen(&mut self, client: ClientId) {
+            self.0.lock().unwrap().push(client);
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(FrozenAlerts(alerts.clone())));
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        engine
+            .process_transaction(1, 1, Transaction::Dispute(None))
+            .unwrap();
+        // Rejected transactions are not observed
+        assert!(
+            engine
+                .process_transaction(2, 1, Transaction::Chargeback(None))
+                .is_err()
+        );
+        assert!(alerts.lock().unwrap().is_empty());
+        engine
+            .process_transaction(1, 1, Transaction::Chargeback(None))
+            .unwrap();
+        assert_eq!(*alerts.lock().unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::config::PrecisionPolicy;
+use crate::sink::{
+    CsvSink, DEFAULT_SQL_TABLE, JsonSink, JsonlSink, OutputSink, SqlSink, write_to_sink,
+};
+use crate::types::{AccountProfile, ClientId, DisputeState, OutputError};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Number of digits after decimal point in the output
+pub const OUTPUT_SCALE: u32 = 4;
+
+/// The format of the summaries in the output
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A single array of accounts
+    Json,
+    /// One account per line
+    Jsonl,
+    /// Statements which upsert the accounts into the `accounts` table, see `SqlSink`
+    Sql,
+    /// A parquet file, see `ParquetSink`
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "sql" => Ok(OutputFormat::Sql),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("a parquet output requires the `parquet` feature".to_string()),
+            _ => Err(format!(
+                "invalid output format {s}, expected one of csv, json, jsonl, sql"
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// The file extension of an output in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Sql => "sql",
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+
+    /// Whether the output is text which can be written to a terminal, e.g. the balances of a dry run on stderr
+    pub fn is_text(&self) -> bool {
+        match self {
+            OutputFormat::Csv | OutputFormat::Json | OutputFormat::Jsonl | OutputFormat::Sql => {
+                true
+            }
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => false,
+        }
+    }
+
+    /// The sink which writes the summaries into `writer` in this format
+    pub fn sink<'a>(&self, writer: impl Write + 'a) -> Box<dyn OutputSink + 'a> {
+        match self {
+            OutputFormat::Csv => Box::new(CsvSink::new(writer)),
+            OutputFormat::Json => Box::new(JsonSink::new(writer)),
+            OutputFormat::Jsonl => Box::new(JsonlSink::new(writer)),
+            OutputFormat::Sql => Box::new(SqlSink::new(writer, DEFAULT_SQL_TABLE)),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => Box::new(crate::parquet::ParquetSink::new(writer)),
+        }
+    }
+}
+
+/// The output record for a single client
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct AccountSummary {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// Only in the output when a fee policy is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fees_collected: Option<Decimal>,
+    /// The dispute statistics are only in the output with `EngineConfig::extended_output`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_disputes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_disputes: Option<u32>,
+    /// The chargebacks including the reversed ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chargebacks: Option<u32>,
+    /// The part of held which is held by the open disputes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disputed_amount_held: Option<Decimal>,
+}
+
+impl AccountSummary {
+    /// The summary with `OUTPUT_SCALE` decimal places
+    pub fn new(client: ClientId, profile: &AccountProfile) -> Self {
+        Self::with_precision(client, profile, None)
+    }
+
+    /// The summary rounded with `precision`, or with `OUTPUT_SCALE` decimal places when it is None
+    pub fn with_precision(
+        client: ClientId,
+        profile: &AccountProfile,
+        precision: Option<&PrecisionPolicy>,
+    ) -> Self {
+        Self {
+            client,
+            available: rescale(profile.available, precision),
+            held: rescale(profile.held, precision),
+            total: rescale(profile.total(), precision),
+            locked: profile.is_frozen(),
+            fees_collected: None,
+            open_disputes: None,
+            resolved_disputes: None,
+            chargebacks: None,
+            disputed_amount_held: None,
+        }
+    }
+
+    /// Add the fees collected from the client to the summary
+    pub fn with_fees(self, profile: &AccountProfile, precision: Option<&PrecisionPolicy>) -> Self {
+        Self {
+            fees_collected: Some(rescale(profile.fees_collected, precision)),
+            ..self
+        }
+    }
+
+    /// Add the dispute statistics of the client to the summary, from the cases of the transactions it still keeps
+    pub fn with_dispute_stats(
+        self,
+        profile: &AccountProfile,
+        precision: Option<&PrecisionPolicy>,
+    ) -> Self {
+        let (mut open, mut resolved, mut chargebacks) = (0u32, 0u32, 0u32);
+        let mut amount_held = Decimal::ZERO;
+        for case in profile
+            .transactions
+            .values()
+            .flat_map(|record| record.cases.iter())
+        {
+            match case.state {
+                DisputeState::Open => {
+                    open += 1;
+                    amount_held = amount_held.saturating_add(case.amount);
+                }
+                DisputeState::Resolved => resolved += 1,
+                DisputeState::Chargeback | DisputeState::Reversed => chargebacks += 1,
+            }
+        }
+        Self {
+            open_disputes: Some(open),
+            resolved_disputes: Some(resolved),
+            chargebacks: Some(chargebacks),
+            disputed_amount_held: Some(rescale(amount_held, precision)),
+            ..self
+        }
+    }
+}
+
+/// Round `amount` with `precision`, or pad it to `OUTPUT_SCALE` decimal places when it is None
+pub(crate) fn rescale(mut amount: Decimal, precision: Option<&PrecisionPolicy>) -> Decimal {
+    match precision {
+        Some(precision) => precision.rescale(amount),
+        None => {
+            amount.rescale(OUTPUT_SCALE);
+            amount
+        }
+    }
+}
+
+/// Write the summary of all `accounts` as csv into `writer`
+/// This will output clients in arbitrary order, but it is fine as mentioned in the instructions
+pub fn write_accounts<'a>(
+    accounts: impl IntoIterator<Item = (ClientId, &'a AccountProfile)>,
+    writer: impl Write,
+) -> Result<(), OutputError> {
+    write_summaries(
+        accounts
+            .into_iter()
+            .map(|(client, profile)| AccountSummary::new(client, profile)),
+        writer,
+    )
+}
+
+/// Write the `summaries` as csv into `writer`
+pub fn write_summaries(
+    summaries: impl IntoIterator<Item = AccountSummary>,
+    writer: impl Write,
+) -> Result<(), OutputError> {
+    write_to_sink(summaries, &mut CsvSink::new(writer))
+}
+
+/// Write the `summaries` into `writer` in `format`, the amounts are strings in json so they keep their scale
+pub fn write_summaries_as(
+    summaries: impl IntoIterator<Item = AccountSummary>,
+    format: OutputFormat,
+    writer: impl Write,
+) -> Result<(), OutputError> {
+    write_to_sink(summaries, format.sink(writer).as_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AccountStatus;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_write_accounts() {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            1,
+            AccountProfile {
+                available: Decimal::new(15, 1),
+                held: Decimal::from(2),
+                status: AccountStatus::Frozen,
+                ..Default::default()
+            },
+        );
+        let mut output = vec![];
+        write_accounts(accounts.iter().map(|(c, p)| (*c, p)), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.5000,2.0000,3.5000,true\n"
+        );
+    }
+
+    #[test]
+    fn test_write_summaries_as() {
+        let summary = || AccountSummary {
+            client: 1,
+            available: Decimal::new(15000, 4),
+            held: Decimal::ZERO,
+            total: Decimal::new(15000, 4),
+            locked: false,
+            fees_collected: None,
+            open_disputes: None,
+            resolved_disputes: None,
+            chargebacks: None,
+            disputed_amount_held: None,
+        };
+        let mut output = vec![];
+        write_summaries_as([summary()], OutputFormat::Json, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"client\":1,\"available\":\"1.5000\",\"held\":\"0\",\"total\":\"1.5000\",\"locked\":false}]\n"
+        );
+
+        let mut output = vec![];
+        write_summaries_as([summary(), summary()], OutputFormat::Jsonl, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 2);
+    }
+}
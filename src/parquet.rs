@@ -0,0 +1,280 @@
+use crate::engine::Engine;
+use crate::output::AccountSummary;
+use crate::sink::OutputSink;
+use crate::store::StateStore;
+use crate::types::{ClientId, OutputError, RowProcessingError};
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::basic::Compression;
+use ::parquet::errors::ParquetError;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::reader::ChunkReader;
+use arrow::array::{ArrayRef, BooleanArray, Decimal128Array, UInt16Array, UInt32Array};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::sync::Arc;
+
+/// The fields of the row at `i` of `batch` as text, to report a rejected row like a csv record
+fn row_record(batch: &RecordBatch, i: usize) -> StringRecord {
+    let options = FormatOptions::default();
+    batch
+        .columns()
+        .iter()
+        .map(|column| {
+            ArrayFormatter::try_new(column, &options).map_or_else(
+                |_| String::new(),
+                |formatter| formatter.value(i).to_string(),
+            )
+        })
+        .collect()
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Process the transactions of a parquet file with the columns of the csv input, see `process_record_batch`
+    /// `on_error` is called for every rejected row with its row number, starting at 1, the fields of the row
+    /// and the reason. When `on_error` returns an error we stop processing and return it
+    /// Returns the number of rows read, including the rejected ones
+    pub fn process_parquet_with_errors<E: From<ParquetError> + From<ArrowError>>(
+        &mut self,
+        input: impl ChunkReader + 'static,
+        mut on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(input)?.build()?;
+        let mut rows = 0;
+        for batch in reader {
+            let batch = batch?;
+            self.process_record_batch_with_errors(&batch, |i, err| {
+                on_error(rows + i as u64 + 1, &row_record(&batch, i), err)
+            })?;
+            rows += batch.num_rows() as u64;
+        }
+        Ok(rows)
+    }
+}
+
+/// The summaries as a parquet file, the amounts are `Decimal128` columns with the scale of the summaries
+/// A parquet file is written at once, so the accounts are collected and written by `finish`.
+/// The optional columns of the summaries are only in the file when an account has them
+pub struct ParquetSink<W: Write> {
+    writer: W,
+    clients: Vec<ClientId>,
+    available: Vec<Decimal>,
+    held: Vec<Decimal>,
+    total: Vec<Decimal>,
+    locked: Vec<bool>,
+    fees_collected: Vec<Option<Decimal>>,
+    open_disputes: Vec<Option<u32>>,
+    resolved_disputes: Vec<Option<u32>>,
+    chargebacks: Vec<Option<u32>>,
+    disputed_amount_held: Vec<Option<Decimal>>,
+}
+
+impl<W: Write> ParquetSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            clients: Vec::new(),
+            available: Vec::new(),
+            held: Vec::new(),
+            total: Vec::new(),
+            locked: Vec::new(),
+            fees_collected: Vec::new(),
+            open_disputes: Vec::new(),
+            resolved_disputes: Vec::new(),
+            chargebacks: Vec::new(),
+            disputed_amount_held: Vec::new(),
+        }
+    }
+
+    /// The collected summaries as a single batch
+    fn record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        // The summaries are rounded to the same scale, the largest one is kept if they are not
+        let scale = (self.available.iter())
+            .chain(&self.held)
+            .chain(&self.total)
+            .chain(self.fees_collected.iter().flatten())
+            .chain(self.disputed_amount_held.iter().flatten())
+            .map(Decimal::scale)
+            .max()
+            .unwrap_or(0);
+        let decimals = |amounts: &mut dyn Iterator<Item = Option<Decimal>>| {
+            let array: Decimal128Array = amounts
+                .map(|amount| {
+                    amount.map(|mut amount| {
+                        amount.rescale(scale);
+                        amount.mantissa()
+                    })
+                })
+                .collect();
+            array
+                .with_precision_and_scale(38, scale as i8)
+                .map(|array| Arc::new(array) as ArrayRef)
+        };
+        let mut columns = vec![
+            (
+                "client",
+                Arc::new(UInt16Array::from(self.clients.clone())) as ArrayRef,
+            ),
+            (
+                "available",
+                decimals(&mut self.available.iter().copied().map(Some))?,
+            ),
+            ("held", decimals(&mut self.held.iter().copied().map(Some))?),
+            (
+                "total",
+                decimals(&mut self.total.iter().copied().map(Some))?,
+            ),
+            ("locked", Arc::new(BooleanArray::from(self.locked.clone()))),
+        ];
+        if self.fees_collected.iter().any(Option::is_some) {
+            columns.push((
+                "fees_collected",
+                decimals(&mut self.fees_collected.iter().copied())?,
+            ));
+        }
+        for (name, counts) in [
+            ("open_disputes", &self.open_disputes),
+            ("resolved_disputes", &self.resolved_disputes),
+            ("chargebacks", &self.chargebacks),
+        ] {
+            if counts.iter().any(Option::is_some) {
+                columns.push((name, Arc::new(UInt32Array::from(counts.clone()))));
+            }
+        }
+        if self.disputed_amount_held.iter().any(Option::is_some) {
+            columns.push((
+                "disputed_amount_held",
+                decimals(&mut self.disputed_amount_held.iter().copied())?,
+            ));
+        }
+        RecordBatch::try_from_iter(columns)
+    }
+}
+
+impl<W: Write> OutputSink for ParquetSink<W> {
+    fn write_account(&mut self, summary: &AccountSummary) -> Result<(), OutputError> {
+        self.clients.push(summary.client);
+        self.available.push(summary.available);
+        self.held.push(summary.held);
+        self.total.push(summary.total);
+        self.locked.push(summary.locked);
+        self.fees_collected.push(summary.fees_collected);
+        self.open_disputes.push(summary.open_disputes);
+        self.resolved_disputes.push(summary.resolved_disputes);
+        self.chargebacks.push(summary.chargebacks);
+        self.disputed_amount_held.push(summary.disputed_amount_held);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), OutputError> {
+        let batch = self
+            .record_batch()
+            .map_err(|err| OutputError::Sink(Box::new(err)))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        // The writer of the sink is not `Send`, e.g. a locked stdout, so the file is written in memory first
+        let write = || -> Result<Vec<u8>, ParquetError> {
+            let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), Some(properties))?;
+            writer.write(&batch)?;
+            writer.into_inner()
+        };
+        let bytes = write().map_err(|err| OutputError::Sink(Box::new(err)))?;
+        self.writer.write_all(&bytes)?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::write_to_sink;
+    use arrow::array::{AsArray, Int64Array, StringArray};
+    use arrow::datatypes::Decimal128Type;
+    use bytes::Bytes;
+    use std::str::FromStr;
+
+    fn parquet(batch: &RecordBatch) -> Bytes {
+        let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), None).unwrap();
+        writer.write(batch).unwrap();
+        Bytes::from(writer.into_inner().unwrap())
+    }
+
+    #[test]
+    fn test_process_parquet() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "withdrawal",
+                    "deposit",
+                    "dispute",
+                ])) as ArrayRef,
+            ),
+            ("client", Arc::new(Int64Array::from(vec![1, 1, 2, 2]))),
+            ("tx", Arc::new(Int64Array::from(vec![1, 2, 3, 3]))),
+            (
+                "amount",
+                Arc::new(StringArray::from(vec![
+                    Some("1.5"),
+                    Some("2"),
+                    Some("3"),
+                    None,
+                ])),
+            ),
+        ])
+        .unwrap();
+        let mut engine = Engine::new();
+        let mut errors = Vec::new();
+        let rows = engine
+            .process_parquet_with_errors(parquet(&batch), |row, record, err| {
+                errors.push((
+                    row,
+                    record.iter().collect::<Vec<_>>().join(","),
+                    err.reason(),
+                ));
+                Ok::<(), Box<dyn std::error::Error>>(())
+            })
+            .unwrap();
+        assert_eq!(rows, 4);
+        assert_eq!(
+            errors,
+            [(2, "withdrawal,1,2,2".to_string(), "insufficient_funds")]
+        );
+        assert_eq!(
+            engine.account(1).unwrap().available,
+            Decimal::from_str("1.5").unwrap()
+        );
+        assert_eq!(engine.account(2).unwrap().held, Decimal::from(3));
+    }
+
+    #[test]
+    fn test_parquet_sink() {
+        let mut engine = Engine::new();
+        engine.process_csv("type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,2\n".as_bytes());
+        let mut summaries: Vec<_> = engine.summaries().collect();
+        summaries.sort_by_key(|summary| summary.client);
+        let mut output = Vec::new();
+        write_to_sink(summaries, &mut ParquetSink::new(&mut output)).unwrap();
+
+        let mut reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(output))
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        assert!(reader.next().is_none());
+        assert_eq!(batch.num_columns(), 5);
+        let available = batch
+            .column_by_name("available")
+            .unwrap()
+            .as_primitive::<Decimal128Type>();
+        // The summaries have 4 decimal places
+        assert_eq!(available.values(), &[15_000, 20_000]);
+        assert_eq!(available.scale(), 4);
+    }
+}
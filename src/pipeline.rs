@@ -0,0 +1,636 @@
+use crate::config::EngineConfig;
+use crate::engine::Engine;
+use crate::hash::HashMap;
+use crate::record::{ColumnIndex, lossy};
+use crate::store::StateStore;
+use crate::types::{
+    CsvInputRow, RowProcessingError, Transaction, TransactionId, TransactionParsingError,
+    TransactionType,
+};
+use csv::{ByteRecord, StringRecord};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// How many records are parsed together, so the threads don't synchronize for every row
+const CHUNK_ROWS: usize = 1024;
+
+/// How many chunks can wait in a channel per parser before the sender blocks
+const CHUNKS_PER_PARSER: usize = 4;
+
+/// A record read from the input, or the error reading it with its line number
+type ReadRecord = Result<ByteRecord, (u64, csv::Error)>;
+
+/// A batch of parsed records for an applier of `process_csv_partitioned`, with their index in the chunk
+type Batch = Vec<(usize, ParsedRecord)>;
+
+/// A rejected row with its index in the chunk, its line and its record
+type ChunkError = (usize, u64, ByteRecord, RowProcessingError);
+
+/// A record after the parser stage, ready to be applied
+struct ParsedRecord {
+    line: u64,
+    record: ByteRecord,
+    row: ParsedRow,
+}
+
+enum ParsedRow {
+    /// The row doesn't match `EngineConfig::filter`
+    Skipped,
+    /// The row and the result of `Engine::parse_row`, which is None for a schedule row
+    Parsed(
+        Box<CsvInputRow>,
+        Option<Result<Transaction, TransactionParsingError>>,
+    ),
+    Rejected(RowProcessingError),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Placement {
+    /// A skipped or rejected row doesn't touch any account
+    Nowhere,
+    Applier(usize),
+    /// A row using a transaction id of the clients of other appliers, see `apply_shared`
+    Shared(usize),
+    Serial,
+}
+
+/// The channels of an applier thread, it gets an engine with its batch and returns them with the rejected rows
+type Worker<S> = (
+    SyncSender<(Engine<S>, Batch)>,
+    Receiver<(Engine<S>, Vec<ChunkError>)>,
+);
+
+/// Apply the `batches` of every applier on its thread and wait for them, the batches are emptied
+fn run_batches<S: StateStore>(
+    workers: &[Worker<S>],
+    partitions: &mut Vec<Engine<S>>,
+    batches: &mut [Batch],
+    errors: &mut Vec<ChunkError>,
+) {
+    for ((jobs, _), (partition, batch)) in workers
+        .iter()
+        .zip(partitions.drain(..).zip(batches.iter_mut()))
+    {
+        jobs.send((partition, mem::take(batch)))
+            .expect("applier panicked");
+    }
+    for (_, done) in workers {
+        let (partition, batch_errors) = done.recv().expect("applier panicked");
+        partitions.push(partition);
+        errors.extend(batch_errors);
+    }
+}
+
+/// Apply a row using a transaction id of the clients of other appliers on the engine of its client, while all
+/// appliers wait. The index entry of the id is moved to that engine for the row, so it sees the same index as a
+/// serial run, and moved back to the engine of the client it belongs to afterwards
+fn apply_shared<S: StateStore>(
+    partitions: &mut [Engine<S>],
+    applier: usize,
+    index: usize,
+    record: ParsedRecord,
+) -> Option<ChunkError> {
+    let ParsedRow::Parsed(row, transaction) = record.row else {
+        return None;
+    };
+    let id = row.tx;
+    let owner = partitions
+        .iter_mut()
+        .find_map(|partition| partition.tx_index.remove(&id));
+    let engine = &mut partitions[applier];
+    if let Some(owner) = owner {
+        engine.tx_index.insert(id, owner);
+    }
+    let result = engine.process_parsed_row(&row, transaction);
+    if let Some(owner) = engine.tx_index.remove(&id) {
+        partitions[owner as usize % partitions.len()]
+            .tx_index
+            .insert(id, owner);
+    }
+    Some((index, record.line, record.record, result.err()?))
+}
+
+/// Read `input` in chunks on the calling thread, parse them on `parsers` threads, and call `apply` with the parsed
+/// chunks in input order on another thread. Returns the number of rows read, or the first error of `apply`
+fn run_pipeline<E: Send>(
+    config: &EngineConfig,
+    input: impl Read,
+    parsers: usize,
+    mut apply: impl FnMut(Vec<ParsedRecord>) -> Result<(), E> + Send,
+) -> Result<u64, E> {
+    let mut rdr = config.csv.reader(input);
+    let headers = match config.csv.headers(&mut rdr) {
+        Ok(headers) => headers,
+        Err(err) => {
+            let record = ParsedRecord {
+                line: 1,
+                record: ByteRecord::new(),
+                row: ParsedRow::Rejected(err.into()),
+            };
+            return apply(vec![record]).map(|()| 0);
+        }
+    };
+    let columns = ColumnIndex::new(&headers);
+    let headers = headers.into_byte_record();
+    let parsers = parsers.max(1);
+    let (chunk_sender, chunk_receiver) =
+        mpsc::sync_channel::<(usize, Vec<ReadRecord>)>(parsers * CHUNKS_PER_PARSER);
+    let (parsed_sender, parsed_receiver) = mpsc::sync_channel(parsers * CHUNKS_PER_PARSER);
+    let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+
+    thread::scope(|scope| {
+        for _ in 0..parsers {
+            let chunks = Arc::clone(&chunk_receiver);
+            let parsed_sender = parsed_sender.clone();
+            // The parsing only depends on the config, an engine without accounts parses like the one applying
+            let parser: Engine = Engine::with_config(config.clone());
+            let (headers, columns) = (&headers, &columns);
+            scope.spawn(move || {
+                loop {
+                    // The lock is only held while waiting for the next chunk
+                    let Ok((seq, chunk)) = chunks.lock().expect("parser panicked").recv() else {
+                        break;
+                    };
+                    let parsed: Vec<_> = chunk
+                        .into_iter()
+                        .map(|record| parser.parse_record(record, headers, columns))
+                        .collect();
+                    // The applier is gone when it stopped at an error
+                    if parsed_sender.send((seq, parsed)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // The reader must see the parsers are gone when the applier stopped at an error
+        drop(chunk_receiver);
+        drop(parsed_sender);
+
+        let applier = scope.spawn(move || {
+            // The chunks arrive in any order, they are applied in the order they were read
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            for (seq, parsed) in parsed_receiver {
+                pending.insert(seq, parsed);
+                while let Some(parsed) = pending.remove(&next) {
+                    next += 1;
+                    apply(parsed)?;
+                }
+            }
+            Ok(())
+        });
+
+        let mut rows = 0;
+        let mut seq = 0;
+        let mut chunk = Vec::with_capacity(CHUNK_ROWS);
+        let mut record = ByteRecord::new();
+        loop {
+            match rdr.read_byte_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => chunk.push(Ok(record.clone())),
+                Err(err) => {
+                    let line = rdr.position().line();
+                    // An I/O error means we can't read any further
+                    let fatal = err.is_io_error();
+                    chunk.push(Err((line, err)));
+                    if fatal {
+                        rows += 1;
+                        break;
+                    }
+                }
+            }
+            rows += 1;
+            if chunk.len() == CHUNK_ROWS {
+                let full = mem::replace(&mut chunk, Vec::with_capacity(CHUNK_ROWS));
+                if chunk_sender.send((seq, full)).is_err() {
+                    break;
+                }
+                seq += 1;
+            }
+        }
+        if !chunk.is_empty() {
+            _ = chunk_sender.send((seq, chunk));
+        }
+        // The parsers stop when the chunks run out, and the applier when the parsers stop
+        drop(chunk_sender);
+        applier.join().expect("applier panicked").map(|()| rows)
+    })
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Same as `process_csv_with_errors` with the work split into stages connected by bounded channels:
+    /// the calling thread reads the records in chunks, `parsers` threads deserialize and parse them,
+    /// and one applier thread applies them in input order, so the result is the same as a serial run
+    /// `on_error` is called on the applier thread, in input order
+    pub fn process_csv_pipelined<E: Send>(
+        &mut self,
+        input: impl Read,
+        parsers: usize,
+        mut on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E> + Send,
+    ) -> Result<u64, E> {
+        let config = self.config.clone();
+        run_pipeline(&config, input, parsers, |chunk| {
+            for record in chunk {
+                self.apply_parsed(record, &mut on_error)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Same as `process_csv_pipelined` with `appliers` threads applying the rows, each of them owns the accounts of
+    /// the clients with `client % appliers` equal to its index. The rows of a client are applied in input order,
+    /// so the accounts and the rejected rows are exactly the same as with a serial run
+    /// A row using a transaction id of the clients of another applier is applied while the other appliers wait.
+    /// A chunk with a row which changes several clients (a transfer, a schedule, or a timestamp which runs the
+    /// schedules or the dispute timeout) is applied serially on this engine. With observers, a ledger, a
+    /// write-ahead log or `detect_duplicate_rows` every row is applied serially, since they depend on the order of
+    /// all rows
+    /// `on_error` is called in input order after each chunk, so when it stops the run the rest of the chunk is applied
+    pub fn process_csv_partitioned<E: Send>(
+        &mut self,
+        input: impl Read,
+        parsers: usize,
+        appliers: usize,
+        mut on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E> + Send,
+    ) -> Result<u64, E> {
+        if appliers <= 1 || !self.can_partition() {
+            return self.process_csv_pipelined(input, parsers, on_error);
+        }
+        let config = self.config.clone();
+        let mut owners = IdOwners::new();
+        let mut partitions = Vec::new();
+
+        let result = thread::scope(|scope| {
+            let workers: Vec<Worker<S>> = (0..appliers)
+                .map(|_| {
+                    let (job_sender, jobs) = mpsc::sync_channel::<(Engine<S>, Batch)>(1);
+                    let (done_sender, done) = mpsc::sync_channel(1);
+                    scope.spawn(move || {
+                        for (mut engine, batch) in jobs {
+                            let errors = engine.apply_batch(batch);
+                            if done_sender.send((engine, errors)).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    (job_sender, done)
+                })
+                .collect();
+
+            let engine = &mut *self;
+            let partitions = &mut partitions;
+            // The workers stop when this closure is dropped at the end of the input
+            run_pipeline(&config, input, parsers, move |chunk| {
+                let mut scheduled = !engine.schedules.is_empty();
+                let placements: Vec<_> = chunk
+                    .iter()
+                    .map(|record| engine.placement(record, &mut owners, appliers, &mut scheduled))
+                    .collect();
+                if placements.contains(&Placement::Serial) {
+                    for partition in partitions.drain(..) {
+                        engine.merge_partition(partition);
+                    }
+                    for record in chunk {
+                        engine.apply_parsed(record, &mut on_error)?;
+                    }
+                    return Ok(());
+                }
+
+                if partitions.is_empty() {
+                    *partitions = engine.split(appliers);
+                    // The ids of the transactions created serially, e.g. by a schedule, are not in the rows
+                    for (applier, partition) in partitions.iter().enumerate() {
+                        for &id in partition.tx_index.keys() {
+                            owners.register(id, applier);
+                        }
+                    }
+                }
+                let mut batches: Vec<Batch> = (0..appliers).map(|_| Vec::new()).collect();
+                let mut errors = Vec::new();
+                for (index, (record, placement)) in chunk.into_iter().zip(placements).enumerate() {
+                    match (placement, record.row) {
+                        (Placement::Applier(applier), row) => {
+                            batches[applier].push((index, ParsedRecord { row, ..record }))
+                        }
+                        (Placement::Shared(applier), row) => {
+                            // The rows before it are applied first
+                            run_batches(&workers, partitions, &mut batches, &mut errors);
+                            let record = ParsedRecord { row, ..record };
+                            errors.extend(apply_shared(partitions, applier, index, record));
+                        }
+                        (_, ParsedRow::Rejected(err)) => {
+                            errors.push((index, record.line, record.record, err))
+                        }
+                        _ => {}
+                    }
+                }
+                run_batches(&workers, partitions, &mut batches, &mut errors);
+                // The rejected rows are reported in input order, like a serial run
+                errors.sort_by_key(|(index, ..)| *index);
+                for (_, line, record, err) in errors {
+                    on_error(line, &lossy(&record), err)?;
+                }
+                Ok(())
+            })
+        });
+        for partition in partitions {
+            self.merge_partition(partition);
+        }
+        result
+    }
+
+    /// Whether the rows of different clients can be applied on different threads without changing the result
+    fn can_partition(&self) -> bool {
+        #[cfg(feature = "fs")]
+        if self.wal.is_some() {
+            return false;
+        }
+        self.observers.is_empty() && self.ledger.is_none() && !self.config.detect_duplicate_rows
+    }
+
+    /// Move the accounts and their transaction ids into `appliers` engines by client
+    /// The schedules and the open disputes stay here, the rows which use them are applied serially
+    fn split(&mut self, appliers: usize) -> Vec<Engine<S>> {
+        let mut partitions: Vec<Engine<S>> = (0..appliers)
+            .map(|_| Engine {
+                config: self.config.clone(),
+                ..Engine::default()
+            })
+            .collect();
+        for (client, profile) in self.store.drain() {
+            partitions[client as usize % appliers]
+                .store
+                .insert(client, profile);
+        }
+        for (id, client) in self.tx_index.drain() {
+            partitions[client as usize % appliers]
+                .tx_index
+                .insert(id, client);
+        }
+        partitions
+    }
+
+    /// Where a parsed record of `process_csv_partitioned` is applied
+    /// `scheduled` is set once a schedule row was seen, since its occurrences run at the timestamps of every client
+    fn placement(
+        &self,
+        record: &ParsedRecord,
+        owners: &mut IdOwners,
+        appliers: usize,
+        scheduled: &mut bool,
+    ) -> Placement {
+        let ParsedRow::Parsed(row, transaction) = &record.row else {
+            return Placement::Nowhere;
+        };
+        let applier = row.client as usize % appliers;
+        let Some(transaction) = transaction else {
+            *scheduled = true;
+            return Placement::Serial;
+        };
+        // The schedules stay on this engine, so a row using an id left to one of them is applied here
+        if matches!(transaction, Ok(Transaction::Transfer(..)))
+            || self.schedules.reserves(row.client, row.tx)
+            || row.timestamp.is_some() && (*scheduled || self.config.dispute_timeout.is_some())
+        {
+            return Placement::Serial;
+        }
+        // An unlock, open, close or flag row doesn't use its id
+        let indexed = transaction.as_ref().is_ok_and(|transaction| {
+            !matches!(
+                transaction,
+                Transaction::Unlock
+                    | Transaction::OpenAccount
+                    | Transaction::CloseAccount
+                    | Transaction::Flag(_)
+            )
+        });
+        if indexed && !owners.register(row.tx, applier) {
+            return Placement::Shared(applier);
+        }
+        Placement::Applier(applier)
+    }
+
+    /// Apply the rows of an applier, returns the rejected ones with their index in the chunk
+    fn apply_batch(&mut self, batch: Batch) -> Vec<ChunkError> {
+        batch
+            .into_iter()
+            .filter_map(|(index, parsed)| {
+                let ParsedRow::Parsed(row, transaction) = parsed.row else {
+                    return None;
+                };
+                let err = self.process_parsed_row(&row, transaction).err()?;
+                Some((index, parsed.line, parsed.record, err))
+            })
+            .collect()
+    }
+
+    /// The parser stage of a record
+    fn parse_record(
+        &self,
+        record: ReadRecord,
+        headers: &ByteRecord,
+        columns: &ColumnIndex,
+    ) -> ParsedRecord {
+        let record = match record {
+            Ok(record) => record,
+            Err((line, err)) => {
+                return ParsedRecord {
+                    line,
+                    record: ByteRecord::new(),
+                    row: ParsedRow::Rejected(err.into()),
+                };
+            }
+        };
+        let line = record.position().map_or(0, |pos| pos.line());
+        let row = match self.deserialize_row(&record, headers, columns) {
+            Err(err) => ParsedRow::Rejected(err.into()),
+            Ok(row) if !self.config.filter.matches(&row) => ParsedRow::Skipped,
+            Ok(row) => {
+                let transaction = (row.transaction_type != TransactionType::Schedule)
+                    .then(|| self.parse_row(&row));
+                ParsedRow::Parsed(Box::new(row), transaction)
+            }
+        };
+        ParsedRecord { line, record, row }
+    }
+
+    /// The applier stage of a record
+    fn apply_parsed<E>(
+        &mut self,
+        parsed: ParsedRecord,
+        on_error: &mut impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let err = match parsed.row {
+            ParsedRow::Skipped => return Ok(()),
+            ParsedRow::Parsed(row, transaction) => {
+                let result = self.process_parsed_row(&row, transaction);
+                self.report_rejected_occurrences(parsed.line, on_error)?;
+                match result {
+                    Ok(()) => return Ok(()),
+                    Err(err) => err,
+                }
+            }
+            ParsedRow::Rejected(err) => err,
+        };
+        on_error(parsed.line, &lossy(&parsed.record), err)
+    }
+}
+
+/// Where the transaction ids were used by `process_csv_partitioned`, an id is registered for the rows using it
+/// and for the index entries moved into the appliers
+/// The index of the ids is shared by all clients, so an id used by the clients of two appliers is only
+/// looked up while the other appliers wait from then on
+struct IdOwners(HashMap<TransactionId, Option<usize>>);
+
+impl IdOwners {
+    fn new() -> Self {
+        Self(HashMap::default())
+    }
+
+    /// Record that `applier` uses `id`, returns false if another applier used it before
+    fn register(&mut self, id: TransactionId, applier: usize) -> bool {
+        let owner = self.0.entry(id).or_insert(Some(applier));
+        if *owner != Some(applier) {
+            *owner = None;
+        }
+        owner.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DisputeTimeout, DisputeTimeoutAction};
+    use crate::testutil::{GeneratorConfig, TransactionGenerator, write_csv};
+    use std::convert::Infallible;
+
+    /// The accounts with their transactions and the index of the ids as json, so the amounts are compared with
+    /// their scale
+    fn state(engine: &Engine) -> serde_json::Value {
+        let accounts: BTreeMap<_, _> = engine.store.iter().collect();
+        let mut accounts = serde_json::to_value(accounts).unwrap();
+        // The ids are a set, they are listed in arbitrary order
+        for account in accounts.as_object_mut().unwrap().values_mut() {
+            let ids = account["transaction_ids"].as_array_mut().unwrap();
+            ids.sort_by_key(|id| id.as_u64());
+        }
+        serde_json::to_value((accounts, &engine.tx_index)).unwrap()
+    }
+
+    fn assert_same_as_serial(input: &[u8], config: &EngineConfig) {
+        let mut serial = Engine::with_config(config.clone());
+        let expected: Vec<_> = serial
+            .process_csv_collect_errors(input)
+            .into_iter()
+            .map(|err| (err.line, err.kind.to_string()))
+            .collect();
+        for appliers in [2, 3, 8] {
+            let mut engine = Engine::with_config(config.clone());
+            let mut errors = Vec::new();
+            engine
+                .process_csv_partitioned(input, 2, appliers, |line, _, err| {
+                    errors.push((line, err.to_string()));
+                    Ok::<(), Infallible>(())
+                })
+                .unwrap();
+            assert_eq!(errors, expected);
+            assert_eq!(state(&engine), state(&serial));
+            assert_eq!(engine.schedules().len(), serial.schedules().len());
+        }
+    }
+
+    #[test]
+    fn test_process_csv_pipelined() {
+        let generator = TransactionGenerator::new(
+            7,
+            GeneratorConfig {
+                clients: 50,
+                adversarial_ratio: 0.2,
+                ..Default::default()
+            },
+        );
+        let mut input = Vec::new();
+        write_csv(generator, 5000, &mut input).unwrap();
+        input.extend_from_slice(b"deposit,1,x,1\n");
+
+        let mut serial = Engine::new();
+        let expected: Vec<_> = serial
+            .process_csv_collect_errors(input.as_slice())
+            .into_iter()
+            .map(|err| (err.line, err.kind.to_string()))
+            .collect();
+
+        let mut engine = Engine::new();
+        let mut errors = Vec::new();
+        let rows = engine
+            .process_csv_pipelined(input.as_slice(), 3, |line, _, err| {
+                errors.push((line, err.to_string()));
+                Ok::<(), Infallible>(())
+            })
+            .unwrap();
+        assert_eq!(rows, 5001);
+        assert_eq!(errors, expected);
+        let mut summaries: Vec<_> = engine.summaries().collect();
+        let mut expected: Vec<_> = serial.summaries().collect();
+        summaries.sort_by_key(|summary| summary.client);
+        expected.sort_by_key(|summary| summary.client);
+        assert_eq!(summaries, expected);
+
+        // The pipeline stops at the first error of `on_error`
+        let mut engine = Engine::new();
+        let res = engine.process_csv_pipelined(input.as_slice(), 1, |line, _, _| Err(line));
+        assert_eq!(res, Err(errors[0].0));
+    }
+
+    #[test]
+    fn test_process_csv_partitioned() {
+        // The adversarial rows reuse the ids of other clients
+        let generator = TransactionGenerator::new(
+            11,
+            GeneratorConfig {
+                clients: 100,
+                adversarial_ratio: 0.001,
+                ..Default::default()
+            },
+        );
+        let mut input = Vec::new();
+        write_csv(generator, 20_000, &mut input).unwrap();
+        assert_same_as_serial(&input, &EngineConfig::default());
+
+        // Every other chunk has timestamps and transfers, which depend on the other clients
+        let mut input = String::from("type,client,tx,amount,to,timestamp,interval,count\n");
+        for i in 0..6000 {
+            let (client, tx) = (i / 5 % 40 + 1, i + 1);
+            let timed = i / CHUNK_ROWS % 2 == 1;
+            let timestamp = if timed { i.to_string() } else { String::new() };
+            let row = match i % 5 {
+                _ if i == 1500 => format!("schedule,{client},900000,2,,{i},10,20"),
+                _ if i % 97 == 96 => {
+                    format!("deposit,{},{},1,,{timestamp}", client + 1, tx - 5)
+                }
+                0 | 1 => format!("deposit,{client},{tx},{},,{timestamp}", i % 7 + 1),
+                2 => format!("withdrawal,{client},{tx},3,,{timestamp}"),
+                3 => format!("dispute,{client},{},,,{timestamp}", tx - 3),
+                _ if timed && i % 50 == 4 => {
+                    format!("transfer,{client},{tx},2,{},", client % 40 + 1)
+                }
+                _ => format!("resolve,{client},{},,,{timestamp}", tx - 4),
+            };
+            input.push_str(&row);
+            input.push('\n');
+        }
+        assert_same_as_serial(input.as_bytes(), &EngineConfig::default());
+        let config = EngineConfig {
+            dispute_timeout: Some(DisputeTimeout {
+                after: 100,
+                action: DisputeTimeoutAction::Chargeback,
+            }),
+            ..Default::default()
+        };
+        assert_same_as_serial(input.as_bytes(), &config);
+    }
+}
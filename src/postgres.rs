@@ -0,0 +1,163 @@
+use crate::ledger::LedgerEntry;
+use crate::output::AccountSummary;
+use crate::sink::{OutputSink, quote_identifier};
+use crate::types::OutputError;
+use postgres::types::ToSql;
+use postgres::{Client, NoTls};
+use std::collections::HashMap;
+
+/// An `OutputSink` which upserts the accounts into a PostgreSQL table in a single transaction
+/// The accounts are collected and written by `finish` with parameterized statements, the values are cast in the
+/// statements so any integer or numeric column type of the table works. The table needs the same columns as the one of
+/// `SqlSink`, the password can be in the url
+pub struct PostgresSink {
+    client: Client,
+    table: String,
+    accounts: Vec<AccountSummary>,
+    ledger: Option<(String, Vec<LedgerEntry>)>,
+}
+
+impl PostgresSink {
+    /// Connect to the database of `url`, e.g. `postgres://user@host/reporting`, the accounts go to `table`
+    /// Nothing is written before `finish`
+    pub fn connect(url: &str, table: &str) -> Result<Self, postgres::Error> {
+        Ok(Self {
+            client: Client::connect(url, NoTls)?,
+            table: quote_identifier(table),
+            accounts: Vec::new(),
+            ledger: None,
+        })
+    }
+
+    /// Append the ledger `entries` to `table` in the transaction of the accounts
+    /// The table has the columns of the `--audit-log` csv, the entries of every run are appended
+    pub fn write_ledger(&mut self, table: &str, entries: &[LedgerEntry]) {
+        self.ledger = Some((quote_identifier(table), entries.to_vec()));
+    }
+}
+
+/// The upsert of an account by `client` with the optional `columns` of its summary and their types
+fn upsert_statement(table: &str, columns: &[(&str, &str)]) -> String {
+    let required = [
+        ("client", "integer"),
+        ("available", "numeric"),
+        ("held", "numeric"),
+        ("total", "numeric"),
+        ("locked", "boolean"),
+    ];
+    let (mut names, mut values, mut updates) = (Vec::new(), Vec::new(), Vec::new());
+    for (i, (column, sql_type)) in required.iter().chain(columns).enumerate() {
+        names.push(*column);
+        values.push(format!("${}::{sql_type}", i + 1));
+        if *column != "client" {
+            updates.push(format!("{column} = EXCLUDED.{column}"));
+        }
+    }
+    format!(
+        "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT (client) DO UPDATE SET {}",
+        names.join(", "),
+        values.join(", "),
+        updates.join(", ")
+    )
+}
+
+impl OutputSink for PostgresSink {
+    fn write_account(&mut self, summary: &AccountSummary) -> Result<(), OutputError> {
+        self.accounts.push(summary.clone());
+        Ok(())
+    }
+
+    /// Write the ledger and upsert the accounts in a transaction, a failed statement rolls everything back
+    fn finish(&mut self) -> Result<(), OutputError> {
+        let sink = |err: postgres::Error| OutputError::Sink(Box::new(err));
+        let mut transaction = self.client.transaction().map_err(sink)?;
+        if let Some((table, entries)) = self.ledger.take() {
+            let insert = transaction
+                .prepare(&format!(
+                    "INSERT INTO {table} (\"timestamp\", client, tx, \"type\", amount, available, held, locked, \
+                     rejection, reason) VALUES ($1::bigint, $2::integer, $3::bigint, $4::text, $5::numeric, \
+                     $6::numeric, $7::numeric, $8::boolean, $9::text, $10::bigint)"
+                ))
+                .map_err(sink)?;
+            for entry in &entries {
+                transaction
+                    .execute(
+                        &insert,
+                        &[
+                            &(entry.timestamp as i64),
+                            &i32::from(entry.client),
+                            &i64::from(entry.tx),
+                            &entry.transaction_type,
+                            &entry.amount,
+                            &entry.available,
+                            &entry.held,
+                            &entry.locked,
+                            &entry.rejection,
+                            &entry.reason.map(i64::from),
+                        ],
+                    )
+                    .map_err(sink)?;
+            }
+        }
+        // A statement for every set of optional columns, they are the same for all accounts of a run
+        let mut statements = HashMap::new();
+        for summary in self.accounts.drain(..) {
+            let counts = [
+                ("open_disputes", summary.open_disputes),
+                ("resolved_disputes", summary.resolved_disputes),
+                ("chargebacks", summary.chargebacks),
+            ]
+            .map(|(column, count)| (column, count.map(i64::from)));
+            let client = i32::from(summary.client);
+            let mut columns = Vec::new();
+            let mut values: Vec<&(dyn ToSql + Sync)> = vec![
+                &client,
+                &summary.available,
+                &summary.held,
+                &summary.total,
+                &summary.locked,
+            ];
+            if let Some(fees) = &summary.fees_collected {
+                columns.push(("fees_collected", "numeric"));
+                values.push(fees);
+            }
+            for (column, count) in &counts {
+                if let Some(count) = count {
+                    columns.push((column, "bigint"));
+                    values.push(count);
+                }
+            }
+            if let Some(amount) = &summary.disputed_amount_held {
+                columns.push(("disputed_amount_held", "numeric"));
+                values.push(amount);
+            }
+            let statement = match statements.get(&columns) {
+                Some(statement) => statement,
+                None => {
+                    let statement = transaction
+                        .prepare(&upsert_statement(&self.table, &columns))
+                        .map_err(sink)?;
+                    statements.entry(columns).or_insert(statement)
+                }
+            };
+            transaction.execute(statement, &values).map_err(sink)?;
+        }
+        transaction.commit().map_err(sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsert_statement() {
+        assert_eq!(
+            upsert_statement("\"accounts\"", &[("fees_collected", "numeric")]),
+            "INSERT INTO \"accounts\" (client, available, held, total, locked, fees_collected) \
+             VALUES ($1::integer, $2::numeric, $3::numeric, $4::numeric, $5::boolean, $6::numeric) \
+             ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, held = EXCLUDED.held, \
+             total = EXCLUDED.total, locked = EXCLUDED.locked, fees_collected = EXCLUDED.fees_collected"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use crate::engine::Engine;
+use crate::output::AccountSummary;
+use crate::store::StateStore;
+use crate::types::{
+    AccountStatus, CaseId, ClientId, DisputeState, FlagRecord, Timestamp, TransactionId,
+    TransactionKind, TransactionRecord, TransactionState, TransactionType,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// How many transactions `query` lists by default
+pub const DEFAULT_RECENT_TRANSACTIONS: usize = 10;
+
+/// The state of a single account for investigations, see `Engine::query`
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct AccountQuery {
+    #[serde(flatten)]
+    pub summary: AccountSummary,
+    pub status: AccountStatus,
+    pub open_disputes: Vec<OpenDispute>,
+    /// The latest deposits and withdrawals, the newest first
+    pub recent_transactions: Vec<RecentTransaction>,
+    /// The flags set by the operators, the oldest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<FlagRecord>,
+}
+
+/// An open dispute of a deposit or withdrawal
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct OpenDispute {
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub case_id: Option<CaseId>,
+    pub amount: Decimal,
+}
+
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct RecentTransaction {
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub amount: Decimal,
+    pub timestamp: Option<Timestamp>,
+    pub state: TransactionState,
+}
+
+impl<S: StateStore> Engine<S> {
+    /// The balances, the open disputes and the `recent` latest transactions of `client`
+    /// The transactions are ordered by timestamp and then by id, since the input order is not stored
+    pub fn query(&self, client: ClientId, recent: usize) -> Option<AccountQuery> {
+        let profile = self.account(client)?;
+        let mut records: Vec<(TransactionId, TransactionType, &TransactionRecord)> = profile
+            .transactions
+            .iter()
+            .map(|(tx, kind, record)| {
+                let transaction_type = match kind {
+                    TransactionKind::Deposit => TransactionType::Deposit,
+                    TransactionKind::Withdrawal => TransactionType::Withdrawal,
+                };
+                (tx, transaction_type, record)
+            })
+            .collect();
+        records.sort_by_key(|(tx, _, record)| (record.timestamp, *tx));
+
+        let open_disputes = records
+            .iter()
+            .flat_map(|(tx, transaction_type, record)| {
+                record
+                    .cases
+                    .iter()
+                    .filter(|case| case.state == DisputeState::Open)
+                    .map(|case| OpenDispute {
+                        tx: *tx,
+                        transaction_type: *transaction_type,
+                        case_id: case.id,
+                        amount: case.amount,
+                    })
+            })
+            .collect();
+        let recent_transactions = records
+            .iter()
+            .rev()
+            .take(recent)
+            .map(|(tx, transaction_type, record)| RecentTransaction {
+                tx: *tx,
+                transaction_type: *transaction_type,
+                amount: record.amount,
+                timestamp: record.timestamp,
+                state: record.state(),
+            })
+            .collect();
+        Some(AccountQuery {
+            // The count of the extended output would clash with the list of the open disputes
+            summary: AccountSummary {
+                open_disputes: None,
+                ..self.summary(client)?
+            },
+            status: profile.status,
+            open_disputes,
+            recent_transactions,
+            flags: profile.flags.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let mut engine = Engine::new();
+        engine.process_csv(
+            "type,client,tx,amount,timestamp\n\
+            deposit,1,1,5,10\n\
+            deposit,1,2,3,20\n\
+            withdrawal,1,3,1,30\n\
+            dispute,1,1,,40\n"
+                .as_bytes(),
+        );
+        assert!(engine.query(2, 10).is_none());
+
+        let query = engine.query(1, 2).unwrap();
+        assert_eq!(query.summary.available, Decimal::from(2));
+        assert_eq!(query.summary.held, Decimal::from(5));
+        assert_eq!(
+            query.open_disputes,
+            vec![OpenDispute {
+                tx: 1,
+                transaction_type: TransactionType::Deposit,
+                case_id: None,
+                amount: Decimal::from(5),
+            }]
+        );
+        let recent: Vec<_> = query
+            .recent_transactions
+            .iter()
+            .map(|transaction| transaction.tx)
+            .collect();
+        assert_eq!(recent, vec![3, 2]);
+
+        let json = serde_json::to_value(&query).unwrap();
+        assert_eq!(json["client"], 1);
+        assert_eq!(json["recent_transactions"][0]["type"], "withdrawal");
+    }
+}
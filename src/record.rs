@@ -0,0 +1,238 @@
+use crate::config::EngineConfig;
+#[cfg(feature = "fast-decimal")]
+use crate::fixed::FixedPoint;
+use crate::types::{CsvInputRow, TransactionType};
+use csv::{ByteRecord, StringRecord};
+use rust_decimal::Decimal;
+use std::borrow::Cow;
+use std::str::{self, FromStr};
+
+/// The positions of our columns in the input, they are looked up once from the headers
+#[derive(Debug, Clone, Default)]
+pub struct ColumnIndex {
+    transaction_type: Option<usize>,
+    client: Option<usize>,
+    tx: Option<usize>,
+    amount: Option<usize>,
+    to: Option<usize>,
+    timestamp: Option<usize>,
+    case_id: Option<usize>,
+    interval: Option<usize>,
+    count: Option<usize>,
+    reason: Option<usize>,
+}
+
+impl ColumnIndex {
+    pub fn new(headers: &StringRecord) -> Self {
+        let position = |column: &str| headers.iter().position(|name| name == column);
+        Self {
+            transaction_type: position("type"),
+            client: position("client"),
+            tx: position("tx"),
+            amount: position("amount"),
+            to: position("to"),
+            timestamp: position("timestamp"),
+            case_id: position("case_id"),
+            interval: position("interval"),
+            count: position("count"),
+            reason: position("reason"),
+        }
+    }
+
+    /// Parse a record without serde and without allocating, None if any field is not in the plain format,
+    /// e.g. a type in another case or an amount in scientific notation
+    /// The caller falls back to deserializing the record then, which accepts the same rows and reports
+    /// the precise error, so this is only a fast path
+    pub fn parse(&self, record: &ByteRecord) -> Option<CsvInputRow> {
+        Some(CsvInputRow {
+            transaction_type: parse_type(field(record, self.transaction_type)?)?,
+            client: parse_number(field(record, self.client)?)?,
+            tx: parse_number(field(record, self.tx)?)?,
+            amount: amount(record, self.amount)?,
+            to: optional(record, self.to)?,
+            timestamp: optional(record, self.timestamp)?,
+            case_id: optional(record, self.case_id)?,
+            interval: optional(record, self.interval)?,
+            count: optional(record, self.count)?,
+            reason: optional(record, self.reason)?,
+        })
+    }
+}
+
+/// The record of a rejected row for error reports, invalid utf-8 is replaced
+pub fn lossy(record: &ByteRecord) -> StringRecord {
+    StringRecord::from_byte_record_lossy(record.clone())
+}
+
+/// Deserialize a csv record with `headers`, the type aliases of `config` are applied to its type first
+/// and formatted amounts are made plain with `lenient_amounts`
+/// Plain records are parsed by `columns` without serde
+pub(crate) fn deserialize_record(
+    config: &EngineConfig,
+    record: &ByteRecord,
+    headers: &ByteRecord,
+    columns: &ColumnIndex,
+) -> csv::Result<CsvInputRow> {
+    let aliases = config.type_aliases.as_ref();
+    if let Some(row) = columns.parse(record) {
+        let name = row.transaction_type.name();
+        if aliases.is_none_or(|aliases| aliases.resolve(name) == name) {
+            return Ok(row);
+        }
+    }
+    let mut record = Cow::Borrowed(record);
+    if let Some(aliases) = aliases
+        && let Some(column) = headers.iter().position(|name| name == b"type")
+        && let Some(transaction_type) = record.get(column)
+        && let Ok(transaction_type) = str::from_utf8(transaction_type)
+        && let resolved = aliases.resolve(transaction_type)
+        && resolved != transaction_type
+    {
+        record = Cow::Owned(replace_field(&record, column, resolved.as_bytes()));
+    }
+    if config.lenient_amounts
+        && let Some(column) = headers.iter().position(|name| name == b"amount")
+        && let Some(amount) = record.get(column).and_then(lenient_amount)
+    {
+        record = Cow::Owned(replace_field(&record, column, amount.as_bytes()));
+    }
+    record.deserialize(Some(headers))
+}
+
+/// A copy of `record` with the field at `column` replaced by `value`
+fn replace_field(record: &ByteRecord, column: usize, value: &[u8]) -> ByteRecord {
+    let mut replaced: ByteRecord = record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| if i == column { value } else { field })
+        .collect();
+    replaced.set_position(record.position().cloned());
+    replaced
+}
+
+fn field(record: &ByteRecord, column: Option<usize>) -> Option<&[u8]> {
+    record.get(column?)
+}
+
+fn parse_number<T: FromStr>(field: &[u8]) -> Option<T> {
+    str::from_utf8(field).ok()?.parse().ok()
+}
+
+/// Some(None) for a missing column or an empty field, None if the field can't be parsed
+fn optional<T: FromStr>(record: &ByteRecord, column: Option<usize>) -> Option<Option<T>> {
+    match column.and_then(|column| record.get(column)) {
+        None | Some(b"") => Some(None),
+        Some(field) => parse_number(field).map(Some),
+    }
+}
+
+/// Same as `optional`, with the fixed point parser of the `fast-decimal` feature
+#[cfg(feature = "fast-decimal")]
+fn amount(record: &ByteRecord, column: Option<usize>) -> Option<Option<Decimal>> {
+    match column.and_then(|column| record.get(column)) {
+        None | Some(b"") => Some(None),
+        Some(field) => FixedPoint::parse(field)
+            .map(FixedPoint::to_decimal)
+            .or_else(|| parse_number(field))
+            .map(Some),
+    }
+}
+
+#[cfg(not(feature = "fast-decimal"))]
+fn amount(record: &ByteRecord, column: Option<usize>) -> Option<Option<Decimal>> {
+    optional(record, column)
+}
+
+/// A formatted amount like `$1,234.50` in the plain format, None if it is not formatted or not valid
+/// Whitespace and a `$` before the number are removed, as well as the `,` between groups of three digits
+/// A `,` anywhere else is kept and rejected, since it may be a decimal separator
+pub(crate) fn lenient_amount(field: &[u8]) -> Option<String> {
+    let field = str::from_utf8(field).ok()?.trim();
+    let (sign, rest) = match field.strip_prefix('-') {
+        Some(rest) => ("-", rest.trim_start()),
+        None => ("", field),
+    };
+    let rest = rest.strip_prefix('$').unwrap_or(rest).trim_start();
+    let (integer, fraction) = match rest.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (rest, None),
+    };
+    let mut groups = integer.split(',');
+    let first = groups.next()?;
+    let grouped = integer.contains(',');
+    if grouped && !(1..=3).contains(&first.len())
+        || groups.any(|group| group.len() != 3)
+        || fraction.is_some_and(|fraction| fraction.contains(','))
+    {
+        return None;
+    }
+    let plain = format!("{sign}{}", rest.replace(',', ""));
+    (plain != field).then_some(plain)
+}
+
+fn parse_type(field: &[u8]) -> Option<TransactionType> {
+    match field {
+        b"deposit" => Some(TransactionType::Deposit),
+        b"withdrawal" => Some(TransactionType::Withdrawal),
+        b"dispute" => Some(TransactionType::Dispute),
+        b"resolve" => Some(TransactionType::Resolve),
+        b"chargeback" => Some(TransactionType::Chargeback),
+        b"chargeback_reversal" => Some(TransactionType::ChargebackReversal),
+        b"unlock" | b"unfreeze" => Some(TransactionType::Unlock),
+        b"open_account" => Some(TransactionType::OpenAccount),
+        b"close_account" => Some(TransactionType::CloseAccount),
+        b"transfer" => Some(TransactionType::Transfer),
+        b"schedule" => Some(TransactionType::Schedule),
+        b"adjustment" => Some(TransactionType::Adjustment),
+        b"flag" => Some(TransactionType::Flag),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_matches_deserialize() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "to", "case_id"]);
+        let columns = ColumnIndex::new(&headers);
+        let rows = [
+            vec!["deposit", "1", "2", "1.5", "", ""],
+            vec!["transfer", "1", "3", "0.0001", "2", ""],
+            vec!["dispute", "1", "2", "", "", "7"],
+            vec!["resolve", "1", "2"],
+            vec!["unfreeze", "65535", "4294967295", "", "", ""],
+            vec!["deposit", "1", "2", "1e3", "", ""],
+            vec!["Deposit", "1", "2", "1", "", ""],
+            vec!["deposit", "1", "-2", "1", "", ""],
+            vec!["deposit", "65536", "2", "1", "", ""],
+            vec!["deposit", "", "2", "1", "", ""],
+            vec!["refund", "1", "2", "1", "", ""],
+        ];
+        for row in rows {
+            let record = StringRecord::from(row);
+            let expected = record.deserialize::<CsvInputRow>(Some(&headers)).ok();
+            match columns.parse(record.as_byte_record()) {
+                Some(row) => assert_eq!(Some(row), expected, "{record:?}"),
+                // Only rows outside of the plain format take the slow path
+                None => assert!(
+                    expected.is_none() || record.iter().any(|field| field == "1e3"),
+                    "{record:?}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_lenient_amount() {
+        let amount = |field: &str| lenient_amount(field.as_bytes());
+        assert_eq!(amount("$1,234.50").as_deref(), Some("1234.50"));
+        assert_eq!(amount(" $ 12 ").as_deref(), Some("12"));
+        assert_eq!(amount("-$1,000,000").as_deref(), Some("-1000000"));
+        assert_eq!(amount("1.5"), None);
+        assert_eq!(amount("1,5"), None);
+        assert_eq!(amount("1234,567"), None);
+        assert_eq!(amount("1,234.5,6"), None);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::engine::Engine;
+use crate::record::lenient_amount;
+use crate::store::StateStore;
+use crate::types::{CsvInputRow, RowProcessingError};
+use arrow::array::{Array, AsArray, PrimitiveArray, StringArray};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    ArrowPrimitiveType, DataType, Decimal128Type, UInt16Type, UInt32Type, UInt64Type,
+};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// The amount column of a batch, a decimal column is read without formatting it as text
+enum Amounts<'a> {
+    Decimal(&'a PrimitiveArray<Decimal128Type>, u32),
+    Text(&'a StringArray),
+}
+
+impl Amounts<'_> {
+    fn value(&self, i: usize, lenient: bool) -> Result<Option<Decimal>, ArrowError> {
+        match self {
+            Amounts::Decimal(amounts, _) if amounts.is_null(i) => Ok(None),
+            Amounts::Decimal(amounts, scale) => {
+                Decimal::try_from_i128_with_scale(amounts.value(i), *scale)
+                    .map(Some)
+                    .map_err(|err| ArrowError::InvalidArgumentError(format!("amount: {err}")))
+            }
+            Amounts::Text(amounts) if amounts.is_null(i) => Ok(None),
+            Amounts::Text(amounts) => {
+                let amount = amounts.value(i).trim();
+                if amount.is_empty() {
+                    return Ok(None);
+                }
+                let plain = lenient.then(|| lenient_amount(amount.as_bytes())).flatten();
+                Decimal::from_str(plain.as_deref().unwrap_or(amount))
+                    .map(Some)
+                    .map_err(|_| ArrowError::ParseError(format!("invalid amount {amount:?}")))
+            }
+        }
+    }
+}
+
+/// The columns of a batch cast to the types of `CsvInputRow`
+struct Columns<'a> {
+    types: &'a StringArray,
+    clients: &'a PrimitiveArray<UInt16Type>,
+    txs: &'a PrimitiveArray<UInt32Type>,
+    amounts: Option<Amounts<'a>>,
+    to: Option<&'a PrimitiveArray<UInt16Type>>,
+    timestamps: Option<&'a PrimitiveArray<UInt64Type>>,
+    case_ids: Option<&'a PrimitiveArray<UInt32Type>>,
+    intervals: Option<&'a PrimitiveArray<UInt64Type>>,
+    counts: Option<&'a PrimitiveArray<UInt32Type>>,
+    reasons: Option<&'a PrimitiveArray<UInt32Type>>,
+}
+
+impl Columns<'_> {
+    /// The row at `i` with the type aliases and `lenient_amounts` of the config of `engine`
+    fn row<S: StateStore>(
+        &self,
+        engine: &Engine<S>,
+        i: usize,
+    ) -> Result<CsvInputRow, RowProcessingError> {
+        if self.types.is_null(i) {
+            return Err(ArrowError::InvalidArgumentError("type is missing".into()).into());
+        }
+        let amount = match &self.amounts {
+            Some(amounts) => amounts.value(i, engine.config().lenient_amounts)?,
+            None => None,
+        };
+        Ok(CsvInputRow {
+            transaction_type: engine.parse_type(self.types.value(i).trim())?,
+            client: required(self.clients, "client", i)?,
+            tx: required(self.txs, "tx", i)?,
+            amount,
+            to: optional(self.to, i),
+            timestamp: optional(self.timestamps, i),
+            case_id: optional(self.case_ids, i),
+            interval: optional(self.intervals, i),
+            count: optional(self.counts, i),
+            reason: optional(self.reasons, i),
+        })
+    }
+}
+
+/// The value at `i` of an optional column, None for a missing column or a null
+fn optional<T: ArrowPrimitiveType>(
+    column: Option<&PrimitiveArray<T>>,
+    i: usize,
+) -> Option<T::Native> {
+    column
+        .filter(|column| column.is_valid(i))
+        .map(|column| column.value(i))
+}
+
+/// The value at `i` of a required column, a null is also what a value out of the range of our type is cast to
+fn required<T: ArrowPrimitiveType>(
+    column: &PrimitiveArray<T>,
+    name: &str,
+    i: usize,
+) -> Result<T::Native, ArrowError> {
+    if column.is_null(i) {
+        return Err(ArrowError::InvalidArgumentError(format!(
+            "{name} is missing or out of range"
+        )));
+    }
+    Ok(column.value(i))
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Apply all rows of an Arrow `batch` in order, same as `process_row` for each of them
+    /// The columns are found by name like the csv headers, with the column mapping of the csv options,
+    /// and cast to our types, so e.g. an `Int64` client column or a dictionary encoded type column is accepted.
+    /// The amount can be a decimal or a text column. Rows skipped by the filter of the config are not rejected
+    /// Returns the index and the error of every rejected row, or an error if a column is missing or can't be cast
+    pub fn process_record_batch(
+        &mut self,
+        batch: &RecordBatch,
+    ) -> Result<Vec<(usize, RowProcessingError)>, ArrowError> {
+        let mut errors = Vec::new();
+        self.process_record_batch_with_errors(batch, |i, err| {
+            errors.push((i, err));
+            Ok::<(), ArrowError>(())
+        })?;
+        Ok(errors)
+    }
+
+    /// Same as `process_record_batch` but `on_error` is called for every rejected row with its index
+    /// When `on_error` returns an error we stop processing and return it
+    pub fn process_record_batch_with_errors<E: From<ArrowError>>(
+        &mut self,
+        batch: &RecordBatch,
+        mut on_error: impl FnMut(usize, RowProcessingError) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let schema = batch.schema();
+        let position = |name: &str| {
+            schema.fields().iter().position(|field| {
+                let field = field.name().as_str();
+                self.config
+                    .csv
+                    .columns
+                    .get(field)
+                    .map_or(field, String::as_str)
+                    == name
+            })
+        };
+        let column = |name: &str, data_type: &DataType| {
+            position(name)
+                .map(|position| cast(batch.column(position), data_type))
+                .transpose()
+        };
+        let present = |name: &str, data_type: &DataType| {
+            column(name, data_type)?
+                .ok_or_else(|| ArrowError::SchemaError(format!("missing column {name}")))
+        };
+        let types = present("type", &DataType::Utf8)?;
+        let clients = present("client", &DataType::UInt16)?;
+        let txs = present("tx", &DataType::UInt32)?;
+        let to = column("to", &DataType::UInt16)?;
+        let timestamps = column("timestamp", &DataType::UInt64)?;
+        let case_ids = column("case_id", &DataType::UInt32)?;
+        let intervals = column("interval", &DataType::UInt64)?;
+        let counts = column("count", &DataType::UInt32)?;
+        let reasons = column("reason", &DataType::UInt32)?;
+        // A decimal keeps its mantissa and scale, the other amount types are read like the csv field
+        let amounts = match position("amount").map(|position| batch.column(position)) {
+            Some(amounts) => match amounts.data_type() {
+                DataType::Decimal128(_, scale) if *scale >= 0 => Some(amounts.clone()),
+                _ => Some(cast(amounts, &DataType::Utf8)?),
+            },
+            None => None,
+        };
+        let columns = Columns {
+            types: types.as_string::<i32>(),
+            clients: clients.as_primitive(),
+            txs: txs.as_primitive(),
+            amounts: amounts.as_ref().map(|amounts| match amounts.data_type() {
+                DataType::Decimal128(_, scale) => {
+                    Amounts::Decimal(amounts.as_primitive(), *scale as u32)
+                }
+                _ => Amounts::Text(amounts.as_string::<i32>()),
+            }),
+            to: to.as_ref().map(|to| to.as_primitive()),
+            timestamps: timestamps
+                .as_ref()
+                .map(|timestamps| timestamps.as_primitive()),
+            case_ids: case_ids.as_ref().map(|case_ids| case_ids.as_primitive()),
+            intervals: intervals.as_ref().map(|intervals| intervals.as_primitive()),
+            counts: counts.as_ref().map(|counts| counts.as_primitive()),
+            reasons: reasons.as_ref().map(|reasons| reasons.as_primitive()),
+        };
+        for i in 0..batch.num_rows() {
+            let result = match columns.row(self, i) {
+                Ok(row) if !self.config.filter.matches(&row) => Ok(()),
+                Ok(row) => self.process_row(&row),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                on_error(i, err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{ArrayRef, Decimal128Array, Int64Array, StringArray, UInt16Array};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_process_record_batch() {
+        let mut engine = Engine::new();
+        let batch = RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "deposit",
+                    "transfer",
+                    "withdrawal",
+                    "dispute",
+                    "refund",
+                ])) as ArrayRef,
+            ),
+            // Any integer column is cast to our ids
+            (
+                "client",
+                Arc::new(Int64Array::from(vec![1, 2, 1, 2, 2, 1])) as ArrayRef,
+            ),
+            ("tx", Arc::new(Int64Array::from(vec![1, 2, 3, 4, 2, 5]))),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from(vec![
+                        Some(50_000),
+                        Some(10_000),
+                        Some(20_000),
+                        Some(100_000),
+                        None,
+                        None,
+                    ])
+                    .with_precision_and_scale(10, 4)
+                    .unwrap(),
+                ),
+            ),
+            (
+                "to",
+                Arc::new(UInt16Array::from(vec![
+                    None,
+                    None,
+                    Some(2),
+                    None,
+                    None,
+                    None,
+                ])),
+            ),
+        ])
+        .unwrap();
+
+        let errors = engine.process_record_batch(&batch).unwrap();
+        let rejected = errors.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        assert_eq!(rejected, vec![3, 5]);
+        assert!(matches!(errors[1].1, RowProcessingError::Parsing(_)));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(3));
+        assert_eq!(engine.account(2).unwrap().available, Decimal::from(2));
+        assert_eq!(engine.account(2).unwrap().held, Decimal::from(1));
+    }
+
+    #[test]
+    fn test_process_record_batch_columns() {
+        // The text amounts and the column mapping of the csv options apply like to a csv input
+        let mut engine = Engine::new();
+        let mut config = engine.config().clone();
+        config
+            .csv
+            .columns
+            .insert("customer".to_string(), "client".to_string());
+        engine.set_config(config);
+        let batch = RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec!["deposit", "deposit", "deposit"])) as ArrayRef,
+            ),
+            ("customer", Arc::new(Int64Array::from(vec![1, 1, 70_000]))),
+            ("tx", Arc::new(Int64Array::from(vec![1, 2, 3]))),
+            (
+                "amount",
+                Arc::new(StringArray::from(vec![" 1.5", "abc", "1"])),
+            ),
+        ])
+        .unwrap();
+        let errors = engine.process_record_batch(&batch).unwrap();
+        let rejected = errors.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        assert_eq!(rejected, vec![1, 2]);
+        assert!(matches!(errors[0].1, RowProcessingError::Arrow(_)));
+        assert_eq!(
+            engine.account(1).unwrap().available,
+            Decimal::from_str("1.5").unwrap()
+        );
+
+        // A batch without a required column is rejected as a whole
+        let batch = RecordBatch::try_from_iter([(
+            "type",
+            Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef,
+        )])
+        .unwrap();
+        assert!(matches!(
+            Engine::new().process_record_batch(&batch),
+            Err(ArrowError::SchemaError(_))
+        ));
+    }
+}
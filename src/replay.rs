@@ -0,0 +1,100 @@
+use crate::engine::Engine;
+use crate::record::lossy;
+use crate::store::StateStore;
+use crate::types::{CsvInputRow, RowProcessingError, TransactionId};
+use csv::StringRecord;
+use std::io::Read;
+
+/// Where `Engine::replay_csv` stops processing the input
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReplayUntil {
+    /// Stop right before the first row with this transaction id, so its account is in the state before it
+    Tx(TransactionId),
+    /// Stop after the row at this line of the input, the header is line 1
+    Line(u64),
+}
+
+impl ReplayUntil {
+    fn reached(&self, line: u64, row: &CsvInputRow) -> bool {
+        match self {
+            ReplayUntil::Tx(tx) => row.tx == *tx,
+            ReplayUntil::Line(until) => line > *until,
+        }
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Same as `process_csv_with_errors` but stops at `until`, returns whether `until` was reached
+    /// When it is not reached the whole input is processed
+    pub fn replay_csv<E>(
+        &mut self,
+        input: impl Read,
+        until: ReplayUntil,
+        mut on_error: impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<bool, E> {
+        let mut reached = false;
+        self.process_csv_until(
+            input,
+            |line, row| {
+                reached = until.reached(line, row);
+                reached
+            },
+            |engine, line, record, result| {
+                engine.report_rejected_occurrences(line, &mut on_error)?;
+                match result {
+                    Ok(_) => Ok(()),
+                    Err(err) => on_error(line, &lossy(record), err),
+                }
+            },
+        )?;
+        Ok(reached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TransactionState;
+    use rust_decimal::Decimal;
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,1,1,5
+deposit,1,2,3
+dispute,1,1,
+withdrawal,1,3,1
+";
+
+    fn replay(until: ReplayUntil) -> (Engine, bool) {
+        let mut engine = Engine::new();
+        let reached = engine
+            .replay_csv(INPUT.as_bytes(), until, |_, _, err| Err(err))
+            .unwrap();
+        (engine, reached)
+    }
+
+    #[test]
+    fn test_replay_until_tx() {
+        let (engine, reached) = replay(ReplayUntil::Tx(3));
+        assert!(reached);
+        let profile = engine.account(1).unwrap();
+        assert_eq!(profile.available, Decimal::from(3));
+        assert_eq!(
+            profile.transactions.deposit(1).unwrap().state(),
+            TransactionState::UnderDispute
+        );
+        assert!(profile.transactions.withdrawals().next().is_none());
+
+        let (engine, reached) = replay(ReplayUntil::Tx(4));
+        assert!(!reached);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(2));
+    }
+
+    #[test]
+    fn test_replay_until_line() {
+        let (engine, reached) = replay(ReplayUntil::Line(3));
+        assert!(reached);
+        let profile = engine.account(1).unwrap();
+        assert_eq!(profile.available, Decimal::from(8));
+        assert!(profile.transactions.deposit(1).unwrap().state() != TransactionState::UnderDispute);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::config::PrecisionPolicy;
+use crate::engine::Engine;
+use crate::output::AccountSummary;
+use crate::store::StateStore;
+use crate::types::{AccountProfile, ClientId, TransactionState};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+
+/// The output format of the report
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("invalid report format {s}, expected csv or json")),
+        }
+    }
+}
+
+/// Statistics of a single client computed from its accepted deposits and withdrawals
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct ClientReport {
+    pub client: ClientId,
+    pub deposits: usize,
+    pub withdrawals: usize,
+    /// The sum of the amounts of all deposits and withdrawals
+    pub volume: Decimal,
+    pub disputes: u32,
+    pub chargebacks: usize,
+    /// The largest amount of a deposit or withdrawal
+    pub largest_transaction: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl ClientReport {
+    pub fn new(
+        client: ClientId,
+        profile: &AccountProfile,
+        precision: Option<&PrecisionPolicy>,
+    ) -> Self {
+        let summary = AccountSummary::with_precision(client, profile, precision);
+        let records = || profile.transactions.values();
+        Self {
+            client,
+            deposits: profile.transactions.deposits().count(),
+            withdrawals: profile.transactions.withdrawals().count(),
+            volume: records()
+                .map(|record| record.amount)
+                .fold(Decimal::ZERO, Decimal::saturating_add),
+            disputes: records().map(|record| record.disputes()).sum(),
+            chargebacks: records()
+                .filter(|record| record.state() == TransactionState::Chargeback)
+                .count(),
+            largest_transaction: records()
+                .map(|record| record.amount)
+                .max()
+                .unwrap_or_default(),
+            available: summary.available,
+            held: summary.held,
+            total: summary.total,
+            locked: summary.locked,
+        }
+    }
+}
+
+impl ClientReport {
+    /// Round the volume and the largest transaction with `precision` too, the balances are already rounded
+    pub fn with_fixed_scale(self, precision: &PrecisionPolicy) -> Self {
+        Self {
+            volume: precision.rescale(self.volume),
+            largest_transaction: precision.rescale(self.largest_transaction),
+            ..self
+        }
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// The reports of all selected clients in arbitrary order, see `Engine::is_selected`
+    pub fn reports(&self) -> impl Iterator<Item = ClientReport> {
+        self.accounts()
+            .filter(|(client, _)| self.is_selected(*client))
+            .map(|(client, profile)| {
+                let precision = self.config().output_precision();
+                let report = ClientReport::new(client, profile, precision.as_ref());
+                match (self.config().output_scale, precision) {
+                    (Some(_), Some(precision)) => report.with_fixed_scale(&precision),
+                    _ => report,
+                }
+            })
+    }
+}
+
+/// Write the `reports` into `writer`, json is written as a single array
+pub fn write_reports(
+    reports: impl IntoIterator<Item = ClientReport>,
+    format: ReportFormat,
+    mut writer: impl Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        ReportFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            for report in reports {
+                wtr.serialize(report)?;
+            }
+            wtr.flush()?;
+        }
+        ReportFormat::Json => {
+            let reports = reports.into_iter().collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut writer, &reports)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Transaction;
+
+    #[test]
+    fn test_reports() {
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        engine
+            .process_transaction(1, 2, Transaction::Deposit(Decimal::from(2)))
+            .unwrap();
+        engine
+            .process_transaction(1, 3, Transaction::Withdrawal(Decimal::from(3)))
+            .unwrap();
+        engine
+            .process_transaction(1, 2, Transaction::Dispute(None))
+            .unwrap();
+        engine
+            .process_transaction(1, 2, Transaction::Chargeback(None))
+            .unwrap();
+
+        let mut output = vec![];
+        write_reports(engine.reports(), ReportFormat::Csv, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,deposits,withdrawals,volume,disputes,chargebacks,largest_transaction,available,held,total,locked\n\
+             1,2,1,10,1,1,5,2.0000,0.0000,2.0000,true\n"
+        );
+
+        let mut output = vec![];
+        write_reports(engine.reports(), ReportFormat::Json, &mut output).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json[0]["chargebacks"], 1);
+
+        let mut config = engine.config().clone();
+        config.output_scale = Some(2);
+        engine.set_config(config);
+        let mut output = vec![];
+        write_reports(engine.reports(), ReportFormat::Csv, &mut output).unwrap();
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .ends_with("\n1,2,1,10.00,1,1,5.00,2.00,0.00,2.00,true\n")
+        );
+    }
+}
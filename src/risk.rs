@@ -0,0 +1,200 @@
+use crate::config::{boolean, decimal, integer, table};
+use crate::types::{ConfigError, RiskLimit, Timestamp, Transaction};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use toml_edit::{DocumentMut, TableLike};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Limits on the deposits and withdrawals of every client, they are checked before a transaction is applied
+/// The daily limits use the UTC day of the row timestamp, the rows without a timestamp count as a single day
+/// A transfer counts as a withdrawal of the source and a deposit of the destination
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RiskRules {
+    pub max_deposit: Option<Decimal>,
+    pub max_daily_withdrawals: Option<u32>,
+    /// The limit of the deposits plus the withdrawals of a client in a day
+    pub max_daily_volume: Option<Decimal>,
+    /// Freeze the account when a limit is exceeded, otherwise only the transaction is rejected
+    pub freeze: bool,
+}
+
+impl RiskRules {
+    /// Parse the `[risk]` table of a TOML document, the rules are empty without the table
+    /// ```toml
+    /// [risk]
+    /// max_deposit = "10000"
+    /// max_daily_withdrawals = 5
+    /// max_daily_volume = 50000
+    /// freeze = true
+    /// ```
+    pub fn parse(toml: &str) -> Result<Self, ConfigError> {
+        let document = DocumentMut::from_str(toml)?;
+        match document.get("risk") {
+            Some(item) => Self::from_table(table(item, "risk")?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub(crate) fn from_table(table: &dyn TableLike) -> Result<Self, ConfigError> {
+        let mut rules = Self::default();
+        for (key, item) in table.iter() {
+            let name = format!("risk.{key}");
+            match key {
+                "max_deposit" => rules.max_deposit = Some(decimal(item, &name)?),
+                "max_daily_withdrawals" => {
+                    rules.max_daily_withdrawals = Some(integer(item, &name)?);
+                }
+                "max_daily_volume" => rules.max_daily_volume = Some(decimal(item, &name)?),
+                "freeze" => rules.freeze = boolean(item, &name)?,
+                _ => return Err(ConfigError::UnknownKey(name)),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// The limit `transaction` would exceed with the counters of its account
+    pub fn check(
+        &self,
+        counters: &RiskCounters,
+        transaction: &Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), RiskLimit> {
+        let (amount, withdrawal) = match *transaction {
+            Transaction::Deposit(amount) => (amount, false),
+            Transaction::Withdrawal(amount) => (amount, true),
+            _ => return Ok(()),
+        };
+        if !withdrawal
+            && let Some(max) = self.max_deposit
+            && amount > max
+        {
+            return Err(RiskLimit::MaxDeposit(max));
+        }
+        let (withdrawals, volume) = counters.today(day(timestamp));
+        if withdrawal
+            && let Some(max) = self.max_daily_withdrawals
+            && withdrawals >= max
+        {
+            return Err(RiskLimit::DailyWithdrawals(max));
+        }
+        if let Some(max) = self.max_daily_volume
+            && volume.checked_add(amount).is_none_or(|volume| volume > max)
+        {
+            return Err(RiskLimit::DailyVolume(max));
+        }
+        Ok(())
+    }
+}
+
+/// The counters of an account for the daily limits of `RiskRules`
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RiskCounters {
+    /// The day of the counted transactions, None for the transactions without a timestamp
+    pub day: Option<u64>,
+    pub withdrawals: u32,
+    pub volume: Decimal,
+    /// Whether a limit froze the account
+    pub tripped: bool,
+}
+
+impl RiskCounters {
+    /// Count an accepted deposit or withdrawal, the counters restart on a new day
+    pub fn record(&mut self, transaction: &Transaction, timestamp: Option<Timestamp>) {
+        let (amount, withdrawal) = match *transaction {
+            Transaction::Deposit(amount) => (amount, false),
+            Transaction::Withdrawal(amount) => (amount, true),
+            _ => return,
+        };
+        let (withdrawals, volume) = self.today(day(timestamp));
+        self.day = day(timestamp);
+        self.withdrawals = withdrawals.saturating_add(u32::from(withdrawal));
+        self.volume = volume.saturating_add(amount);
+    }
+
+    fn today(&self, day: Option<u64>) -> (u32, Decimal) {
+        if self.day == day {
+            (self.withdrawals, self.volume)
+        } else {
+            (0, Decimal::ZERO)
+        }
+    }
+}
+
+fn day(timestamp: Option<Timestamp>) -> Option<u64> {
+    timestamp.map(|timestamp| timestamp / SECONDS_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_risk_rules() {
+        let rules = RiskRules::parse(
+            "[risk]\nmax_deposit = \"100.5\"\nmax_daily_withdrawals = 2\nmax_daily_volume = 300\nfreeze = true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            RiskRules {
+                max_deposit: Some(Decimal::new(1005, 1)),
+                max_daily_withdrawals: Some(2),
+                max_daily_volume: Some(Decimal::from(300)),
+                freeze: true,
+            }
+        );
+        assert_eq!(RiskRules::parse("").unwrap(), RiskRules::default());
+        assert!(matches!(
+            RiskRules::parse("[risk]\nmax_deposits = 1\n"),
+            Err(ConfigError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            RiskRules::parse("[risk]\nmax_daily_withdrawals = -1\n"),
+            Err(ConfigError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            RiskRules::parse("[risk\n"),
+            Err(ConfigError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn test_risk_counters() {
+        let rules = RiskRules {
+            max_daily_withdrawals: Some(1),
+            max_daily_volume: Some(Decimal::from(10)),
+            ..Default::default()
+        };
+        let mut counters = RiskCounters::default();
+        let withdrawal = Transaction::Withdrawal(Decimal::from(4));
+        assert!(rules.check(&counters, &withdrawal, Some(10)).is_ok());
+        counters.record(&withdrawal, Some(10));
+        assert_eq!(
+            rules.check(&counters, &withdrawal, Some(20)),
+            Err(RiskLimit::DailyWithdrawals(1))
+        );
+        let deposit = Transaction::Deposit(Decimal::from(7));
+        assert_eq!(
+            rules.check(&counters, &deposit, Some(20)),
+            Err(RiskLimit::DailyVolume(Decimal::from(10)))
+        );
+        // The next day starts from zero
+        assert!(
+            rules
+                .check(&counters, &withdrawal, Some(SECONDS_PER_DAY))
+                .is_ok()
+        );
+        assert!(
+            rules
+                .check(&counters, &deposit, Some(SECONDS_PER_DAY))
+                .is_ok()
+        );
+        counters.record(&deposit, Some(SECONDS_PER_DAY));
+        assert_eq!(
+            (counters.withdrawals, counters.volume),
+            (0, Decimal::from(7))
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use crate::engine::Engine;
+use crate::store::StateStore;
+use crate::types::{
+    ClientId, CsvInputRow, RowProcessingError, RowRejection, Timestamp, Transaction, TransactionId,
+    TransactionParsingError, TransactionProcessingError,
+};
+use csv::StringRecord;
+use std::collections::BTreeMap;
+use std::mem;
+
+/// A deposit or withdrawal which recurs `count` times every `interval` seconds
+/// The occurrences use consecutive transaction ids starting at the tx id of the schedule row
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Schedule {
+    pub client: ClientId,
+    /// The transaction id of the next occurrence
+    pub tx: TransactionId,
+    pub transaction: Transaction,
+    /// The timestamp of the next occurrence
+    pub next: Timestamp,
+    pub interval: u64,
+    /// The number of occurrences left
+    pub count: u32,
+}
+
+impl Schedule {
+    /// Parse a schedule row, a positive amount schedules deposits and a negative amount withdrawals
+    /// The first occurrence is at the timestamp of the row
+    pub fn parse(row: &CsvInputRow) -> Result<Self, TransactionParsingError> {
+        let amount = row.amount.ok_or(TransactionParsingError::MissingAmount)?;
+        let (Some(next), Some(interval), Some(count)) = (
+            row.timestamp,
+            row.interval.filter(|interval| *interval > 0),
+            row.count,
+        ) else {
+            return Err(TransactionParsingError::IncompleteSchedule);
+        };
+        let transaction = if amount.is_sign_negative() {
+            Transaction::Withdrawal(-amount)
+        } else {
+            Transaction::Deposit(amount)
+        };
+        Ok(Self {
+            client: row.client,
+            tx: row.tx,
+            transaction,
+            next,
+            interval,
+            count,
+        })
+    }
+
+    /// The transaction id of the last occurrence
+    pub fn last_tx(&self) -> TransactionId {
+        self.tx.saturating_add(self.count.saturating_sub(1))
+    }
+
+    /// The schedule after its next occurrence, None when it was the last one
+    fn advance(self) -> Option<Self> {
+        Some(Self {
+            tx: self.tx.checked_add(1)?,
+            next: self.next.checked_add(self.interval)?,
+            count: self.count.checked_sub(1).filter(|count| *count > 0)?,
+            ..self
+        })
+    }
+}
+
+/// An occurrence of a schedule which was rejected, e.g. a withdrawal without enough funds
+#[derive(Debug)]
+pub struct RejectedOccurrence {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub transaction: Transaction,
+    pub timestamp: Timestamp,
+    pub error: TransactionProcessingError,
+}
+
+impl RejectedOccurrence {
+    /// The occurrence as a row with the `type,client,tx,amount,timestamp` columns
+    pub fn record(&self) -> StringRecord {
+        let amount = self.transaction.amount().unwrap_or_default();
+        StringRecord::from(vec![
+            self.transaction.type_name().to_string(),
+            self.client.to_string(),
+            self.tx.to_string(),
+            amount.to_string(),
+            self.timestamp.to_string(),
+        ])
+    }
+}
+
+/// The pending schedules ordered by their next occurrence, schedules due at the same time keep the order
+/// they were added in
+#[derive(Debug, Default)]
+pub struct Schedules {
+    pending: BTreeMap<(Timestamp, u64), Schedule>,
+    /// The ids left to the occurrences by client and next id, with the last id
+    reserved: BTreeMap<(ClientId, TransactionId), TransactionId>,
+    added: u64,
+}
+
+impl Schedules {
+    pub fn push(&mut self, schedule: Schedule) {
+        if schedule.count > 0 {
+            self.pending.insert((schedule.next, self.added), schedule);
+            self.reserved
+                .insert((schedule.client, schedule.tx), schedule.last_tx());
+            self.added += 1;
+        }
+    }
+
+    /// Remove the schedule with the earliest next occurrence if it is due at `until`
+    fn pop_due(&mut self, until: Timestamp) -> Option<Schedule> {
+        let entry = self.pending.first_entry()?;
+        let schedule = (entry.key().0 <= until).then(|| entry.remove())?;
+        self.reserved.remove(&(schedule.client, schedule.tx));
+        Some(schedule)
+    }
+
+    /// Whether `tx` is left to an occurrence of a pending schedule of `client`
+    pub fn reserves(&self, client: ClientId, tx: TransactionId) -> bool {
+        self.reserved
+            .range(..=(client, tx))
+            .next_back()
+            .is_some_and(|(&(owner, _), &last)| owner == client && tx <= last)
+    }
+
+    /// Whether any id of the occurrences of `schedule` is left to a pending schedule of the same client
+    fn overlaps(&self, schedule: &Schedule) -> bool {
+        self.reserves(schedule.client, schedule.tx)
+            || self
+                .reserved
+                .range((schedule.client, schedule.tx)..=(schedule.client, schedule.last_tx()))
+                .next()
+                .is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Schedule> {
+        self.pending.values()
+    }
+
+    /// Remove all schedules in the order of their next occurrence
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Schedule> {
+        self.reserved.clear();
+        mem::take(&mut self.pending).into_values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Parse a schedule row and add it, the amount goes through the same policies as a deposit or withdrawal
+    /// The row is logged into the write-ahead log, its occurrences are not
+    /// The ids of the occurrences are reserved, a schedule using an id left to another one of the client is rejected
+    pub(crate) fn process_schedule_row(&mut self, row: &CsvInputRow) -> Result<(), RowRejection> {
+        let mut schedule = Schedule::parse(row)?;
+        schedule.transaction = self.apply_policies(schedule.transaction)?;
+        if self.schedules.overlaps(&schedule) {
+            return Err(TransactionProcessingError::InvalidTransactionId(schedule.tx).into());
+        }
+        #[cfg(feature = "fs")]
+        if let Some(wal) = &mut self.wal {
+            wal.append_row(row)
+                .map_err(crate::types::TransactionProcessingError::WalWriteFailed)?;
+        }
+        self.without_wal(|engine| engine.add_schedule(schedule));
+        Ok(())
+    }
+
+    /// Add a schedule, its occurrences are processed once the replay reaches their timestamp
+    /// The first occurrence is due right away
+    pub fn add_schedule(&mut self, schedule: Schedule) {
+        self.schedules.push(schedule);
+        self.run_schedules(schedule.next);
+    }
+
+    /// Process the occurrences due at or before `until` in timestamp order, returns how many were accepted
+    /// A rejected occurrence, e.g. a withdrawal without enough funds, is skipped and the schedule goes on,
+    /// it is kept until the next row for `report_rejected_occurrences`
+    pub fn run_schedules(&mut self, until: Timestamp) -> usize {
+        let mut accepted = 0;
+        while let Some(schedule) = self.schedules.pop_due(until) {
+            let res = self.process_transaction_at(
+                schedule.client,
+                schedule.tx,
+                schedule.transaction,
+                Some(schedule.next),
+            );
+            match res {
+                Ok(()) => accepted += 1,
+                Err(error) => self.rejected_occurrences.push(RejectedOccurrence {
+                    client: schedule.client,
+                    tx: schedule.tx,
+                    transaction: schedule.transaction,
+                    timestamp: schedule.next,
+                    error,
+                }),
+            }
+            if let Some(schedule) = schedule.advance() {
+                self.schedules.push(schedule);
+            }
+        }
+        accepted
+    }
+
+    /// Pass the occurrences rejected while the last row was applied to `on_error` with the line of that row,
+    /// they are reported before the row since they were due before it
+    pub(crate) fn report_rejected_occurrences<E>(
+        &mut self,
+        line: u64,
+        on_error: &mut impl FnMut(u64, &StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for occurrence in mem::take(&mut self.rejected_occurrences) {
+            on_error(line, &occurrence.record(), occurrence.error.into())?;
+        }
+        Ok(())
+    }
+
+    /// The schedules which have occurrences left
+    pub fn schedules(&self) -> &Schedules {
+        &self.schedules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RowProcessingError;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_schedules() {
+        let input = "type,client,tx,amount,timestamp,interval,count\n\
+            deposit,1,1,10,0,,\n\
+            schedule,1,100,-3,10,10,4\n\
+            schedule,2,200,5,15,,\n\
+            deposit,2,2,1,25,,\n\
+            dispute,1,101,,26,,\n\
+            deposit,1,103,1,27,,\n\
+            schedule,1,102,1,28,10,2\n\
+            deposit,1,3,1,40,,\n";
+        let mut engine = Engine::new();
+        let mut errors = Vec::new();
+        engine
+            .process_csv_with_errors(input.as_bytes(), |line, record, err| {
+                let row = record.iter().collect::<Vec<_>>().join(",");
+                errors.push((line, err.reason(), row));
+                Ok::<(), RowProcessingError>(())
+            })
+            .unwrap();
+        // The ids up to 103 are left to the schedule of client 1 until its last occurrence
+        // The withdrawals at 10, 20 and 30 are accepted, the one at 40 is processed before the deposit at 40
+        // and is rejected for insufficient funds, it is reported with the line of that deposit
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|(line, reason, row)| (*line, *reason, row.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (4, "incomplete_schedule", "schedule,2,200,5,15,,"),
+                (7, "invalid_transaction_id", "deposit,1,103,1,27,,"),
+                (8, "invalid_transaction_id", "schedule,1,102,1,28,10,2"),
+                (9, "insufficient_funds", "withdrawal,1,103,3,40"),
+            ]
+        );
+        let profile = engine.account(1).unwrap();
+        assert_eq!(profile.available, Decimal::from(2));
+        assert_eq!(profile.held, Decimal::from(3));
+        assert_eq!(profile.transactions.withdrawals().count(), 3);
+        assert_eq!(
+            profile.transactions.withdrawal(102).unwrap().timestamp,
+            Some(30)
+        );
+        assert!(engine.schedules().is_empty());
+    }
+
+    #[test]
+    fn test_pending_schedules() {
+        let mut engine = Engine::new();
+        engine.add_schedule(Schedule {
+            client: 1,
+            tx: 1,
+            transaction: Transaction::Deposit(Decimal::from(2)),
+            next: 100,
+            interval: 60,
+            count: 3,
+        });
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(2));
+        assert_eq!(engine.schedules().len(), 1);
+        assert_eq!(engine.run_schedules(200), 1);
+        assert_eq!(engine.schedules().iter().next().unwrap().next, 220);
+        assert_eq!(engine.run_schedules(Timestamp::MAX), 1);
+        assert!(engine.schedules().is_empty());
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(6));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::engine::Engine;
+use crate::metrics::ServiceMetrics;
+use crate::output::AccountSummary;
+use crate::types::{
+    ClientId, CsvInputRow, RowProcessingError, Transaction, TransactionId,
+    TransactionProcessingError,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// How many updates can be queued for a subscriber, one which falls further behind is dropped
+pub const SUBSCRIBER_QUEUE_SIZE: usize = 1024;
+
+/// The new balances of an account after the transaction `tx` changed it
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct AccountUpdate {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+/// A thread safe handle to a shared engine for long-running services
+/// It is transport agnostic, the server modes only translate their requests into these calls,
+/// so every transaction still goes through `Engine::process_transaction`
+#[derive(Debug, Clone, Default)]
+pub struct EngineService {
+    engine: Arc<Mutex<Engine>>,
+    metrics: Arc<Mutex<ServiceMetrics>>,
+    subscribers: Arc<Mutex<Vec<SyncSender<AccountUpdate>>>>,
+}
+
+impl EngineService {
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engine: Arc::new(Mutex::new(engine)),
+            metrics: Arc::default(),
+            subscribers: Arc::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Engine> {
+        self.engine.lock().expect("engine lock poisoned")
+    }
+
+    pub fn submit_transaction(
+        &self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let start = Instant::now();
+        let mut engine = self.lock();
+        let res = engine.process_transaction(client, id, transaction);
+        if res.is_ok() {
+            let to = match transaction {
+                Transaction::Transfer(to, _) => Some(to),
+                _ => None,
+            };
+            self.publish(&engine, id, [Some(client), to]);
+        }
+        drop(engine);
+        let rejection = res.as_ref().err().map(|err| err.reason());
+        self.record(transaction.type_name(), rejection, start);
+        res
+    }
+
+    pub fn submit_row(&self, row: &CsvInputRow) -> Result<(), RowProcessingError> {
+        let start = Instant::now();
+        let mut engine = self.lock();
+        let res = engine.process_row(row);
+        if res.is_ok() {
+            self.publish(&engine, row.tx, [Some(row.client), row.to]);
+        }
+        drop(engine);
+        let rejection = res.as_ref().err().map(|err| err.reason());
+        self.record(row.transaction_type.name(), rejection, start);
+        res
+    }
+
+    /// Receive an `AccountUpdate` for every account changed by a transaction submitted after this call
+    /// The subscription ends when the receiver is dropped, or when `SUBSCRIBER_QUEUE_SIZE` updates are not received
+    /// yet, so a slow subscriber never holds up the transactions nor grows the memory
+    pub fn subscribe(&self) -> Receiver<AccountUpdate> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE_SIZE);
+        self.subscribers
+            .lock()
+            .expect("subscribers lock poisoned")
+            .push(sender);
+        receiver
+    }
+
+    /// Send the balances of `clients` to the subscribers, this is called with the engine locked so
+    /// every subscriber sees the updates in the order the transactions were processed
+    fn publish(&self, engine: &Engine, tx: TransactionId, clients: [Option<ClientId>; 2]) {
+        let mut subscribers = self.subscribers.lock().expect("subscribers lock poisoned");
+        if subscribers.is_empty() {
+            return;
+        }
+        let mut clients = clients.into_iter().flatten().collect::<Vec<_>>();
+        clients.dedup();
+        for summary in clients
+            .into_iter()
+            .filter_map(|client| engine.summary(client))
+        {
+            let update = AccountUpdate {
+                client: summary.client,
+                tx,
+                available: summary.available,
+                held: summary.held,
+                locked: summary.locked,
+            };
+            subscribers.retain(|subscriber| subscriber.try_send(update.clone()).is_ok());
+        }
+    }
+
+    fn record(
+        &self,
+        transaction_type: &'static str,
+        rejection: Option<&'static str>,
+        start: Instant,
+    ) {
+        self.metrics.lock().expect("metrics lock poisoned").record(
+            transaction_type,
+            rejection,
+            start.elapsed(),
+        );
+    }
+
+    /// The metrics of the submitted transactions in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        let accounts = self.accounts();
+        self.metrics
+            .lock()
+            .expect("metrics lock poisoned")
+            .render(&accounts)
+    }
+
+    pub fn get_account(&self, client: ClientId) -> Option<AccountSummary> {
+        self.lock().summary(client)
+    }
+
+    /// A consistent view of all accounts at the time of the call
+    pub fn accounts(&self) -> Vec<AccountSummary> {
+        self.lock().summaries().collect()
+    }
+
+    /// Run `f` with exclusive access to the engine, e.g. to save a snapshot
+    pub fn with_engine<T>(&self, f: impl FnOnce(&mut Engine) -> T) -> T {
+        f(&mut self.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_submissions() {
+        let service = EngineService::default();
+        let handles: Vec<_> = (0..4u32)
+            .map(|i| {
+                let service = service.clone();
+                thread::spawn(move || {
+                    for tx in 0..100 {
+                        let res = service.submit_transaction(
+                            1,
+                            i * 100 + tx,
+                            Transaction::Deposit(Decimal::from(1)),
+                        );
+                        assert!(res.is_ok());
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let account = service.get_account(1).unwrap();
+        assert_eq!(account.available, Decimal::from(400));
+        assert!(service.get_account(2).is_none());
+        assert_eq!(service.accounts().len(), 1);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let service = EngineService::default();
+        let updates = service.subscribe();
+        let dropped = service.subscribe();
+        drop(dropped);
+
+        service
+            .submit_transaction(1, 1, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        assert!(
+            service
+                .submit_transaction(1, 2, Transaction::Withdrawal(Decimal::from(9)))
+                .is_err()
+        );
+        service
+            .submit_transaction(1, 3, Transaction::Transfer(2, Decimal::from(2)))
+            .unwrap();
+
+        let updates: Vec<_> = updates
+            .try_iter()
+            .map(|u| (u.client, u.tx, u.available))
+            .collect();
+        assert_eq!(
+            updates,
+            [
+                (1, 1, Decimal::from(5)),
+                (1, 3, Decimal::from(3)),
+                (2, 3, Decimal::from(2)),
+            ]
+        );
+        assert_eq!(service.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_lagging_subscriber() {
+        let service = EngineService::default();
+        let lagging = service.subscribe();
+        let updates = service.subscribe();
+        for tx in 0..=SUBSCRIBER_QUEUE_SIZE as u32 {
+            service
+                .submit_transaction(1, tx, Transaction::Deposit(Decimal::ONE))
+                .unwrap();
+            assert_eq!(updates.recv().unwrap().tx, tx);
+        }
+        // The queue of the lagging one is full, so it was dropped with the updates it received
+        assert_eq!(service.subscribers.lock().unwrap().len(), 1);
+        assert_eq!(lagging.try_iter().count(), SUBSCRIBER_QUEUE_SIZE);
+        assert!(lagging.recv().is_err());
+    }
+}
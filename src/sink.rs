@@ -0,0 +1,287 @@
+use crate::ledger::LedgerEntry;
+use crate::output::AccountSummary;
+use crate::types::OutputError;
+use std::io::Write;
+
+/// The table `SqlSink` writes to by default
+pub const DEFAULT_SQL_TABLE: &str = "accounts";
+
+/// A destination of the account summaries, e.g. a file, a database or a message queue
+/// `write_account` is called for every account and `finish` once after the last one
+pub trait OutputSink {
+    fn write_account(&mut self, summary: &AccountSummary) -> Result<(), OutputError>;
+
+    /// Complete the output, e.g. close a json array, and flush it
+    fn finish(&mut self) -> Result<(), OutputError>;
+}
+
+/// Write all `summaries` into `sink` and finish it
+pub fn write_to_sink(
+    summaries: impl IntoIterator<Item = AccountSummary>,
+    sink: &mut (impl OutputSink + ?Sized),
+) -> Result<(), OutputError> {
+    for summary in summaries {
+        sink.write_account(&summary)?;
+    }
+    sink.finish()
+}
+
+/// The summaries as csv with a header
+pub struct CsvSink<W: Write> {
+    wtr: csv::Writer<W>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            wtr: csv::Writer::from_writer(writer),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write_account(&mut self, summary: &AccountSummary) -> Result<(), OutputError> {
+        Ok(self.wtr.serialize(summary)?)
+    }
+
+    fn finish(&mut self) -> Result<(), OutputError> {
+        Ok(self.wtr.flush()?)
+    }
+}
+
+/// The summaries as a single json array, it is streamed so the accounts are never collected
+pub struct JsonSink<W: Write> {
+    writer: W,
+    first: bool,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            first: true,
+        }
+    }
+}
+
+impl<W: Write> OutputSink for JsonSink<W> {
+    fn write_account(&mut self, summary: &AccountSummary) -> Result<(), OutputError> {
+        self.writer
+            .write_all(if self.first { b"[" } else { b"," })?;
+        self.first = false;
+        Ok(serde_json::to_writer(&mut self.writer, summary)?)
+    }
+
+    fn finish(&mut self) -> Result<(), OutputError> {
+        if self.first {
+            self.writer.write_all(b"[")?;
+            self.first = false;
+        }
+        self.writer.write_all(b"]\n")?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// One json object per line
+pub struct JsonlSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> OutputSink for JsonlSink<W> {
+    fn write_account(&mut self, summary: &AccountSummary) -> Result<(), OutputError> {
+        serde_json::to_writer(&mut self.writer, summary)?;
+        Ok(self.writer.write_all(b"\n")?)
+    }
+
+    fn finish(&mut self) -> Result<(), OutputError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// The summaries as SQL statements which upsert them into a table by `client` in a single transaction,
+/// e.g. to pipe them into `psql` or `sqlite3`
+/// The table needs a unique `client` column, a `fees_collected` column when a fee policy is configured, and the
+/// `open_disputes`, `resolved_disputes`, `chargebacks` and `disputed_amount_held` columns with the extended output
+pub struct SqlSink<W: Write> {
+    writer: W,
+    table: String,
+    started: bool,
+}
+
+impl<W: Write> SqlSink<W> {
+    pub fn new(writer: W, table: &str) -> Self {
+        Self {
+            writer,
+            table: quote_identifier(table),
+            started: false,
+        }
+    }
+
+    /// Append the ledger `entries` to `table` in the same transaction as the accounts
+    /// The table has the columns of the `--audit-log` csv, the entries of every run are appended
+    pub fn write_ledger(
+        &mut self,
+        table: &str,
+        entries: &[LedgerEntry],
+    ) -> Result<(), OutputError> {
+        self.begin()?;
+        let table = quote_identifier(table);
+        for entry in entries {
+            writeln!(
+                self.writer,
+                "INSERT INTO {table} (\"timestamp\", client, tx, \"type\", amount, available, held, locked, rejection, \
+                 reason) VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {});",
+                entry.timestamp,
+                entry.client,
+                entry.tx,
+                quote_literal(&entry.transaction_type),
+                entry
+                    .amount
+                    .map_or_else(|| "NULL".to_string(), |amount| amount.to_string()),
+                entry.available,
+                entry.held,
+                entry.locked,
+                entry
+                    .rejection
+                    .as_deref()
+                    .map_or_else(|| "NULL".to_string(), quote_literal),
+                entry
+                    .reason
+                    .map_or_else(|| "NULL".to_string(), |reason| reason.to_string()),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn begin(&mut self) -> Result<(), OutputError> {
+        if !self.started {
+            self.writer.write_all(b"BEGIN;\n")?;
+            self.started = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputSink for SqlSink<W> {
+    fn write_account(&mut self, summary: &AccountSummary) -> Result<(), OutputError> {
+        self.begin()?;
+        let optional = [
+            (
+                "fees_collected",
+                summary.fees_collected.map(|fees| fees.to_string()),
+            ),
+            (
+                "open_disputes",
+                summary.open_disputes.map(|count| count.to_string()),
+            ),
+            (
+                "resolved_disputes",
+                summary.resolved_disputes.map(|count| count.to_string()),
+            ),
+            (
+                "chargebacks",
+                summary.chargebacks.map(|count| count.to_string()),
+            ),
+            (
+                "disputed_amount_held",
+                summary
+                    .disputed_amount_held
+                    .map(|amount| amount.to_string()),
+            ),
+        ];
+        let (mut columns, mut values, mut updates) = (String::new(), String::new(), String::new());
+        for (column, value) in optional {
+            if let Some(value) = value {
+                columns.push_str(&format!(", {column}"));
+                values.push_str(&format!(", {value}"));
+                updates.push_str(&format!(", {column} = EXCLUDED.{column}"));
+            }
+        }
+        writeln!(
+            self.writer,
+            "INSERT INTO {} (client, available, held, total, locked{columns}) \
+             VALUES ({}, {}, {}, {}, {}{values}) \
+             ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, held = EXCLUDED.held, \
+             total = EXCLUDED.total, locked = EXCLUDED.locked{updates};",
+            self.table,
+            summary.client,
+            summary.available,
+            summary.held,
+            summary.total,
+            summary.locked,
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), OutputError> {
+        self.begin()?;
+        self.writer.write_all(b"COMMIT;\n")?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// A table name as a quoted SQL identifier, a `.` separates the schema from the table
+pub(crate) fn quote_identifier(name: &str) -> String {
+    name.split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn summary(client: u16) -> AccountSummary {
+        AccountSummary {
+            client,
+            available: Decimal::new(15000, 4),
+            held: Decimal::ZERO,
+            total: Decimal::new(15000, 4),
+            locked: false,
+            fees_collected: None,
+            open_disputes: None,
+            resolved_disputes: None,
+            chargebacks: None,
+            disputed_amount_held: None,
+        }
+    }
+
+    #[test]
+    fn test_json_sink() {
+        let mut output = vec![];
+        write_to_sink([summary(1), summary(2)], &mut JsonSink::new(&mut output)).unwrap();
+        let accounts: Vec<AccountSummary> = serde_json::from_slice(&output).unwrap();
+        assert_eq!(accounts, [summary(1), summary(2)]);
+
+        let mut output = vec![];
+        write_to_sink([], &mut JsonSink::new(&mut output)).unwrap();
+        assert_eq!(output, b"[]\n");
+    }
+
+    #[test]
+    fn test_sql_sink() {
+        let mut output = vec![];
+        write_to_sink([summary(1)], &mut SqlSink::new(&mut output, "bank.a\"b")).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "BEGIN;
+INSERT INTO \"bank\".\"a\"\"b\" (client, available, held, total, locked) VALUES (1, 1.5000, 0, 1.5000, false) \
+ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, held = EXCLUDED.held, \
+total = EXCLUDED.total, locked = EXCLUDED.locked;
+COMMIT;
+"
+        );
+    }
+}
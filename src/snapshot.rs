@@ -0,0 +1,162 @@
+use crate::checkpoint::InputPosition;
+use crate::engine::Engine;
+use crate::store::StateStore;
+use crate::types::{AccountProfile, ClientId, SnapshotError};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The persisted state of an engine
+/// We store the full `AccountProfile` including the transactions and their dispute states,
+/// so that the following batches can still dispute the transactions from previous batches
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Snapshot {
+    accounts: HashMap<ClientId, AccountProfile>,
+    /// How far the inputs were applied, see `InputPosition`
+    #[serde(default)]
+    position: Option<InputPosition>,
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Save the state of all accounts into a json file at `path`
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        self.save_snapshot_at(path, None)
+    }
+
+    /// Same as `save_snapshot` with the position of the inputs the state belongs to, see `load_snapshot_at`
+    pub fn save_snapshot_at(
+        &self,
+        path: impl AsRef<Path>,
+        position: Option<InputPosition>,
+    ) -> Result<(), SnapshotError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_snapshot_at(&mut writer, position)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the state of all accounts as json into `writer`, in the format of `save_snapshot`
+    pub fn write_snapshot(&self, writer: impl Write) -> Result<(), SnapshotError> {
+        self.write_snapshot_at(writer, None)
+    }
+
+    /// Same as `write_snapshot` with the position of the inputs the state belongs to
+    pub(crate) fn write_snapshot_at(
+        &self,
+        writer: impl Write,
+        position: Option<InputPosition>,
+    ) -> Result<(), SnapshotError> {
+        serde_json::to_writer(
+            writer,
+            &SnapshotRef {
+                accounts: StoreRef(&self.store),
+                position,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Create an engine from a snapshot saved by `save_snapshot`
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Ok(Self::load_snapshot_at(path)?.0)
+    }
+
+    /// Same as `load_snapshot`, together with the position of the inputs if the snapshot has one
+    /// A run can continue right after the last row applied to the snapshot, so no row is applied twice
+    pub fn load_snapshot_at(
+        path: impl AsRef<Path>,
+    ) -> Result<(Self, Option<InputPosition>), SnapshotError> {
+        Self::read_snapshot(BufReader::new(File::open(path)?))
+    }
+
+    /// Create an engine from the json of a snapshot, together with its position if it has one
+    pub(crate) fn read_snapshot(
+        reader: impl Read,
+    ) -> Result<(Self, Option<InputPosition>), SnapshotError> {
+        let snapshot: Snapshot = serde_json::from_reader(reader)?;
+        let mut engine = Self::default();
+        for (client, profile) in snapshot.accounts {
+            engine.index_transactions(client, &profile);
+            engine.store.insert(client, profile);
+        }
+        Ok((engine, snapshot.position))
+    }
+}
+
+/// Same layout as `Snapshot` but borrows the state, so we don't need to clone the accounts when saving
+#[derive(Serialize)]
+#[serde(bound = "")]
+struct SnapshotRef<'a, S: StateStore> {
+    accounts: StoreRef<'a, S>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<InputPosition>,
+}
+
+/// Serialize the accounts of a store as a map
+struct StoreRef<'a, S: StateStore>(&'a S);
+
+impl<S: StateStore> Serialize for StoreRef<'_, S> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.collect_map(self.0.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Transaction, TransactionState};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::new(12345, 4)))
+            .unwrap();
+        engine
+            .process_transaction(1, 2, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        engine
+            .process_transaction(1, 3, Transaction::Withdrawal(Decimal::from(1)))
+            .unwrap();
+        engine
+            .process_transaction(1, 1, Transaction::Dispute(None))
+            .unwrap();
+        engine
+            .process_transaction(2, 4, Transaction::Deposit(Decimal::from(7)))
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+        engine.save_snapshot(&path).unwrap();
+        let mut restored: Engine = Engine::load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.store(), engine.store());
+
+        // The restored engine can continue the dispute from the previous run
+        assert_eq!(
+            restored
+                .account(1)
+                .unwrap()
+                .transactions
+                .deposit(1)
+                .unwrap()
+                .state(),
+            TransactionState::UnderDispute
+        );
+        assert!(
+            restored
+                .process_transaction(1, 1, Transaction::Resolve(None))
+                .is_ok()
+        );
+        // Replaying an overlapping file is rejected for the ids seen before the snapshot
+        assert!(restored.has_seen(4));
+        assert!(!restored.has_seen(5));
+        assert!(
+            restored
+                .process_transaction(2, 4, Transaction::Deposit(Decimal::from(7)))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::EngineConfig;
+use crate::engine::Engine;
+use crate::record::{ColumnIndex, deserialize_record};
+use crate::store::StateStore;
+use crate::types::{CsvInputRow, RowError};
+use csv::ByteRecord;
+use std::convert::Infallible;
+use std::io::{BufRead, Read};
+use std::mem;
+use std::str::FromStr;
+
+/// The format of the input files
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// Parquet files with the columns of the csv input, see `Engine::process_parquet_with_errors`
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(InputFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("parquet inputs require the `parquet` feature".to_string()),
+            _ => Err(format!("invalid input format {s}, expected csv or parquet")),
+        }
+    }
+}
+
+/// A source of input rows for `Engine::process_source`
+/// Implement it to process another input format, e.g. a proprietary one, with the same loop as the csv inputs
+pub trait InputSource {
+    /// The next row or the reason it can't be decoded, None at the end of the input
+    fn next_row(&mut self) -> Option<Result<CsvInputRow, RowError>>;
+
+    /// The line of the row returned last, used to report a row rejected by the engine
+    fn line(&self) -> u64;
+}
+
+/// The rows of a csv input with the csv options, the type aliases and `lenient_amounts` of a config
+pub struct CsvSource<R> {
+    rdr: csv::Reader<R>,
+    config: EngineConfig,
+    headers: ByteRecord,
+    columns: ColumnIndex,
+    record: ByteRecord,
+    line: u64,
+    /// The error of the headers, it is returned as the first row
+    error: Option<csv::Error>,
+    done: bool,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(input: R, config: &EngineConfig) -> Self {
+        let mut rdr = config.csv.reader(input);
+        let (headers, error) = match config.csv.headers(&mut rdr) {
+            Ok(headers) => (headers, None),
+            Err(err) => (Default::default(), Some(err)),
+        };
+        Self {
+            rdr,
+            config: config.clone(),
+            columns: ColumnIndex::new(&headers),
+            headers: headers.into_byte_record(),
+            record: ByteRecord::new(),
+            line: 1,
+            error,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> InputSource for CsvSource<R> {
+    fn next_row(&mut self) -> Option<Result<CsvInputRow, RowError>> {
+        if self.done {
+            return None;
+        }
+        // Without valid headers no row can be deserialized
+        if let Some(err) = self.error.take() {
+            self.done = true;
+            return Some(Err(RowError {
+                line: 1,
+                kind: err.into(),
+            }));
+        }
+        match self.rdr.read_byte_record(&mut self.record) {
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Ok(true) => {
+                self.line = self.record.position().map_or(0, |pos| pos.line());
+                let row =
+                    deserialize_record(&self.config, &self.record, &self.headers, &self.columns);
+                Some(row.map_err(|err| RowError {
+                    line: self.line,
+                    kind: err.into(),
+                }))
+            }
+            Err(err) => {
+                self.line = self.rdr.position().line();
+                // An I/O error means we can't read any further
+                self.done = err.is_io_error();
+                Some(Err(RowError {
+                    line: self.line,
+                    kind: err.into(),
+                }))
+            }
+        }
+    }
+
+    fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+/// The rows of an input with one json object per line, like the `--consume json` messages
+/// Empty lines are skipped
+pub struct JsonLinesSource<R> {
+    input: R,
+    buf: String,
+    line: u64,
+    done: bool,
+}
+
+impl<R: BufRead> JsonLinesSource<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            buf: String::new(),
+            line: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for JsonLinesSource<R> {
+    fn next_row(&mut self) -> Option<Result<CsvInputRow, RowError>> {
+        while !self.done {
+            self.buf.clear();
+            self.line += 1;
+            match self.input.read_line(&mut self.buf) {
+                Ok(0) => self.done = true,
+                Ok(_) if self.buf.trim().is_empty() => {}
+                Ok(_) => {
+                    return Some(serde_json::from_str(&self.buf).map_err(|err| RowError {
+                        line: self.line,
+                        kind: err.into(),
+                    }));
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(RowError {
+                        line: self.line,
+                        kind: csv::Error::from(err).into(),
+                    }));
+                }
+            }
+        }
+        None
+    }
+
+    fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Process the rows of `source`, rejected rows are ignored silently like in `process_csv`
+    /// Returns the number of rows read, including the rejected ones
+    pub fn process_source(&mut self, source: &mut impl InputSource) -> u64 {
+        self.process_source_with_errors(source, |_| Ok::<(), Infallible>(()))
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// Same as `process_source` but `on_error` is called for every rejected row
+    /// When `on_error` returns an error we stop processing and return it
+    pub fn process_source_with_errors<E>(
+        &mut self,
+        source: &mut impl InputSource,
+        mut on_error: impl FnMut(RowError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        #[cfg(feature = "trace")]
+        let span = tracing::info_span!("process_source", rows = tracing::field::Empty).entered();
+        let mut rows = 0;
+        while let Some(row) = source.next_row() {
+            rows += 1;
+            match row {
+                Ok(row) if !self.config.filter.matches(&row) => {}
+                Ok(row) => {
+                    let result = self.process_row(&row);
+                    let line = source.line();
+                    // The occurrences of the schedules due at the row were applied before it
+                    for occurrence in mem::take(&mut self.rejected_occurrences) {
+                        let kind = occurrence.error.into();
+                        on_error(RowError { line, kind })?;
+                    }
+                    if let Err(kind) = result {
+                        on_error(RowError { line, kind })?;
+                    }
+                }
+                Err(err) => on_error(err)?,
+            }
+        }
+        #[cfg(feature = "trace")]
+        span.record("rows", rows);
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_process_source() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "3"}
+
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": "5"}
+{"type": "deposit", "client": "x", "tx": 3}
+"#;
+        let mut engine = Engine::new();
+        let mut errors = Vec::new();
+        let rows = engine
+            .process_source_with_errors(&mut JsonLinesSource::new(input.as_bytes()), |err| {
+                errors.push((err.line, err.kind.reason()));
+                Ok::<(), Infallible>(())
+            })
+            .unwrap();
+        assert_eq!(rows, 3);
+        assert_eq!(errors, [(3, "insufficient_funds"), (4, "malformed_row")]);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(3));
+
+        // The csv source rejects the same rows as `process_csv_collect_errors`
+        let input = "type,client,tx,amount\ndeposit,1,1,3\nwithdrawal,1,2,5\ndeposit,x,3,1\n";
+        let mut engine = Engine::new();
+        let mut source = CsvSource::new(input.as_bytes(), engine.config());
+        let mut errors = Vec::new();
+        engine
+            .process_source_with_errors(&mut source, |err| {
+                errors.push((err.line, err.kind.reason()));
+                Ok::<(), Infallible>(())
+            })
+            .unwrap();
+        let expected: Vec<_> = Engine::new()
+            .process_csv_collect_errors(input.as_bytes())
+            .into_iter()
+            .map(|err| (err.line, err.kind.reason()))
+            .collect();
+        assert_eq!(errors, expected);
+    }
+}
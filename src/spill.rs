@@ -0,0 +1,372 @@
+use crate::compact::TxRecord;
+use crate::hash::HashMap;
+use crate::store::StateStore;
+use crate::types::{
+    AccountProfile, ClientId, DisputeCase, DisputeState, TransactionId, TransactionKind,
+    TransactionRecord,
+};
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::collections::hash_map::Entry;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// How many transaction records `SpillStore::default` keeps in memory
+pub const DEFAULT_RESIDENT_TRANSACTIONS: usize = 1_000_000;
+
+/// The size of a slot in the index file of a client, the slot of transaction `id` is at `id * SLOT_SIZE`
+const SLOT_SIZE: u64 = 49;
+
+const SLOT_EMPTY: u8 = 0;
+const SLOT_ID_ONLY: u8 = 1;
+const SLOT_DEPOSIT: u8 = 2;
+const SLOT_WITHDRAWAL: u8 = 3;
+
+/// The index file of a single client
+#[derive(Debug)]
+struct IndexFile {
+    file: File,
+    len: u64,
+}
+
+/// A store which keeps the balances of all accounts in memory, but only the most recently used transaction records
+/// The other records are spilled into one index file per client under `dir`. The file is addressed by the
+/// transaction id, so a dispute of any historical transaction is a single read and we don't keep an index in memory
+/// The files are sparse, only the slots of spilled transactions take disk space
+/// `iter` only sees the records in memory, the balances are always complete and `drain` loads everything back
+#[derive(Debug)]
+pub struct SpillStore {
+    accounts: HashMap<ClientId, AccountProfile>,
+    dir: PathBuf,
+    capacity: usize,
+    files: HashMap<ClientId, IndexFile>,
+    /// The generation of the last use of each transaction in memory, the oldest one is spilled first
+    resident: HashMap<(ClientId, TransactionId), u64>,
+    /// The uses in order, an entry is outdated if the transaction was used again later
+    queue: VecDeque<(ClientId, TransactionId, u64)>,
+    generation: u64,
+}
+
+impl Default for SpillStore {
+    fn default() -> Self {
+        let dir = std::env::temp_dir().join(format!("rust-challenge-spill-{}", std::process::id()));
+        Self::new(dir, DEFAULT_RESIDENT_TRANSACTIONS)
+    }
+}
+
+impl SpillStore {
+    /// Keep at most `capacity` transaction records in memory and spill the others into `dir`
+    /// The directory is created on the first spill, existing index files in it are overwritten
+    pub fn new(dir: impl Into<PathBuf>, capacity: usize) -> Self {
+        Self {
+            accounts: HashMap::default(),
+            dir: dir.into(),
+            // A transfer uses the same id on two accounts, both of them must stay in memory
+            capacity: capacity.max(2),
+            files: HashMap::default(),
+            resident: HashMap::default(),
+            queue: VecDeque::new(),
+            generation: 0,
+        }
+    }
+
+    /// The number of transactions in memory
+    pub fn resident_transactions(&self) -> usize {
+        self.resident.len()
+    }
+
+    fn touch(&mut self, client: ClientId, id: TransactionId) {
+        self.generation += 1;
+        self.resident.insert((client, id), self.generation);
+        self.queue.push_back((client, id, self.generation));
+        // Drop the outdated uses so the queue stays bounded when the same transactions are used again and again
+        if self.queue.len() > 2 * self.capacity {
+            let resident = &self.resident;
+            self.queue.retain(|(client, id, generation)| {
+                resident.get(&(*client, *id)) == Some(generation)
+            });
+        }
+    }
+
+    /// Spill the least recently used transactions until there is room for a new one
+    fn evict(&mut self) -> io::Result<()> {
+        while self.resident.len() >= self.capacity {
+            let Some((client, id, generation)) = self.queue.pop_front() else {
+                break;
+            };
+            if self.resident.get(&(client, id)) != Some(&generation) {
+                continue;
+            }
+            self.resident.remove(&(client, id));
+            self.spill(client, id)?;
+        }
+        Ok(())
+    }
+
+    /// Move the record of transaction `id` from the account into the index file of `client`
+    fn spill(&mut self, client: ClientId, id: TransactionId) -> io::Result<()> {
+        let Some(profile) = self.accounts.get_mut(&client) else {
+            return Ok(());
+        };
+        // A record with several disputes doesn't fit into a slot, it stays in memory until it is used again
+        if profile
+            .transactions
+            .get(id)
+            .is_some_and(|(_, record)| record.cases.len() > 1)
+        {
+            return Ok(());
+        }
+        // The id of a rejected withdrawal is used without a record
+        let slot = match profile.transactions.remove(id) {
+            Some(TxRecord::Deposit(record)) => encode(SLOT_DEPOSIT, &record),
+            Some(TxRecord::Withdrawal(record)) => encode(SLOT_WITHDRAWAL, &record),
+            Some(TxRecord::NoRecord) => encode(SLOT_ID_ONLY, &TransactionRecord::default()),
+            None => return Ok(()),
+        };
+        let index = match self.files.entry(client) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                fs::create_dir_all(&self.dir)?;
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(self.dir.join(format!("{client}.idx")))?;
+                entry.insert(IndexFile { file, len: 0 })
+            }
+        };
+        let offset = id as u64 * SLOT_SIZE;
+        index.file.seek(SeekFrom::Start(offset))?;
+        index.file.write_all(&slot)?;
+        index.len = index.len.max(offset + SLOT_SIZE);
+        Ok(())
+    }
+
+    /// Move the record of transaction `id` from the index file back into the account, if it was spilled
+    fn restore(&mut self, client: ClientId, id: TransactionId) -> io::Result<()> {
+        let Some(index) = self.files.get_mut(&client) else {
+            return Ok(());
+        };
+        let offset = id as u64 * SLOT_SIZE;
+        if offset >= index.len {
+            return Ok(());
+        }
+        let mut slot = [0u8; SLOT_SIZE as usize];
+        index.file.seek(SeekFrom::Start(offset))?;
+        index.file.read_exact(&mut slot)?;
+        if slot[0] == SLOT_EMPTY {
+            return Ok(());
+        }
+        // Clear the slot so a transaction is never both in memory and on disk
+        index.file.seek(SeekFrom::Start(offset))?;
+        index.file.write_all(&[SLOT_EMPTY])?;
+        let profile = self.accounts.entry(client).or_default();
+        load(profile, id, &slot);
+        Ok(())
+    }
+
+    /// Load all spilled transactions of `client` back into its account
+    fn restore_all(&mut self, client: ClientId) -> io::Result<()> {
+        let Some(mut index) = self.files.remove(&client) else {
+            return Ok(());
+        };
+        let profile = self.accounts.entry(client).or_default();
+        index.file.seek(SeekFrom::Start(0))?;
+        let mut reader = io::BufReader::new(index.file.take(index.len));
+        let mut slot = [0u8; SLOT_SIZE as usize];
+        for id in 0..(index.len / SLOT_SIZE) {
+            reader.read_exact(&mut slot)?;
+            if slot[0] != SLOT_EMPTY {
+                load(profile, id as TransactionId, &slot);
+            }
+        }
+        fs::remove_file(self.dir.join(format!("{client}.idx")))
+    }
+}
+
+impl Drop for SpillStore {
+    fn drop(&mut self) {
+        for client in self.files.keys() {
+            _ = fs::remove_file(self.dir.join(format!("{client}.idx")));
+        }
+    }
+}
+
+/// A slot has room for a single dispute case, see `spill`
+fn encode(kind: u8, record: &TransactionRecord) -> [u8; SLOT_SIZE as usize] {
+    let mut slot = [0u8; SLOT_SIZE as usize];
+    slot[0] = kind;
+    if let Some(case) = record.cases.first() {
+        slot[1] = match case.state {
+            DisputeState::Open => 1,
+            DisputeState::Resolved => 2,
+            DisputeState::Chargeback => 3,
+            DisputeState::Reversed => 4,
+        };
+        if let Some(id) = case.id {
+            slot[2] = 1;
+            slot[4..8].copy_from_slice(&id.to_le_bytes());
+        }
+        slot[33..49].copy_from_slice(&case.amount.serialize());
+    }
+    slot[8..24].copy_from_slice(&record.amount.serialize());
+    if let Some(timestamp) = record.timestamp {
+        slot[24] = 1;
+        slot[25..33].copy_from_slice(&timestamp.to_le_bytes());
+    }
+    slot
+}
+
+fn load(profile: &mut AccountProfile, id: TransactionId, slot: &[u8; SLOT_SIZE as usize]) {
+    let state = match slot[1] {
+        1 => Some(DisputeState::Open),
+        2 => Some(DisputeState::Resolved),
+        3 => Some(DisputeState::Chargeback),
+        4 => Some(DisputeState::Reversed),
+        _ => None,
+    };
+    let record = TransactionRecord {
+        amount: Decimal::deserialize(slot[8..24].try_into().unwrap()),
+        cases: state
+            .map(|state| DisputeCase {
+                id: (slot[2] == 1).then(|| u32::from_le_bytes(slot[4..8].try_into().unwrap())),
+                amount: Decimal::deserialize(slot[33..49].try_into().unwrap()),
+                state,
+                // A spilled dispute is not aged, `--dispute-timeout-days` can't be used with spilling
+                opened: None,
+            })
+            .into_iter()
+            .collect(),
+        timestamp: (slot[24] == 1).then(|| u64::from_le_bytes(slot[25..33].try_into().unwrap())),
+    };
+    match slot[0] {
+        SLOT_DEPOSIT => profile
+            .transactions
+            .insert(id, TransactionKind::Deposit, record),
+        SLOT_WITHDRAWAL => profile
+            .transactions
+            .insert(id, TransactionKind::Withdrawal, record),
+        _ => _ = profile.transactions.use_id(id),
+    }
+}
+
+impl StateStore for SpillStore {
+    fn get(&self, client: ClientId) -> Option<&AccountProfile> {
+        self.accounts.get(&client)
+    }
+
+    fn get_or_create(&mut self, client: ClientId) -> &mut AccountProfile {
+        self.accounts.entry(client).or_default()
+    }
+
+    fn insert(&mut self, client: ClientId, profile: AccountProfile) {
+        let ids = profile.transactions.ids().collect::<Vec<_>>();
+        self.accounts.insert(client, profile);
+        for id in ids {
+            self.touch(client, id);
+        }
+    }
+
+    fn drain(&mut self) -> Vec<(ClientId, AccountProfile)> {
+        let clients = self.files.keys().copied().collect::<Vec<_>>();
+        for client in clients {
+            self.restore_all(client)
+                .expect("failed to read the spilled transactions");
+        }
+        self.resident.clear();
+        self.queue.clear();
+        self.accounts.drain().collect()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (ClientId, &AccountProfile)> + '_> {
+        Box::new(
+            self.accounts
+                .iter()
+                .map(|(client, profile)| (*client, profile)),
+        )
+    }
+
+    fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    fn prepare_transaction(&mut self, client: ClientId, id: TransactionId) -> io::Result<()> {
+        if !self.resident.contains_key(&(client, id)) {
+            self.evict()?;
+            self.restore(client, id)?;
+        }
+        self.touch(client, id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::types::{Transaction, TransactionState};
+
+    #[test]
+    fn test_spill_store() {
+        let dir = std::env::temp_dir().join(format!("spill-test-{}", std::process::id()));
+        let mut engine = Engine::with_store(SpillStore::new(&dir, 2));
+        for id in 1..=10 {
+            engine
+                .process_transaction(1, id, Transaction::Deposit(Decimal::from(id)))
+                .unwrap();
+        }
+        engine
+            .process_transaction(1, 11, Transaction::Withdrawal(Decimal::from(50)))
+            .unwrap();
+        assert!(engine.store().resident_transactions() <= 2);
+        assert!(engine.account(1).unwrap().transactions.deposits().count() <= 2);
+
+        // Both the duplication check and the disputes still see the spilled transactions
+        assert!(
+            engine
+                .process_transaction(1, 3, Transaction::Deposit(Decimal::from(1)))
+                .is_err()
+        );
+        engine
+            .process_transaction(1, 1, Transaction::Dispute(None))
+            .unwrap();
+        engine
+            .process_transaction(1, 11, Transaction::Dispute(Some(7)))
+            .unwrap();
+        for id in 12..=20 {
+            engine
+                .process_transaction(2, id, Transaction::Deposit(Decimal::from(1)))
+                .unwrap();
+        }
+        engine
+            .process_transaction(1, 1, Transaction::Chargeback(None))
+            .unwrap();
+        let profile = engine.account(1).unwrap();
+        assert_eq!(profile.available, Decimal::from(4));
+        assert_eq!(profile.held, Decimal::from(50));
+        assert!(profile.is_frozen());
+
+        let mut accounts = engine.store.drain();
+        accounts.sort_by_key(|(client, _)| *client);
+        assert_eq!(accounts[0].1.transactions.deposits().count(), 10);
+        assert_eq!(
+            accounts[0].1.transactions.deposit(1).unwrap().state(),
+            TransactionState::Chargeback
+        );
+        assert_eq!(
+            accounts[0].1.transactions.withdrawal(11).unwrap().state(),
+            TransactionState::UnderDispute
+        );
+        assert_eq!(
+            accounts[0].1.transactions.withdrawal(11).unwrap().cases[0].id,
+            Some(7)
+        );
+        assert_eq!(accounts[0].1.transactions.len(), 11);
+        assert_eq!(accounts[1].1.transactions.len(), 9);
+        drop(engine);
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+        fs::remove_dir(&dir).unwrap();
+    }
+}
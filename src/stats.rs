@@ -0,0 +1,178 @@
+use crate::engine::Engine;
+use crate::output::rescale;
+use crate::store::StateStore;
+use crate::types::{RowProcessingError, TransactionParsingError, TransactionProcessingError};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Metadata of a batch run for monitoring
+#[derive(Serialize, Debug, Default, Eq, PartialEq)]
+pub struct RunStats {
+    pub rows_read: u64,
+    pub rows_rejected: u64,
+    /// The number of rejected rows for each reason, see `RowProcessingError::reason`
+    pub rejected_by_reason: BTreeMap<&'static str, u64>,
+    pub accounts: usize,
+    pub accounts_frozen: usize,
+    /// The withdrawal fees of all clients with the precision of the output, only with a fee policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fees_collected: Option<Decimal>,
+    pub elapsed_ms: u128,
+}
+
+impl RunStats {
+    pub fn record_rejection(&mut self, err: &RowProcessingError) {
+        self.rows_rejected += 1;
+        *self.rejected_by_reason.entry(err.reason()).or_default() += 1;
+    }
+
+    /// Fill in the account counts from `engine` and the elapsed time at the end of the run
+    pub fn finish<S: StateStore>(&mut self, engine: &Engine<S>, elapsed: Duration) {
+        self.add_accounts(engine);
+        self.elapsed_ms = elapsed.as_millis();
+    }
+
+    /// Add the account counts and the collected fees of `engine`, a run with an engine per tenant adds the counts
+    /// of every tenant
+    pub fn add_accounts<S: StateStore>(&mut self, engine: &Engine<S>) {
+        self.accounts += engine.accounts().count();
+        self.accounts_frozen += engine
+            .accounts()
+            .filter(|(_, profile)| profile.is_frozen())
+            .count();
+        if engine.config().fee_policy.is_some() {
+            let precision = engine.config().output_precision();
+            let fees = rescale(engine.total_fees(), precision.as_ref());
+            let total = self.fees_collected.get_or_insert_default();
+            *total = total.saturating_add(fees);
+        }
+    }
+}
+
+impl RowProcessingError {
+    /// A short stable name of the reason, used to group rejections in the statistics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RowProcessingError::Csv(_) | RowProcessingError::Json(_) => "malformed_row",
+            #[cfg(feature = "arrow")]
+            RowProcessingError::Arrow(_) => "malformed_row",
+            RowProcessingError::Parsing(err) => err.reason(),
+            RowProcessingError::Processing(err) => err.reason(),
+        }
+    }
+}
+
+impl TransactionParsingError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TransactionParsingError::MissingAmount => "missing_amount",
+            TransactionParsingError::MissingDestination => "missing_destination",
+            TransactionParsingError::MissingReason => "missing_reason",
+            TransactionParsingError::TooManyDecimalPlaces(_) => "too_many_decimal_places",
+            TransactionParsingError::NonPositiveAmount(_) => "non_positive_amount",
+            TransactionParsingError::InvalidType => "unknown_type",
+            TransactionParsingError::IncompleteSchedule => "incomplete_schedule",
+            TransactionParsingError::MissingTenant => "missing_tenant",
+            TransactionParsingError::InvalidTenant(_) => "invalid_tenant",
+        }
+    }
+}
+
+impl TransactionProcessingError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TransactionProcessingError::AccountIsFrozen => "account_frozen",
+            TransactionProcessingError::AccountIsClosed => "account_closed",
+            TransactionProcessingError::AccountIsNotFrozen => "account_not_frozen",
+            TransactionProcessingError::HeldAmountNotZero(_) => "held_not_zero",
+            TransactionProcessingError::InvalidTransactionId(_) => "invalid_transaction_id",
+            TransactionProcessingError::AvailableAmountTooLow(..) => "insufficient_funds",
+            TransactionProcessingError::DisputeAmountTooHigh(_) => "dispute_amount_too_high",
+            TransactionProcessingError::InvalidCaseId(_) => "invalid_case_id",
+            TransactionProcessingError::InvalidTransactionState => "invalid_transaction_state",
+            TransactionProcessingError::TransferToSelf => "transfer_to_self",
+            TransactionProcessingError::TransferNotSupported => "transfer_not_supported",
+            TransactionProcessingError::ClientMismatch(_) => "client_mismatch",
+            TransactionProcessingError::DuplicateTransactionId(..) => "duplicate_transaction_id",
+            TransactionProcessingError::ArithmeticOverflow => "arithmetic_overflow",
+            TransactionProcessingError::OverdraftLimitExceeded(_) => "overdraft_limit_exceeded",
+            TransactionProcessingError::DisputeWindowExpired => "dispute_window_expired",
+            TransactionProcessingError::OutOfOrder(..) => "out_of_order",
+            TransactionProcessingError::WalWriteFailed(_) => "wal_write_failed",
+            TransactionProcessingError::StoreFailed(_) => "store_failed",
+            TransactionProcessingError::RiskLimitExceeded(_) => "risk_limit_exceeded",
+            TransactionProcessingError::TransactionEvicted(_) => "transaction_evicted",
+            TransactionProcessingError::DuplicateRow(_) => "duplicate_row",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EngineConfig, FeePolicy};
+    use std::convert::Infallible;
+
+    #[test]
+    fn test_run_stats() {
+        let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,1,1.0
+unknown,1,2,1.0
+withdrawal,1,3,5.0
+dispute,1,1,
+chargeback,1,1,
+";
+        let mut engine = Engine::new();
+        let mut stats = RunStats::default();
+        stats.rows_read = engine
+            .process_csv_with_errors(input.as_bytes(), |_, _, err| {
+                stats.record_rejection(&err);
+                Ok::<(), Infallible>(())
+            })
+            .unwrap();
+        stats.finish(&engine, Duration::from_millis(5));
+        assert_eq!(
+            stats,
+            RunStats {
+                rows_read: 6,
+                rows_rejected: 3,
+                rejected_by_reason: BTreeMap::from([
+                    ("insufficient_funds", 1),
+                    ("invalid_transaction_id", 1),
+                    ("malformed_row", 1),
+                ]),
+                accounts: 1,
+                accounts_frozen: 1,
+                fees_collected: None,
+                elapsed_ms: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_run_stats_fees() {
+        let input = "type,client,tx,amount
+deposit,1,1,10
+withdrawal,1,2,1
+deposit,2,3,10
+withdrawal,2,4,2
+";
+        let mut engine = Engine::with_config(EngineConfig {
+            fee_policy: Some(FeePolicy {
+                flat: Decimal::new(1, 1),
+                percentage: Decimal::from(5),
+            }),
+            output_scale: Some(2),
+            ..EngineConfig::default()
+        });
+        engine.process_csv(input.as_bytes());
+        let mut stats = RunStats::default();
+        stats.finish(&engine, Duration::ZERO);
+        assert_eq!(stats.fees_collected, Some(Decimal::new(35, 2)));
+        let json = serde_json::to_string(&stats).unwrap();
+        assert!(json.contains(r#""fees_collected":"0.35""#), "{json}");
+    }
+}
@@ -0,0 +1,176 @@
+use crate::hash::HashMap;
+use crate::types::{AccountProfile, ClientId, Transaction, TransactionId};
+use std::fmt::Debug;
+use std::io;
+
+/// The default store keeping all accounts in memory
+pub type MemoryStore = HashMap<ClientId, AccountProfile>;
+
+/// Storage for the states of all clients used by `Engine`
+/// The engine only touches the accounts through this trait, so the states can live somewhere else than a `HashMap`
+pub trait StateStore: Debug + Default + Send {
+    /// The account of `client`, None if we never saw this client
+    fn get(&self, client: ClientId) -> Option<&AccountProfile>;
+
+    /// The account of `client` for update, it is created on first use
+    fn get_or_create(&mut self, client: ClientId) -> &mut AccountProfile;
+
+    /// Insert or replace the account of `client`
+    fn insert(&mut self, client: ClientId, profile: AccountProfile);
+
+    /// Remove all accounts from the store and return them
+    fn drain(&mut self) -> Vec<(ClientId, AccountProfile)>;
+
+    /// All accounts in arbitrary order
+    fn iter(&self) -> Box<dyn Iterator<Item = (ClientId, &AccountProfile)> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Called before a transaction of `client` with transaction id `id` is applied
+    /// A store which doesn't keep all transaction records in memory must load the record of `id` here
+    fn prepare_transaction(&mut self, _client: ClientId, _id: TransactionId) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after a transaction of `client` is accepted, a persistent store can write the change here
+    fn record_transaction(
+        &mut self,
+        _client: ClientId,
+        _id: TransactionId,
+        _transaction: &Transaction,
+    ) {
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, client: ClientId) -> Option<&AccountProfile> {
+        HashMap::get(self, &client)
+    }
+
+    fn get_or_create(&mut self, client: ClientId) -> &mut AccountProfile {
+        self.entry(client).or_default()
+    }
+
+    fn insert(&mut self, client: ClientId, profile: AccountProfile) {
+        HashMap::insert(self, client, profile);
+    }
+
+    fn drain(&mut self) -> Vec<(ClientId, AccountProfile)> {
+        HashMap::drain(self).collect()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (ClientId, &AccountProfile)> + '_> {
+        Box::new(HashMap::iter(self).map(|(client, profile)| (*client, profile)))
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+/// A store with a slot for every possible `ClientId`, so an account is found by indexing without hashing
+/// The table always has 65536 slots (about 20MB), which is worth its memory when most of the client ids are used
+#[derive(Debug)]
+pub struct VecStore {
+    accounts: Vec<Option<AccountProfile>>,
+    len: usize,
+}
+
+impl VecStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for VecStore {
+    fn default() -> Self {
+        Self {
+            accounts: (0..=ClientId::MAX).map(|_| None).collect(),
+            len: 0,
+        }
+    }
+}
+
+impl StateStore for VecStore {
+    fn get(&self, client: ClientId) -> Option<&AccountProfile> {
+        self.accounts[client as usize].as_ref()
+    }
+
+    fn get_or_create(&mut self, client: ClientId) -> &mut AccountProfile {
+        let slot = &mut self.accounts[client as usize];
+        if slot.is_none() {
+            self.len += 1;
+        }
+        slot.get_or_insert_default()
+    }
+
+    fn insert(&mut self, client: ClientId, profile: AccountProfile) {
+        if self.accounts[client as usize].replace(profile).is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn drain(&mut self) -> Vec<(ClientId, AccountProfile)> {
+        self.len = 0;
+        (0..=ClientId::MAX)
+            .zip(&mut self.accounts)
+            .filter_map(|(client, slot)| Some((client, slot.take()?)))
+            .collect()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (ClientId, &AccountProfile)> + '_> {
+        Box::new(
+            (0..=ClientId::MAX)
+                .zip(&self.accounts)
+                .filter_map(|(client, slot)| Some((client, slot.as_ref()?))),
+        )
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_vec_store() {
+        let mut engine = Engine::with_store(VecStore::new());
+        engine
+            .process_transaction(0, 1, Transaction::Deposit(Decimal::from(3)))
+            .unwrap();
+        engine
+            .process_transaction(u16::MAX, 2, Transaction::Deposit(Decimal::from(5)))
+            .unwrap();
+        engine
+            .process_transaction(u16::MAX, 3, Transaction::Withdrawal(Decimal::from(1)))
+            .unwrap();
+        assert_eq!(engine.summaries().count(), 2);
+        assert_eq!(
+            engine.account(u16::MAX).unwrap().available,
+            Decimal::from(4)
+        );
+        assert!(engine.account(7).is_none());
+
+        // The accounts are listed by client id
+        let mut store = VecStore::new();
+        store.insert(9, AccountProfile::default());
+        store.insert(2, AccountProfile::default());
+        store.insert(9, AccountProfile::default());
+        assert_eq!(store.len(), 2);
+        assert_eq!(
+            store.iter().map(|(client, _)| client).collect::<Vec<_>>(),
+            [2, 9]
+        );
+        assert_eq!(store.drain().len(), 2);
+        assert!(store.is_empty() && store.get(9).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::engine::Engine;
+use crate::store::StateStore;
+use crate::types::{CSV_COLUMNS, CsvInputRow};
+use csv::{ReaderBuilder, StringRecord};
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::FromStr;
+
+/// How the transactions are encoded in a message stream
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MessageEncoding {
+    /// One json object per line with the same fields as the csv input
+    Json,
+    /// One csv row per message without a header
+    Csv,
+}
+
+impl FromStr for MessageEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(MessageEncoding::Json),
+            "csv" => Ok(MessageEncoding::Csv),
+            _ => Err(format!("invalid encoding {s}, expected json or csv")),
+        }
+    }
+}
+
+impl MessageEncoding {
+    /// The row of a single message, e.g. the payload of a Kafka message, None when it is malformed
+    pub fn decode(self, message: &[u8]) -> Option<CsvInputRow> {
+        match self {
+            MessageEncoding::Json => serde_json::from_slice(message).ok(),
+            MessageEncoding::Csv => {
+                let headers = StringRecord::from(CSV_COLUMNS.to_vec());
+                let mut record = StringRecord::new();
+                csv_reader(message).read_record(&mut record).ok()?;
+                record.deserialize(Some(&headers)).ok()
+            }
+        }
+    }
+}
+
+/// The reader of csv messages, they have no header and may leave out the trailing columns
+fn csv_reader<R: Read>(input: R) -> csv::Reader<R> {
+    ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input)
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Apply the row of a message unless it is filtered out, a rejected or malformed message is ignored silently
+    pub(crate) fn consume_message(&mut self, row: Option<CsvInputRow>) {
+        if let Some(row) = row.filter(|row| self.config().filter.matches(row)) {
+            _ = self.process_row(&row);
+        }
+    }
+
+    /// Apply a never ending stream of messages, e.g. the messages of a Kafka topic piped in by a consumer
+    /// `on_checkpoint` is called with the engine after every `every` messages and once at the end of the stream,
+    /// so the caller can emit snapshots of the accounts periodically. Returns the number of messages
+    /// Rejected and malformed messages are ignored silently, same as `process_csv`
+    pub fn consume_messages(
+        &mut self,
+        input: impl Read,
+        encoding: MessageEncoding,
+        every: usize,
+        mut on_checkpoint: impl FnMut(&Self) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        let every = every.max(1);
+        let mut count = 0;
+        let mut consume = |engine: &mut Self, row: Option<CsvInputRow>| {
+            engine.consume_message(row);
+            count += 1;
+            match count % every {
+                0 => on_checkpoint(engine),
+                _ => Ok(()),
+            }
+        };
+        match encoding {
+            MessageEncoding::Json => {
+                for line in BufReader::new(input).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    consume(self, serde_json::from_str(&line).ok())?;
+                }
+            }
+            MessageEncoding::Csv => {
+                let headers = StringRecord::from(CSV_COLUMNS.to_vec());
+                let mut rdr = csv_reader(input);
+                let mut record = StringRecord::new();
+                while rdr.read_record(&mut record)? {
+                    consume(self, record.deserialize(Some(&headers)).ok())?;
+                }
+            }
+        }
+        on_checkpoint(self)?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_consume_messages() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "3"}
+            {"type": "withdrawal", "client": 1, "tx": 2, "amount": "1"}
+            {"type": "deposit", "client": "x", "tx": 3}
+            {"type": "deposit", "client": 2, "tx": 4, "amount": "2.5"}"#;
+        let mut engine = Engine::new();
+        let mut checkpoints = Vec::new();
+        let count = engine
+            .consume_messages(input.as_bytes(), MessageEncoding::Json, 2, |engine| {
+                checkpoints.push(engine.accounts().count());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(checkpoints, vec![1, 2, 2]);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(2));
+
+        let input = "deposit,3,5,1.5\ntransfer,3,6,1,4\nbad\n";
+        let count = engine
+            .consume_messages(input.as_bytes(), MessageEncoding::Csv, 100, |_| Ok(()))
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(engine.account(3).unwrap().available, Decimal::new(5, 1));
+        assert_eq!(engine.account(4).unwrap().available, Decimal::from(1));
+    }
+
+    #[test]
+    fn test_decode() {
+        let row = MessageEncoding::Csv.decode(b"deposit, 1, 2, 1.5").unwrap();
+        assert_eq!((row.client, row.tx), (1, 2));
+        assert_eq!(row.amount, Some(Decimal::new(15, 1)));
+        let row = MessageEncoding::Json
+            .decode(br#"{"type": "dispute", "client": 1, "tx": 2}"#)
+            .unwrap();
+        assert_eq!(row.amount, None);
+        assert!(MessageEncoding::Csv.decode(b"bad").is_none());
+        assert!(MessageEncoding::Json.decode(b"deposit,1,2,1.5").is_none());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::output::write_summaries;
+use crate::service::EngineService;
+use crate::types::{CSV_COLUMNS, CsvInputRow};
+use csv::{ReaderBuilder, StringRecord};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::thread;
+
+/// A line protocol on top of `EngineService` for clients which can't speak HTTP
+/// Every line is one command, answered with `OK` or `ERR <reason>`:
+/// - a transaction, either a json object like the `--consume json` messages or a csv row without a header
+/// - `DUMP` writes the accounts as csv with a header, followed by a line with `END`
+/// - `QUIT` closes the connection
+///
+/// Every connection is handled on its own thread and stays open until the client closes it
+pub fn listen(addr: impl ToSocketAddrs, service: EngineService) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let service = service.clone();
+        thread::spawn(move || {
+            // The client may have gone away, there is no one to report the error to
+            let reader = BufReader::new(stream.try_clone()?);
+            handle_session(&service, reader, stream)
+        });
+    }
+    Ok(())
+}
+
+/// Answer the commands of a connection until it ends, this is separated from the socket so it can be tested
+pub fn handle_session(
+    service: &EngineService,
+    reader: impl BufRead,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    let headers = StringRecord::from(CSV_COLUMNS.to_vec());
+    for line in reader.lines() {
+        let line = line?;
+        match line.trim() {
+            "" => continue,
+            "QUIT" => break,
+            "DUMP" => {
+                write_summaries(service.accounts(), &mut writer).map_err(io::Error::other)?;
+                writeln!(writer, "END")?;
+            }
+            line => match parse_row(line, &headers) {
+                Err(err) => writeln!(writer, "ERR {err}")?,
+                Ok(row) => match service.submit_row(&row) {
+                    Ok(()) => writeln!(writer, "OK")?,
+                    Err(err) => writeln!(writer, "ERR {err}")?,
+                },
+            },
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// A line starting with `{` is json, anything else a csv row in the order of the input columns
+fn parse_row(line: &str, headers: &StringRecord) -> Result<CsvInputRow, String> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).map_err(|err| err.to_string());
+    }
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(line.as_bytes());
+    let mut record = StringRecord::new();
+    rdr.read_record(&mut record)
+        .map_err(|err| err.to_string())?;
+    record
+        .deserialize(Some(headers))
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_session() {
+        let service = EngineService::default();
+        let input = r#"deposit, 1, 1, 1.5
+{"type": "deposit", "client": 2, "tx": 2, "amount": "2"}
+
+withdrawal,1,3,5
+not a transaction
+DUMP
+QUIT
+deposit,1,4,1
+"#;
+        let mut output = Vec::new();
+        handle_session(&service, input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..2], ["OK", "OK"]);
+        assert!(lines[2].starts_with("ERR "));
+        assert!(lines[3].starts_with("ERR "));
+        assert_eq!(lines[4], "client,available,held,total,locked");
+        let mut accounts = lines[5..7].to_vec();
+        accounts.sort();
+        assert_eq!(
+            accounts,
+            [
+                "1,1.5000,0.0000,1.5000,false",
+                "2,2.0000,0.0000,2.0000,false"
+            ]
+        );
+        assert_eq!(lines[7..], ["END"]);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::config::EngineConfig;
+use crate::engine::Engine;
+use crate::output::AccountSummary;
+use crate::record::{ColumnIndex, lossy};
+#[cfg(feature = "fs")]
+use crate::types::SnapshotError;
+use crate::types::{ClientId, CsvInputRow, RowProcessingError, TenantId, TransactionParsingError};
+use csv::ByteRecord;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+#[cfg(feature = "fs")]
+use std::{fs, path::Path};
+
+/// Separate engines for the accounts of several tenants, e.g. the partner banks we process files for
+/// A tenant has its own clients and transaction ids, so the same client or id in two tenants never collide
+/// The tenant of a row is in its `tenant` column
+pub struct Tenants {
+    engines: BTreeMap<TenantId, Engine>,
+    config: EngineConfig,
+    /// Called on the engine of every new tenant, e.g. to register observers
+    setup: Box<dyn FnMut(&mut Engine)>,
+}
+
+impl fmt::Debug for Tenants {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tenants")
+            .field("engines", &self.engines)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Tenants {
+    fn default() -> Self {
+        Self::new(EngineConfig::default())
+    }
+}
+
+/// The output record of a client of a tenant
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct TenantSummary {
+    pub tenant: TenantId,
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fees_collected: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_disputes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_disputes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chargebacks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disputed_amount_held: Option<Decimal>,
+}
+
+impl TenantSummary {
+    fn new(tenant: &str, summary: AccountSummary) -> Self {
+        Self {
+            tenant: tenant.to_string(),
+            client: summary.client,
+            available: summary.available,
+            held: summary.held,
+            total: summary.total,
+            locked: summary.locked,
+            fees_collected: summary.fees_collected,
+            open_disputes: summary.open_disputes,
+            resolved_disputes: summary.resolved_disputes,
+            chargebacks: summary.chargebacks,
+            disputed_amount_held: summary.disputed_amount_held,
+        }
+    }
+}
+
+impl Tenants {
+    /// The engine of every tenant uses `config`
+    pub fn new(config: EngineConfig) -> Self {
+        Self {
+            engines: BTreeMap::new(),
+            config,
+            setup: Box::new(|_| {}),
+        }
+    }
+
+    /// Call `setup` on the engine of every tenant, the existing ones and the ones seen later
+    pub fn set_setup(&mut self, mut setup: impl FnMut(&mut Engine) + 'static) {
+        for engine in self.engines.values_mut() {
+            setup(engine);
+        }
+        self.setup = Box::new(setup);
+    }
+
+    pub fn engine(&self, tenant: &str) -> Option<&Engine> {
+        self.engines.get(tenant)
+    }
+
+    /// The engine of `tenant`, it is created on first use
+    /// A tenant is a name of letters, digits, `-` and `_`, since it is used in file names
+    pub fn engine_mut(&mut self, tenant: &str) -> Result<&mut Engine, TransactionParsingError> {
+        if tenant.is_empty() {
+            return Err(TransactionParsingError::MissingTenant);
+        }
+        if !is_valid_tenant(tenant) {
+            return Err(TransactionParsingError::InvalidTenant(tenant.to_string()));
+        }
+        if !self.engines.contains_key(tenant) {
+            let mut engine = Engine::with_config(self.config.clone());
+            (self.setup)(&mut engine);
+            self.engines.insert(tenant.to_string(), engine);
+        }
+        Ok(self
+            .engines
+            .get_mut(tenant)
+            .expect("tenant was just inserted"))
+    }
+
+    /// The tenants in order of their names
+    pub fn iter(&self) -> impl Iterator<Item = (&TenantId, &Engine)> {
+        self.engines.iter()
+    }
+
+    pub fn process_row(
+        &mut self,
+        tenant: &str,
+        row: &CsvInputRow,
+    ) -> Result<(), RowProcessingError> {
+        self.engine_mut(tenant)?.process_row(row)
+    }
+
+    /// Same as `Engine::process_csv_with_errors`, every row is applied to the engine of its tenant
+    /// A row without a tenant is rejected
+    pub fn process_csv_with_errors<E>(
+        &mut self,
+        input: impl Read,
+        mut on_error: impl FnMut(u64, &csv::StringRecord, RowProcessingError) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        let mut rdr = self.config.csv.reader(input);
+        let headers = match self.config.csv.headers(&mut rdr) {
+            Ok(headers) => headers,
+            Err(err) => return on_error(1, &Default::default(), err.into()).map(|_| 0),
+        };
+        let columns = ColumnIndex::new(&headers);
+        let tenant_column = headers.iter().position(|name| name == "tenant");
+        let headers = headers.into_byte_record();
+        let mut record = ByteRecord::new();
+        let mut rows = 0;
+        loop {
+            match rdr.read_byte_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => {
+                    rows += 1;
+                    let line = record.position().map_or(0, |pos| pos.line());
+                    let tenant = tenant_column
+                        .and_then(|column| record.get(column))
+                        .map(String::from_utf8_lossy)
+                        .unwrap_or_default();
+                    if let Err(err) = self.process_record(&tenant, &record, &headers, &columns) {
+                        on_error(line, &lossy(&record), err)?;
+                    }
+                }
+                Err(err) => {
+                    rows += 1;
+                    let line = rdr.position().line();
+                    // An I/O error means we can't read any further
+                    let fatal = err.is_io_error();
+                    on_error(line, &Default::default(), err.into())?;
+                    if fatal {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(rows)
+    }
+
+    fn process_record(
+        &mut self,
+        tenant: &str,
+        record: &ByteRecord,
+        headers: &ByteRecord,
+        columns: &ColumnIndex,
+    ) -> Result<(), RowProcessingError> {
+        let engine = self.engine_mut(tenant)?;
+        let row = engine.deserialize_row(record, headers, columns)?;
+        if !engine.config().filter.matches(&row) {
+            return Ok(());
+        }
+        engine.process_row(&row)
+    }
+
+    /// The accounts of all tenants, ordered by tenant
+    pub fn summaries(&self) -> impl Iterator<Item = TenantSummary> {
+        self.engines.iter().flat_map(|(tenant, engine)| {
+            engine
+                .summaries()
+                .map(|summary| TenantSummary::new(tenant, summary))
+        })
+    }
+
+    /// Write the accounts of all tenants as csv with a leading `tenant` column
+    pub fn write_summaries(&self, writer: impl Write) -> Result<(), csv::Error> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        for summary in self.summaries() {
+            wtr.serialize(summary)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Save a snapshot of every tenant as `<tenant>.json` into `dir`
+    #[cfg(feature = "fs")]
+    pub fn save_snapshots(&self, dir: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        fs::create_dir_all(&dir)?;
+        for (tenant, engine) in &self.engines {
+            engine.save_snapshot(dir.as_ref().join(format!("{tenant}.json")))?;
+        }
+        Ok(())
+    }
+
+    /// Load the snapshots of `save_snapshots`, the engines use `config`
+    #[cfg(feature = "fs")]
+    pub fn load_snapshots(
+        dir: impl AsRef<Path>,
+        config: EngineConfig,
+    ) -> Result<Self, SnapshotError> {
+        let mut tenants = Self::new(config);
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(tenant) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+                .filter(|tenant| is_valid_tenant(tenant))
+            else {
+                continue;
+            };
+            let mut engine = Engine::load_snapshot(&path)?;
+            engine.set_config(tenants.config.clone());
+            tenants.engines.insert(tenant.to_string(), engine);
+        }
+        Ok(tenants)
+    }
+}
+
+fn is_valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    #[test]
+    fn test_tenants() {
+        let input = "type,client,tx,amount,tenant
+deposit,1,1,5,bank-a
+deposit,1,1,7,bank-b
+withdrawal,1,2,6,bank-a
+withdrawal,1,2,6,bank-b
+deposit,2,3,1,
+deposit,2,3,1,../etc
+";
+        let mut tenants = Tenants::default();
+        let mut errors = Vec::new();
+        let rows = tenants
+            .process_csv_with_errors(input.as_bytes(), |line, _, err| {
+                errors.push((line, err.reason()));
+                Ok::<(), Infallible>(())
+            })
+            .unwrap();
+        assert_eq!(rows, 6);
+        // The same client and transaction id are separate accounts in two tenants
+        assert_eq!(
+            errors,
+            [
+                (4, "insufficient_funds"),
+                (6, "missing_tenant"),
+                (7, "invalid_tenant")
+            ]
+        );
+        let a = tenants.engine("bank-a").unwrap().account(1).unwrap();
+        assert_eq!(a.available, Decimal::from(5));
+        let b = tenants.engine("bank-b").unwrap().account(1).unwrap();
+        assert_eq!(b.available, Decimal::from(1));
+
+        let mut output = Vec::new();
+        tenants.write_summaries(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tenant,client,available,held,total,locked
+bank-a,1,5.0000,0.0000,5.0000,false
+bank-b,1,1.0000,0.0000,1.0000,false
+"
+        );
+    }
+}
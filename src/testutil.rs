@@ -0,0 +1,241 @@
+use crate::types::{ClientId, CsvInputRow, Transaction, TransactionId};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+use std::io::Write;
+
+/// The options of a `TransactionGenerator`
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    /// The clients are 1 to `clients`
+    pub clients: ClientId,
+    /// The largest amount of a deposit, amounts have up to 4 decimal places
+    pub max_amount: Decimal,
+    /// The chance of a dispute, resolve or chargeback of an earlier deposit
+    pub dispute_ratio: f64,
+    /// The chance of an adversarial transaction, e.g. an unknown or reused tx id, a zero or negative amount,
+    /// or a dispute of another client's transaction
+    pub adversarial_ratio: f64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            clients: 10,
+            max_amount: Decimal::from(1000),
+            dispute_ratio: 0.3,
+            adversarial_ratio: 0.0,
+        }
+    }
+}
+
+/// An endless, reproducible sequence of random transactions
+/// Without adversarial transactions the disputes, resolves and chargebacks only reference existing deposits
+/// of the same client, so most of the sequence is accepted
+#[derive(Debug)]
+pub struct TransactionGenerator {
+    rng: StdRng,
+    config: GeneratorConfig,
+    next_tx: TransactionId,
+    /// The deposits generated so far, the targets of disputes
+    deposits: Vec<(ClientId, TransactionId)>,
+}
+
+impl TransactionGenerator {
+    /// The same `seed` always generates the same sequence
+    pub fn new(seed: u64, config: GeneratorConfig) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            config,
+            next_tx: 1,
+            deposits: Vec::new(),
+        }
+    }
+
+    fn client(&mut self) -> ClientId {
+        self.rng.gen_range(1..=self.config.clients.max(1))
+    }
+
+    fn amount(&mut self) -> Decimal {
+        let max = (self.config.max_amount * Decimal::from(10_000))
+            .try_into()
+            .unwrap_or(1u64)
+            .max(1);
+        Decimal::new(self.rng.gen_range(1..=max) as i64, 4)
+    }
+
+    fn valid(&mut self) -> (ClientId, TransactionId, Transaction) {
+        let id = self.next_tx;
+        let client = self.client();
+        if !self.deposits.is_empty() && self.rng.gen_bool(self.config.dispute_ratio.clamp(0.0, 1.0))
+        {
+            let (client, id) = self.deposits[self.rng.gen_range(0..self.deposits.len())];
+            let transaction = match self.rng.gen_range(0..4) {
+                0 | 1 => Transaction::Dispute(None),
+                2 => Transaction::Resolve(None),
+                _ => Transaction::Chargeback(None),
+            };
+            return (client, id, transaction);
+        }
+        match self.rng.gen_range(0..8) {
+            0..=3 => {
+                self.next_tx += 1;
+                self.deposits.push((client, id));
+                (client, id, Transaction::Deposit(self.amount()))
+            }
+            4..=5 => {
+                self.next_tx += 1;
+                (client, id, Transaction::Withdrawal(self.amount()))
+            }
+            6 => {
+                self.next_tx += 1;
+                let to = match self.client() {
+                    to if to == client => to % self.config.clients.max(1) + 1,
+                    to => to,
+                };
+                (client, id, Transaction::Transfer(to, self.amount()))
+            }
+            _ => (client, 0, Transaction::Unlock),
+        }
+    }
+
+    fn adversarial(&mut self) -> (ClientId, TransactionId, Transaction) {
+        let client = self.client();
+        let reused = self.rng.gen_range(0..self.next_tx);
+        match self.rng.gen_range(0..6) {
+            0 => (client, reused, Transaction::Deposit(self.amount())),
+            1 => (client, self.next_tx, Transaction::Deposit(Decimal::ZERO)),
+            2 => (
+                client,
+                self.next_tx,
+                Transaction::Withdrawal(-self.amount()),
+            ),
+            3 => (client, reused, Transaction::Dispute(None)),
+            4 => (client, self.next_tx, Transaction::Withdrawal(Decimal::MAX)),
+            _ => (
+                client,
+                self.next_tx,
+                Transaction::Transfer(client, self.amount()),
+            ),
+        }
+    }
+}
+
+/// Write `rows` generated transactions as csv with a header row
+pub fn write_csv(
+    generator: TransactionGenerator,
+    rows: usize,
+    writer: impl Write,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for (client, tx, transaction) in generator.take(rows) {
+        wtr.serialize(CsvInputRow::new(client, tx, &transaction))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+impl Iterator for TransactionGenerator {
+    type Item = (ClientId, TransactionId, Transaction);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self
+            .rng
+            .gen_bool(self.config.adversarial_ratio.clamp(0.0, 1.0))
+        {
+            Some(self.adversarial())
+        } else {
+            Some(self.valid())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::types::RowProcessingError;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn total(engine: &Engine) -> Decimal {
+        engine.accounts().map(|(_, profile)| profile.total()).sum()
+    }
+
+    /// Amounts with up to 4 decimal places, including zero and negative ones which the parsing rejects
+    fn amount() -> impl Strategy<Value = Decimal> {
+        (-10_000i64..10_000_000).prop_map(|units| Decimal::new(units, 4))
+    }
+
+    /// A few clients and ids, so the disputes, reused ids and transfers often hit an existing transaction
+    fn transaction() -> impl Strategy<Value = (ClientId, TransactionId, Transaction)> {
+        let transaction = prop_oneof![
+            3 => amount().prop_map(Transaction::Deposit),
+            2 => amount().prop_map(Transaction::Withdrawal),
+            2 => Just(Transaction::Dispute(None)),
+            1 => Just(Transaction::Resolve(None)),
+            1 => Just(Transaction::Chargeback(None)),
+            1 => Just(Transaction::Unlock),
+            1 => (1..=5u16, amount()).prop_map(|(to, amount)| Transaction::Transfer(to, amount)),
+        ];
+        (1..=5u16, 1..=40u32, transaction)
+    }
+
+    proptest! {
+        #[test]
+        fn test_invariants(transactions in vec(transaction(), 0..200)) {
+            let mut engine = Engine::new();
+            for (client, id, transaction) in transactions {
+                let before = engine
+                    .account(client)
+                    .map(|profile| (profile.available, profile.held, profile.is_frozen()));
+                let total_before = total(&engine);
+                // Negative and zero amounts are rejected by the parsing step, same as a csv row
+                let res = engine
+                    .apply_policies(transaction)
+                    .map_err(RowProcessingError::from)
+                    .and_then(|transaction| {
+                        Ok(engine.process_transaction(client, id, transaction)?)
+                    });
+
+                let Some(profile) = engine.account(client) else {
+                    continue;
+                };
+                prop_assert!(!profile.held.is_sign_negative() || profile.held.is_zero());
+                if let Some((available, held, true)) = before
+                    && transaction != Transaction::Unlock
+                {
+                    prop_assert!(res.is_err(), "frozen account accepted {:?}", transaction);
+                    prop_assert_eq!((profile.available, profile.held), (available, held));
+                }
+                if let (Ok(()), Transaction::Transfer(..)) = (&res, transaction) {
+                    prop_assert_eq!(total(&engine), total_before, "transfer changed the total");
+                }
+                if res.is_err() {
+                    prop_assert_eq!(total(&engine), total_before, "rejected {:?}", transaction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut out = Vec::new();
+        let generator = TransactionGenerator::new(1, GeneratorConfig::default());
+        write_csv(generator, 100, &mut out).unwrap();
+        let mut engine = Engine::new();
+        assert_eq!(engine.process_csv_bytes(&out), 100);
+        assert!(engine.accounts().count() > 0);
+    }
+
+    #[test]
+    fn test_generator_is_reproducible() {
+        let a = TransactionGenerator::new(7, GeneratorConfig::default())
+            .take(100)
+            .collect::<Vec<_>>();
+        let b = TransactionGenerator::new(7, GeneratorConfig::default())
+            .take(100)
+            .collect::<Vec<_>>();
+        assert_eq!(a, b);
+    }
+}
@@ -1,10 +1,49 @@
+use crate::amount::checked_update;
+use crate::compact::TxRecord;
+use crate::config::{DisputePolicy, EngineConfig};
+use crate::risk::RiskCounters;
 use crate::types::{
-    AccountProfile, CsvInputRow, Transaction, TransactionId, TransactionParsingError,
-    TransactionProcessingError, TransactionState,
+    AccountProfile, AccountStatus, CaseId, ClientId, CsvInputRow, DisputeCase, DisputeState,
+    FlagRecord, InvariantViolation, ReasonCode, Timestamp, Transaction, TransactionId,
+    TransactionKind, TransactionParsingError, TransactionProcessingError, TransactionRecord,
+    TransactionState, TransactionType,
 };
 use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// The state of an account a withdrawal changes besides its record, so the debit of a transfer can be undone
+pub(crate) struct DebitUndo {
+    available: Decimal,
+    held: Decimal,
+    fees_collected: Decimal,
+    risk: RiskCounters,
+    last_timestamp: Option<Timestamp>,
+}
 
 impl AccountProfile {
+    /// An account which starts from known balances instead of zero, e.g. carried over from a previous run
+    /// The balances count as the opening balance in `check_invariants`
+    pub fn with_balances(available: Decimal, held: Decimal) -> Self {
+        let mut profile = Self {
+            available,
+            held,
+            ..Self::default()
+        };
+        profile.opening = Some(profile.total());
+        profile
+    }
+
+    /// The available and held funds, None when their sum doesn't fit into a `Decimal`
+    /// A transaction is only accepted when the new total fits, so only balances set directly can overflow
+    pub fn checked_total(&self) -> Option<Decimal> {
+        self.available.checked_add(self.held)
+    }
+
+    /// Same as `checked_total`, saturated at the limits of `Decimal`
+    pub fn total(&self) -> Decimal {
+        self.available.saturating_add(self.held)
+    }
+
     /// The main handler for transaction
     /// When we accept a transaction, we will return Ok(()) and mutate the state of `AccountProfile`
     /// When we reject a transaction, we will return Err with TransactionProcessingError
@@ -13,104 +52,821 @@ impl AccountProfile {
         id: TransactionId,
         transaction: Transaction,
     ) -> Result<(), TransactionProcessingError> {
-        if self.frozen {
-            return Err(TransactionProcessingError::AccountIsFrozen);
+        self.process_transaction_with(id, transaction, &EngineConfig::default())
+    }
+
+    /// Same as `process_transaction` but with the policies in `config`
+    pub fn process_transaction_with(
+        &mut self,
+        id: TransactionId,
+        transaction: Transaction,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionProcessingError> {
+        self.process_transaction_at(id, transaction, None, config)
+    }
+
+    /// Same as `process_transaction_with` for a transaction which happened at `timestamp`
+    /// The timestamp is stored in the record of a deposit or withdrawal
+    pub fn process_transaction_at(
+        &mut self,
+        id: TransactionId,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionProcessingError> {
+        // A frozen account only accepts the unlock transaction, chargeback reversals and the operator corrections,
+        // a closed account only accepts reopening
+        match self.status {
+            AccountStatus::Frozen
+                if !matches!(
+                    transaction,
+                    Transaction::Unlock
+                        | Transaction::ChargebackReversal(_)
+                        | Transaction::Adjustment(..)
+                        | Transaction::Flag(_)
+                ) =>
+            {
+                return Err(TransactionProcessingError::AccountIsFrozen);
+            }
+            AccountStatus::Closed if transaction != Transaction::OpenAccount => {
+                return Err(TransactionProcessingError::AccountIsClosed);
+            }
+            _ => {}
+        }
+        self.validate_timestamp(timestamp, config)?;
+        self.enforce_risk(&transaction, timestamp, config)?;
+        self.apply_transaction(id, transaction, timestamp, config)?;
+        if config.risk.is_some() {
+            self.risk.record(&transaction, timestamp);
+        }
+        if let Some(timestamp) = timestamp {
+            self.last_timestamp = self.last_timestamp.max(Some(timestamp));
         }
+        Ok(())
+    }
+
+    fn apply_transaction(
+        &mut self,
+        id: TransactionId,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionProcessingError> {
+        let (available, held) = (self.available, self.held);
         match transaction {
             Transaction::Deposit(amount) => {
-                self.validate_unique_id(id)?;
-                self.deposit_transactions
-                    .insert(id, (TransactionState::Normal, amount));
-                self.available += amount;
+                self.validate_unique_id(id, config)?;
+                (self.available, self.held) =
+                    checked_update(available, held, amount, Decimal::ZERO)?;
+                self.transactions.insert(
+                    id,
+                    TransactionKind::Deposit,
+                    TransactionRecord::new(amount, timestamp),
+                );
             }
             Transaction::Withdrawal(amount) => {
                 // My assumption here is that the tx ID should be unique for deposit and withdrawal
                 // Note that even if the withdrawal was rejected due to other reason, we still consume this ID
-                self.validate_unique_id(id)?;
-                if self.available < amount {
-                    return Err(TransactionProcessingError::AvailableAmountTooLow(
-                        self.available,
-                        amount,
-                    ));
+                self.validate_unique_id(id, config)?;
+                // The fee is not part of the record, so it is not returned if the withdrawal is charged back
+                let fee = config.withdrawal_fee(amount)?;
+                let debit = amount
+                    .checked_add(fee)
+                    .ok_or(TransactionProcessingError::ArithmeticOverflow)?;
+                let (new_available, new_held) =
+                    checked_update(available, held, -debit, Decimal::ZERO)?;
+                let limit = self.overdraft_limit(config);
+                if new_available < -limit {
+                    if limit.is_zero() {
+                        return Err(TransactionProcessingError::AvailableAmountTooLow(
+                            available, debit,
+                        ));
+                    }
+                    return Err(TransactionProcessingError::OverdraftLimitExceeded(limit));
                 }
-                self.available -= amount;
+                self.fees_collected = self
+                    .fees_collected
+                    .checked_add(fee)
+                    .ok_or(TransactionProcessingError::ArithmeticOverflow)?;
+                (self.available, self.held) = (new_available, new_held);
+                self.transactions.insert(
+                    id,
+                    TransactionKind::Withdrawal,
+                    TransactionRecord::new(amount, timestamp),
+                );
+            }
+            Transaction::Dispute(_)
+            | Transaction::PartialDispute(..)
+            | Transaction::Resolve(_)
+            | Transaction::Chargeback(_)
+            | Transaction::ChargebackReversal(_) => {
+                self.process_dispute(id, transaction, timestamp, config)?;
             }
-            Transaction::Dispute => {
-                let available = self.available;
-                let (state, amount) = self.get_deposit_transaction(id)?;
-                if state != &TransactionState::Normal {
+            Transaction::Transfer(..) => {
+                return Err(TransactionProcessingError::TransferNotSupported);
+            }
+            Transaction::Adjustment(amount, _) => {
+                // The id is used without a record, so an adjustment can't be disputed
+                self.validate_unique_id(id, config)?;
+                let (new_available, new_held) =
+                    checked_update(available, held, amount, Decimal::ZERO)?;
+                // A correction can't take more than a withdrawal could
+                let limit = self.overdraft_limit(config);
+                if amount.is_sign_negative() && new_available < -limit {
+                    if limit.is_zero() {
+                        return Err(TransactionProcessingError::AvailableAmountTooLow(
+                            available, -amount,
+                        ));
+                    }
+                    return Err(TransactionProcessingError::OverdraftLimitExceeded(limit));
+                }
+                self.adjusted = self
+                    .adjusted
+                    .checked_add(amount)
+                    .ok_or(TransactionProcessingError::ArithmeticOverflow)?;
+                (self.available, self.held) = (new_available, new_held);
+            }
+            // The id is only a reference, it is not checked against the used ids
+            Transaction::Flag(reason) => self.flags.push(FlagRecord {
+                tx: id,
+                reason,
+                timestamp,
+            }),
+            Transaction::Unlock => self.unfreeze(config)?,
+            Transaction::OpenAccount => self.status = AccountStatus::Open,
+            Transaction::CloseAccount => {
+                if !held.is_zero() {
+                    return Err(TransactionProcessingError::HeldAmountNotZero(held));
+                }
+                self.status = AccountStatus::Closed;
+            }
+        }
+        Ok(())
+    }
+
+    /// Clear the frozen flag after a chargeback, so the account can be used again
+    /// Depending on `config` we require the held amount to be zero
+    pub fn unfreeze(&mut self, config: &EngineConfig) -> Result<(), TransactionProcessingError> {
+        if !self.is_frozen() {
+            return Err(TransactionProcessingError::AccountIsNotFrozen);
+        }
+        if config.unfreeze_requires_zero_held && !self.held.is_zero() {
+            return Err(TransactionProcessingError::HeldAmountNotZero(self.held));
+        }
+        self.status = AccountStatus::Open;
+        Ok(())
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.status == AccountStatus::Frozen
+    }
+
+    /// Check the balances against the stored transactions, they hold after every accepted transaction
+    /// The total is the deposits minus the withdrawals and the fees plus the adjustments, where a charged back deposit is taken away
+    /// and a withdrawal under dispute or charged back is counted as returned
+    /// It needs all records of the account, so it doesn't work with the records spilled by `SpillStore`
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        if self.held.is_sign_negative() && !self.held.is_zero() {
+            return Err(InvariantViolation::NegativeHeld(self.held));
+        }
+        let cases = |record: &TransactionRecord, states: &[DisputeState]| {
+            record
+                .cases
+                .iter()
+                .filter(|case| states.contains(&case.state))
+                .try_fold(Decimal::ZERO, |sum, case| sum.checked_add(case.amount))
+        };
+        let deposits = self
+            .transactions
+            .deposits()
+            .try_fold(Decimal::ZERO, |sum, (_, record)| {
+                sum.checked_add(
+                    record
+                        .amount
+                        .checked_sub(cases(record, &[DisputeState::Chargeback])?)?,
+                )
+            });
+        let withdrawals =
+            self.transactions
+                .withdrawals()
+                .try_fold(Decimal::ZERO, |sum, (_, record)| {
+                    let returned = cases(record, &[DisputeState::Open, DisputeState::Chargeback])?;
+                    sum.checked_add(record.amount.checked_sub(returned)?)
+                });
+        let net = deposits
+            .zip(withdrawals)
+            .and_then(|(deposits, withdrawals)| deposits.checked_sub(withdrawals))
+            .and_then(|net| net.checked_add(self.evicted_net))
+            .and_then(|net| net.checked_add(self.opening.unwrap_or_default()))
+            .and_then(|net| net.checked_add(self.adjusted))
+            .and_then(|net| net.checked_sub(self.fees_collected));
+        let total = self.checked_total();
+        // The amounts near the limits of `Decimal` can't be summed, those accounts are not checked
+        if let (Some(net), Some(total)) = (net, total)
+            && net != total
+        {
+            return Err(InvariantViolation::TotalMismatch(total, net));
+        }
+        // A reversal only unfreezes the account with `unfreeze_on_reversal`
+        let charged_back = self
+            .transactions
+            .values()
+            .flat_map(|record| &record.cases)
+            .any(|case| {
+                matches!(
+                    case.state,
+                    DisputeState::Chargeback | DisputeState::Reversed
+                )
+            });
+        // A seeded account may have been frozen by a chargeback we don't have the records of
+        if self.is_frozen() && !charged_back && !self.risk.tripped && self.opening.is_none() {
+            return Err(InvariantViolation::FrozenWithoutChargeback);
+        }
+        Ok(())
+    }
+
+    /// Check `transaction` against the risk rules of `config`
+    /// The transaction is rejected on a breach, and the account is frozen when the rules say so
+    pub fn enforce_risk(
+        &mut self,
+        transaction: &Transaction,
+        timestamp: Option<Timestamp>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionProcessingError> {
+        let Some(rules) = &config.risk else {
+            return Ok(());
+        };
+        rules
+            .check(&self.risk, transaction, timestamp)
+            .inspect_err(|_| {
+                if rules.freeze {
+                    self.status = AccountStatus::Frozen;
+                    self.risk.tripped = true;
+                }
+            })
+            .map_err(TransactionProcessingError::RiskLimitExceeded)
+    }
+
+    /// How far available can go below zero on withdrawals, the limit of the account takes precedence
+    pub fn overdraft_limit(&self, config: &EngineConfig) -> Decimal {
+        self.overdraft_limit
+            .or(config.overdraft_limit)
+            .unwrap_or_default()
+    }
+
+    /// Reject a transaction earlier than the last accepted one when `strict_timestamps` is set
+    pub fn validate_timestamp(
+        &self,
+        timestamp: Option<Timestamp>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionProcessingError> {
+        match (timestamp, self.last_timestamp) {
+            (Some(timestamp), Some(last)) if config.strict_timestamps && timestamp < last => {
+                Err(TransactionProcessingError::OutOfOrder(timestamp, last))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check if this account can receive a new incoming transaction `id` without mutating the state
+    pub fn validate_incoming(&self, id: TransactionId) -> Result<(), TransactionProcessingError> {
+        match self.status {
+            AccountStatus::Frozen => return Err(TransactionProcessingError::AccountIsFrozen),
+            AccountStatus::Closed => return Err(TransactionProcessingError::AccountIsClosed),
+            AccountStatus::Open => {}
+        }
+        if self.transactions.contains(id) || self.evicted_ids.contains(id) {
+            return Err(TransactionProcessingError::InvalidTransactionId(id));
+        }
+        Ok(())
+    }
+
+    /// The state to pass to `undo_debit` after a withdrawal
+    pub(crate) fn debit_undo(&self) -> DebitUndo {
+        DebitUndo {
+            available: self.available,
+            held: self.held,
+            fees_collected: self.fees_collected,
+            risk: self.risk.clone(),
+            last_timestamp: self.last_timestamp,
+        }
+    }
+
+    /// Undo the withdrawal `id` applied since `undo` was taken, its id is free again
+    pub(crate) fn undo_debit(&mut self, id: TransactionId, undo: DebitUndo) {
+        self.transactions.remove(id);
+        if self.recent.back() == Some(&id) {
+            self.recent.pop_back();
+        }
+        self.available = undo.available;
+        self.held = undo.held;
+        self.fees_collected = undo.fees_collected;
+        self.risk = undo.risk;
+        self.last_timestamp = undo.last_timestamp;
+    }
+
+    /// The dispute state machine for a deposit or withdrawal transaction
+    /// Disputing a deposit moves its amount from available to held, a chargeback takes it away
+    /// Disputing a withdrawal means the fund may need to go back to the client, so we put it into held,
+    /// a chargeback returns it to available. Both chargebacks freeze the account unless `FreezeOnChargeback` says
+    /// otherwise
+    fn process_dispute(
+        &mut self,
+        id: TransactionId,
+        transaction: Transaction,
+        timestamp: Option<Timestamp>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionProcessingError> {
+        let (available, held) = (self.available, self.held);
+        let (record, kind) = self.get_disputed_transaction(id)?;
+        match transaction {
+            Transaction::Dispute(_) | Transaction::PartialDispute(..) => {
+                if record.state() == TransactionState::Chargeback
+                    || config
+                        .max_disputes
+                        .is_some_and(|max| record.cases.len() >= max as usize)
+                {
                     return Err(TransactionProcessingError::InvalidTransactionState);
                 }
+                let case_id = transaction.case_id();
+                if let Some(case_id) = case_id
+                    && record.cases.iter().any(|case| case.id == Some(case_id))
+                {
+                    return Err(TransactionProcessingError::InvalidCaseId(case_id));
+                }
+                // A dispute without an amount disputes everything which is not disputed yet
+                let undisputed = record.undisputed();
+                let amount = transaction.amount().unwrap_or(undisputed);
+                if amount.is_zero() {
+                    return Err(TransactionProcessingError::InvalidTransactionState);
+                }
+                if amount > undisputed {
+                    return Err(TransactionProcessingError::DisputeAmountTooHigh(undisputed));
+                }
+                validate_dispute_window(record, timestamp, config)?;
+                let available_delta = match kind {
+                    TransactionKind::Deposit => -amount,
+                    TransactionKind::Withdrawal => Decimal::ZERO,
+                };
                 // This is a special case where the user already withdrawal the fund
-                // The instruction didn't mention how to handle this case, here I assume we need to reject this dispute
-                if available < amount {
+                // The instruction didn't mention how to handle this case, by default we reject this dispute
+                if kind == TransactionKind::Deposit
+                    && available < amount
+                    && config.dispute_policy == DisputePolicy::RejectIfInsufficient
+                {
                     return Err(TransactionProcessingError::AvailableAmountTooLow(
                         available, amount,
                     ));
                 }
-                *state = TransactionState::UnderDispute;
-                self.available -= amount;
-                self.held += amount;
+                let balances = checked_update(available, held, available_delta, amount)?;
+                record.cases.push(DisputeCase {
+                    id: case_id,
+                    amount,
+                    state: DisputeState::Open,
+                    opened: timestamp,
+                });
+                (self.available, self.held) = balances;
             }
-            Transaction::Resolve => {
-                let (state, amount) = self.get_deposit_transaction(id)?;
-                if state != &TransactionState::UnderDispute {
-                    return Err(TransactionProcessingError::InvalidTransactionState);
+            Transaction::Resolve(case_id) => {
+                let case = record.find_case(case_id, DisputeState::Open)?;
+                let available_delta = match kind {
+                    TransactionKind::Deposit => case.amount,
+                    TransactionKind::Withdrawal => Decimal::ZERO,
+                };
+                let balances = checked_update(available, held, available_delta, -case.amount)?;
+                case.state = DisputeState::Resolved;
+                (self.available, self.held) = balances;
+            }
+            Transaction::Chargeback(case_id) => {
+                let case = record.find_case(case_id, DisputeState::Open)?;
+                let available_delta = match kind {
+                    TransactionKind::Deposit => Decimal::ZERO,
+                    TransactionKind::Withdrawal => case.amount,
+                };
+                let balances = checked_update(available, held, available_delta, -case.amount)?;
+                case.state = DisputeState::Chargeback;
+                let freeze = config.freeze_on_chargeback.freezes(case.amount);
+                (self.available, self.held) = balances;
+                if freeze {
+                    self.status = AccountStatus::Frozen;
                 }
-                *state = TransactionState::Normal;
-                self.available += amount;
-                self.held -= amount;
             }
-            Transaction::Chargeback => {
-                let (state, amount) = self.get_deposit_transaction(id)?;
-                if state != &TransactionState::UnderDispute {
-                    return Err(TransactionProcessingError::InvalidTransactionState);
+            Transaction::ChargebackReversal(case_id) => {
+                let case = record.find_case(case_id, DisputeState::Chargeback)?;
+                let amount = case.amount;
+                // The chargeback of a deposit took the fund away, the reversal credits it again
+                // The chargeback of a withdrawal returned the fund, the reversal debits it again
+                let available_delta = match kind {
+                    TransactionKind::Deposit => amount,
+                    TransactionKind::Withdrawal => -amount,
+                };
+                if kind == TransactionKind::Withdrawal
+                    && available < amount
+                    && config.dispute_policy == DisputePolicy::RejectIfInsufficient
+                {
+                    return Err(TransactionProcessingError::AvailableAmountTooLow(
+                        available, amount,
+                    ));
+                }
+                let balances = checked_update(available, held, available_delta, Decimal::ZERO)?;
+                case.state = DisputeState::Reversed;
+                (self.available, self.held) = balances;
+                if config.unfreeze_on_reversal
+                    && self.is_frozen()
+                    && !self.has_freezing_chargeback(config)
+                    && (!config.unfreeze_requires_zero_held || held.is_zero())
+                {
+                    self.status = AccountStatus::Open;
                 }
-                *state = TransactionState::Chargeback;
-                self.held -= amount;
-                self.frozen = true;
             }
+            _ => return Err(TransactionProcessingError::InvalidTransactionState),
         }
         Ok(())
     }
 
-    fn get_deposit_transaction(
+    /// Whether a dispute of any transaction is charged back, not reversed, and froze the account
+    fn has_freezing_chargeback(&self, config: &EngineConfig) -> bool {
+        self.transactions
+            .values()
+            .flat_map(|record| &record.cases)
+            .any(|case| {
+                case.state == DisputeState::Chargeback
+                    && config.freeze_on_chargeback.freezes(case.amount)
+            })
+    }
+
+    /// The record of the deposit or withdrawal `id` together with its kind
+    fn get_disputed_transaction(
         &mut self,
         id: TransactionId,
-    ) -> Result<(&mut TransactionState, Decimal), TransactionProcessingError> {
-        match self.deposit_transactions.get_mut(&id) {
-            None => Err(TransactionProcessingError::InvalidTransactionId(id)),
-            Some((state, amount)) => Ok((state, *amount)),
-        }
+    ) -> Result<(&mut TransactionRecord, TransactionKind), TransactionProcessingError> {
+        let evicted = self.evicted_ids.contains(id);
+        self.transactions
+            .get_mut(id)
+            .map(|(kind, record)| (record, kind))
+            .ok_or(if evicted {
+                TransactionProcessingError::TransactionEvicted(id)
+            } else {
+                TransactionProcessingError::InvalidTransactionId(id)
+            })
     }
 
-    fn validate_unique_id(&mut self, id: TransactionId) -> Result<(), TransactionProcessingError> {
-        if self.transaction_ids.contains(&id) {
+    fn validate_unique_id(
+        &mut self,
+        id: TransactionId,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionProcessingError> {
+        if self.evicted_ids.contains(id) || !self.transactions.use_id(id) {
             return Err(TransactionProcessingError::InvalidTransactionId(id));
         }
-        self.transaction_ids.insert(id);
+        if config.max_transactions_per_account.is_some() {
+            self.recent.push_back(id);
+        }
         Ok(())
     }
+
+    /// Evict the oldest transactions until at most `max` are left, and return their ids
+    /// A transaction with a dispute which is not resolved is kept, so it can still be resolved or charged back
+    /// The evicted ids are kept in `evicted_ids`, so they are reported as evicted and can't be used again
+    pub fn evict_transactions(&mut self, max: usize) -> Vec<TransactionId> {
+        let mut evicted = Vec::new();
+        let mut kept = 0;
+        while self.transactions.len() > max && kept < self.recent.len() {
+            let Some(id) = self.recent.pop_front() else {
+                break;
+            };
+            let settled = self.transactions.get(id).is_none_or(|(_, record)| {
+                record
+                    .cases
+                    .iter()
+                    .all(|case| case.state == DisputeState::Resolved)
+            });
+            if !settled {
+                self.recent.push_back(id);
+                kept += 1;
+                continue;
+            }
+            match self.transactions.remove(id) {
+                Some(TxRecord::Deposit(record)) => {
+                    self.evicted_net = self.evicted_net.saturating_add(record.amount);
+                }
+                Some(TxRecord::Withdrawal(record)) => {
+                    self.evicted_net = self.evicted_net.saturating_sub(record.amount);
+                }
+                Some(TxRecord::NoRecord) => {}
+                None => continue,
+            }
+            self.evicted += 1;
+            self.evicted_ids.insert(id);
+            evicted.push(id);
+        }
+        evicted
+    }
+}
+
+/// Reject a dispute at `timestamp` if the transaction is older than the dispute window
+fn validate_dispute_window(
+    record: &TransactionRecord,
+    timestamp: Option<Timestamp>,
+    config: &EngineConfig,
+) -> Result<(), TransactionProcessingError> {
+    match (config.dispute_window, record.timestamp, timestamp) {
+        (Some(window), Some(created), Some(timestamp))
+            if timestamp.saturating_sub(created) > window =>
+        {
+            Err(TransactionProcessingError::DisputeWindowExpired)
+        }
+        _ => Ok(()),
+    }
+}
+
+impl TransactionRecord {
+    pub fn new(amount: Decimal, timestamp: Option<Timestamp>) -> Self {
+        Self {
+            amount,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    /// Chargeback if any dispute was charged back, otherwise under dispute if any dispute is open
+    pub fn state(&self) -> TransactionState {
+        let has = |state| self.cases.iter().any(|case| case.state == state);
+        if has(DisputeState::Chargeback) {
+            TransactionState::Chargeback
+        } else if has(DisputeState::Open) {
+            TransactionState::UnderDispute
+        } else {
+            TransactionState::Normal
+        }
+    }
+
+    /// How many times this transaction was disputed
+    pub fn disputes(&self) -> u32 {
+        self.cases.len() as u32
+    }
+
+    /// The amount which is neither under dispute nor charged back
+    pub fn undisputed(&self) -> Decimal {
+        self.cases
+            .iter()
+            .filter(|case| matches!(case.state, DisputeState::Open | DisputeState::Chargeback))
+            .fold(self.amount, |undisputed, case| undisputed - case.amount)
+    }
+
+    /// The dispute `case_id` which must be in `state`, or the oldest dispute in `state` without a case id
+    fn find_case(
+        &mut self,
+        case_id: Option<CaseId>,
+        state: DisputeState,
+    ) -> Result<&mut DisputeCase, TransactionProcessingError> {
+        let case = match case_id {
+            Some(case_id) => self
+                .cases
+                .iter_mut()
+                .find(|case| case.id == Some(case_id))
+                .ok_or(TransactionProcessingError::InvalidCaseId(case_id))?,
+            None => self
+                .cases
+                .iter_mut()
+                .find(|case| case.state == state)
+                .ok_or(TransactionProcessingError::InvalidTransactionState)?,
+        };
+        if case.state != state {
+            return Err(TransactionProcessingError::InvalidTransactionState);
+        }
+        Ok(case)
+    }
 }
 
+impl TransactionType {
+    /// The name of the type used in csv
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::ChargebackReversal => "chargeback_reversal",
+            TransactionType::Unlock => "unlock",
+            TransactionType::OpenAccount => "open_account",
+            TransactionType::CloseAccount => "close_account",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Schedule => "schedule",
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Flag => "flag",
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = TransactionParsingError;
+
+    /// Same names as the deserialization of a row, for the inputs which are not deserialized with serde
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "chargeback_reversal" => Ok(TransactionType::ChargebackReversal),
+            "unlock" | "unfreeze" => Ok(TransactionType::Unlock),
+            "open_account" => Ok(TransactionType::OpenAccount),
+            "close_account" => Ok(TransactionType::CloseAccount),
+            "transfer" => Ok(TransactionType::Transfer),
+            "schedule" => Ok(TransactionType::Schedule),
+            "adjustment" => Ok(TransactionType::Adjustment),
+            "flag" => Ok(TransactionType::Flag),
+            _ => Err(TransactionParsingError::InvalidType),
+        }
+    }
+}
+
+impl Transaction {
+    pub fn transaction_type(&self) -> TransactionType {
+        match self {
+            Transaction::Deposit(_) => TransactionType::Deposit,
+            Transaction::Withdrawal(_) => TransactionType::Withdrawal,
+            Transaction::Dispute(_) | Transaction::PartialDispute(..) => TransactionType::Dispute,
+            Transaction::Resolve(_) => TransactionType::Resolve,
+            Transaction::Chargeback(_) => TransactionType::Chargeback,
+            Transaction::ChargebackReversal(_) => TransactionType::ChargebackReversal,
+            Transaction::Unlock => TransactionType::Unlock,
+            Transaction::OpenAccount => TransactionType::OpenAccount,
+            Transaction::CloseAccount => TransactionType::CloseAccount,
+            Transaction::Transfer(..) => TransactionType::Transfer,
+            Transaction::Adjustment(..) => TransactionType::Adjustment,
+            Transaction::Flag(_) => TransactionType::Flag,
+        }
+    }
+
+    /// The name of the transaction type used in csv
+    pub fn type_name(&self) -> &'static str {
+        self.transaction_type().name()
+    }
+
+    /// The amount of the transaction if it carries one
+    pub fn amount(&self) -> Option<Decimal> {
+        match self {
+            Transaction::Deposit(amount)
+            | Transaction::Withdrawal(amount)
+            | Transaction::Transfer(_, amount)
+            | Transaction::PartialDispute(amount, _)
+            | Transaction::Adjustment(amount, _) => Some(*amount),
+            Transaction::Dispute(_)
+            | Transaction::Resolve(_)
+            | Transaction::Chargeback(_)
+            | Transaction::ChargebackReversal(_)
+            | Transaction::Unlock
+            | Transaction::OpenAccount
+            | Transaction::CloseAccount
+            | Transaction::Flag(_) => None,
+        }
+    }
+
+    /// The reason code of an adjustment or a flag
+    pub fn reason(&self) -> Option<ReasonCode> {
+        match self {
+            Transaction::Adjustment(_, reason) | Transaction::Flag(reason) => Some(*reason),
+            _ => None,
+        }
+    }
+
+    /// The case id of a dispute, resolve, chargeback or reversal if it has one
+    pub fn case_id(&self) -> Option<CaseId> {
+        match self {
+            Transaction::Dispute(case)
+            | Transaction::PartialDispute(_, case)
+            | Transaction::Resolve(case)
+            | Transaction::Chargeback(case)
+            | Transaction::ChargebackReversal(case) => *case,
+            _ => None,
+        }
+    }
+
+    /// The same transaction with its amount replaced, transactions without an amount are unchanged
+    pub fn with_amount(self, amount: Decimal) -> Self {
+        match self {
+            Transaction::Deposit(_) => Transaction::Deposit(amount),
+            Transaction::Withdrawal(_) => Transaction::Withdrawal(amount),
+            Transaction::Transfer(to, _) => Transaction::Transfer(to, amount),
+            Transaction::PartialDispute(_, case) => Transaction::PartialDispute(amount, case),
+            Transaction::Adjustment(_, reason) => Transaction::Adjustment(amount, reason),
+            Transaction::Dispute(_)
+            | Transaction::Resolve(_)
+            | Transaction::Chargeback(_)
+            | Transaction::ChargebackReversal(_)
+            | Transaction::Unlock
+            | Transaction::OpenAccount
+            | Transaction::CloseAccount
+            | Transaction::Flag(_) => self,
+        }
+    }
+}
+
+impl CsvInputRow {
+    /// The row representing `transaction`, this is the reverse of `parse_transaction`
+    pub fn new(client: ClientId, tx: TransactionId, transaction: &Transaction) -> Self {
+        Self {
+            transaction_type: transaction.transaction_type(),
+            client,
+            tx,
+            amount: transaction.amount(),
+            to: match transaction {
+                Transaction::Transfer(to, _) => Some(*to),
+                _ => None,
+            },
+            timestamp: None,
+            case_id: transaction.case_id(),
+            interval: None,
+            count: None,
+            reason: transaction.reason(),
+        }
+    }
+}
+
+/// Reject negative amounts, and zero amounts unless `allow_zero` is set
+/// A negative deposit would otherwise act as a withdrawal without any of the checks
+/// The amount of an adjustment is signed, so only a zero amount is rejected
+pub fn validate_transaction(
+    transaction: &Transaction,
+    allow_zero: bool,
+) -> Result<(), TransactionParsingError> {
+    if let Transaction::Adjustment(amount, _) = transaction {
+        return match amount.is_zero() && !allow_zero {
+            true => Err(TransactionParsingError::NonPositiveAmount(*amount)),
+            false => Ok(()),
+        };
+    }
+    match transaction.amount() {
+        Some(amount) if amount.is_sign_negative() && !amount.is_zero() => {
+            Err(TransactionParsingError::NonPositiveAmount(amount))
+        }
+        Some(amount) if amount.is_zero() && !allow_zero => {
+            Err(TransactionParsingError::NonPositiveAmount(amount))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse the row into a transaction, the amount is validated separately by `validate_transaction`
 pub fn parse_transaction(row: &CsvInputRow) -> Result<Transaction, TransactionParsingError> {
-    match row.transaction_type.as_str() {
-        "deposit" => Ok(Transaction::Deposit(
-            row.amount.ok_or(TransactionParsingError::MissingAmount)?,
+    parse_fields(
+        row.transaction_type,
+        row.amount,
+        row.to,
+        row.case_id,
+        row.reason,
+    )
+}
+
+/// Same as `parse_transaction` for the fields of a row which is not a `CsvInputRow`
+/// A schedule is not a single transaction, it is only supported by `Engine::process_row`
+pub fn parse_fields(
+    transaction_type: TransactionType,
+    amount: Option<Decimal>,
+    to: Option<ClientId>,
+    case: Option<CaseId>,
+    reason: Option<ReasonCode>,
+) -> Result<Transaction, TransactionParsingError> {
+    match transaction_type {
+        TransactionType::Deposit => Ok(Transaction::Deposit(
+            amount.ok_or(TransactionParsingError::MissingAmount)?,
         )),
-        "withdrawal" => Ok(Transaction::Withdrawal(
-            row.amount.ok_or(TransactionParsingError::MissingAmount)?,
+        TransactionType::Withdrawal => Ok(Transaction::Withdrawal(
+            amount.ok_or(TransactionParsingError::MissingAmount)?,
+        )),
+        TransactionType::Dispute => Ok(match amount {
+            Some(amount) => Transaction::PartialDispute(amount, case),
+            None => Transaction::Dispute(case),
+        }),
+        TransactionType::Resolve => Ok(Transaction::Resolve(case)),
+        TransactionType::Chargeback => Ok(Transaction::Chargeback(case)),
+        TransactionType::ChargebackReversal => Ok(Transaction::ChargebackReversal(case)),
+        TransactionType::Unlock => Ok(Transaction::Unlock),
+        TransactionType::OpenAccount => Ok(Transaction::OpenAccount),
+        TransactionType::CloseAccount => Ok(Transaction::CloseAccount),
+        TransactionType::Transfer => Ok(Transaction::Transfer(
+            to.ok_or(TransactionParsingError::MissingDestination)?,
+            amount.ok_or(TransactionParsingError::MissingAmount)?,
+        )),
+        TransactionType::Schedule => Err(TransactionParsingError::InvalidType),
+        TransactionType::Adjustment => Ok(Transaction::Adjustment(
+            amount.ok_or(TransactionParsingError::MissingAmount)?,
+            reason.ok_or(TransactionParsingError::MissingReason)?,
+        )),
+        TransactionType::Flag => Ok(Transaction::Flag(
+            reason.ok_or(TransactionParsingError::MissingReason)?,
         )),
-        "dispute" => Ok(Transaction::Dispute),
-        "resolve" => Ok(Transaction::Resolve),
-        "chargeback" => Ok(Transaction::Chargeback),
-        _ => Err(TransactionParsingError::InvalidType),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::FreezeOnChargeback;
     #[test]
     fn test_transaction_processing() {
         let mut profile = AccountProfile::default();
@@ -120,8 +876,8 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(profile.available, Decimal::from(10));
         assert_eq!(profile.held, Decimal::from(0));
-        assert!(profile.deposit_transactions.contains_key(&1));
-        assert_eq!(profile.frozen, false);
+        assert!(profile.transactions.deposit(1).is_some());
+        assert!(!profile.is_frozen());
 
         let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
         assert!(res.is_err());
@@ -130,67 +886,67 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(profile.available, Decimal::from(15));
         assert_eq!(profile.held, Decimal::from(0));
-        assert_eq!(profile.deposit_transactions.len(), 2);
-        assert_eq!(profile.frozen, false);
+        assert_eq!(profile.transactions.deposits().count(), 2);
+        assert!(!profile.is_frozen());
 
         // Withdrawal
         let res = profile.process_transaction(3, Transaction::Withdrawal(Decimal::from(2)));
         assert!(res.is_ok());
         assert_eq!(profile.available, Decimal::from(13));
         assert_eq!(profile.held, Decimal::from(0));
-        assert_eq!(profile.deposit_transactions.len(), 2);
-        assert_eq!(profile.frozen, false);
+        assert_eq!(profile.transactions.deposits().count(), 2);
+        assert!(!profile.is_frozen());
 
         // Dispute -> Resolve
-        let res = profile.process_transaction(1, Transaction::Dispute);
+        let res = profile.process_transaction(1, Transaction::Dispute(None));
         assert!(res.is_ok());
         assert_eq!(profile.available, Decimal::from(3));
         assert_eq!(profile.held, Decimal::from(10));
         assert_eq!(
-            profile.deposit_transactions.get(&1).unwrap().0,
+            profile.transactions.deposit(1).unwrap().state(),
             TransactionState::UnderDispute
         );
-        assert_eq!(profile.frozen, false);
+        assert!(!profile.is_frozen());
 
-        let res = profile.process_transaction(1, Transaction::Dispute);
+        let res = profile.process_transaction(1, Transaction::Dispute(None));
         assert!(res.is_err());
 
-        let res = profile.process_transaction(3, Transaction::Dispute);
+        let res = profile.process_transaction(4, Transaction::Dispute(None));
         assert!(res.is_err());
 
-        let res = profile.process_transaction(1, Transaction::Resolve);
+        let res = profile.process_transaction(1, Transaction::Resolve(None));
         assert!(res.is_ok());
         assert_eq!(profile.available, Decimal::from(13));
         assert_eq!(profile.held, Decimal::from(0));
         assert_eq!(
-            profile.deposit_transactions.get(&1).unwrap().0,
+            profile.transactions.deposit(1).unwrap().state(),
             TransactionState::Normal
         );
-        assert_eq!(profile.frozen, false);
+        assert!(!profile.is_frozen());
 
-        let res = profile.process_transaction(1, Transaction::Resolve);
+        let res = profile.process_transaction(1, Transaction::Resolve(None));
         assert!(res.is_err());
 
         // Dispute -> ChargeBack
-        let res = profile.process_transaction(2, Transaction::Dispute);
+        let res = profile.process_transaction(2, Transaction::Dispute(None));
         assert!(res.is_ok());
         assert_eq!(profile.available, Decimal::from(8));
         assert_eq!(profile.held, Decimal::from(5));
         assert_eq!(
-            profile.deposit_transactions.get(&2).unwrap().0,
+            profile.transactions.deposit(2).unwrap().state(),
             TransactionState::UnderDispute
         );
-        assert_eq!(profile.frozen, false);
+        assert!(!profile.is_frozen());
 
-        let res = profile.process_transaction(2, Transaction::Chargeback);
+        let res = profile.process_transaction(2, Transaction::Chargeback(None));
         assert!(res.is_ok());
         assert_eq!(profile.available, Decimal::from(8));
         assert_eq!(profile.held, Decimal::from(0));
         assert_eq!(
-            profile.deposit_transactions.get(&2).unwrap().0,
+            profile.transactions.deposit(2).unwrap().state(),
             TransactionState::Chargeback
         );
-        assert_eq!(profile.frozen, true);
+        assert!(profile.is_frozen());
 
         let res = profile.process_transaction(4, Transaction::Deposit(Decimal::from(20)));
         assert!(res.is_err());
@@ -206,10 +962,814 @@ mod tests {
         let res = profile.process_transaction(2, Transaction::Withdrawal(Decimal::from(10)));
         assert!(res.is_ok());
 
-        let res = profile.process_transaction(1, Transaction::Dispute);
+        let res = profile.process_transaction(1, Transaction::Dispute(None));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_withdrawal_dispute() {
+        let mut profile = AccountProfile::default();
+
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+
+        let res = profile.process_transaction(2, Transaction::Withdrawal(Decimal::from(4)));
+        assert!(res.is_ok());
+        assert!(profile.transactions.withdrawal(2).is_some());
+
+        // Dispute -> Resolve, the withdrawal stands
+        let res = profile.process_transaction(2, Transaction::Dispute(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(6));
+        assert_eq!(profile.held, Decimal::from(4));
+
+        let res = profile.process_transaction(2, Transaction::Resolve(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(6));
+        assert_eq!(profile.held, Decimal::from(0));
+
+        // Dispute -> Chargeback, the fund goes back to the client
+        let res = profile.process_transaction(2, Transaction::Dispute(None));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(2, Transaction::Chargeback(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(10));
+        assert_eq!(profile.held, Decimal::from(0));
+        assert_eq!(
+            profile.transactions.withdrawal(2).unwrap().state(),
+            TransactionState::Chargeback
+        );
+        assert!(profile.is_frozen());
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let mut profile = AccountProfile::default();
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(11), None));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::DisputeAmountTooHigh(_))
+        ));
+
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(4), None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(6));
+        assert_eq!(profile.held, Decimal::from(4));
+        let record = profile.transactions.deposit(1).unwrap();
+        assert_eq!(record.undisputed(), Decimal::from(6));
+
+        let res = profile.process_transaction(1, Transaction::Resolve(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(10));
+        assert_eq!(profile.held, Decimal::from(0));
+
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(3), None));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(1, Transaction::Chargeback(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(7));
+        assert_eq!(profile.held, Decimal::from(0));
+        assert_eq!(
+            parse_fields(
+                TransactionType::Dispute,
+                Some(Decimal::from(3)),
+                None,
+                None,
+                None
+            )
+            .unwrap(),
+            Transaction::PartialDispute(Decimal::from(3), None)
+        );
+    }
+
+    #[test]
+    fn test_concurrent_disputes() {
+        let mut profile = AccountProfile::default();
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(3), None));
+        assert!(res.is_ok());
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(5), None));
+        assert!(res.is_ok());
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(3), None));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::DisputeAmountTooHigh(_))
+        ));
+        assert_eq!(profile.held, Decimal::from(8));
+
+        // The oldest dispute is resolved first, the other one is charged back
+        let res = profile.process_transaction(1, Transaction::Resolve(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(5));
+        assert_eq!(profile.held, Decimal::from(5));
+        let record = profile.transactions.deposit(1).unwrap();
+        assert_eq!(record.state(), TransactionState::UnderDispute);
+        assert_eq!(record.undisputed(), Decimal::from(5));
+
+        // A dispute without an amount disputes the rest
+        let res = profile.process_transaction(1, Transaction::Dispute(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.held, Decimal::from(10));
+
+        let res = profile.process_transaction(1, Transaction::Chargeback(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.held, Decimal::from(5));
+        let record = profile.transactions.deposit(1).unwrap();
+        assert_eq!(record.state(), TransactionState::Chargeback);
+        assert_eq!(
+            record
+                .cases
+                .iter()
+                .map(|case| case.state)
+                .collect::<Vec<_>>(),
+            vec![
+                DisputeState::Resolved,
+                DisputeState::Chargeback,
+                DisputeState::Open
+            ]
+        );
+        assert!(profile.is_frozen());
+    }
+
+    #[test]
+    fn test_case_ids() {
+        let mut profile = AccountProfile::default();
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(3), Some(1)));
+        assert!(res.is_ok());
+        let res =
+            profile.process_transaction(1, Transaction::PartialDispute(Decimal::from(2), Some(1)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidCaseId(1))
+        ));
+        let res = profile.process_transaction(1, Transaction::Dispute(Some(2)));
+        assert!(res.is_ok());
+
+        // The case id picks the dispute instead of the oldest one
+        let res = profile.process_transaction(1, Transaction::Resolve(Some(3)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidCaseId(3))
+        ));
+        let res = profile.process_transaction(1, Transaction::Resolve(Some(2)));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(7));
+        assert_eq!(profile.held, Decimal::from(3));
+        let res = profile.process_transaction(1, Transaction::Chargeback(Some(2)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidTransactionState)
+        ));
+        let res = profile.process_transaction(1, Transaction::Chargeback(Some(1)));
+        assert!(res.is_ok());
+        assert_eq!(profile.held, Decimal::ZERO);
+        assert!(profile.is_frozen());
+    }
+
+    #[test]
+    fn test_validate_transaction() {
+        let deposit = Transaction::Deposit(Decimal::from(1));
+        assert!(validate_transaction(&deposit, false).is_ok());
+
+        let negative = Transaction::Withdrawal(Decimal::from(-5));
+        assert!(validate_transaction(&negative, false).is_err());
+        assert!(validate_transaction(&negative, true).is_err());
+
+        let zero = Transaction::Deposit(Decimal::new(0, 4));
+        assert!(validate_transaction(&zero, false).is_err());
+        assert!(validate_transaction(&zero, true).is_ok());
+
+        assert!(validate_transaction(&Transaction::Dispute(None), false).is_ok());
+    }
+
+    #[test]
+    fn test_dispute_after_withdrawal_allow_negative() {
+        let mut profile = AccountProfile::default();
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy::AllowNegativeAvailable,
+            ..Default::default()
+        };
+
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+
+        let res = profile.process_transaction(2, Transaction::Withdrawal(Decimal::from(8)));
+        assert!(res.is_ok());
+
+        let res = profile.process_transaction_with(1, Transaction::Dispute(None), &config);
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(-8));
+        assert_eq!(profile.held, Decimal::from(10));
+
+        // No more withdrawals until the balance recovers
+        let res =
+            profile.process_transaction_with(3, Transaction::Withdrawal(Decimal::from(1)), &config);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_max_disputes() {
+        let mut profile = AccountProfile::default();
+        let config = EngineConfig {
+            max_disputes: Some(2),
+            ..Default::default()
+        };
+
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+
+        for _ in 0..2 {
+            let res = profile.process_transaction_with(1, Transaction::Dispute(None), &config);
+            assert!(res.is_ok());
+            let res = profile.process_transaction_with(1, Transaction::Resolve(None), &config);
+            assert!(res.is_ok());
+        }
+        let record = profile.transactions.deposit(1).unwrap();
+        assert_eq!(record.state(), TransactionState::Normal);
+        assert_eq!(record.disputes(), 2);
+        assert_eq!(profile.available, Decimal::from(10));
+
+        let res = profile.process_transaction_with(1, Transaction::Dispute(None), &config);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_unlock() {
+        let mut profile = AccountProfile::default();
+
+        let res = profile.process_transaction(1, Transaction::Unlock);
+        assert!(res.is_err());
+
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(2, Transaction::Deposit(Decimal::from(5)));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(1, Transaction::Dispute(None));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(2, Transaction::Dispute(None));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(1, Transaction::Chargeback(None));
+        assert!(res.is_ok());
+        assert!(profile.is_frozen());
+
+        // Transaction 2 is still under dispute
+        let config = EngineConfig {
+            unfreeze_requires_zero_held: true,
+            ..Default::default()
+        };
+        let res = profile.process_transaction_with(3, Transaction::Unlock, &config);
+        assert!(res.is_err());
+        assert!(profile.is_frozen());
+
+        let res = profile.process_transaction(3, Transaction::Unlock);
+        assert!(res.is_ok());
+        assert!(!profile.is_frozen());
+        let res = profile.process_transaction(2, Transaction::Resolve(None));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(5));
+    }
+
+    #[test]
+    fn test_account_lifecycle() {
+        let mut profile = AccountProfile::default();
+        let res = profile.process_transaction(0, Transaction::OpenAccount);
+        assert!(res.is_ok());
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::from(10)));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(1, Transaction::Dispute(None));
+        assert!(res.is_ok());
+
+        let res = profile.process_transaction(0, Transaction::CloseAccount);
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::HeldAmountNotZero(_))
+        ));
+        let res = profile.process_transaction(1, Transaction::Resolve(None));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(0, Transaction::CloseAccount);
+        assert!(res.is_ok());
+        assert_eq!(profile.status, AccountStatus::Closed);
+
+        let res = profile.process_transaction(2, Transaction::Deposit(Decimal::from(1)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::AccountIsClosed)
+        ));
+        assert!(profile.validate_incoming(2).is_err());
+
+        let res = profile.process_transaction(0, Transaction::OpenAccount);
+        assert!(res.is_ok());
+        let res = profile.process_transaction(2, Transaction::Deposit(Decimal::from(1)));
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(11));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let mut profile = AccountProfile::default();
+        let config = EngineConfig {
+            strict_timestamps: true,
+            ..Default::default()
+        };
+
+        let res = profile.process_transaction_at(
+            1,
+            Transaction::Deposit(Decimal::from(10)),
+            Some(100),
+            &config,
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            profile.transactions.deposit(1).unwrap().timestamp,
+            Some(100)
+        );
+        let res = profile.process_transaction_at(
+            2,
+            Transaction::Deposit(Decimal::from(10)),
+            Some(50),
+            &config,
+        );
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::OutOfOrder(50, 100))
+        ));
+        // Rows without a timestamp are not checked
+        let res = profile.process_transaction_at(
+            2,
+            Transaction::Deposit(Decimal::from(10)),
+            None,
+            &config,
+        );
+        assert!(res.is_ok());
+        let res = profile.process_transaction_at(1, Transaction::Dispute(None), Some(100), &config);
+        assert!(res.is_ok());
+        assert_eq!(profile.last_timestamp, Some(100));
+
+        // Out of order transactions are accepted by default
+        let res = profile.process_transaction_at(
+            3,
+            Transaction::Deposit(Decimal::from(10)),
+            Some(50),
+            &EngineConfig::default(),
+        );
+        assert!(res.is_ok());
+        assert_eq!(profile.last_timestamp, Some(100));
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let mut profile = AccountProfile::default();
+        let config = EngineConfig {
+            dispute_window: Some(100),
+            ..Default::default()
+        };
+        let deposit = Transaction::Deposit(Decimal::from(10));
+        assert!(
+            profile
+                .process_transaction_at(1, deposit, Some(1000), &config)
+                .is_ok()
+        );
+        assert!(
+            profile
+                .process_transaction_at(2, deposit, Some(1000), &config)
+                .is_ok()
+        );
+        assert!(
+            profile
+                .process_transaction_at(3, deposit, None, &config)
+                .is_ok()
+        );
+
+        let res =
+            profile.process_transaction_at(1, Transaction::Dispute(None), Some(1100), &config);
+        assert!(res.is_ok());
+        let res =
+            profile.process_transaction_at(2, Transaction::Dispute(None), Some(1101), &config);
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::DisputeWindowExpired)
+        ));
+        // We can't tell the age of a transaction without a timestamp
+        let res =
+            profile.process_transaction_at(3, Transaction::Dispute(None), Some(5000), &config);
+        assert!(res.is_ok());
+        assert_eq!(profile.held, Decimal::from(20));
+    }
+
+    #[test]
+    fn test_overdraft_limit() {
+        let mut profile = AccountProfile::default();
+        let config = EngineConfig {
+            overdraft_limit: Some(Decimal::from(10)),
+            ..Default::default()
+        };
+        let res =
+            profile.process_transaction_with(1, Transaction::Deposit(Decimal::from(5)), &config);
+        assert!(res.is_ok());
+        let res = profile.process_transaction_with(
+            2,
+            Transaction::Withdrawal(Decimal::from(15)),
+            &config,
+        );
+        assert!(res.is_ok());
+        assert_eq!(profile.available, Decimal::from(-10));
+        let res = profile.process_transaction_with(
+            3,
+            Transaction::Withdrawal(Decimal::new(1, 2)),
+            &config,
+        );
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::OverdraftLimitExceeded(_))
+        ));
+
+        // The limit of the account overrides the global one
+        profile.overdraft_limit = Some(Decimal::ZERO);
+        let res =
+            profile.process_transaction_with(4, Transaction::Deposit(Decimal::from(20)), &config);
+        assert!(res.is_ok());
+        let res = profile.process_transaction_with(
+            5,
+            Transaction::Withdrawal(Decimal::from(11)),
+            &config,
+        );
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::AvailableAmountTooLow(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_arithmetic_overflow() {
+        let mut profile = AccountProfile::default();
+        let res = profile.process_transaction(1, Transaction::Deposit(Decimal::MAX));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(2, Transaction::Deposit(Decimal::ONE));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::ArithmeticOverflow)
+        ));
+        assert_eq!(profile.available, Decimal::MAX);
+        assert!(profile.transactions.deposit(2).is_none());
+
+        let res = profile.process_transaction(3, Transaction::Withdrawal(Decimal::MAX));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(4, Transaction::Deposit(Decimal::MAX));
+        assert!(res.is_ok());
+        // Disputing both deposits would hold more than the maximum
+        let res = profile.process_transaction(4, Transaction::Dispute(None));
+        assert!(res.is_ok());
+        let res = profile.process_transaction(3, Transaction::Dispute(None));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::ArithmeticOverflow)
+        ));
+        assert_eq!(profile.held, Decimal::MAX);
+        assert_eq!(
+            profile.transactions.withdrawal(3).unwrap().state(),
+            TransactionState::Normal
+        );
+        assert_eq!(profile.checked_total(), Some(Decimal::MAX));
+
+        let profile = AccountProfile::with_balances(Decimal::MAX, Decimal::ONE);
+        assert_eq!(profile.checked_total(), None);
+        assert_eq!(profile.total(), Decimal::MAX);
+    }
+
+    #[test]
+    fn test_chargeback_reversal() {
+        let mut profile = AccountProfile::default();
+        profile
+            .process_transaction(1, Transaction::Deposit(Decimal::from(10)))
+            .unwrap();
+        profile
+            .process_transaction(2, Transaction::Withdrawal(Decimal::from(3)))
+            .unwrap();
+        let res = profile.process_transaction(1, Transaction::ChargebackReversal(None));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidTransactionState)
+        ));
+        profile
+            .process_transaction(2, Transaction::Dispute(None))
+            .unwrap();
+        profile
+            .process_transaction(2, Transaction::Chargeback(None))
+            .unwrap();
+        assert_eq!(profile.available, Decimal::from(10));
+        let config = EngineConfig {
+            unfreeze_on_reversal: true,
+            ..Default::default()
+        };
+        profile
+            .process_transaction_with(2, Transaction::ChargebackReversal(None), &config)
+            .unwrap();
+        assert_eq!(profile.available, Decimal::from(7));
+        assert!(!profile.is_frozen());
+        assert_eq!(profile.check_invariants(), Ok(()));
+
+        // Without the policy the account stays frozen, the reversal is accepted by the frozen account
+        profile
+            .process_transaction(1, Transaction::PartialDispute(Decimal::from(4), Some(9)))
+            .unwrap();
+        profile
+            .process_transaction(1, Transaction::Chargeback(Some(9)))
+            .unwrap();
+        assert_eq!(profile.total(), Decimal::from(3));
+        let res = profile.process_transaction(1, Transaction::ChargebackReversal(Some(8)));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidCaseId(8))
+        ));
+        profile
+            .process_transaction(1, Transaction::ChargebackReversal(Some(9)))
+            .unwrap();
+        assert_eq!(profile.available, Decimal::from(7));
+        assert_eq!(profile.held, Decimal::ZERO);
+        assert!(profile.is_frozen());
+        assert_eq!(
+            profile.transactions.deposit(1).unwrap().state(),
+            TransactionState::Normal
+        );
+        assert_eq!(
+            profile.transactions.deposit(1).unwrap().undisputed(),
+            Decimal::from(10)
+        );
+        assert_eq!(profile.check_invariants(), Ok(()));
+        let res = profile.process_transaction(1, Transaction::ChargebackReversal(Some(9)));
         assert!(res.is_err());
     }
 
+    #[test]
+    fn test_adjustment() {
+        let mut profile = AccountProfile::default();
+        profile
+            .process_transaction(1, Transaction::Deposit(Decimal::from(10)))
+            .unwrap();
+        profile
+            .process_transaction(2, Transaction::Adjustment(Decimal::from(-4), 7))
+            .unwrap();
+        assert_eq!(profile.available, Decimal::from(6));
+        assert_eq!(profile.adjusted, Decimal::from(-4));
+        // The id is used, and an adjustment can't be disputed
+        let res = profile.process_transaction(2, Transaction::Deposit(Decimal::ONE));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::InvalidTransactionId(2))
+        ));
+        let res = profile.process_transaction(2, Transaction::Dispute(None));
+        assert!(res.is_err());
+        let res = profile.process_transaction(3, Transaction::Adjustment(Decimal::from(-7), 7));
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::AvailableAmountTooLow(..))
+        ));
+        let config = EngineConfig {
+            overdraft_limit: Some(Decimal::ONE),
+            ..Default::default()
+        };
+        let res = profile.process_transaction_with(
+            9,
+            Transaction::Adjustment(Decimal::from(-8), 7),
+            &config,
+        );
+        assert!(matches!(
+            res,
+            Err(TransactionProcessingError::OverdraftLimitExceeded(_))
+        ));
+
+        // A frozen account accepts the corrections of the operators
+        profile
+            .process_transaction(1, Transaction::Dispute(None))
+            .unwrap_err();
+        profile
+            .process_transaction(4, Transaction::Deposit(Decimal::from(3)))
+            .unwrap();
+        profile
+            .process_transaction(4, Transaction::Dispute(None))
+            .unwrap();
+        profile
+            .process_transaction(4, Transaction::Chargeback(None))
+            .unwrap();
+        profile
+            .process_transaction(5, Transaction::Adjustment(Decimal::from(2), 1))
+            .unwrap();
+        profile
+            .process_transaction(4, Transaction::Flag(9))
+            .unwrap();
+        assert_eq!(profile.available, Decimal::from(8));
+        assert_eq!(
+            profile.flags,
+            [FlagRecord {
+                tx: 4,
+                reason: 9,
+                timestamp: None
+            }]
+        );
+        assert_eq!(profile.check_invariants(), Ok(()));
+
+        let row = |amount, reason| CsvInputRow {
+            reason,
+            ..CsvInputRow::new(1, 6, &Transaction::Adjustment(amount, 0))
+        };
+        assert_eq!(
+            parse_transaction(&row(Decimal::from(-2), Some(3))).unwrap(),
+            Transaction::Adjustment(Decimal::from(-2), 3)
+        );
+        assert!(matches!(
+            parse_transaction(&row(Decimal::ONE, None)),
+            Err(TransactionParsingError::MissingReason)
+        ));
+        let adjustment = Transaction::Adjustment(Decimal::from(-2), 3);
+        assert!(validate_transaction(&adjustment, false).is_ok());
+        let adjustment = Transaction::Adjustment(Decimal::ZERO, 3);
+        assert!(validate_transaction(&adjustment, false).is_err());
+    }
+
+    #[test]
+    fn test_freeze_on_chargeback() {
+        let config = EngineConfig {
+            freeze_on_chargeback: "above:5".parse().unwrap(),
+            unfreeze_on_reversal: true,
+            ..Default::default()
+        };
+        let mut profile = AccountProfile::default();
+        for (id, transaction) in [
+            (1, Transaction::Deposit(Decimal::from(20))),
+            (1, Transaction::PartialDispute(Decimal::from(5), Some(1))),
+            (1, Transaction::Chargeback(Some(1))),
+        ] {
+            profile
+                .process_transaction_with(id, transaction, &config)
+                .unwrap();
+        }
+        // A chargeback up to the threshold doesn't freeze
+        assert!(!profile.is_frozen());
+        assert_eq!(profile.total(), Decimal::from(15));
+
+        for transaction in [
+            Transaction::Deposit(Decimal::from(6)),
+            Transaction::Dispute(None),
+            Transaction::Chargeback(None),
+        ] {
+            profile
+                .process_transaction_with(2, transaction, &config)
+                .unwrap();
+        }
+        assert!(profile.is_frozen());
+        assert_eq!(profile.check_invariants(), Ok(()));
+        // The small chargeback left doesn't keep the account frozen
+        profile
+            .process_transaction_with(2, Transaction::ChargebackReversal(None), &config)
+            .unwrap();
+        assert!(!profile.is_frozen());
+
+        let config = EngineConfig {
+            freeze_on_chargeback: FreezeOnChargeback::Never,
+            ..Default::default()
+        };
+        let mut profile = AccountProfile::default();
+        for transaction in [
+            Transaction::Deposit(Decimal::from(20)),
+            Transaction::Dispute(None),
+            Transaction::Chargeback(None),
+        ] {
+            profile
+                .process_transaction_with(1, transaction, &config)
+                .unwrap();
+        }
+        assert!(!profile.is_frozen());
+        assert_eq!(profile.total(), Decimal::ZERO);
+
+        assert!("above:-1".parse::<FreezeOnChargeback>().is_err());
+        assert!("sometimes".parse::<FreezeOnChargeback>().is_err());
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut profile = AccountProfile::default();
+        let config = EngineConfig {
+            fee_policy: Some(crate::config::FeePolicy {
+                flat: Decimal::ONE,
+                percentage: Decimal::ZERO,
+            }),
+            ..Default::default()
+        };
+        for (id, transaction) in [
+            (1, Transaction::Deposit(Decimal::from(10))),
+            (2, Transaction::Deposit(Decimal::from(5))),
+            (3, Transaction::Withdrawal(Decimal::from(2))),
+            (3, Transaction::Dispute(None)),
+            (1, Transaction::PartialDispute(Decimal::from(4), None)),
+            (1, Transaction::Chargeback(None)),
+        ] {
+            profile
+                .process_transaction_with(id, transaction, &config)
+                .unwrap();
+            assert_eq!(profile.check_invariants(), Ok(()));
+        }
+
+        profile.available += Decimal::ONE;
+        assert_eq!(
+            profile.check_invariants(),
+            Err(InvariantViolation::TotalMismatch(
+                Decimal::from(11),
+                Decimal::from(10)
+            ))
+        );
+        profile.available -= Decimal::ONE;
+        profile.transactions.get_mut(1).unwrap().1.cases[0].state = DisputeState::Resolved;
+        profile.available += Decimal::from(4);
+        assert_eq!(
+            profile.check_invariants(),
+            Err(InvariantViolation::FrozenWithoutChargeback)
+        );
+    }
+
+    #[test]
+    fn test_undo_debit() {
+        let config = EngineConfig {
+            max_transactions_per_account: Some(5),
+            ..Default::default()
+        };
+        let mut profile = AccountProfile::default();
+        profile
+            .process_transaction_with(1, Transaction::Deposit(Decimal::from(10)), &config)
+            .unwrap();
+        let mut undone = AccountProfile::default();
+        undone
+            .process_transaction_with(1, Transaction::Deposit(Decimal::from(10)), &config)
+            .unwrap();
+        let undo = undone.debit_undo();
+        undone
+            .process_transaction_at(
+                2,
+                Transaction::Withdrawal(Decimal::from(4)),
+                Some(7),
+                &config,
+            )
+            .unwrap();
+        undone.undo_debit(2, undo);
+        assert_eq!(undone, profile);
+    }
+
+    #[test]
+    fn test_evict_transactions() {
+        let config = EngineConfig {
+            max_transactions_per_account: Some(2),
+            ..Default::default()
+        };
+        let mut profile = AccountProfile::default();
+        for (id, transaction) in [
+            (1, Transaction::Deposit(Decimal::from(10))),
+            (1, Transaction::Dispute(None)),
+            (2, Transaction::Deposit(Decimal::from(5))),
+            (3, Transaction::Withdrawal(Decimal::from(2))),
+            (4, Transaction::Withdrawal(Decimal::from(100))),
+        ] {
+            _ = profile.process_transaction_with(id, transaction, &config);
+        }
+        // The disputed deposit is kept, the rejected withdrawal counts as well
+        assert_eq!(profile.evict_transactions(2), [2, 3]);
+        assert_eq!(profile.transactions.len(), 2);
+        assert_eq!(
+            (profile.evicted, profile.evicted_net),
+            (2, Decimal::from(3))
+        );
+        assert_eq!(profile.check_invariants(), Ok(()));
+        assert!(matches!(
+            profile.process_transaction_with(2, Transaction::Dispute(None), &config),
+            Err(TransactionProcessingError::TransactionEvicted(2))
+        ));
+        // An evicted id can't be reused, another unknown id was never seen
+        assert!(matches!(
+            profile.process_transaction_with(2, Transaction::Deposit(Decimal::ONE), &config),
+            Err(TransactionProcessingError::InvalidTransactionId(2))
+        ));
+        assert!(matches!(
+            profile.process_transaction_with(9, Transaction::Dispute(None), &config),
+            Err(TransactionProcessingError::InvalidTransactionId(9))
+        ));
+        profile
+            .process_transaction_with(1, Transaction::Resolve(None), &config)
+            .unwrap();
+        assert_eq!(profile.available, Decimal::from(13));
+    }
+
     #[test]
     fn test_duplicated_id() {
         let mut profile = AccountProfile::default();
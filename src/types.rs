@@ -1,51 +1,232 @@
+use crate::compact::{DisputeCases, EvictedIds, Transactions};
+use crate::risk::RiskCounters;
 use rust_decimal::Decimal;
-use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use thiserror::Error;
 
 pub type ClientId = u16;
 pub type TransactionId = u32;
+/// Identifies a dispute of a transaction, it is unique within the disputes of the transaction
+pub type CaseId = u32;
+/// The name of a tenant with its own accounts, see `Tenants`
+pub type TenantId = String;
+/// Seconds since the unix epoch
+pub type Timestamp = u64;
+/// Why an operator adjusted or flagged an account, the codes are defined by the operators
+pub type ReasonCode = u32;
 
 /// Different transactions and transaction specific data.
 /// Note that we don't store the common fields like client and tx here
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Transaction {
     Deposit(Decimal),
     Withdrawal(Decimal),
-    Dispute,
-    Resolve,
-    Chargeback,
+    /// The dispute transactions have an optional case id to reference a single dispute of the transaction
+    Dispute(Option<CaseId>),
+    /// Dispute only a part of a deposit or withdrawal, a dispute row with an amount
+    PartialDispute(Decimal, Option<CaseId>),
+    /// Without a case id the oldest open dispute is resolved
+    Resolve(Option<CaseId>),
+    /// Without a case id the oldest open dispute is charged back
+    Chargeback(Option<CaseId>),
+    /// The merchant won the representment of a chargeback, without a case id the oldest chargeback is reversed
+    ChargebackReversal(Option<CaseId>),
+    /// Clear the frozen flag of the account, the tx id of the row is not used
+    Unlock,
+    /// Reopen a closed account, it is accepted for an open account so a client can be created explicitly
+    OpenAccount,
+    /// Close the account, it requires the held amount to be zero
+    CloseAccount,
+    /// Move the amount from the client of the row to the destination client
+    /// It spans two accounts, so it can only be processed by `Engine`
+    Transfer(ClientId, Decimal),
+    /// A manual correction of available by an operator, the amount is signed
+    /// It is accepted by a frozen account, and it can't be disputed
+    Adjustment(Decimal, ReasonCode),
+    /// Annotate the account for the operators, see `FlagRecord`, the balances are unchanged
+    /// Its id is a free-form reference, e.g. to a transaction of the account, so it is not checked or used
+    Flag(ReasonCode),
 }
 
-/// The dispute states for a (deposit) transaction
-/// They are used and changed in `Dispute`, `Resolve`, `Chargeback` transactions
-#[derive(Debug, Eq, PartialEq, Default)]
+/// The dispute state of a deposit or withdrawal transaction, see `TransactionRecord::state`
+#[derive(Debug, Eq, PartialEq, Default, Serialize, Deserialize)]
 pub enum TransactionState {
     #[default]
     Normal,
+    /// At least one dispute is open
     UnderDispute,
+    /// At least one dispute was charged back
     Chargeback,
 }
 
+/// The outcome of a single dispute
+/// They are changed in `Dispute`, `Resolve`, `Chargeback` transactions
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DisputeState {
+    Open,
+    Resolved,
+    Chargeback,
+    /// The chargeback was reversed and the fund restored
+    Reversed,
+}
+
+/// A dispute of a part or the whole amount of a transaction
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DisputeCase {
+    pub id: Option<CaseId>,
+    pub amount: Decimal,
+    pub state: DisputeState,
+    /// When the dispute was opened, None if the dispute row doesn't have a timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opened: Option<Timestamp>,
+}
+
+/// The data we store for a deposit or withdrawal transaction
+#[derive(Debug, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub amount: Decimal,
+    /// All disputes of this transaction in the order they were opened, several of them can be open at once
+    #[serde(default)]
+    pub cases: DisputeCases,
+    /// When the transaction happened, None if the input doesn't have timestamps
+    pub timestamp: Option<Timestamp>,
+}
+
+/// A flag set on an account by a `flag` row, e.g. to mark it for a review
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FlagRecord {
+    /// The tx of the flag row, it may reference a transaction of the account, it is not checked
+    pub tx: TransactionId,
+    pub reason: ReasonCode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+}
+
+/// The lifecycle of an account
+/// A frozen account only accepts `Unlock`, `ChargebackReversal`, `Adjustment` and `Flag`,
+/// a closed account only accepts `OpenAccount`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum AccountStatus {
+    #[default]
+    Open,
+    Closed,
+    /// Frozen by a chargeback, a risk rule or a locked opening balance
+    Frozen,
+}
+
 /// The data we store for a single client
-/// For each deposit transaction we store the dispute state and their amount
-#[derive(Debug, Eq, PartialEq, Default)]
+/// For each deposit and withdrawal transaction we store the dispute state and their amount
+#[derive(Debug, Eq, PartialEq, Default, Serialize, Deserialize)]
 pub struct AccountProfile {
     pub available: Decimal,
     pub held: Decimal,
-    pub deposit_transactions: HashMap<TransactionId, (TransactionState, Decimal)>, // tx -> (state, amount)
-    pub transaction_ids: HashSet<TransactionId>,
-    pub frozen: bool,
+    /// The used ids with the records of the deposits and withdrawals
+    #[serde(flatten)]
+    pub transactions: Transactions,
+    pub status: AccountStatus,
+    /// How far available can go below zero on withdrawals, None means the limit of `EngineConfig`
+    pub overdraft_limit: Option<Decimal>,
+    /// The withdrawal fees charged to this client
+    pub fees_collected: Decimal,
+    /// The latest timestamp of the accepted transactions
+    pub last_timestamp: Option<Timestamp>,
+    /// The daily counters of `RiskRules`, they are only updated when the engine has risk rules
+    #[serde(default)]
+    pub risk: RiskCounters,
+    /// The used ids from the oldest, only kept with `EngineConfig::max_transactions_per_account`
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub recent: VecDeque<TransactionId>,
+    /// How many transactions were evicted, and their deposits minus their withdrawals
+    #[serde(default)]
+    pub evicted: u64,
+    #[serde(default)]
+    pub evicted_net: Decimal,
+    /// The evicted ids, they are not used again
+    #[serde(default, skip_serializing_if = "EvictedIds::is_empty")]
+    pub evicted_ids: EvictedIds,
+    /// The balance the account started with when it was seeded, see `AccountProfile::with_balances`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opening: Option<Decimal>,
+    /// The sum of the accepted adjustments
+    #[serde(default)]
+    pub adjusted: Decimal,
+    /// The flags in the order they were set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<FlagRecord>,
+}
+
+/// Whether a record is a deposit or a withdrawal, it decides the direction of the fund in a dispute
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
 }
 
+/// The type column of an input row, an unknown type is rejected when the row is deserialized
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    ChargebackReversal,
+    #[serde(alias = "unfreeze")]
+    Unlock,
+    OpenAccount,
+    CloseAccount,
+    Transfer,
+    /// Recurring deposits or withdrawals, see `Schedule`
+    Schedule,
+    Adjustment,
+    Flag,
+}
+
+/// The columns of `CsvInputRow` in the order they are written, inputs without a header use this order
+pub const CSV_COLUMNS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "to",
+    "timestamp",
+    "case_id",
+    "interval",
+    "count",
+    "reason",
+];
+
 /// This is used to parse input csv
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct CsvInputRow {
     #[serde(rename = "type")]
-    pub transaction_type: String,
+    pub transaction_type: TransactionType,
     pub client: ClientId,
     pub tx: TransactionId,
     pub amount: Option<Decimal>,
+    /// The destination client of a transfer
+    pub to: Option<ClientId>,
+    pub timestamp: Option<Timestamp>,
+    /// The dispute referenced by a dispute, resolve or chargeback
+    pub case_id: Option<CaseId>,
+    /// The seconds between the occurrences of a schedule
+    pub interval: Option<u64>,
+    /// The number of occurrences of a schedule
+    pub count: Option<u32>,
+    /// The reason code of an adjustment or a flag
+    pub reason: Option<ReasonCode>,
+}
+
+/// A row of an opening balances file, an output of a previous run can be used as one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OpeningBalance {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    #[serde(default)]
+    pub locked: bool,
 }
 
 /// Error type for transaction processing
@@ -53,12 +234,70 @@ pub struct CsvInputRow {
 pub enum TransactionProcessingError {
     #[error("account is frozen")]
     AccountIsFrozen,
+    #[error("account is closed")]
+    AccountIsClosed,
+    #[error("account is not frozen")]
+    AccountIsNotFrozen,
+    #[error("held amount {0} is not zero")]
+    HeldAmountNotZero(Decimal),
     #[error("invalid transaction id: {0}")]
     InvalidTransactionId(TransactionId),
     #[error("available amount {0} is less than withdrawal request amount {1}")]
     AvailableAmountTooLow(Decimal, Decimal),
+    #[error("dispute amount exceeds the transaction amount {0}")]
+    DisputeAmountTooHigh(Decimal),
+    #[error("invalid dispute case id: {0}")]
+    InvalidCaseId(CaseId),
     #[error("transaction is not in the expected state")]
     InvalidTransactionState,
+    #[error("cannot transfer to the same client")]
+    TransferToSelf,
+    #[error("transfer can only be processed by the engine")]
+    TransferNotSupported,
+    #[error("transaction id {0} is already used by client {1}")]
+    DuplicateTransactionId(TransactionId, ClientId),
+    #[error("transaction belongs to client {0}")]
+    ClientMismatch(ClientId),
+    #[error("arithmetic overflow")]
+    ArithmeticOverflow,
+    #[error("withdrawal exceeds the overdraft limit {0}")]
+    OverdraftLimitExceeded(Decimal),
+    #[error("the disputed transaction is older than the dispute window")]
+    DisputeWindowExpired,
+    #[error("timestamp {0} is earlier than the last transaction of the client at {1}")]
+    OutOfOrder(Timestamp, Timestamp),
+    #[error("failed to write the write-ahead log: {0}")]
+    WalWriteFailed(std::io::Error),
+    #[error("failed to access the state store: {0}")]
+    StoreFailed(std::io::Error),
+    #[error("risk limit exceeded: {0}")]
+    RiskLimitExceeded(RiskLimit),
+    #[error("transaction {0} is unknown, it may have been evicted")]
+    TransactionEvicted(TransactionId),
+    #[error("duplicate of an earlier row with transaction id {0}")]
+    DuplicateRow(TransactionId),
+}
+
+/// A limit of `RiskRules` which a transaction would exceed
+#[derive(Debug, Error, Clone, Copy, Eq, PartialEq)]
+pub enum RiskLimit {
+    #[error("deposit is larger than {0}")]
+    MaxDeposit(Decimal),
+    #[error("more than {0} withdrawals in a day")]
+    DailyWithdrawals(u32),
+    #[error("daily volume is larger than {0}")]
+    DailyVolume(Decimal),
+}
+
+/// A broken invariant of an account, it means the state machine has a bug, see `AccountProfile::check_invariants`
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum InvariantViolation {
+    #[error("held amount {0} is negative")]
+    NegativeHeld(Decimal),
+    #[error("total {0} doesn't match the net amount {1} of the transactions")]
+    TotalMismatch(Decimal, Decimal),
+    #[error("account is frozen without a chargeback or a reversed chargeback")]
+    FrozenWithoutChargeback,
 }
 
 /// Error type for transaction parsing
@@ -66,6 +305,117 @@ pub enum TransactionProcessingError {
 pub enum TransactionParsingError {
     #[error("missing amount")]
     MissingAmount,
+    #[error("missing destination client")]
+    MissingDestination,
+    #[error("missing reason code")]
+    MissingReason,
+    #[error("amount has more than {0} decimal places")]
+    TooManyDecimalPlaces(u32),
+    #[error("amount {0} is not positive")]
+    NonPositiveAmount(Decimal),
     #[error("invalid type")]
     InvalidType,
+    #[error("a schedule needs a timestamp, a positive interval and a count")]
+    IncompleteSchedule,
+    #[error("missing tenant")]
+    MissingTenant,
+    #[error("invalid tenant {0:?}, expected letters, digits, `-` or `_`")]
+    InvalidTenant(String),
+}
+
+/// Error type for processing a single input row, it can fail at decoding, parsing or processing
+#[derive(Debug, Error)]
+pub enum RowProcessingError {
+    #[error("malformed csv row: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("malformed json row: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A row of an Arrow batch with a missing or invalid value, see `Engine::process_record_batch`
+    #[cfg(feature = "arrow")]
+    #[error("malformed arrow row: {0}")]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[error("failed to parse transaction: {0}")]
+    Parsing(#[from] TransactionParsingError),
+    #[error("transaction rejected: {0}")]
+    Processing(#[from] TransactionProcessingError),
+}
+
+/// Why a row which is already deserialized is rejected, unlike `RowProcessingError` it can't fail at decoding
+#[derive(Debug, Error)]
+pub(crate) enum RowRejection {
+    #[error(transparent)]
+    Parsing(#[from] TransactionParsingError),
+    #[error(transparent)]
+    Processing(#[from] TransactionProcessingError),
+}
+
+impl From<RowRejection> for RowProcessingError {
+    fn from(rejection: RowRejection) -> Self {
+        match rejection {
+            RowRejection::Parsing(error) => RowProcessingError::Parsing(error),
+            RowRejection::Processing(error) => RowProcessingError::Processing(error),
+        }
+    }
+}
+
+/// A rejected row of a csv input, see `Engine::process_csv_collect_errors`
+/// The kind is the same `RowProcessingError` the callback of `Engine::process_csv_with_errors` gets
+#[derive(Debug, Error)]
+#[error("line {line}: {kind}")]
+pub struct RowError {
+    pub line: u64,
+    #[source]
+    pub kind: RowProcessingError,
+}
+
+/// A rejected row with its line number and the reason, see `Engine::process_row_detailed`
+#[derive(Debug)]
+pub struct RejectedRow<E> {
+    pub line: u64,
+    pub row: CsvInputRow,
+    pub error: E,
+}
+
+/// The result of a single row with enough context to log or retry it
+#[derive(Debug)]
+pub enum ProcessingOutcome {
+    Accepted,
+    RejectedParse(RejectedRow<TransactionParsingError>),
+    RejectedProcessing(RejectedRow<TransactionProcessingError>),
+}
+
+/// Error type for saving and loading engine snapshots
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("snapshot io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid snapshot: {0}")]
+    Format(#[from] serde_json::Error),
+}
+
+/// Error type for writing the account summaries into an `OutputSink`
+#[derive(Debug, Error)]
+pub enum OutputError {
+    #[error("output io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to write csv: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("failed to write json: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The error of a sink outside of this crate, e.g. a database client
+    #[error("output sink failed: {0}")]
+    Sink(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Error type for loading the TOML config files
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("config io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid config: {0}")]
+    Toml(#[from] toml_edit::TomlError),
+    #[error("invalid value for {0}")]
+    InvalidValue(String),
+    #[error("unknown key {0}")]
+    UnknownKey(String),
 }
@@ -0,0 +1,230 @@
+use crate::config::EngineConfig;
+use crate::record::lenient_amount;
+use crate::types::{CSV_COLUMNS, TransactionType};
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::str::FromStr;
+
+/// The columns every input needs
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/// How many issues are listed in a report, the others are only counted
+pub const MAX_LISTED_ISSUES: usize = 1000;
+
+/// What is wrong with a field of an input
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaProblem {
+    /// The row can't be read as csv, e.g. invalid utf-8
+    MalformedRow,
+    UnknownType,
+    /// Not an integer from 0 to 65535
+    InvalidClient,
+    /// Not an integer from 0 to 4294967295
+    InvalidTx,
+    InvalidAmount,
+    /// A deposit, withdrawal, transfer, schedule or adjustment without an amount
+    MissingAmount,
+    TooManyDecimalPlaces,
+    /// A `to`, `timestamp`, `case_id`, `interval`, `count` or `reason` which is not a non-negative integer in range
+    InvalidNumber,
+}
+
+/// A field which doesn't match the schema
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct SchemaIssue {
+    pub line: u64,
+    pub column: String,
+    pub value: String,
+    pub problem: SchemaProblem,
+}
+
+/// The result of `validate_csv`, the input is valid when it has no missing column and no issue
+#[derive(Serialize, Debug, Default, Eq, PartialEq)]
+pub struct ValidationReport {
+    pub rows: u64,
+    pub missing_columns: Vec<String>,
+    /// Columns which are ignored by the engine, they are not an error
+    pub unknown_columns: Vec<String>,
+    pub issue_counts: BTreeMap<SchemaProblem, u64>,
+    /// The first `MAX_LISTED_ISSUES` issues in input order
+    pub issues: Vec<SchemaIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.missing_columns.is_empty() && self.issue_counts.is_empty()
+    }
+
+    fn push(&mut self, line: u64, column: &str, value: &str, problem: SchemaProblem) {
+        *self.issue_counts.entry(problem).or_default() += 1;
+        if self.issues.len() < MAX_LISTED_ISSUES {
+            self.issues.push(SchemaIssue {
+                line,
+                column: column.to_string(),
+                value: value.to_string(),
+                problem,
+            });
+        }
+    }
+}
+
+/// Check the headers and every row of a csv input against our schema without processing any balance
+/// The csv dialect, the type aliases and `lenient_amounts` of `config` are applied like in a run, and the amounts
+/// may have at most the decimal places of the precision policy, 4 without one
+pub fn validate_csv(input: impl Read, config: &EngineConfig) -> csv::Result<ValidationReport> {
+    let mut rdr = config.csv.reader(input);
+    let headers = config.csv.headers(&mut rdr)?;
+    let position = |column: &str| headers.iter().position(|name| name == column);
+    let mut report = ValidationReport {
+        missing_columns: REQUIRED_COLUMNS
+            .into_iter()
+            .filter(|column| position(column).is_none())
+            .map(String::from)
+            .collect(),
+        unknown_columns: headers
+            .iter()
+            .filter(|name| !CSV_COLUMNS.contains(name) && *name != "tenant")
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    let scale = config.precision.unwrap_or_default().scale;
+    let mut record = StringRecord::new();
+    loop {
+        match rdr.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {
+                report.rows += 1;
+                let line = record.position().map_or(0, |pos| pos.line());
+                validate_record(&mut report, line, &record, &headers, config, scale);
+            }
+            Err(err) => {
+                report.rows += 1;
+                let line = rdr.position().line();
+                report.push(line, "", "", SchemaProblem::MalformedRow);
+                if err.is_io_error() {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn validate_record(
+    report: &mut ValidationReport,
+    line: u64,
+    record: &StringRecord,
+    headers: &StringRecord,
+    config: &EngineConfig,
+    scale: u32,
+) {
+    let mut transaction_type = None;
+    let mut has_amount = false;
+    for (column, value) in headers.iter().zip(record.iter()) {
+        let problem = match column {
+            "type" => {
+                let resolved = match &config.type_aliases {
+                    Some(aliases) => aliases.resolve(value),
+                    None => value.into(),
+                };
+                transaction_type = TransactionType::from_str(&resolved).ok();
+                transaction_type
+                    .is_none()
+                    .then_some(SchemaProblem::UnknownType)
+            }
+            "client" => u16::from_str(value)
+                .is_err()
+                .then_some(SchemaProblem::InvalidClient),
+            "tx" => u32::from_str(value)
+                .is_err()
+                .then_some(SchemaProblem::InvalidTx),
+            "amount" if !value.is_empty() => {
+                has_amount = true;
+                let plain = config
+                    .lenient_amounts
+                    .then(|| lenient_amount(value.as_bytes()))
+                    .flatten();
+                match Decimal::from_str(plain.as_deref().unwrap_or(value)) {
+                    Err(_) => Some(SchemaProblem::InvalidAmount),
+                    Ok(amount) if amount.normalize().scale() > scale => {
+                        Some(SchemaProblem::TooManyDecimalPlaces)
+                    }
+                    Ok(_) => None,
+                }
+            }
+            "to" | "case_id" if !value.is_empty() => u32::from_str(value)
+                .is_err()
+                .then_some(SchemaProblem::InvalidNumber),
+            "timestamp" | "interval" if !value.is_empty() => u64::from_str(value)
+                .is_err()
+                .then_some(SchemaProblem::InvalidNumber),
+            "count" | "reason" if !value.is_empty() => u32::from_str(value)
+                .is_err()
+                .then_some(SchemaProblem::InvalidNumber),
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            report.push(line, column, value, problem);
+        }
+    }
+    if !has_amount
+        && matches!(
+            transaction_type,
+            Some(
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Transfer
+                    | TransactionType::Schedule
+                    | TransactionType::Adjustment
+            )
+        )
+    {
+        report.push(line, "amount", "", SchemaProblem::MissingAmount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_csv() {
+        let input = "type,client,tx,amount,note
+deposit,1,1,1.5,a
+Refund,70000,-1,1.23456,b
+withdrawal,1,2,,c
+dispute,1,1,,d
+deposit,1,3,abc,e
+";
+        let report = validate_csv(input.as_bytes(), &EngineConfig::default()).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.rows, 5);
+        assert!(report.missing_columns.is_empty());
+        assert_eq!(report.unknown_columns, ["note"]);
+        let issues: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.line, issue.column.as_str(), issue.problem))
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                (3, "type", SchemaProblem::UnknownType),
+                (3, "client", SchemaProblem::InvalidClient),
+                (3, "tx", SchemaProblem::InvalidTx),
+                (3, "amount", SchemaProblem::TooManyDecimalPlaces),
+                (4, "amount", SchemaProblem::MissingAmount),
+                (6, "amount", SchemaProblem::InvalidAmount),
+            ]
+        );
+        assert_eq!(report.issue_counts[&SchemaProblem::InvalidClient], 1);
+
+        let report = validate_csv("client,amount\n".as_bytes(), &EngineConfig::default()).unwrap();
+        assert_eq!(report.missing_columns, ["type", "tx"]);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::config::{CsvOptions, EngineConfig, RowFilter};
+use crate::engine::Engine;
+use crate::store::StateStore;
+use crate::types::{CSV_COLUMNS, ClientId, CsvInputRow, Timestamp, Transaction, TransactionId};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// An append-only log of the transactions accepted by an engine
+/// The log uses the csv format of the input with the default dialect, so recovering is just replaying the log
+/// A transaction is logged before it is applied and removed again when it ends up rejected.
+/// The schedule rows are logged as well, but not their occurrences nor the disputes closed by the timeout,
+/// since processing is deterministic the replay of the rows before them does them again
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    writer: csv::Writer<File>,
+    /// Where the row appended last starts
+    last_row: u64,
+}
+
+impl WriteAheadLog {
+    /// Open the log at `path` for appending, the file is created if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        // A crash may leave a partially written last row, which was never accepted, so it is cut off
+        let len = complete_len(&mut file)?;
+        file.set_len(len)?;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(file);
+        if len == 0 {
+            writer.write_record(CSV_COLUMNS)?;
+            writer.flush()?;
+        }
+        Ok(Self {
+            writer,
+            last_row: len,
+        })
+    }
+
+    /// Append a row and flush it to the OS before returning
+    pub fn append_row(&mut self, row: &CsvInputRow) -> io::Result<()> {
+        self.last_row = self.writer.get_ref().seek(SeekFrom::End(0))?;
+        self.writer.serialize(row)?;
+        self.writer.flush()
+    }
+
+    pub fn append(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        transaction: &Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> io::Result<()> {
+        self.append_row(&CsvInputRow {
+            timestamp,
+            ..CsvInputRow::new(client, id, transaction)
+        })
+    }
+
+    /// Remove the row appended last, for a transaction which ended up rejected
+    pub fn discard_last(&mut self) -> io::Result<()> {
+        self.writer.get_ref().set_len(self.last_row)
+    }
+}
+
+/// The length of `file` without a partial last line
+fn complete_len(file: &mut File) -> io::Result<u64> {
+    let mut end = file.metadata()?.len();
+    let mut chunk = [0u8; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(chunk.len() as u64);
+        let chunk = &mut chunk[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(newline) = chunk.iter().rposition(|&byte| byte == b'\n') {
+            return Ok(start + newline as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+impl<S: StateStore> Engine<S> {
+    /// Rebuild the engine by replaying the write-ahead log at `path`, and keep logging into it afterwards
+    pub fn recover(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::recover_with(path, EngineConfig::default())
+    }
+
+    /// Same as `recover` with the policies of `config`, which must be the config the log was written with,
+    /// otherwise the replay doesn't end with the same balances, e.g. without the fees of the withdrawals
+    /// A rejected row means the log doesn't match `config`, which is an `InvalidData` error. Only the last row
+    /// may be rejected, when we crashed before removing it again, and it is removed then
+    /// The csv options and the filter of `config` only apply to the input, the log is replayed without them
+    pub fn recover_with(path: impl AsRef<Path>, config: EngineConfig) -> io::Result<Self> {
+        let path = path.as_ref();
+        let wal = WriteAheadLog::open(path)?;
+        let mut engine = Self::default();
+        engine.set_config(EngineConfig {
+            csv: CsvOptions::default(),
+            filter: RowFilter::default(),
+            ..config.clone()
+        });
+        let invalid = |line: u64, err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {line} of the write-ahead log is rejected: {err}"),
+            )
+        };
+        let mut rejected = None;
+        engine.process_csv_rows(File::open(path)?, |_, line, record, result| {
+            if let Some((line, _, err)) = rejected.take() {
+                return Err(invalid(line, err));
+            }
+            if let Err(err) = result {
+                rejected = Some((line, record.position().map(csv::Position::byte), err));
+            }
+            Ok(())
+        })?;
+        match rejected {
+            Some((_, Some(start), _)) => wal.writer.get_ref().set_len(start)?,
+            Some((line, None, err)) => return Err(invalid(line, err)),
+            None => {}
+        }
+        engine.set_config(config);
+        engine.wal = Some(wal);
+        Ok(engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DisputeTimeout, DisputeTimeoutAction, FeePolicy};
+    use rust_decimal::Decimal;
+    use std::io::Write;
+
+    #[test]
+    fn test_recover() {
+        let path = std::env::temp_dir().join(format!("wal-{}.csv", std::process::id()));
+        _ = std::fs::remove_file(&path);
+
+        let mut engine: Engine = Engine::recover(&path).unwrap();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::new(15, 1)))
+            .unwrap();
+        engine
+            .process_transaction(1, 2, Transaction::Transfer(2, Decimal::from(1)))
+            .unwrap();
+        assert!(
+            engine
+                .process_transaction(1, 3, Transaction::Withdrawal(Decimal::from(5)))
+                .is_err()
+        );
+        engine
+            .process_transaction(2, 2, Transaction::Dispute(None))
+            .unwrap();
+        drop(engine);
+
+        // Simulate a crash in the middle of writing a row
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"deposit,3,4,").unwrap();
+
+        let mut recovered: Engine = Engine::recover(&path).unwrap();
+        assert_eq!(recovered.account(1).unwrap().available, Decimal::new(5, 1));
+        assert_eq!(recovered.account(2).unwrap().held, Decimal::from(1));
+        assert!(recovered.account(3).is_none());
+
+        recovered
+            .process_transaction(3, 5, Transaction::Deposit(Decimal::from(2)))
+            .unwrap();
+        drop(recovered);
+        let recovered: Engine = Engine::recover(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recovered.account(3).unwrap().available, Decimal::from(2));
+    }
+
+    #[test]
+    fn test_recover_with_config() {
+        let path = std::env::temp_dir().join(format!("wal-fees-{}.csv", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let config = || EngineConfig {
+            fee_policy: Some(FeePolicy {
+                flat: Decimal::ONE,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut engine: Engine = Engine::recover_with(&path, config()).unwrap();
+        engine
+            .process_transaction(1, 1, Transaction::Deposit(Decimal::from(10)))
+            .unwrap();
+        engine
+            .process_transaction(1, 2, Transaction::Withdrawal(Decimal::from(5)))
+            .unwrap();
+        drop(engine);
+
+        let recovered: Engine = Engine::recover_with(&path, config()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let account = recovered.account(1).unwrap();
+        assert_eq!(account.available, Decimal::from(4));
+        assert_eq!(account.fees_collected, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_recover_dialect() {
+        // The input has another dialect, the log is still written and replayed as a default csv
+        let path = std::env::temp_dir().join(format!("wal-dialect-{}.csv", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let config = || {
+            let mut config = EngineConfig::default();
+            config.csv.delimiter = b';';
+            config.csv.has_headers = false;
+            config
+        };
+        for _ in 0..2 {
+            let mut engine: Engine = Engine::recover_with(&path, config()).unwrap();
+            engine.process_csv("deposit;1;1;1.5\n".as_bytes());
+            drop(engine);
+        }
+        let recovered: Engine = Engine::recover_with(&path, config()).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(log.starts_with("type,client,tx,amount"));
+        // The second deposit reused the id, it was rejected and is not in the log
+        assert_eq!(log.lines().count(), 2);
+        assert_eq!(recovered.account(1).unwrap().available, Decimal::new(15, 1));
+        assert_eq!(recovered.config().csv.delimiter, b';');
+    }
+
+    #[test]
+    fn test_recover_schedules() {
+        let path = std::env::temp_dir().join(format!("wal-schedules-{}.csv", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let config = || EngineConfig {
+            dispute_timeout: Some(DisputeTimeout {
+                after: 100,
+                action: DisputeTimeoutAction::Resolve,
+            }),
+            ..Default::default()
+        };
+        let input = "type,client,tx,amount,timestamp,interval,count\n\
+            deposit,1,1,10,0,,\n\
+            deposit,2,2,5,0,,\n\
+            schedule,1,100,-3,10,10,3\n\
+            dispute,2,2,,15,,\n\
+            deposit,3,3,1,120,,\n";
+        let mut engine: Engine = Engine::recover_with(&path, config()).unwrap();
+        engine
+            .process_csv_with_errors(input.as_bytes(), |_, _, err| Err::<(), _>(err))
+            .unwrap();
+        drop(engine);
+
+        // The occurrences and the closed dispute are not logged, the replay does them again
+        let mut recovered: Engine = Engine::recover_with(&path, config()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 6);
+        assert_eq!(recovered.account(1).unwrap().available, Decimal::from(1));
+        let profile = recovered.account(2).unwrap();
+        assert_eq!(profile.available, Decimal::from(5));
+        assert_eq!(profile.held, Decimal::ZERO);
+        assert_eq!(recovered.schedules().len(), 0);
+
+        // A schedule with occurrences left is still pending after the recovery
+        recovered
+            .process_csv_with_errors(
+                "type,client,tx,amount,timestamp,interval,count\n\
+                    schedule,2,200,1,130,10,3\n"
+                    .as_bytes(),
+                |_, _, err| Err::<(), _>(err),
+            )
+            .unwrap();
+        drop(recovered);
+        let mut recovered: Engine = Engine::recover_with(&path, config()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recovered.schedules().len(), 1);
+        assert_eq!(recovered.account(2).unwrap().available, Decimal::from(6));
+        assert_eq!(recovered.run_schedules(150), 2);
+    }
+
+    #[test]
+    fn test_recover_rejected_row() {
+        let path = std::env::temp_dir().join(format!("wal-rejected-{}.csv", std::process::id()));
+        let header = CSV_COLUMNS.join(",");
+        // A rejected last row is left by a crash before it was removed, it is removed on recovery
+        std::fs::write(
+            &path,
+            format!("{header}\ndeposit,1,1,1,,,,,,\nwithdrawal,1,2,5,,,,,,\n"),
+        )
+        .unwrap();
+        let recovered: Engine = Engine::recover(&path).unwrap();
+        assert_eq!(recovered.account(1).unwrap().available, Decimal::ONE);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{header}\ndeposit,1,1,1,,,,,,\n")
+        );
+        drop(recovered);
+
+        // Anywhere else it means the log doesn't match the config
+        std::fs::write(
+            &path,
+            format!("{header}\nwithdrawal,1,2,5,,,,,,\ndeposit,1,1,1,,,,,,\n"),
+        )
+        .unwrap();
+        let err = Engine::<crate::store::MemoryStore>::recover(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2 of the write-ahead log"));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::engine::Engine;
+use crate::output::write_summaries;
+use crate::types::CsvInputRow;
+use wasm_bindgen::prelude::*;
+
+/// An `Engine` for JavaScript, e.g. a browser tool which feeds the transactions of a file and shows the balances
+/// The errors are thrown as strings with the reason, the accounts are returned as csv or json text
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct WasmEngine {
+    engine: Engine,
+}
+
+#[wasm_bindgen]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process a csv input with a header, returns the rejected rows with their line, one per line
+    #[wasm_bindgen(js_name = processCsv)]
+    pub fn process_csv(&mut self, csv: &str) -> String {
+        let errors = self.engine.process_csv_collect_errors(csv.as_bytes());
+        errors.iter().map(|err| format!("{err}\n")).collect()
+    }
+
+    /// Process a transaction given as json with the fields of an input row,
+    /// e.g. `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`
+    #[wasm_bindgen(js_name = submitTransaction)]
+    pub fn submit_transaction(&mut self, json: &str) -> Result<(), String> {
+        let row: CsvInputRow = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        self.engine.process_row(&row).map_err(|err| err.to_string())
+    }
+
+    /// The balances of `client` as json, undefined for an unknown client
+    pub fn account(&self, client: u16) -> Option<String> {
+        let summary = self.engine.summary(client)?;
+        Some(serde_json::to_string(&summary).expect("failed to serialize account"))
+    }
+
+    /// All accounts ordered by client like the csv output of the binary
+    #[wasm_bindgen(js_name = accountsCsv)]
+    pub fn accounts_csv(&self) -> Result<String, String> {
+        let mut summaries: Vec<_> = self.engine.summaries().collect();
+        summaries.sort_by_key(|summary| summary.client);
+        let mut csv = Vec::new();
+        write_summaries(summaries, &mut csv).map_err(|err| err.to_string())?;
+        String::from_utf8(csv).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasm_engine() {
+        let mut engine = WasmEngine::new();
+        let errors = engine.process_csv("type,client,tx,amount\ndeposit,1,1,2\nwithdrawal,1,2,3\n");
+        assert_eq!(errors.lines().count(), 1);
+        assert!(errors.starts_with("line 3: "));
+
+        engine
+            .submit_transaction(r#"{"type": "deposit", "client": 2, "tx": 3, "amount": "1.5"}"#)
+            .unwrap();
+        assert!(engine.submit_transaction("not json").is_err());
+        assert!(
+            engine
+                .submit_transaction(
+                    r#"{"type": "withdrawal", "client": 2, "tx": 4, "amount": "9"}"#
+                )
+                .is_err()
+        );
+
+        assert_eq!(
+            engine.account(2).unwrap(),
+            r#"{"client":2,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        );
+        assert!(engine.account(3).is_none());
+        assert_eq!(
+            engine.accounts_csv().unwrap(),
+            "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n2,1.5000,0.0000,1.5000,false\n"
+        );
+    }
+}